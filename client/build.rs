@@ -1,3 +1,4 @@
+use std::path::Path;
 use std::process::Command;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -10,6 +11,34 @@ fn commit_hash() -> Option<String> {
         .map(|hash| hash.trim().into())
 }
 
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|s| s.trim().into())
+}
+
+// 切换分支或提交后重新生成 GIT_HASH，否则增量编译沿用旧的 hash
+fn rerun_on_commit() {
+    let mut paths = vec![git(&["rev-parse", "--git-path", "HEAD"])];
+    // HEAD 指向的分支，以及 gc 后存放分支的 packed-refs
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        paths.push(git(&["rev-parse", "--git-path", &head_ref]));
+        paths.push(git(&["rev-parse", "--git-path", "packed-refs"]));
+    }
+    // 不存在的路径会使每次编译都重新执行
+    for path in paths
+        .into_iter()
+        .flatten()
+        .filter(|path| Path::new(path).exists())
+    {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
+
 fn build_ts() -> Option<String> {
     Command::new("date")
         .args(&["+%Y-%m-%d %H:%M:%S %Z"])
@@ -20,7 +49,14 @@ fn build_ts() -> Option<String> {
 }
 
 fn main() {
+    rerun_on_commit();
+    println!("cargo:rerun-if-changed=build.rs");
     let mut app_version = String::from(env!("CARGO_PKG_VERSION"));
+    // 上报的 version 中附带 git short hash，非 git 目录编译时为空
+    println!(
+        "cargo:rustc-env=GIT_HASH={}",
+        commit_hash().unwrap_or_default()
+    );
     if let Some(commit_hash) = commit_hash() {
         app_version = format!(
             "v{} GIT:{}, BUILD:{}",
@@ -0,0 +1,123 @@
+#![deny(warnings)]
+use once_cell::sync::Lazy;
+use stat_common::server_status::StatRequest;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// 服务端短暂不可达时缓存上报，恢复后按原顺序补发
+static BUFFER: Lazy<Mutex<Ring>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct Ring {
+    buf: VecDeque<StatRequest>,
+    // 为 0 时不缓存
+    capacity: usize,
+}
+
+impl Ring {
+    // 缓存满时丢弃最早的
+    fn truncate(&mut self) {
+        while self.buf.len() > self.capacity {
+            self.buf.pop_front();
+        }
+    }
+
+    fn push(&mut self, mut stat: StatRequest) {
+        if self.capacity == 0 {
+            return;
+        }
+        stat.buffered = true;
+        self.buf.push_back(stat);
+        if self.buf.len() > self.capacity {
+            warn!("report buffer full, drop oldest");
+        }
+        self.truncate();
+    }
+
+    fn restore(&mut self, stats: Vec<StatRequest>) {
+        for stat in stats.into_iter().rev() {
+            self.buf.push_front(stat);
+        }
+        self.truncate();
+    }
+
+    fn take(&mut self) -> Vec<StatRequest> {
+        self.buf.drain(..).collect()
+    }
+}
+
+pub fn init(capacity: usize) {
+    BUFFER.lock().unwrap().capacity = capacity;
+}
+
+// 发送失败的上报，latest_ts 保留原始采样时间
+pub fn push(stat: StatRequest) {
+    BUFFER.lock().unwrap().push(stat);
+}
+
+// 补发中途失败，未发送的放回队首
+pub fn restore(stats: Vec<StatRequest>) {
+    BUFFER.lock().unwrap().restore(stats);
+}
+
+pub fn take() -> Vec<StatRequest> {
+    BUFFER.lock().unwrap().take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(latest_ts: u64) -> StatRequest {
+        StatRequest {
+            latest_ts,
+            ..Default::default()
+        }
+    }
+
+    fn ts(stats: &[StatRequest]) -> Vec<u64> {
+        stats.iter().map(|o| o.latest_ts).collect()
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut ring = Ring::default();
+        ring.push(stat(1));
+        assert!(ring.take().is_empty());
+    }
+
+    #[test]
+    fn drop_oldest_when_full() {
+        let mut ring = Ring {
+            capacity: 3,
+            ..Default::default()
+        };
+        for i in 1..=5 {
+            ring.push(stat(i));
+        }
+        let stats = ring.take();
+        // 按原顺序补发，保留原始采样时间
+        assert_eq!(ts(&stats), [3, 4, 5]);
+        assert!(stats.iter().all(|o| o.buffered));
+        assert!(ring.take().is_empty());
+    }
+
+    #[test]
+    fn restore_before_new() {
+        let mut ring = Ring {
+            capacity: 3,
+            ..Default::default()
+        };
+        for i in 1..=3 {
+            ring.push(stat(i));
+        }
+        let mut pending = ring.take().into_iter();
+        // 补发第一条成功后失败，期间又有新的上报
+        pending.next();
+        ring.push(stat(4));
+        ring.push(stat(5));
+        ring.restore(pending.collect());
+        // 放回队首，满时仍丢弃最早的
+        assert_eq!(ts(&ring.take()), [3, 4, 5]);
+    }
+}
@@ -0,0 +1,242 @@
+#![deny(warnings)]
+use lazy_static::lazy_static;
+use std::io::{self, ErrorKind};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::sampler;
+
+const SAMPLE_PERIOD: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(3);
+// 1900-01-01 到 1970-01-01 的秒数
+const NTP_EPOCH_OFFSET: f64 = 2_208_988_800.0;
+
+lazy_static! {
+    // 本机时钟相对 NTP 的偏差(毫秒)，本机快为正，不可用时为 None
+    pub static ref G_CLOCK_OFFSET: Arc<Mutex<Option<f64>>> = Arc::new(Default::default());
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+fn ntp_ts(buf: &[u8]) -> f64 {
+    let secs = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64;
+    let frac = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) as f64;
+    secs + frac / 4_294_967_296.0 - NTP_EPOCH_OFFSET
+}
+
+// SNTP(RFC 4330)，offset = ((t2 - t1) + (t3 - t4)) / 2 为服务器相对本机的偏差
+fn query_ntp(server: &str) -> io::Result<f64> {
+    // 未指定端口时为 123
+    let addr = server
+        .to_socket_addrs()
+        .or_else(|_| (server, 123).to_socket_addrs())?
+        .next()
+        .ok_or_else(|| invalid("ntp server not resolved"))?;
+    let socket = UdpSocket::bind(if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(addr)?;
+
+    let mut req = [0u8; 48];
+    // LI = 0, VN = 4, Mode = 3(client)
+    req[0] = 0x23;
+    let t1 = now_secs();
+    socket.send(&req)?;
+    let mut resp = [0u8; 48];
+    let n = socket.recv(&mut resp)?;
+    let t4 = now_secs();
+    parse_ntp(&resp[..n], t1, t4)
+}
+
+// t1 为发送请求、t4 为收到响应时的本机时间
+fn parse_ntp(resp: &[u8], t1: f64, t4: f64) -> io::Result<f64> {
+    if resp.len() < 48 || resp[0] & 0x07 != 4 {
+        return Err(invalid("invalid ntp response"));
+    }
+    // stratum 0 为 kiss-o'-death
+    if resp[1] == 0 {
+        return Err(invalid("ntp server not synchronized"));
+    }
+    let (t2, t3) = (ntp_ts(&resp[32..40]), ntp_ts(&resp[40..48]));
+    let offset = ((t2 - t1) + (t3 - t4)) / 2.0;
+    // 取反: 本机快为正
+    Ok(-offset * 1000.0)
+}
+
+fn run(cmd: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(cmd).args(args).output()?;
+    if !output.status.success() {
+        return Err(invalid(&format!("{} exit with {}", cmd, output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn chrony_offset() -> io::Result<f64> {
+    parse_chrony(&run("chronyc", &["tracking"])?)
+}
+
+// System time     : 0.000012345 seconds fast of NTP time
+fn parse_chrony(out: &str) -> io::Result<f64> {
+    let line = out
+        .lines()
+        .find(|l| l.starts_with("System time"))
+        .ok_or_else(|| invalid("unexpected chronyc output"))?;
+    let v = line
+        .split_once(':')
+        .map(|(_, v)| v.split_whitespace().collect::<Vec<_>>())
+        .unwrap_or_default();
+    match v.as_slice() {
+        [secs, "seconds", dir, ..] => {
+            let secs = secs
+                .parse::<f64>()
+                .map_err(|_| invalid("unexpected chronyc output"))?;
+            Ok(if *dir == "slow" { -secs } else { secs } * 1000.0)
+        }
+        _ => Err(invalid("unexpected chronyc output")),
+    }
+}
+
+fn timesyncd_offset() -> io::Result<f64> {
+    parse_timesyncd(&run("timedatectl", &["timesync-status"])?)
+}
+
+// systemd-timesyncd: `Offset: -264us`，与 chrony 相反为服务器相对本机的偏差
+fn parse_timesyncd(out: &str) -> io::Result<f64> {
+    let v = out
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("Offset:"))
+        .map(|v| v.trim().trim_start_matches('+'))
+        .next()
+        .ok_or_else(|| invalid("timesyncd not synchronized"))?;
+    let (num, unit) = v.split_at(
+        v.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+            .unwrap_or(v.len()),
+    );
+    let num = num
+        .parse::<f64>()
+        .map_err(|_| invalid("unexpected timedatectl output"))?;
+    let ms = match unit {
+        "ns" => num / 1e6,
+        "us" | "μs" => num / 1e3,
+        "ms" => num,
+        "s" => num * 1e3,
+        "min" => num * 6e4,
+        _ => return Err(invalid("unexpected timedatectl output")),
+    };
+    Ok(-ms)
+}
+
+// 设置 ntp_server 时直接查询，否则依次读取 chrony / systemd-timesyncd 的测量值
+pub fn get_clock_offset(ntp_server: &str) -> io::Result<f64> {
+    if !ntp_server.is_empty() {
+        return query_ntp(ntp_server);
+    }
+    chrony_offset()
+        .or_else(|_| timesyncd_offset())
+        .map_err(|_| {
+            io::Error::new(
+                ErrorKind::NotFound,
+                "neither chronyc nor timedatectl timesync-status available, set --ntp-server",
+            )
+        })
+}
+
+pub fn start_clock_collect_t(ntp_server: String) {
+    sampler::spawn_periodic(
+        SAMPLE_PERIOD,
+        move || get_clock_offset(&ntp_server),
+        |err| warn!("collect clock offset error => {}", err),
+        |res| {
+            if let Ok(mut o) = G_CLOCK_OFFSET.lock() {
+                *o = res.ok();
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn chrony_tracking() {
+        let out = "\
+Reference ID    : A9FEA97B (169.254.169.123)
+Stratum         : 4
+System time     : 0.000012345 seconds fast of NTP time
+Last offset     : -0.000003215 seconds
+";
+        assert!(close(parse_chrony(out).unwrap(), 0.012345));
+        let slow = out.replace("fast", "slow");
+        assert!(close(parse_chrony(&slow).unwrap(), -0.012345));
+        assert!(parse_chrony("Stratum : 4\n").is_err());
+        assert!(parse_chrony("System time : n/a\n").is_err());
+    }
+
+    #[test]
+    fn timesyncd_status() {
+        let status = |offset: &str| {
+            format!(
+                "       Server: 185.125.190.56 (ntp.ubuntu.com)\n       Offset: {}\n        Delay: 10.2ms\n",
+                offset
+            )
+        };
+        // 服务器相对本机的偏差，取反后本机快为正
+        assert!(close(parse_timesyncd(&status("-264us")).unwrap(), 0.264));
+        assert!(close(parse_timesyncd(&status("+1.5ms")).unwrap(), -1.5));
+        assert!(close(parse_timesyncd(&status("2s")).unwrap(), -2000.0));
+        assert!(close(parse_timesyncd(&status("500ns")).unwrap(), -0.0005));
+        assert!(parse_timesyncd(&status("3h")).is_err());
+        assert!(parse_timesyncd("       Server: n/a\n").is_err());
+    }
+
+    // 秒 + 1/2^32 秒的小数部分
+    fn put_ts(buf: &mut [u8], unix: f64) {
+        let ntp = unix + NTP_EPOCH_OFFSET;
+        buf[..4].copy_from_slice(&(ntp.trunc() as u32).to_be_bytes());
+        buf[4..8].copy_from_slice(&((ntp.fract() * 4_294_967_296.0) as u32).to_be_bytes());
+    }
+
+    #[test]
+    fn ntp_response() {
+        let mut resp = [0u8; 48];
+        // LI = 0, VN = 4, Mode = 4(server)
+        resp[0] = 0x24;
+        resp[1] = 2;
+        let mut buf = [0u8; 8];
+        put_ts(&mut buf, 1_600_000_000.5);
+        assert!(close(ntp_ts(&buf), 1_600_000_000.5));
+
+        // 服务器比本机慢 0.25s，往返 0.1s
+        let t1 = 1_600_000_000.0;
+        put_ts(&mut resp[32..40], t1 + 0.05 - 0.25);
+        put_ts(&mut resp[40..48], t1 + 0.05 - 0.25);
+        let offset = parse_ntp(&resp, t1, t1 + 0.1).unwrap();
+        assert!((offset - 250.0).abs() < 0.01, "{}", offset);
+
+        assert!(parse_ntp(&resp[..40], t1, t1).is_err());
+        resp[1] = 0;
+        assert!(parse_ntp(&resp, t1, t1).is_err());
+        resp[1] = 2;
+        resp[0] = 0x23;
+        assert!(parse_ntp(&resp, t1, t1).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use clap::{ArgMatches, CommandFactory, FromArgMatches, ValueSource};
+use serde::Deserialize;
+use std::fs;
+
+use crate::{Args, Result};
+
+// 配置文件，字段与命令行参数一致
+// 优先级: 命令行参数 > 配置文件 > 默认值
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileArgs {
+    addr: Option<String>,
+    user: Option<String>,
+    pass: Option<String>,
+    vnstat: Option<bool>,
+    disable_extra: Option<bool>,
+    ip_info: Option<bool>,
+    json: Option<bool>,
+    tls: Option<bool>,
+    ipv6: Option<bool>,
+    disk_mounts: Option<Vec<String>>,
+    disk_warn_pct: Option<f64>,
+    collect_systemd: Option<bool>,
+    collect_docker: Option<bool>,
+    collect_ipmi: Option<bool>,
+    report_fields: Option<Vec<String>>,
+    hmac_secret: Option<String>,
+    signed_auth: Option<bool>,
+    labels: Option<Vec<String>>,
+    report_buffer: Option<usize>,
+    build_tag: Option<String>,
+    collect_clock: Option<bool>,
+    ntp_server: Option<String>,
+    heartbeat_ratio: Option<u64>,
+    report_ifaces: Option<bool>,
+    delta: Option<bool>,
+    transport: Option<String>,
+}
+
+fn from_cli(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+pub fn parse_args() -> Result<Args> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    if args.config.is_empty() {
+        return Ok(args);
+    }
+
+    let content = fs::read_to_string(&args.config)
+        .map_err(|err| format!("can't read config `{}` => {}", args.config, err))?;
+    let file = toml::from_str::<FileArgs>(&content)
+        .map_err(|err| format!("invalid config `{}` => {}", args.config, err))?;
+
+    macro_rules! merge {
+        ($($field:ident),*) => {
+            $(
+                if let Some(v) = file.$field {
+                    if !from_cli(&matches, stringify!($field)) {
+                        args.$field = v;
+                    }
+                }
+            )*
+        };
+    }
+    merge!(
+        addr,
+        user,
+        pass,
+        vnstat,
+        disable_extra,
+        ip_info,
+        json,
+        tls,
+        ipv6,
+        disk_mounts,
+        disk_warn_pct,
+        collect_systemd,
+        collect_docker,
+        collect_ipmi,
+        report_fields,
+        hmac_secret,
+        signed_auth,
+        labels,
+        report_buffer,
+        build_tag,
+        collect_clock,
+        ntp_server,
+        heartbeat_ratio,
+        report_ifaces,
+        delta,
+        transport
+    );
+
+    Ok(args)
+}
@@ -0,0 +1,238 @@
+#![deny(warnings)]
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use stat_common::server_status::StatRequest;
+
+// 服务端已确认的上报，增量以此为基准
+struct Base {
+    id: u32,
+    network_in: u64,
+    network_out: u64,
+    uptime: u64,
+}
+
+lazy_static! {
+    static ref BASE: Mutex<Option<Base>> = Mutex::new(None);
+}
+// --delta
+static WANTED: AtomicBool = AtomicBool::new(false);
+// --delta 且服务端声明支持 delta
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn init(wanted: bool) {
+    WANTED.store(wanted, Ordering::Relaxed);
+}
+
+// 完整上报的响应中更新
+pub fn set_supported(supported: bool) {
+    let enabled = supported && WANTED.load(Ordering::Relaxed);
+    if ENABLED.swap(enabled, Ordering::Relaxed) != enabled {
+        info!("delta report => {}", enabled);
+        if !enabled {
+            reset();
+        }
+    }
+}
+
+// 发送前调用，有已确认的基准且计数未回退时改为增量，否则为完整上报
+pub fn encode(stat: &StatRequest) -> StatRequest {
+    let mut frame = stat.clone();
+    frame.delta_base = 0;
+    if !ENABLED.load(Ordering::Relaxed) || stat.buffered {
+        return frame;
+    }
+    let base = BASE.lock().unwrap();
+    let base = match &*base {
+        Some(base) => base,
+        None => return frame,
+    };
+    // 客户端重启、网卡重置等导致计数回退，发送完整上报
+    if stat.network_in < base.network_in
+        || stat.network_out < base.network_out
+        || stat.uptime < base.uptime
+    {
+        return frame;
+    }
+    frame.network_in = stat.network_in - base.network_in;
+    frame.network_out = stat.network_out - base.network_out;
+    frame.uptime = stat.uptime - base.uptime;
+    frame.delta_base = base.id;
+    frame
+}
+
+// 上报成功后以服务端返回的编号及本次的绝对值作为下次的基准，id 为 0 时不更新
+pub fn ack(id: u32, stat: &StatRequest) {
+    if id == 0 || stat.buffered {
+        return;
+    }
+    *BASE.lock().unwrap() = Some(Base {
+        id,
+        network_in: stat.network_in,
+        network_out: stat.network_out,
+        uptime: stat.uptime,
+    });
+}
+
+// 发送失败或被拒绝(连接重建、服务端重启、基准不一致)，下次发送完整上报
+pub fn reset() {
+    *BASE.lock().unwrap() = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    lazy_static! {
+        // BASE 等为全局状态，测试依次执行
+        static ref LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    fn stat(network_in: u64, network_out: u64, uptime: u64) -> StatRequest {
+        StatRequest {
+            name: "h1".to_string(),
+            network_in,
+            network_out,
+            uptime,
+            cpu: 1.5,
+            ..Default::default()
+        }
+    }
+
+    fn enable() {
+        init(true);
+        set_supported(true);
+        reset();
+    }
+
+    fn counters(frame: &StatRequest) -> (u64, u64, u64, u32) {
+        (
+            frame.network_in,
+            frame.network_out,
+            frame.uptime,
+            frame.delta_base,
+        )
+    }
+
+    #[test]
+    fn delta_after_ack() {
+        let _lock = LOCK.lock().unwrap();
+        enable();
+        let first = stat(1000, 2000, 60);
+        assert_eq!(counters(&encode(&first)), (1000, 2000, 60, 0));
+        ack(7, &first);
+
+        let second = stat(1500, 2100, 61);
+        let frame = encode(&second);
+        assert_eq!(counters(&frame), (500, 100, 1, 7));
+        // 其它字段不变
+        assert_eq!(frame.cpu, 1.5);
+        ack(8, &second);
+        assert_eq!(counters(&encode(&stat(1500, 2100, 62))), (0, 0, 1, 8));
+    }
+
+    #[test]
+    fn counter_reset_sends_full() {
+        let _lock = LOCK.lock().unwrap();
+        enable();
+        ack(1, &stat(1000, 2000, 600));
+        for reset in [
+            stat(10, 2000, 600),
+            stat(1000, 20, 600),
+            stat(1000, 2000, 5),
+        ] {
+            let frame = encode(&reset);
+            assert_eq!(frame.delta_base, 0);
+            assert_eq!(frame, reset);
+        }
+    }
+
+    // 连接重建或发送失败后重新完整上报，直到服务端确认新的基准
+    #[test]
+    fn reconnect_mid_stream() {
+        let _lock = LOCK.lock().unwrap();
+        enable();
+        ack(3, &stat(1000, 2000, 60));
+        assert_eq!(encode(&stat(1100, 2000, 61)).delta_base, 3);
+        reset();
+        assert_eq!(
+            counters(&encode(&stat(1200, 2000, 62))),
+            (1200, 2000, 62, 0)
+        );
+        ack(1, &stat(1200, 2000, 62));
+        assert_eq!(counters(&encode(&stat(1300, 2000, 63))), (100, 0, 1, 1));
+    }
+
+    // grpc 按 protobuf 编码，uint64 为 varint，增量只省下累计计数所占的字节
+    #[test]
+    fn protobuf_frame_size() {
+        use prost::Message;
+
+        let _lock = LOCK.lock().unwrap();
+        enable();
+        // 运行约 35 天，累计入 5 TiB、出 800 GiB
+        let first = StatRequest {
+            version: "1.7.0".to_string(),
+            latest_ts: 1_700_000_000,
+            online4: true,
+            load_1: 0.5,
+            memory_total: 4 << 20,
+            memory_used: 1 << 20,
+            hdd_total: 80 << 10,
+            hdd_used: 20 << 10,
+            ..stat(5 << 40, 800 << 30, 3_000_000)
+        };
+        ack(7, &first);
+        // 1 秒后，入 100 Mbps、出 10 Mbps
+        let busy = StatRequest {
+            latest_ts: first.latest_ts + 1,
+            network_in: first.network_in + 12_500_000,
+            network_out: first.network_out + 1_250_000,
+            uptime: first.uptime + 1,
+            ..first.clone()
+        };
+        let frame = encode(&busy);
+        assert_eq!(frame.delta_base, 7);
+        // 约省 7%
+        assert_eq!((busy.encoded_len(), frame.encoded_len()), (81, 75));
+
+        // 空闲主机计数不变，增量为 0 的字段不编码
+        let idle = StatRequest {
+            latest_ts: first.latest_ts + 1,
+            uptime: first.uptime + 1,
+            ..first.clone()
+        };
+        let frame = encode(&idle);
+        // 约省 20%
+        assert_eq!((idle.encoded_len(), frame.encoded_len()), (81, 64));
+    }
+
+    #[test]
+    fn buffered_and_unsupported() {
+        let _lock = LOCK.lock().unwrap();
+        enable();
+        ack(2, &stat(1000, 2000, 60));
+        // 补发的缓存上报总是完整的，也不作为基准
+        let mut buffered = stat(900, 1900, 50);
+        buffered.buffered = true;
+        assert_eq!(encode(&buffered), buffered);
+        ack(5, &buffered);
+        assert_eq!(encode(&stat(1100, 2000, 61)).delta_base, 2);
+        // id 为 0 不更新基准
+        ack(0, &stat(1100, 2000, 61));
+        assert_eq!(encode(&stat(1100, 2000, 61)).delta_base, 2);
+
+        // 服务端不支持时关闭并清除基准
+        set_supported(false);
+        assert_eq!(encode(&stat(1200, 2000, 62)).delta_base, 0);
+        set_supported(true);
+        assert_eq!(encode(&stat(1200, 2000, 62)).delta_base, 0);
+
+        // 未指定 --delta
+        init(false);
+        set_supported(true);
+        ack(2, &stat(1000, 2000, 60));
+        assert_eq!(encode(&stat(1100, 2000, 61)).delta_base, 0);
+    }
+}
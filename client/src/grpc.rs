@@ -1,19 +1,24 @@
 // #![allow(unused)]
+use prost::Message;
 use std::net::ToSocketAddrs;
 use std::thread;
 use std::time::Duration;
-use tonic::transport::Channel;
-use tonic::{metadata::MetadataValue, Request};
+use tonic::transport::{Channel, ClientTlsConfig};
+use tonic::{metadata::MetadataValue, Code, Request, Status};
 use tower::timeout::Timeout;
 
 use stat_common::server_status::server_status_client::ServerStatusClient;
-use stat_common::server_status::StatRequest;
+use stat_common::server_status::{Heartbeat, Hello, StatRequest};
 
-use crate::sample_all;
+use crate::buffer;
+use crate::delta;
+use crate::handshake;
+use crate::signer::Signer;
 use crate::Args;
-use crate::INTERVAL_MS;
-
-// TODO TLS
+use crate::{
+    heartbeat_rejected, next_heartbeat, report_interval, sample_all, set_report_capabilities,
+    set_report_interval,
+};
 
 pub async fn report(args: &Args, stat_base: &mut StatRequest) -> anyhow::Result<()> {
     if !vec![stat_base.online4, stat_base.online6]
@@ -33,35 +38,184 @@ pub async fn report(args: &Args, stat_base: &mut StatRequest) -> anyhow::Result<
         );
     }
 
-    let token = MetadataValue::try_from(format!("{}@_@{}", args.user, args.pass))?;
+    // 签名认证模式不发送密码
+    let token = match args.signed_auth {
+        true => None,
+        false => Some(MetadataValue::try_from(format!(
+            "{}@_@{}",
+            args.user, args.pass
+        ))?),
+    };
 
-    let channel = Channel::from_shared(args.addr.to_string())?
-        .connect()
-        .await?;
+    let mut endpoint = Channel::from_shared(args.addr.to_string())?;
+    if args.tls {
+        endpoint = endpoint.tls_config(ClientTlsConfig::new())?;
+    }
+    let channel = endpoint.connect().await?;
     let timeout_channel = Timeout::new(channel, Duration::from_millis(3000));
 
     let grpc_client =
         ServerStatusClient::with_interceptor(timeout_channel, move |mut req: Request<()>| {
-            req.metadata_mut().insert("authorization", token.clone());
+            if let Some(token) = &token {
+                req.metadata_mut().insert("authorization", token.clone());
+            }
             Ok(req)
         });
 
     loop {
+        if let Some(hb) = next_heartbeat(args) {
+            let mut client = grpc_client.clone();
+            let signer = Signer::new(args);
+            tokio::spawn(async move {
+                if let Err(status) = heartbeat(&mut client, &signer, hb).await {
+                    error!("grpc heartbeat status => {:?}", status);
+                    heartbeat_rejected();
+                }
+            });
+            thread::sleep(report_interval());
+            continue;
+        }
         let stat_rt = sample_all(args, stat_base);
+        let hello = handshake::start(args);
         let mut client = grpc_client.clone();
+        let signer = Signer::new(args);
         tokio::spawn(async move {
-            let request = tonic::Request::new(stat_rt);
-
-            match client.report(request).await {
-                Ok(resp) => {
-                    info!("grpc report resp => {:?}", resp);
+            if let Some(hello) = hello {
+                if let Err(status) = shake(&mut client, &signer, hello).await {
+                    error!("grpc handshake status => {:?}", status);
+                }
+            }
+            match send(&mut client, &signer, stat_rt.clone()).await {
+                Ok(_) => {
+                    // 上报成功后补发缓存
+                    let mut pending = buffer::take().into_iter();
+                    while let Some(stat) = pending.next() {
+                        if let Err(status) = send(&mut client, &signer, stat.clone()).await {
+                            error!("grpc flush buffered report status => {:?}", status);
+                            if retryable(&status) {
+                                buffer::restore(std::iter::once(stat).chain(pending).collect());
+                            }
+                            break;
+                        }
+                    }
                 }
                 Err(status) => {
                     error!("grpc report status => {:?}", status);
+                    if retryable(&status) {
+                        buffer::push(stat_rt);
+                    }
                 }
             }
         });
 
-        thread::sleep(Duration::from_millis(INTERVAL_MS));
+        thread::sleep(report_interval());
+    }
+}
+
+// 服务端不可达或超时，可缓存后补发
+fn retryable(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::DeadlineExceeded | Code::Cancelled | Code::Unknown
+    )
+}
+
+fn insert_metadata<T>(request: &mut tonic::Request<T>, headers: Vec<(&'static str, String)>) {
+    for (key, value) in headers {
+        if let Ok(value) = MetadataValue::try_from(value) {
+            request.metadata_mut().insert(key, value);
+        }
+    }
+}
+
+async fn send<T>(
+    client: &mut ServerStatusClient<T>,
+    signer: &Signer,
+    stat: StatRequest,
+) -> Result<(), Status>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::Error: Into<tonic::codegen::StdError>,
+    T::ResponseBody: tonic::codegen::Body<Data = tonic::codegen::Bytes> + Send + 'static,
+    <T::ResponseBody as tonic::codegen::Body>::Error: Into<tonic::codegen::StdError> + Send,
+{
+    let frame = delta::encode(&stat);
+    // 服务端按收到的原始字节校验签名，tonic 编码的是同一实例，map 迭代顺序不变，与此处一致
+    let headers = signer.headers(&frame.encode_to_vec());
+    let mut request = tonic::Request::new(frame);
+    insert_metadata(&mut request, headers);
+    let resp = match client.report(request).await {
+        Ok(resp) => resp,
+        Err(status) => {
+            delta::reset();
+            handshake::reset();
+            return Err(status);
+        }
+    };
+    info!("grpc report resp => {:?}", resp);
+    if !stat.buffered {
+        let resp = resp.get_ref();
+        set_report_interval(resp.interval_ms);
+        set_report_capabilities(&resp.capabilities);
+        match resp.code {
+            0 => delta::ack(resp.delta_base, &stat),
+            _ => delta::reset(),
+        }
+    }
+    Ok(())
+}
+
+// 旧服务端没有 Handshake 方法，返回 Unimplemented
+async fn shake<T>(
+    client: &mut ServerStatusClient<T>,
+    signer: &Signer,
+    hello: Hello,
+) -> Result<(), Status>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::Error: Into<tonic::codegen::StdError>,
+    T::ResponseBody: tonic::codegen::Body<Data = tonic::codegen::Bytes> + Send + 'static,
+    <T::ResponseBody as tonic::codegen::Body>::Error: Into<tonic::codegen::StdError> + Send,
+{
+    let headers = signer.headers(&hello.encode_to_vec());
+    let mut request = tonic::Request::new(hello);
+    insert_metadata(&mut request, headers);
+    match client.handshake(request).await {
+        Ok(resp) => {
+            handshake::apply(resp.get_ref());
+            Ok(())
+        }
+        Err(status) if status.code() == Code::Unimplemented => {
+            handshake::unsupported();
+            Ok(())
+        }
+        Err(status) => {
+            handshake::reset();
+            Err(status)
+        }
+    }
+}
+
+async fn heartbeat<T>(
+    client: &mut ServerStatusClient<T>,
+    signer: &Signer,
+    hb: Heartbeat,
+) -> Result<(), Status>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::Error: Into<tonic::codegen::StdError>,
+    T::ResponseBody: tonic::codegen::Body<Data = tonic::codegen::Bytes> + Send + 'static,
+    <T::ResponseBody as tonic::codegen::Body>::Error: Into<tonic::codegen::StdError> + Send,
+{
+    let headers = signer.headers(&hb.encode_to_vec());
+    let mut request = tonic::Request::new(hb);
+    insert_metadata(&mut request, headers);
+    let resp = client.report_heartbeat(request).await?;
+    info!("grpc heartbeat resp => {:?}", resp);
+    if resp.get_ref().code != 0 {
+        return Err(Status::failed_precondition(
+            resp.get_ref().message.to_string(),
+        ));
     }
+    Ok(())
 }
@@ -0,0 +1,146 @@
+#![deny(warnings)]
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use stat_common::server_status::{Hello, HelloResponse};
+use stat_common::{CAP_DELTA, CAP_HEARTBEAT, CAP_IFACES, CAP_SIGNED, PROTO_VERSION};
+
+use crate::{report_version, set_capabilities, set_report_interval, Args};
+
+// 启动及发送失败(连接重建、服务端重启)后需要重新握手
+static PENDING: AtomicBool = AtomicBool::new(true);
+// 服务端不支持握手时不再尝试，按原有方式从上报响应中获取 capabilities
+static UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+// 服务端已在上报响应中声明过 capabilities，说明可识别 x-report-type
+static CAPS_SEEN: AtomicBool = AtomicBool::new(false);
+
+// 服务端下发的采集设置，None 时沿用客户端参数
+#[derive(Default)]
+struct Settings {
+    report_ifaces: Option<bool>,
+    disk_warn_pct: Option<f64>,
+}
+
+lazy_static! {
+    static ref SETTINGS: Mutex<Settings> = Mutex::new(Settings::default());
+    // 最近一次握手协商的功能，未握手时为 None
+    static ref NEGOTIATED: Mutex<Option<Vec<String>>> = Mutex::new(None);
+}
+
+// 需要握手时返回握手消息，同一时间只有一个握手在进行
+pub fn start(args: &Args) -> Option<Hello> {
+    if UNSUPPORTED.load(Ordering::Relaxed) || !PENDING.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    let mut capabilities = Vec::new();
+    if args.heartbeat_ratio > 1 {
+        capabilities.push(CAP_HEARTBEAT.to_string());
+    }
+    if args.signed_auth {
+        capabilities.push(CAP_SIGNED.to_string());
+    }
+    if args.delta {
+        capabilities.push(CAP_DELTA.to_string());
+    }
+    if args.report_ifaces {
+        capabilities.push(CAP_IFACES.to_string());
+    }
+    Some(Hello {
+        name: args.user.to_string(),
+        version: report_version(args),
+        proto_version: PROTO_VERSION,
+        capabilities,
+    })
+}
+
+// 更早的 http 服务端不识别 x-report-type，会把握手当作完整上报，须先确认服务端声明过 capabilities
+pub fn http_ready() -> bool {
+    CAPS_SEEN.load(Ordering::Relaxed)
+}
+
+pub fn set_caps_seen(caps: &[String]) {
+    if !caps.is_empty() {
+        CAPS_SEEN.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn apply(resp: &HelloResponse) {
+    info!(
+        "handshake ok, server proto {}, negotiated {:?}, report_ifaces {:?}, disk_warn_pct {:?}",
+        resp.proto_version, resp.capabilities, resp.report_ifaces, resp.disk_warn_pct
+    );
+    set_report_interval(resp.interval_ms);
+    *NEGOTIATED.lock().unwrap() = Some(resp.capabilities.clone());
+    set_capabilities(&resp.capabilities);
+    let mut settings = SETTINGS.lock().unwrap();
+    settings.report_ifaces = resp.report_ifaces;
+    settings.disk_warn_pct = resp.disk_warn_pct;
+}
+
+// 上报响应中的功能，握手后不超出协商结果，如服务端重启后尚未重新握手时声明的全部功能
+pub fn negotiated(caps: &[String]) -> Vec<String> {
+    match NEGOTIATED.lock().unwrap().as_ref() {
+        Some(negotiated) => caps
+            .iter()
+            .filter(|c| negotiated.contains(c))
+            .cloned()
+            .collect(),
+        None => caps.to_vec(),
+    }
+}
+
+// 握手失败，下次发送前重试
+pub fn reset() {
+    PENDING.store(true, Ordering::Relaxed);
+}
+
+pub fn unsupported() {
+    *NEGOTIATED.lock().unwrap() = None;
+    if !UNSUPPORTED.swap(true, Ordering::Relaxed) {
+        info!("server does not support handshake, use legacy capabilities");
+    }
+}
+
+pub fn report_ifaces(args: &Args) -> bool {
+    SETTINGS
+        .lock()
+        .unwrap()
+        .report_ifaces
+        .unwrap_or(args.report_ifaces)
+}
+
+pub fn disk_warn_pct(args: &Args) -> f64 {
+    SETTINGS
+        .lock()
+        .unwrap()
+        .disk_warn_pct
+        .unwrap_or(args.disk_warn_pct)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(list: &[&str]) -> Vec<String> {
+        list.iter().map(|c| c.to_string()).collect()
+    }
+
+    // 服务端重启后尚未重新握手时声明全部功能，不覆盖协商结果
+    #[test]
+    fn report_caps_within_negotiated() {
+        *NEGOTIATED.lock().unwrap() = Some(caps(&[CAP_HEARTBEAT, CAP_SIGNED]));
+        assert_eq!(
+            negotiated(&caps(&[CAP_HEARTBEAT, CAP_SIGNED, CAP_DELTA, CAP_IFACES])),
+            caps(&[CAP_HEARTBEAT, CAP_SIGNED])
+        );
+        assert!(negotiated(&caps(&[CAP_DELTA])).is_empty());
+
+        // 未握手(旧服务端)时按上报响应
+        *NEGOTIATED.lock().unwrap() = None;
+        assert_eq!(
+            negotiated(&caps(&[CAP_HEARTBEAT, CAP_DELTA])),
+            caps(&[CAP_HEARTBEAT, CAP_DELTA])
+        );
+    }
+}
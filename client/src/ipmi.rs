@@ -0,0 +1,151 @@
+#![deny(warnings)]
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use stat_common::server_status::Sensor;
+
+use crate::sampler;
+
+// ipmitool 访问 BMC 较慢(数秒)，不必频繁采集
+const SAMPLE_PERIOD: Duration = Duration::from_secs(60);
+const MAX_SENSORS: usize = 32;
+const IPMI_DEV: &str = "/dev/ipmi0";
+
+lazy_static! {
+    // 不可用时为空
+    pub static ref G_SENSORS: Arc<Mutex<Vec<Sensor>>> = Arc::new(Default::default());
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+// 只上报温度、风扇和电源，其余(电压、事件日志、机箱入侵等)忽略
+fn is_psu(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.starts_with("ps") || name.contains("psu") || name.contains("power supply")
+}
+
+// ipmitool sensor 每行: 名称 | 值 | 单位 | 状态 | 阈值...，示例见测试
+fn parse_line(line: &str) -> Option<Sensor> {
+    let cols = line.split('|').map(str::trim).collect::<Vec<_>>();
+    let (name, value, unit, status) = match cols.as_slice() {
+        [name, value, unit, status, ..] => (*name, *value, *unit, *status),
+        _ => return None,
+    };
+    // 未安装或未读到的传感器为 na
+    if name.is_empty() || value == "na" {
+        return None;
+    }
+    let (value, unit) = match unit {
+        "degrees C" => (value.parse::<f64>().ok()?, "C"),
+        "RPM" => (value.parse::<f64>().ok()?, "RPM"),
+        "discrete" if is_psu(name) => {
+            let v = value.trim_start_matches("0x");
+            (u64::from_str_radix(v, 16).ok()? as f64, "")
+        }
+        _ => return None,
+    };
+    Some(Sensor {
+        name: name.to_string(),
+        value,
+        unit: unit.to_string(),
+        status: status.to_string(),
+    })
+}
+
+pub fn get_sensors() -> io::Result<Vec<Sensor>> {
+    let output = Command::new("ipmitool").arg("sensor").output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // 无 /dev/ipmi0 权限或未加载 ipmi_si/ipmi_devintf 模块
+        return Err(invalid(stderr.lines().next().unwrap_or("ipmitool failed")));
+    }
+    Ok(parse_sensors(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_sensors(output: &str) -> Vec<Sensor> {
+    output
+        .lines()
+        .filter_map(parse_line)
+        .take(MAX_SENSORS)
+        .collect()
+}
+
+// 需要 root 读写 /dev/ipmi0，启动时检查
+pub fn check_permission() -> io::Result<()> {
+    File::open(IPMI_DEV).map(|_| ())
+}
+
+pub fn start_ipmi_collect_t() {
+    sampler::spawn_periodic(
+        SAMPLE_PERIOD,
+        get_sensors,
+        |err| {
+            if err.kind() == ErrorKind::NotFound {
+                warn!("ipmitool not found, install ipmitool to collect BMC sensors");
+            } else {
+                warn!("collect ipmi sensors error => {}", err);
+            }
+        },
+        |res| {
+            if let Ok(mut o) = G_SENSORS.lock() {
+                *o = res.unwrap_or_default();
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "\
+Inlet Temp       | 24.000     | degrees C  | ok    | na        | na        | na        | 42.000    | 47.000    | na
+Fan1 RPM         | 4080.000   | RPM        | ok    | na        | 360.000   | 600.000   | na        | na        | na
+PS1 Status       | 0x1        | discrete   | 0x0100| na        | na        | na        | na        | na        | na
+Fan2 RPM         | na         | RPM        | na    | na        | 360.000   | 600.000   | na        | na        | na
+Voltage 1        | 230.000    | Volts      | ok    | na        | na        | na        | na        | na        | na
+Intrusion        | 0x0        | discrete   | 0x0000| na        | na        | na        | na        | na        | na
+";
+
+    #[test]
+    fn parse_sensor_lines() {
+        let sensors = parse_sensors(OUTPUT);
+        let got = sensors
+            .iter()
+            .map(|o| (o.name.as_str(), o.value, o.unit.as_str(), o.status.as_str()))
+            .collect::<Vec<_>>();
+        // 未读到的传感器、电压及电源以外的离散传感器忽略
+        assert_eq!(
+            got,
+            [
+                ("Inlet Temp", 24.0, "C", "ok"),
+                ("Fan1 RPM", 4080.0, "RPM", "ok"),
+                ("PS1 Status", 1.0, "", "0x0100"),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("Inlet Temp | 24.000").is_none());
+        assert!(parse_line(" | 24.000 | degrees C | ok").is_none());
+        assert!(parse_line("Inlet Temp | warm | degrees C | ok").is_none());
+        assert!(parse_line("PSU2 Status | 0xzz | discrete | 0x0100").is_none());
+    }
+
+    #[test]
+    fn sensor_limit() {
+        let output = (0..MAX_SENSORS + 5)
+            .map(|i| format!("Temp{} | 30.000 | degrees C | ok | na", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(parse_sensors(&output).len(), MAX_SENSORS);
+    }
+}
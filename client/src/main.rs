@@ -3,27 +3,104 @@
 extern crate log;
 extern crate pretty_env_logger;
 use clap::Parser;
-use hyper::header;
 use once_cell::sync::Lazy;
 use prost::Message;
+use reqwest::header;
 use std::net::ToSocketAddrs;
 use std::process;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
 use sysinfo::{System, SystemExt};
 use tokio::time;
 
-use stat_common::server_status::{IpInfo, StatRequest, SysInfo};
+use stat_common::server_status::{
+    Heartbeat, Hello, HelloResponse, IpInfo, StatRequest, SysInfo, Units,
+};
+use stat_common::sign::USER_HEADER;
+use stat_common::units;
+use stat_common::{CAP_DELTA, CAP_HEARTBEAT, REPORT_TYPE_HEADER, REPORT_TYPE_HELLO};
 type GenericError = Box<dyn std::error::Error + Send + Sync>;
 type Result<T> = std::result::Result<T, GenericError>;
+mod buffer;
+mod clock;
+mod config;
+mod delta;
+mod docker;
 mod grpc;
+mod handshake;
 mod ip_api;
+mod ipmi;
+mod sampler;
+mod signer;
 mod status;
 mod sys_info;
+mod temp;
+mod validity;
+mod ws;
 
 const INTERVAL_MS: u64 = 1000;
+// 服务端下发的上报间隔
+static REPORT_INTERVAL_MS: AtomicU64 = AtomicU64::new(INTERVAL_MS);
+
+pub fn report_interval() -> Duration {
+    Duration::from_millis(REPORT_INTERVAL_MS.load(Ordering::Relaxed))
+}
+
+// 服务端声明支持心跳前及心跳失败后只发送完整上报
+static HEARTBEAT_READY: AtomicBool = AtomicBool::new(false);
+// 上报序号，每轮上报(完整上报或心跳)加 1，从 1 开始
+static REPORT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// 每轮上报开始时调用，分配本轮序号，应发送心跳时返回心跳消息，每 heartbeat_ratio 次中第 1 次为完整上报
+pub fn next_heartbeat(args: &Args) -> Option<Heartbeat> {
+    let seq = REPORT_SEQ.fetch_add(1, Ordering::Relaxed) + 1;
+    if args.heartbeat_ratio <= 1
+        || !HEARTBEAT_READY.load(Ordering::Relaxed)
+        || (seq - 1) % args.heartbeat_ratio == 0
+    {
+        return None;
+    }
+    Some(Heartbeat {
+        name: args.user.to_string(),
+        seq,
+    })
+}
+
+// 完整上报的响应中更新服务端功能
+pub fn set_report_capabilities(caps: &[String]) {
+    set_capabilities(&handshake::negotiated(caps));
+}
+
+// 握手及完整上报的响应中更新服务端功能
+pub fn set_capabilities(caps: &[String]) {
+    handshake::set_caps_seen(caps);
+    let ready = caps.iter().any(|c| c == CAP_HEARTBEAT);
+    if HEARTBEAT_READY.swap(ready, Ordering::Relaxed) != ready {
+        info!("server heartbeat support => {}", ready);
+    }
+    delta::set_supported(caps.iter().any(|c| c == CAP_DELTA));
+}
+
+// 心跳被拒绝(如服务端重启后尚无该主机数据)，下次改为完整上报
+pub fn heartbeat_rejected() {
+    HEARTBEAT_READY.store(false, Ordering::Relaxed);
+}
+
+// 0 恢复默认，不允许比默认更频繁
+pub fn set_report_interval(ms: u64) {
+    let ms = if ms == 0 {
+        INTERVAL_MS
+    } else {
+        ms.max(INTERVAL_MS)
+    };
+    let old = REPORT_INTERVAL_MS.swap(ms, Ordering::Relaxed);
+    if old != ms {
+        info!("report interval changed by server => {}ms", ms);
+    }
+}
 
 #[derive(Default)]
 pub struct ClientConfig {
@@ -36,6 +113,13 @@ pub static G_CONFIG: Lazy<Mutex<ClientConfig>> = Lazy::new(|| Mutex::new(ClientC
 #[derive(Parser, Debug, Clone)]
 #[clap(author, version = env!("APP_VERSION"), about, long_about = None)]
 pub struct Args {
+    #[clap(
+        short = 'c',
+        long = "config",
+        default_value = "",
+        help = "config file, cli args override file values"
+    )]
+    config: String,
     #[clap(short, long, default_value = "http://127.0.0.1:8080/report")]
     addr: String,
     #[clap(short, long, default_value = "h1", help = "username")]
@@ -53,8 +137,234 @@ pub struct Args {
     ip_info: bool,
     #[clap(long = "json", help = "use json protocol, default:false")]
     json: bool,
+    #[clap(long = "tls", help = "enable grpc tls, default:false")]
+    tls: bool,
     #[clap(short = '6', long = "ipv6", help = "ipv6 only, default:false")]
     ipv6: bool,
+    #[clap(
+        long = "disk-mounts",
+        value_delimiter = ',',
+        help = "only count these mount points in hdd, eg: /,/data"
+    )]
+    disk_mounts: Vec<String>,
+    #[clap(
+        long = "disk-warn-pct",
+        default_value = "0",
+        help = "report mounts with usage over this percent as hot_mounts, 0 to disable"
+    )]
+    disk_warn_pct: f64,
+    #[clap(
+        long = "collect-systemd",
+        help = "report failed systemd units, default:false"
+    )]
+    collect_systemd: bool,
+    #[clap(
+        long = "collect-docker",
+        help = "report docker/podman container counts, default:false"
+    )]
+    collect_docker: bool,
+    #[clap(
+        long = "collect-ipmi",
+        help = "report BMC temperature, fan and PSU sensors via `ipmitool sensor`, requires root, default:false"
+    )]
+    collect_ipmi: bool,
+    #[clap(
+        long = "report-fields",
+        value_delimiter = ',',
+        help = "only report these fields, eg: cpu,memory (all by default)"
+    )]
+    report_fields: Vec<String>,
+    #[clap(
+        long = "hmac-secret",
+        default_value = "",
+        help = "sign reports with HMAC-SHA256, same as host hmac_secret on server"
+    )]
+    hmac_secret: String,
+    #[clap(
+        long = "signed-auth",
+        help = "authenticate by HMAC signature with timestamp and nonce instead of sending password, requires --hmac-secret"
+    )]
+    signed_auth: bool,
+    #[clap(
+        long = "labels",
+        value_delimiter = ',',
+        help = "host labels, eg: env=prod,dc=fra1"
+    )]
+    labels: Vec<String>,
+    #[clap(
+        long = "report-buffer",
+        default_value = "30",
+        help = "buffer up to N failed reports and resend on recovery, 0 to disable"
+    )]
+    report_buffer: usize,
+    #[clap(
+        long = "build-tag",
+        default_value = "",
+        help = "appended to reported version as build metadata, eg: canary => 1.1.1+canary"
+    )]
+    build_tag: String,
+    #[clap(
+        long = "collect-clock",
+        help = "report clock offset from chronyc/timedatectl or --ntp-server, default:false"
+    )]
+    collect_clock: bool,
+    #[clap(
+        long = "ntp-server",
+        default_value = "",
+        help = "query this NTP server for clock offset instead of local chrony/timesyncd, eg: pool.ntp.org"
+    )]
+    ntp_server: String,
+    #[clap(
+        long = "heartbeat-ratio",
+        default_value = "1",
+        help = "send a full report every N reports and lightweight heartbeats in between, if the server supports it"
+    )]
+    heartbeat_ratio: u64,
+    #[clap(
+        long = "report-ifaces",
+        help = "report per-interface speed and traffic in addition to totals, default:false"
+    )]
+    report_ifaces: bool,
+    #[clap(
+        long = "delta",
+        help = "send network_in/out and uptime as deltas against the last acknowledged report, if the server supports it"
+    )]
+    delta: bool,
+    #[clap(
+        long = "transport",
+        default_value = "",
+        help = "http|ws|grpc, default: by addr scheme, ws also accepts the http(s) report addr"
+    )]
+    transport: String,
+}
+
+// --transport 指定时按其修改 addr 协议，ws 使用与 http 相同的 /report 地址
+fn apply_transport(args: &mut Args) -> Result<()> {
+    let addr = &args.addr;
+    args.addr = match args.transport.as_str() {
+        "" => return Ok(()),
+        "http" if addr.starts_with("ws") => addr.replacen("ws", "http", 1),
+        "ws" if addr.starts_with("http") => addr.replacen("http", "ws", 1),
+        "http" | "ws" if !addr.starts_with("grpc") => return Ok(()),
+        "grpc" if addr.starts_with("grpc") => return Ok(()),
+        "http" | "ws" | "grpc" => {
+            return Err(format!(
+                "--transport {} doesn't match addr `{}`",
+                args.transport, args.addr
+            )
+            .into())
+        }
+        t => return Err(format!("invalid transport `{}`, expect http|ws|grpc", t).into()),
+    };
+    Ok(())
+}
+
+// 上报的版本号: 1.1.1+canary.abc1234，tag 及 git hash 作为 semver build metadata
+pub fn report_version(args: &Args) -> String {
+    let meta = [args.build_tag.as_str(), env!("GIT_HASH")]
+        .iter()
+        .filter(|s| !s.is_empty())
+        .copied()
+        .collect::<Vec<_>>();
+    if meta.is_empty() {
+        return env!("CARGO_PKG_VERSION").to_string();
+    }
+    format!("{}+{}", env!("CARGO_PKG_VERSION"), meta.join("."))
+}
+
+// --report-fields 可选值，name/online4/online6/labels 始终上报
+const REPORT_FIELDS: &[&str] = &[
+    "uptime",
+    "load",
+    "cpu",
+    "memory",
+    "swap",
+    "oom",
+    "hdd",
+    "traffic",
+    "speed",
+    "ifaces",
+    "systemd",
+    "containers",
+    "temp",
+    "clock",
+    "ipmi",
+    "ip_info",
+    "sys_info",
+];
+
+// 清空不在白名单中的字段
+fn apply_report_fields(fields: &[String], stat: &mut StatRequest) {
+    if fields.is_empty() {
+        return;
+    }
+    let omit = |field: &str| !fields.iter().any(|f| f.eq(field));
+    if omit("uptime") {
+        stat.uptime = 0;
+    }
+    if omit("load") {
+        stat.load_1 = 0.0;
+        stat.load_5 = 0.0;
+        stat.load_15 = 0.0;
+    }
+    if omit("cpu") {
+        stat.cpu = 0.0;
+    }
+    if omit("memory") {
+        stat.memory_total = 0;
+        stat.memory_used = 0;
+    }
+    if omit("swap") {
+        stat.swap_total = 0;
+        stat.swap_used = 0;
+    }
+    if omit("oom") {
+        stat.oom_kills = None;
+    }
+    if omit("hdd") {
+        stat.hdd_total = 0;
+        stat.hdd_used = 0;
+        stat.hot_mounts.clear();
+    }
+    if omit("traffic") {
+        stat.network_in = 0;
+        stat.network_out = 0;
+        stat.last_network_in = 0;
+        stat.last_network_out = 0;
+        stat.month_network_in = 0;
+        stat.month_network_out = 0;
+    }
+    if omit("speed") {
+        stat.network_rx = 0;
+        stat.network_tx = 0;
+    }
+    if omit("ifaces") {
+        stat.ifaces.clear();
+    }
+    if omit("systemd") {
+        stat.failed_units.clear();
+    }
+    if omit("containers") {
+        stat.containers_running = None;
+        stat.containers_total = None;
+    }
+    if omit("temp") {
+        stat.cpu_temp = None;
+        stat.core_temps.clear();
+        stat.package_temp = None;
+    }
+    if omit("clock") {
+        stat.clock_offset_ms = None;
+    }
+    if omit("ipmi") {
+        stat.sensors.clear();
+    }
+    if omit("ip_info") {
+        stat.ip_info = None;
+    }
+    if omit("sys_info") {
+        stat.sys_info = None;
+    }
 }
 
 fn sample_all(args: &Args, stat_base: &StatRequest) -> StatRequest {
@@ -70,6 +380,40 @@ fn sample_all(args: &Args, stat_base: &StatRequest) -> StatRequest {
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_secs();
+    stat_rt.proto_version = stat_common::PROTO_VERSION;
+    // 本轮 next_heartbeat 分配的序号
+    stat_rt.seq = REPORT_SEQ.load(Ordering::Relaxed);
+    stat_rt.units = Some(Units {
+        memory: units::MEMORY_UNIT.to_string(),
+        disk: units::DISK_UNIT.to_string(),
+    });
+
+    let temps = temp::get_temps();
+    stat_rt.cpu_temp = temps.cpu_temp;
+    stat_rt.core_temps = temps.core_temps;
+    stat_rt.package_temp = temps.package_temp;
+    stat_rt.oom_kills = status::get_oom_kills();
+
+    if args.collect_docker {
+        if let Ok(o) = docker::G_CONTAINERS.lock() {
+            if let Some((running, total)) = *o {
+                stat_rt.containers_running = Some(running);
+                stat_rt.containers_total = Some(total);
+            }
+        }
+    }
+
+    if args.collect_clock {
+        if let Ok(o) = clock::G_CLOCK_OFFSET.lock() {
+            stat_rt.clock_offset_ms = *o;
+        }
+    }
+
+    if args.collect_ipmi {
+        if let Ok(o) = ipmi::G_SENSORS.lock() {
+            stat_rt.sensors = o.clone();
+        }
+    }
 
     if !args.disable_extra {
         if let Ok(o) = G_CONFIG.lock() {
@@ -81,14 +425,191 @@ fn sample_all(args: &Args, stat_base: &StatRequest) -> StatRequest {
             }
         }
     }
+    status::mark_unavailable(&mut stat_rt);
+    validity::check(&mut stat_rt);
+    apply_report_fields(&args.report_fields, &mut stat_rt);
 
     stat_rt
 }
 
+// POST /report 的响应，http 及 ws 传输相同
+pub struct Reply {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+#[derive(Clone)]
+enum Transport {
+    Http(reqwest::Client),
+    Ws(Arc<ws::WsConn>),
+}
+
+#[derive(Clone)]
+struct HttpReporter {
+    transport: Transport,
+    url: String,
+    user: String,
+    pass: String,
+    json: bool,
+    signer: signer::Signer,
+}
+
+impl HttpReporter {
+    async fn post(
+        &self,
+        body: Vec<u8>,
+        content_type: &str,
+        report_type: Option<&str>,
+    ) -> Result<Reply> {
+        let mut headers = vec![(header::CONTENT_TYPE.as_str(), content_type.to_string())];
+        if let Some(report_type) = report_type {
+            headers.push((REPORT_TYPE_HEADER, report_type.to_string()));
+        }
+        // 签名认证模式不发送密码
+        if self.signer.signed_auth {
+            headers.push((USER_HEADER, self.user.to_string()));
+        }
+        headers.extend(self.signer.headers(&body));
+
+        let client = match &self.transport {
+            Transport::Http(client) => client,
+            // basic auth 在建立连接时发送
+            Transport::Ws(conn) => return conn.request(headers, body).await,
+        };
+        let mut req = client.post(&self.url).timeout(Duration::from_secs(3));
+        if !self.signer.signed_auth {
+            req = req.basic_auth(&self.user, Some(&self.pass));
+        }
+        for (key, value) in headers {
+            req = req.header(key, value);
+        }
+        let resp = req.body(body).send().await?;
+        Ok(Reply {
+            status: resp.status().as_u16(),
+            body: resp.bytes().await?.to_vec(),
+        })
+    }
+
+    // 网络错误或 5xx 时返回 Err，可缓存后补发
+    async fn send(&self, stat: &StatRequest) -> Result<()> {
+        let frame = delta::encode(stat);
+        let (body, content_type) = if self.json {
+            let data = serde_json::to_string(&frame)?;
+            trace!("json_str => {:?}", serde_json::to_string(&data)?);
+            (data.into_bytes(), "application/json")
+        } else {
+            (frame.encode_to_vec(), "application/octet-stream")
+        };
+        // byte 581, json str 1281
+
+        let resp = match self.post(body, content_type, None).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                delta::reset();
+                handshake::reset();
+                return Err(err);
+            }
+        };
+        info!(
+            "report resp => {} {}",
+            resp.status,
+            String::from_utf8_lossy(&resp.body)
+        );
+        if resp.status >= 500 {
+            delta::reset();
+            handshake::reset();
+            return Err(format!("server error => {}", resp.status).into());
+        }
+        if !stat.buffered {
+            match resp.json::<serde_json::Value>() {
+                Ok(v) => {
+                    set_report_interval(v["interval_ms"].as_u64().unwrap_or_default());
+                    set_report_capabilities(
+                        &serde_json::from_value::<Vec<String>>(v["capabilities"].clone())
+                            .unwrap_or_default(),
+                    );
+                    match v["code"].as_i64() {
+                        Some(0) => delta::ack(v["delta_base"].as_u64().unwrap_or(0) as u32, stat),
+                        _ => delta::reset(),
+                    }
+                }
+                Err(_) => delta::reset(),
+            }
+        }
+        Ok(())
+    }
+
+    // 握手失败时下次发送前重试，不支持 hello 的服务端返回 400
+    async fn hello(&self, hello: &Hello) -> Result<()> {
+        let (body, content_type) = if self.json {
+            (serde_json::to_vec(hello)?, "application/json")
+        } else {
+            (hello.encode_to_vec(), "application/octet-stream")
+        };
+        let resp = match self.post(body, content_type, Some(REPORT_TYPE_HELLO)).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                handshake::reset();
+                return Err(err);
+            }
+        };
+        if resp.status == 400 {
+            handshake::unsupported();
+            return Ok(());
+        }
+        match resp.json::<HelloResponse>() {
+            Ok(v) => handshake::apply(&v),
+            Err(err) => {
+                handshake::reset();
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // 心跳不缓存，失败后下次改为完整上报
+    async fn heartbeat(&self, hb: &Heartbeat) -> Result<()> {
+        let (body, content_type) = if self.json {
+            (serde_json::to_vec(hb)?, "application/json")
+        } else {
+            (hb.encode_to_vec(), "application/octet-stream")
+        };
+        let resp = self.post(body, content_type, Some(CAP_HEARTBEAT)).await?;
+        info!(
+            "heartbeat resp => {} {}",
+            resp.status,
+            String::from_utf8_lossy(&resp.body)
+        );
+        let v = resp.json::<serde_json::Value>()?;
+        if v["code"].as_i64() != Some(0) {
+            return Err(format!("heartbeat rejected => {}", v).into());
+        }
+        Ok(())
+    }
+
+    // 上报成功后补发缓存
+    async fn flush(&self) {
+        let mut pending = buffer::take().into_iter();
+        while let Some(stat) = pending.next() {
+            if let Err(err) = self.send(&stat).await {
+                error!("flush buffered report error => {:?}", err);
+                buffer::restore(std::iter::once(stat).chain(pending).collect());
+                return;
+            }
+        }
+    }
+}
+
 fn http_report(args: &Args, stat_base: &mut StatRequest) -> Result<()> {
     let mut domain = args.addr.split('/').collect::<Vec<&str>>()[2].to_owned();
     if !domain.contains(':') {
-        if args.addr.contains("https") {
+        if args.addr.starts_with("https") || args.addr.starts_with("wss") {
             domain = format!("{}:443", domain);
         } else {
             domain = format!("{}:80", domain);
@@ -103,59 +624,69 @@ fn http_report(args: &Args, stat_base: &mut StatRequest) -> Result<()> {
         stat_base.online6 = ipv6;
     }
 
-    let http_client = reqwest::Client::builder()
-        .pool_max_idle_per_host(1)
-        .connect_timeout(Duration::from_secs(5))
-        .user_agent(format!(
-            "{}/{}",
-            env!("CARGO_BIN_NAME"),
-            env!("CARGO_PKG_VERSION")
-        ))
-        .build()?;
+    let user_agent = format!("{}/{}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
+    let transport = if args.addr.starts_with("ws") {
+        Transport::Ws(Arc::new(ws::WsConn::new(
+            &args.addr,
+            &args.user,
+            &args.pass,
+            signer::Signer::new(args),
+            &user_agent,
+        )))
+    } else {
+        Transport::Http(
+            reqwest::Client::builder()
+                .pool_max_idle_per_host(1)
+                .connect_timeout(Duration::from_secs(5))
+                .user_agent(user_agent)
+                .build()?,
+        )
+    };
+    let reporter = HttpReporter {
+        transport,
+        url: args.addr.to_string(),
+        user: args.user.to_string(),
+        pass: args.pass.to_string(),
+        json: args.json,
+        signer: signer::Signer::new(args),
+    };
     loop {
+        if let Some(hb) = next_heartbeat(args) {
+            let reporter = reporter.clone();
+            tokio::spawn(async move {
+                if let Err(err) = reporter.heartbeat(&hb).await {
+                    error!("heartbeat error => {:?}", err);
+                    heartbeat_rejected();
+                }
+            });
+            thread::sleep(report_interval());
+            continue;
+        }
         let stat_rt = sample_all(args, stat_base);
-
-        let body_data: Option<Vec<u8>>;
-        let mut content_type = "application/octet-stream";
-        if args.json {
-            let data = serde_json::to_string(&stat_rt)?;
-            trace!("json_str => {:?}", serde_json::to_string(&data)?);
-            body_data = Some(data.into());
-            content_type = "application/json";
+        let hello = if handshake::http_ready() {
+            handshake::start(args)
         } else {
-            let buf = stat_rt.encode_to_vec();
-            body_data = Some(buf);
-            // content_type = "application/octet-stream";
-        }
-        // byte 581, json str 1281
-        // dbg!(&body_data.as_ref().unwrap().len());
-
-        let client = http_client.clone();
-        let url = args.addr.to_string();
-        let auth_user = args.user.to_string();
-        let auth_pass = args.pass.to_string();
+            None
+        };
 
         // http
+        let reporter = reporter.clone();
         tokio::spawn(async move {
-            match client
-                .post(&url)
-                .basic_auth(auth_user, Some(auth_pass))
-                .timeout(Duration::from_secs(3))
-                .header(header::CONTENT_TYPE, content_type)
-                .body(body_data.unwrap())
-                .send()
-                .await
-            {
-                Ok(resp) => {
-                    info!("report resp => {:?}", resp);
+            if let Some(hello) = hello {
+                if let Err(err) = reporter.hello(&hello).await {
+                    error!("hello error => {:?}", err);
                 }
+            }
+            match reporter.send(&stat_rt).await {
+                Ok(_) => reporter.flush().await,
                 Err(err) => {
                     error!("report error => {:?}", err);
+                    buffer::push(stat_rt);
                 }
             }
         });
 
-        thread::sleep(Duration::from_millis(INTERVAL_MS));
+        thread::sleep(report_interval());
     }
 }
 
@@ -183,8 +714,24 @@ async fn refresh_ip_info(args: &Args) {
 #[tokio::main]
 async fn main() -> Result<()> {
     pretty_env_logger::init();
-    let args = Args::parse();
-    dbg!(&args);
+    let mut args = config::parse_args()?;
+    if !args
+        .build_tag
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        return Err(format!(
+            "invalid build tag `{}`, allowed: [0-9A-Za-z-.]",
+            args.build_tag
+        )
+        .into());
+    }
+    apply_transport(&mut args)?;
+    delta::init(args.delta);
+    if args.signed_auth && args.hmac_secret.is_empty() {
+        return Err("--signed-auth requires --hmac-secret".into());
+    }
+    eprintln!("version: {}", report_version(&args));
 
     if args.ip_info {
         let info = ip_api::get_ip_info(args.ipv6).await?;
@@ -221,6 +768,48 @@ async fn main() -> Result<()> {
         sys_info::start_net_speed_collect_t();
     }
 
+    if args.collect_systemd {
+        status::start_failed_units_collect_t();
+    }
+    if args.collect_docker {
+        docker::start_docker_collect_t();
+    }
+    if args.collect_clock {
+        clock::start_clock_collect_t(args.ntp_server.clone());
+    }
+    if args.collect_ipmi {
+        ipmi::start_ipmi_collect_t();
+    }
+
+    if let Some(field) = args
+        .report_fields
+        .iter()
+        .find(|f| !REPORT_FIELDS.contains(&f.as_str()))
+    {
+        eprintln!(
+            "❗ invalid report field `{}`, expect one of {:?}",
+            field, REPORT_FIELDS
+        );
+        process::exit(1);
+    }
+
+    // 因权限或未安装而不可用的指标在每次采样时加入
+    status::check_permissions(&args);
+    status::start_permission_check_t(args.clone());
+
+    // 未上报的字段服务端显示为 N/A
+    let unavailable_metrics = if args.report_fields.is_empty() {
+        Vec::new()
+    } else {
+        REPORT_FIELDS
+            .iter()
+            .filter(|field| !args.report_fields.iter().any(|f| f.eq(*field)))
+            .map(|field| field.to_string())
+            .collect()
+    };
+
+    buffer::init(args.report_buffer);
+
     let (ipv4, ipv6) = status::get_network();
     eprintln!("get_network (ipv4, ipv6) => ({}, {})", ipv4, ipv6);
 
@@ -236,10 +825,17 @@ async fn main() -> Result<()> {
         online4: ipv4,
         online6: ipv6,
         vnstat: args.vnstat,
+        labels: args
+            .labels
+            .iter()
+            .filter_map(|s| s.split_once('='))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect(),
+        unavailable_metrics,
         ..Default::default()
     };
 
-    if args.addr.starts_with("http") {
+    if args.addr.starts_with("http") || args.addr.starts_with("ws") {
         let result = http_report(&args, &mut stat_base);
         dbg!(&result);
     } else if args.addr.starts_with("grpc") {
@@ -0,0 +1,303 @@
+#![deny(warnings)]
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use arc_swap::ArcSwap;
+use stat_common::server_status::StatRequest;
+
+// 采集线程 panic 后重新开始采集的间隔
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+// 超过该数量的采样周期未更新视为过期
+const STALE_PERIODS: u64 = 5;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// 采集线程最近一次更新数据的时间，线程退出或卡住时 sample() 据此不再上报旧数据
+pub struct Freshness {
+    // 同 unavailable_metrics 中的名称
+    metric: &'static str,
+    period_ms: u64,
+    updated_ms: AtomicU64,
+    stale: AtomicBool,
+}
+
+impl Freshness {
+    pub const fn new(metric: &'static str, period_ms: u64) -> Self {
+        Self {
+            metric,
+            period_ms,
+            updated_ms: AtomicU64::new(0),
+            stale: AtomicBool::new(false),
+        }
+    }
+
+    // 采集线程写入数据后调用
+    pub fn touch(&self) {
+        self.updated_ms.store(now_ms(), Ordering::Relaxed);
+    }
+
+    fn is_stale_at(&self, now: u64) -> bool {
+        let updated = self.updated_ms.load(Ordering::Relaxed);
+        updated == 0 || now.saturating_sub(updated) > STALE_PERIODS * self.period_ms
+    }
+
+    // 数据过期时加入 unavailable_metrics(前端显示 N/A)并返回 false，不上报冻结的旧值
+    pub fn check(&self, stat: &mut StatRequest) -> bool {
+        let stale = self.is_stale_at(now_ms());
+        if self.stale.swap(stale, Ordering::Relaxed) != stale {
+            if stale {
+                warn!(
+                    "{} sampler not updated for {} periods, report as unavailable",
+                    self.metric, STALE_PERIODS
+                );
+            } else {
+                info!("{} sampler recovered", self.metric);
+            }
+        }
+        if stale && !stat.unavailable_metrics.iter().any(|m| m == self.metric) {
+            stat.unavailable_metrics.push(self.metric.to_string());
+        }
+        !stale
+    }
+}
+
+// f64 按位存入 AtomicU64，读写不加锁
+pub struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    // 0.0 的位模式为 0
+    pub const fn zero() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn load(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn store(&self, v: f64) {
+        self.0.store(v.to_bits(), Ordering::Relaxed);
+    }
+}
+
+// 采集线程每次整体替换的数据快照，读取方只克隆 Arc
+// 不加锁，上报线程读取时不会被采集线程阻塞，也不存在锁中毒
+#[derive(Default)]
+pub struct Snapshot<T>(ArcSwap<T>);
+
+impl<T> Snapshot<T> {
+    pub fn load(&self) -> Arc<T> {
+        self.0.load_full()
+    }
+
+    pub fn store(&self, v: T) {
+        self.0.store(Arc::new(v));
+    }
+}
+
+// 启动采集线程，run 为采集循环(状态在 run 内初始化)，panic 时记录日志并在 RESTART_DELAY 后重新执行
+pub fn spawn<F>(name: &'static str, run: F)
+where
+    F: Fn() + Send + 'static,
+{
+    thread::spawn(move || loop {
+        if let Err(err) = panic::catch_unwind(AssertUnwindSafe(&run)) {
+            let msg = err
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| err.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            error!(
+                "{} sampler panicked => {}, restart in {:?}",
+                name, msg, RESTART_DELAY
+            );
+        }
+        thread::sleep(RESTART_DELAY);
+    });
+}
+
+// 同一类错误只记录一次，恢复后重新记录
+#[derive(Default)]
+pub struct ErrorOnce(Option<io::ErrorKind>);
+
+impl ErrorOnce {
+    // 返回需要记录的错误
+    pub fn check<'a, T>(&mut self, res: &'a io::Result<T>) -> Option<&'a io::Error> {
+        match res {
+            Ok(_) => {
+                self.0 = None;
+                None
+            }
+            Err(err) if self.0 != Some(err.kind()) => {
+                self.0 = Some(err.kind());
+                Some(err)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+// 每隔 period 执行 collect 并交给 store，错误经 ErrorOnce 去重后由 on_err 记录
+pub fn spawn_periodic<T, C, E, S>(period: Duration, collect: C, on_err: E, store: S)
+where
+    C: Fn() -> io::Result<T> + Send + 'static,
+    E: Fn(&io::Error) + Send + 'static,
+    S: Fn(io::Result<T>) + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut errors = ErrorOnce::default();
+        loop {
+            let res = collect();
+            if let Some(err) = errors.check(&res) {
+                on_err(err);
+            }
+            store(res);
+            thread::sleep(period);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD_MS: u64 = 1000;
+
+    #[test]
+    fn error_logged_once() {
+        let err = |kind| Err::<(), _>(io::Error::new(kind, "x"));
+        let mut errors = ErrorOnce::default();
+        assert!(errors.check(&err(io::ErrorKind::NotFound)).is_some());
+        assert!(errors.check(&err(io::ErrorKind::NotFound)).is_none());
+        // 错误类型变化时记录
+        assert!(errors
+            .check(&err(io::ErrorKind::PermissionDenied))
+            .is_some());
+        assert!(errors.check(&Ok(())).is_none());
+        // 恢复后再次出错重新记录
+        assert!(errors
+            .check(&err(io::ErrorKind::PermissionDenied))
+            .is_some());
+    }
+
+    #[test]
+    fn never_updated_is_stale() {
+        let fresh = Freshness::new("cpu", PERIOD_MS);
+        let mut stat = StatRequest::default();
+        assert!(!fresh.check(&mut stat));
+        assert_eq!(stat.unavailable_metrics, ["cpu"]);
+    }
+
+    #[test]
+    fn stale_timestamp() {
+        let fresh = Freshness::new("speed", PERIOD_MS);
+        fresh.touch();
+        let mut stat = StatRequest::default();
+        assert!(fresh.check(&mut stat));
+        assert!(stat.unavailable_metrics.is_empty());
+
+        // 未超过 STALE_PERIODS 个周期仍视为有效
+        let now = now_ms();
+        fresh
+            .updated_ms
+            .store(now - STALE_PERIODS * PERIOD_MS + 100, Ordering::Relaxed);
+        assert!(fresh.check(&mut stat));
+        assert!(stat.unavailable_metrics.is_empty());
+
+        // 采集线程卡住，时间戳停留在 STALE_PERIODS 个周期之前
+        fresh
+            .updated_ms
+            .store(now - (STALE_PERIODS + 1) * PERIOD_MS, Ordering::Relaxed);
+        assert!(!fresh.check(&mut stat));
+        assert!(fresh.stale.load(Ordering::Relaxed));
+        // 同一上报中多次检查不重复加入
+        assert!(!fresh.check(&mut stat));
+        assert_eq!(stat.unavailable_metrics, ["speed"]);
+
+        // 采集恢复后不再标记，已有的其他指标保持不变
+        fresh.touch();
+        let mut stat = StatRequest::default();
+        stat.unavailable_metrics.push("swap".to_string());
+        assert!(fresh.check(&mut stat));
+        assert!(!fresh.stale.load(Ordering::Relaxed));
+        assert_eq!(stat.unavailable_metrics, ["swap"]);
+    }
+
+    #[test]
+    fn clock_goes_back() {
+        let fresh = Freshness::new("cpu", PERIOD_MS);
+        // 系统时间回拨后 updated_ms 大于当前时间，不视为过期
+        fresh
+            .updated_ms
+            .store(now_ms() + 60 * PERIOD_MS, Ordering::Relaxed);
+        assert!(fresh.check(&mut StatRequest::default()));
+    }
+
+    // 一个采集线程写入，多个读取线程并发读取，快照内各字段始终一致，且不会读到旧于之前读到的值
+    #[test]
+    fn concurrent_snapshot_and_atomic() {
+        const WRITES: u64 = 50_000;
+        const READERS: usize = 8;
+        let snapshot = Arc::new(Snapshot::<(u64, u64, u64)>::default());
+        let cpu = Arc::new(AtomicF64::zero());
+        let done = Arc::new(AtomicBool::new(false));
+        cpu.store(0.5);
+
+        let readers = (0..READERS)
+            .map(|_| {
+                let (snapshot, cpu, done) = (snapshot.clone(), cpu.clone(), done.clone());
+                thread::spawn(move || {
+                    let (mut last, mut last_cpu, mut reads) = (0, 0.0, 0u64);
+                    while !done.load(Ordering::Relaxed) {
+                        let v = snapshot.load();
+                        assert_eq!((v.1, v.2), (v.0 * 2, v.0 * 3));
+                        assert!(v.0 >= last);
+                        last = v.0;
+                        let c = cpu.load();
+                        assert_eq!(c.fract(), 0.5);
+                        assert!(c >= last_cpu);
+                        last_cpu = c;
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 1..=WRITES {
+            snapshot.store((i, i * 2, i * 3));
+            cpu.store(i as f64 + 0.5);
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(*snapshot.load(), (WRITES, WRITES * 2, WRITES * 3));
+        assert_eq!(cpu.load(), WRITES as f64 + 0.5);
+    }
+
+    // 读取方 panic 不影响之后的读写
+    #[test]
+    fn snapshot_survives_reader_panic() {
+        let snapshot = Arc::new(Snapshot::<Vec<u64>>::default());
+        snapshot.store(vec![1, 2, 3]);
+        let reader = snapshot.clone();
+        let res = thread::spawn(move || {
+            let v = reader.load();
+            assert!(v.is_empty(), "reader panic");
+        })
+        .join();
+        assert!(res.is_err());
+        assert_eq!(*snapshot.load(), [1, 2, 3]);
+        snapshot.store(vec![4]);
+        assert_eq!(*snapshot.load(), [4]);
+    }
+}
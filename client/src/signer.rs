@@ -0,0 +1,61 @@
+#![deny(warnings)]
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use stat_common::sign::{self, NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+use crate::Args;
+
+static NONCE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// 上报签名，signed_auth 时不发送密码，签名附加时间戳和 nonce 供服务端防重放
+#[derive(Debug, Clone)]
+pub struct Signer {
+    secret: String,
+    pub signed_auth: bool,
+}
+
+impl Signer {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            secret: args.hmac_secret.to_string(),
+            signed_auth: args.signed_auth,
+        }
+    }
+
+    // 需附加到 http header 或 grpc metadata 的签名字段，未设置密钥时为空
+    pub fn headers(&self, body: &[u8]) -> Vec<(&'static str, String)> {
+        if self.secret.is_empty() {
+            return Vec::new();
+        }
+        if !self.signed_auth {
+            return vec![(SIGNATURE_HEADER, sign::sign(&self.secret, body))];
+        }
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let nonce = nonce();
+        let signature = sign::sign(&self.secret, &sign::signed_data(body, ts, &nonce));
+        vec![
+            (TIMESTAMP_HEADER, ts.to_string()),
+            (NONCE_HEADER, nonce),
+            (SIGNATURE_HEADER, signature),
+        ]
+    }
+}
+
+// 纳秒时间 + pid + 序号，重启后也不会与之前的重复
+fn nonce() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!(
+        "{:x}-{:x}-{:x}",
+        nanos,
+        process::id(),
+        NONCE_SEQ.fetch_add(1, Ordering::Relaxed)
+    )
+}
@@ -7,24 +7,26 @@ use std::fs;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::{self, ErrorKind};
 use std::net::TcpStream;
 use std::net::{Shutdown, ToSocketAddrs};
 use std::process::Command;
 use std::str;
-use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::sampler::{self, AtomicF64, Freshness, Snapshot};
 use crate::Args;
-use stat_common::server_status::StatRequest;
+use stat_common::server_status::{IfaceStat, MountInfo, StatRequest};
 
 const SAMPLE_PERIOD: u64 = 1000; //ms
 const TIMEOUT_MS: u64 = 1000;
 static IPV4_ADDR: &str = "ipv4.google.com:80";
 static IPV6_ADDR: &str = "ipv6.google.com:80";
 
+// 读取失败时返回 0，由 validity::check 处理
 pub fn get_uptime() -> u64 {
     fs::read_to_string("/proc/uptime")
         .map(|contents| {
@@ -33,7 +35,7 @@ pub fn get_uptime() -> u64 {
             }
             0
         })
-        .unwrap()
+        .unwrap_or(0)
 }
 
 pub fn get_loadavg() -> (f64, f64, f64) {
@@ -44,102 +46,175 @@ pub fn get_loadavg() -> (f64, f64, f64) {
             if vec.len() >= 3 {
                 let a = vec[0..3]
                     .iter()
-                    .map(|v| v.parse::<f64>().unwrap())
+                    .map(|v| v.parse::<f64>().unwrap_or(f64::NAN))
                     .collect::<Vec<f64>>();
 
                 return (a[0], a[1], a[2]);
             }
-            (0.0, 0.0, 0.0)
+            (f64::NAN, f64::NAN, f64::NAN)
         })
-        .unwrap()
+        .unwrap_or((f64::NAN, f64::NAN, f64::NAN))
 }
 
 static MEMORY_REGEX: &str = r#"^(?P<key>\S*):\s*(?P<value>\d*)\s*kB"#;
 lazy_static! {
     static ref MEMORY_REGEX_RE: Regex = Regex::new(MEMORY_REGEX).unwrap();
 }
+// 读取失败或缺少字段时对应值为 0，由 validity::check 处理
 pub fn get_memory() -> (u64, u64, u64, u64) {
-    let file = File::open("/proc/meminfo").unwrap();
-    let buf_reader = BufReader::new(file);
     let mut res_dict = HashMap::new();
-    for line in buf_reader.lines() {
-        let l = line.unwrap();
-        if let Some(caps) = MEMORY_REGEX_RE.captures(&l) {
-            res_dict.insert(
-                caps["key"].to_string(),
-                caps["value"].parse::<u64>().unwrap(),
-            );
-        };
+    if let Ok(file) = File::open("/proc/meminfo") {
+        for l in BufReader::new(file).lines().map_while(|line| line.ok()) {
+            if let Some(caps) = MEMORY_REGEX_RE.captures(&l) {
+                if let Ok(v) = caps["value"].parse::<u64>() {
+                    res_dict.insert(caps["key"].to_string(), v);
+                }
+            };
+        }
     }
+    let get = |key: &str| res_dict.get(key).copied().unwrap_or(0);
 
-    let mem_total = res_dict["MemTotal"];
-    let swap_total = res_dict["SwapTotal"];
-    let swap_free = res_dict["SwapFree"];
+    let mem_total = get("MemTotal");
+    let swap_total = get("SwapTotal");
+    let swap_free = get("SwapFree");
 
     let mem_used = mem_total
-        - res_dict["MemFree"]
-        - res_dict["Buffers"]
-        - res_dict["Cached"]
-        - res_dict["SReclaimable"];
+        .saturating_sub(get("MemFree"))
+        .saturating_sub(get("Buffers"))
+        .saturating_sub(get("Cached"))
+        .saturating_sub(get("SReclaimable"));
 
     (mem_total, mem_used, swap_total, swap_free)
 }
 
+lazy_static! {
+    static ref OOM_KILL_LAST: Mutex<Option<u64>> = Mutex::new(None);
+}
+// 距上次调用的 oom_kill 增量，首次调用为 0，内核 4.13 以下无此项
+pub fn get_oom_kills() -> Option<u64> {
+    let contents = fs::read_to_string("/proc/vmstat").ok()?;
+    let cur = contents
+        .lines()
+        .find_map(|l| l.strip_prefix("oom_kill "))?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    let mut last = OOM_KILL_LAST.lock().unwrap();
+    let delta = last.map_or(0, |last| cur.saturating_sub(last));
+    *last = Some(cur);
+    Some(delta)
+}
+
 static IFACE_IGNORE_VEC: &[&str] = &["lo", "docker", "vnet", "veth", "vmbr", "kube", "br-"];
-pub fn get_vnstat_traffic() -> (u64, u64, u64, u64) {
+// vnstat 未安装、守护进程未运行或尚无数据时返回错误
+fn get_vnstat_traffic() -> anyhow::Result<(u64, u64, u64, u64)> {
     let local_now = Local::now();
     let (mut network_in, mut network_out, mut m_network_in, mut m_network_out) = (0, 0, 0, 0);
-    let a = Command::new("/usr/bin/vnstat")
-        .args(&["--json", "m"])
-        .output()
-        .expect("failed to execute vnstat")
-        .stdout;
-    let b = str::from_utf8(&a).unwrap();
-    let j: HashMap<&str, serde_json::Value> = serde_json::from_str(b).unwrap();
-    for iface in j["interfaces"].as_array().unwrap() {
-        let name = iface["name"].as_str().unwrap();
+    let output = Command::new("/usr/bin/vnstat")
+        .args(["--json", "m"])
+        .output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "vnstat exit with {} => {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let j: HashMap<&str, serde_json::Value> =
+        serde_json::from_str(str::from_utf8(&output.stdout)?)?;
+    let interfaces = j
+        .get("interfaces")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| anyhow::anyhow!("vnstat json has no interfaces"))?;
+    for iface in interfaces {
+        let name = iface["name"].as_str().unwrap_or_default();
         if IFACE_IGNORE_VEC.iter().any(|sk| name.contains(*sk)) {
             continue;
         }
-        let total_o = iface["traffic"]["total"].as_object().unwrap();
-        let month_v = iface["traffic"]["month"].as_array().unwrap();
-        network_in += total_o["rx"].as_u64().unwrap();
-        network_out += total_o["tx"].as_u64().unwrap();
-
-        for data in month_v {
-            let year = data["date"]["year"].as_i64().unwrap() as i32;
-            let month = data["date"]["month"].as_i64().unwrap() as u32;
+        let total_o = &iface["traffic"]["total"];
+        network_in += total_o["rx"].as_u64().unwrap_or_default();
+        network_out += total_o["tx"].as_u64().unwrap_or_default();
+
+        for data in iface["traffic"]["month"].as_array().into_iter().flatten() {
+            let year = data["date"]["year"].as_i64().unwrap_or_default() as i32;
+            let month = data["date"]["month"].as_i64().unwrap_or_default() as u32;
             if local_now.year() != year || local_now.month() != month {
                 continue;
             }
 
-            m_network_in += data["rx"].as_u64().unwrap();
-            m_network_out += data["tx"].as_u64().unwrap();
+            m_network_in += data["rx"].as_u64().unwrap_or_default();
+            m_network_out += data["tx"].as_u64().unwrap_or_default();
         }
     }
 
-    (network_in, network_out, m_network_in, m_network_out)
+    // 新安装时数据库为空，总量为 0
+    if network_in == 0 && network_out == 0 {
+        anyhow::bail!("vnstat has no data yet");
+    }
+    Ok((network_in, network_out, m_network_in, m_network_out))
+}
+
+// last_network_in/out 为本月开始时的总流量(总流量 - 月流量)
+// vnstat 数据库轮转或月中新增网卡时月流量可能短暂大于总流量，此时按 0 上报，避免相减溢出
+pub fn month_start_traffic(total: u64, month: u64) -> u64 {
+    total.checked_sub(month).unwrap_or_else(|| {
+        debug!(
+            "vnstat month traffic {} exceeds total {}, report last traffic as 0",
+            month, total
+        );
+        0
+    })
+}
+
+lazy_static! {
+    // 上次 vnstat 错误，用于只记录一次日志
+    static ref VNSTAT_ERR: Mutex<Option<String>> = Mutex::new(None);
+}
+
+// vnstat 不可用时返回 None，本次改用系统网卡计数，数据恢复后自动切回
+pub fn vnstat_traffic() -> Option<(u64, u64, u64, u64)> {
+    let res = get_vnstat_traffic();
+    let mut last_err = VNSTAT_ERR.lock().unwrap();
+    match res {
+        Ok(o) => {
+            if last_err.take().is_some() {
+                info!("vnstat data available again, use vnstat traffic");
+            }
+            Some(o)
+        }
+        Err(err) => {
+            let err = err.to_string();
+            if last_err.as_ref() != Some(&err) {
+                warn!(
+                    "vnstat unavailable, fall back to system traffic counters => {}",
+                    err
+                );
+                *last_err = Some(err);
+            }
+            None
+        }
+    }
 }
 
 static TRAFFIC_REGEX: &str = r#"([^\s]+):[\s]{0,}(\d+)\s+(\d+)\s+(\d+)\s+(\d+)\s+(\d+)\s+(\d+)\s+(\d+)\s+(\d+)\s+(\d+)\s+(\d+)\s+(\d+)"#;
 lazy_static! {
     static ref TRAFFIC_REGEX_RE: Regex = Regex::new(TRAFFIC_REGEX).unwrap();
 }
-pub fn get_sys_traffic() -> (u64, u64) {
+// 读取失败时返回 None，流量为 0 由 validity::check 处理
+pub fn get_sys_traffic() -> Option<(u64, u64)> {
     let (mut network_in, mut network_out) = (0, 0);
-    let file = File::open("/proc/net/dev").unwrap();
+    let file = File::open("/proc/net/dev").ok()?;
     let buf_reader = BufReader::new(file);
-    for line in buf_reader.lines() {
-        let l = line.unwrap();
-
+    for l in buf_reader.lines().map_while(|line| line.ok()) {
         TRAFFIC_REGEX_RE.captures(&l).and_then(|caps| {
             // println!("caps[0]=>{:?}", caps.get(0).unwrap().as_str());
-            let name = caps.get(1).unwrap().as_str();
+            let name = caps.get(1)?.as_str();
             if IFACE_IGNORE_VEC.iter().any(|sk| name.contains(*sk)) {
                 return None;
             }
-            let net_in = caps.get(2).unwrap().as_str().parse::<u64>().unwrap();
-            let net_out = caps.get(10).unwrap().as_str().parse::<u64>().unwrap();
+            // 计数器超出 u64 等异常行跳过
+            let net_in = caps.get(2)?.as_str().parse::<u64>().ok()?;
+            let net_out = caps.get(10)?.as_str().parse::<u64>().ok()?;
 
             network_in += net_in;
             network_out += net_out;
@@ -147,52 +222,257 @@ pub fn get_sys_traffic() -> (u64, u64) {
         });
     }
 
-    (network_in, network_out)
+    Some((network_in, network_out))
 }
 
 static DF_CMD:&str = "df -Tlm --total -t ext4 -t ext3 -t ext2 -t reiserfs -t jfs -t ntfs -t fat32 -t btrfs -t fuseblk -t zfs -t simfs -t xfs";
-pub fn get_hdd() -> (u64, u64) {
-    let (mut hdd_total, mut hdd_used) = (0, 0);
-    let a = &Command::new("/bin/sh")
-        .args(&["-c", DF_CMD])
-        .output()
-        .expect("failed to execute df")
-        .stdout;
-    let _ = str::from_utf8(a).map(|s| {
-        s.trim().split('\n').last().map(|s| {
-            let vec: Vec<&str> = s.split_whitespace().collect();
-            // dbg!(&vec);
-            hdd_total = vec[2].parse::<u64>().unwrap();
-            hdd_used = vec[3].parse::<u64>().unwrap();
-            Some(())
-        });
+// 使用率不低于 warn_pct 时返回，warn_pct 为 0 不启用
+pub fn hot_mount(
+    mount_point: &str,
+    fs_type: &str,
+    total: u64,
+    used: u64,
+    warn_pct: f64,
+) -> Option<MountInfo> {
+    if warn_pct <= 0.0 || total == 0 || (used as f64) * 100.0 < warn_pct * total as f64 {
+        return None;
+    }
+    Some(MountInfo {
+        mount_point: mount_point.to_string(),
+        fs_type: fs_type.to_string(),
+        total,
+        used,
+        ..Default::default()
+    })
+}
+
+// df 执行失败或输出无法解析时返回 None，硬盘为 0 由 validity::check 处理并记录日志
+pub fn get_hdd(disk_mounts: &[String], warn_pct: f64) -> Option<(u64, u64, Vec<MountInfo>)> {
+    let output = if disk_mounts.is_empty() {
+        Command::new("/bin/sh").args(&["-c", DF_CMD]).output()
+    } else {
+        // 指定挂载点时忽略文件系统类型过滤
+        Command::new("df")
+            .args(["-Tm", "--total"])
+            .args(disk_mounts)
+            .output()
+    }
+    .ok()?;
+    parse_df(str::from_utf8(&output.stdout).ok()?, warn_pct)
+}
+
+// df -Tm --total 的输出，最后一行为合计
+fn parse_df(s: &str, warn_pct: f64) -> Option<(u64, u64, Vec<MountInfo>)> {
+    let vec: Vec<&str> = s.trim().split('\n').last()?.split_whitespace().collect();
+    let hdd_total = vec.get(2)?.parse::<u64>().ok()?;
+    let hdd_used = vec.get(3)?.parse::<u64>().ok()?;
+
+    let mut hot_mounts = Vec::new();
+    // Filesystem Type 1M-blocks Used Available Use% Mounted on，跳过表头及 total 行
+    for line in s.trim().split('\n').skip(1) {
+        let vec: Vec<&str> = line.split_whitespace().collect();
+        if vec.len() < 7 || vec[0] == "total" {
+            continue;
+        }
+        if let (Ok(total), Ok(used)) = (vec[2].parse::<u64>(), vec[3].parse::<u64>()) {
+            hot_mounts.extend(hot_mount(
+                &vec[6..].join(" "),
+                vec[1],
+                total,
+                used,
+                warn_pct,
+            ));
+        }
+    }
+
+    Some((hdd_total, hdd_used, hot_mounts))
+}
+
+// 权限或安装状态可能在运行中变化(如安装 vnstat、加入 docker 组)，定期重新检查
+const PERMISSION_CHECK_PERIOD: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    // 因权限或未安装而不可用的指标
+    static ref G_UNAVAILABLE: Snapshot<Vec<String>> = Snapshot::default();
+}
+
+// 指标不可用的原因，其余错误(如 vnstat 尚无数据)由各采集处理
+fn unavailable_reason(err: &io::Error) -> Option<&'static str> {
+    match err.kind() {
+        ErrorKind::NotFound => Some("not installed"),
+        ErrorKind::PermissionDenied => Some("permission denied"),
+        _ => None,
+    }
+}
+
+// 命令已安装但无权限时退出码非 0，按 stderr 区分
+fn stderr_error(cmd: &str, stderr: &str) -> io::Error {
+    let lower = stderr.to_ascii_lowercase();
+    let kind = if ["permission denied", "access denied", "not permitted"]
+        .iter()
+        .any(|s| lower.contains(s))
+    {
+        ErrorKind::PermissionDenied
+    } else {
+        ErrorKind::Other
+    };
+    io::Error::new(kind, format!("{} => {}", cmd, stderr.trim()))
+}
+
+fn probe_cmd(cmd: &str, args: &[&str]) -> io::Result<()> {
+    let output = Command::new(cmd).args(args).output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(stderr_error(cmd, &String::from_utf8_lossy(&output.stderr)))
+}
+
+// 非 root 运行时部分采集会静默返回 0，返回不可用的 (指标, 原因)
+fn probe_permissions(args: &Args) -> Vec<(&'static str, &'static str)> {
+    let mut probes: Vec<(&'static str, io::Result<()>)> = Vec::new();
+    // 部分环境可以打开但读取失败，实际读取一次
+    for (metric, path) in [
+        ("cpu", "/proc/stat"),
+        ("memory", "/proc/meminfo"),
+        ("traffic", "/proc/net/dev"),
+    ] {
+        probes.push((metric, fs::read(path).map(|_| ())));
+    }
+    if args.vnstat {
+        probes.push(("vnstat", probe_cmd("/usr/bin/vnstat", &["--json", "m"])));
+    }
+    if args.collect_systemd {
+        probes.push((
+            "systemd",
+            probe_cmd("systemctl", &["--failed", "--no-legend", "--plain"]),
+        ));
+    }
+    if args.collect_docker {
+        probes.push(("containers", crate::docker::get_containers().map(|_| ())));
+    }
+    if args.collect_ipmi {
+        probes.push(("ipmi", crate::ipmi::check_permission()));
+    }
+
+    probes
+        .into_iter()
+        .filter_map(|(metric, res)| {
+            res.err()
+                .as_ref()
+                .and_then(unavailable_reason)
+                .map(|reason| (metric, reason))
+        })
+        .collect()
+}
+
+// 启动时检查一次
+pub fn check_permissions(args: &Args) {
+    let probes = probe_permissions(args);
+    for (metric, reason) in &probes {
+        eprintln!("❗ {} unavailable => {}", metric, reason);
+    }
+    G_UNAVAILABLE.store(probes.into_iter().map(|(m, _)| m.to_string()).collect());
+}
+
+pub fn start_permission_check_t(args: Args) {
+    sampler::spawn("permission", move || loop {
+        thread::sleep(PERMISSION_CHECK_PERIOD);
+        let probes = probe_permissions(&args);
+        let prev = G_UNAVAILABLE.load();
+        for (metric, reason) in &probes {
+            if !prev.iter().any(|m| m == metric) {
+                warn!("{} unavailable => {}", metric, reason);
+            }
+        }
+        for metric in prev.iter() {
+            if !probes.iter().any(|(m, _)| m == metric) {
+                info!("{} available again", metric);
+            }
+        }
+        G_UNAVAILABLE.store(probes.into_iter().map(|(m, _)| m.to_string()).collect());
     });
+}
 
-    (hdd_total, hdd_used)
+// 加入当前因权限或未安装而不可用的指标
+pub fn mark_unavailable(stat: &mut StatRequest) {
+    for metric in G_UNAVAILABLE.load().iter() {
+        if !stat.unavailable_metrics.contains(metric) {
+            stat.unavailable_metrics.push(metric.to_string());
+        }
+    }
+}
+
+// 单元状态变化不频繁，避免每次采样都启动 systemctl
+const FAILED_UNITS_PERIOD: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    pub static ref G_FAILED_UNITS: Snapshot<Vec<String>> = Snapshot::default();
+}
+
+// `systemctl --failed --no-legend --plain` 每行第一列为单元名
+fn parse_failed_units(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|l| l.split_whitespace().next())
+        .map(|unit| unit.to_string())
+        .collect()
+}
+
+fn get_failed_units() -> io::Result<Vec<String>> {
+    let output = Command::new("systemctl")
+        .args(["--failed", "--no-legend", "--plain"])
+        .output()?;
+    if !output.status.success() {
+        return Err(stderr_error(
+            "systemctl",
+            &String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    Ok(parse_failed_units(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// 非 systemd 系统或无权限时上报为空
+pub fn start_failed_units_collect_t() {
+    sampler::spawn_periodic(
+        FAILED_UNITS_PERIOD,
+        get_failed_units,
+        |err| warn!("collect failed systemd units error => {}", err),
+        |res| G_FAILED_UNITS.store(res.unwrap_or_default()),
+    );
 }
 
 #[derive(Debug, Default)]
 pub struct NetSpeed {
-    pub diff: f64,
     pub clock: f64,
     pub netrx: u64,
     pub nettx: u64,
     pub avgrx: u64,
     pub avgtx: u64,
+    // 各网卡实时网速及累计流量，--report-ifaces 时上报
+    pub ifaces: Vec<IfaceStat>,
 }
 
 lazy_static! {
-    pub static ref G_NET_SPEED: Arc<Mutex<NetSpeed>> = Arc::new(Default::default());
+    pub static ref G_NET_SPEED: Snapshot<NetSpeed> = Snapshot::default();
+}
+static NET_SPEED_FRESHNESS: Freshness = Freshness::new("speed", SAMPLE_PERIOD);
+
+// 两次采样间的速率，计数器回绕/重置或时间未前进(含系统时间回拨)时为 0
+fn rate(cur: u64, prev: u64, diff: f64) -> u64 {
+    if diff <= 0.0 {
+        return 0;
+    }
+    (cur.saturating_sub(prev) as f64 / diff) as u64
 }
 
 #[allow(unused)]
 pub fn start_net_speed_collect_t() {
-    thread::spawn(|| loop {
+    sampler::spawn("speed", || loop {
         let _ = File::open("/proc/net/dev").map(|file| {
             let buf_reader = BufReader::new(file);
             let (mut avgrx, mut avgtx) = (0, 0);
-            for line in buf_reader.lines() {
-                let l = line.unwrap();
+            let mut totals = Vec::new();
+            for l in buf_reader.lines().map_while(|line| line.ok()) {
                 let v: Vec<&str> = l.split(':').collect();
                 if v.len() < 2 {
                     continue;
@@ -202,8 +482,16 @@ pub fn start_net_speed_collect_t() {
                     continue;
                 }
                 let v1: Vec<&str> = v[1].split_whitespace().collect();
-                avgrx += v1[0].parse::<u64>().unwrap();
-                avgtx += v1[8].parse::<u64>().unwrap();
+                let (rx, tx) = match (
+                    v1.first().and_then(|s| s.parse::<u64>().ok()),
+                    v1.get(8).and_then(|s| s.parse::<u64>().ok()),
+                ) {
+                    (Some(rx), Some(tx)) => (rx, tx),
+                    _ => continue,
+                };
+                avgrx += rx;
+                avgtx += tx;
+                totals.push((v[0].trim().to_string(), rx, tx));
             }
 
             let now = SystemTime::now()
@@ -211,61 +499,87 @@ pub fn start_net_speed_collect_t() {
                 .unwrap()
                 .as_secs() as f64;
 
-            if let Ok(mut t) = G_NET_SPEED.lock() {
-                t.diff = now - t.clock;
-                t.clock = now;
-                t.netrx = ((avgrx - t.avgrx) as f64 / t.diff) as u64;
-                t.nettx = ((avgtx - t.avgtx) as f64 / t.diff) as u64;
-                t.avgrx = avgrx;
-                t.avgtx = avgtx;
+            // 只有采集线程写入，基于上次快照计算后整体替换
+            let prev = G_NET_SPEED.load();
+            let diff = now - prev.clock;
+            // 网卡计数器重置或新出现时本次网速为 0
+            let ifaces = totals
+                .into_iter()
+                .map(|(name, rx, tx)| {
+                    let (speed_rx, speed_tx) = prev
+                        .ifaces
+                        .iter()
+                        .find(|o| o.name == name)
+                        .map_or((0, 0), |o| {
+                            (rate(rx, o.total_in, diff), rate(tx, o.total_out, diff))
+                        });
+                    IfaceStat {
+                        name,
+                        rx: speed_rx,
+                        tx: speed_tx,
+                        total_in: rx,
+                        total_out: tx,
+                        ..Default::default()
+                    }
+                })
+                .collect();
+            let t = NetSpeed {
+                clock: now,
+                netrx: rate(avgrx, prev.avgrx, diff),
+                nettx: rate(avgtx, prev.avgtx, diff),
+                avgrx,
+                avgtx,
+                ifaces,
+            };
 
-                // dbg!(&t);
-            }
+            // dbg!(&t);
+            G_NET_SPEED.store(t);
+            NET_SPEED_FRESHNESS.touch();
         });
         thread::sleep(Duration::from_millis(SAMPLE_PERIOD));
     });
 }
 
-lazy_static! {
-    pub static ref G_CPU_PERCENT: Arc<Mutex<f64>> = Arc::new(Default::default());
-}
+pub static G_CPU_PERCENT: AtomicF64 = AtomicF64::zero();
+static CPU_FRESHNESS: Freshness = Freshness::new("cpu", SAMPLE_PERIOD);
+
 #[allow(unused)]
 pub fn start_cpu_percent_collect_t() {
-    let mut pre_cpu: Vec<u64> = vec![0, 0, 0, 0];
-    thread::spawn(move || loop {
-        let _ = File::open("/proc/stat").map(|file| {
-            let mut buf_reader = BufReader::new(file);
-            let mut buf = String::new();
-            let _ = buf_reader.read_line(&mut buf).map(|_| {
-                let cur_cpu = buf
-                    .split_whitespace()
-                    .enumerate()
-                    .filter(|&(idx, _)| idx > 0 && idx < 5)
-                    .map(|(_, e)| e.parse::<u64>().unwrap())
-                    .collect::<Vec<_>>();
-
-                let pre: u64 = pre_cpu.iter().sum();
-                let cur: u64 = cur_cpu.iter().sum();
-                let mut st = cur - pre;
-                if st == 0 {
-                    st = 1;
-                }
-
-                let res = 100.0 - (100.0 * (cur_cpu[3] - pre_cpu[3]) as f64 / st as f64);
-
-                // dbg!(&pre_cpu);
-                // dbg!(&cur_cpu);
-
-                pre_cpu = cur_cpu;
-
-                if let Ok(mut cpu_percent) = G_CPU_PERCENT.lock() {
-                    *cpu_percent = res.round();
-                    // dbg!(cpu_percent);
-                }
+    sampler::spawn("cpu", || {
+        let mut pre_cpu: Vec<u64> = vec![0, 0, 0, 0];
+        loop {
+            let _ = File::open("/proc/stat").map(|file| {
+                let mut buf_reader = BufReader::new(file);
+                let mut buf = String::new();
+                let _ = buf_reader.read_line(&mut buf).map(|_| {
+                    let cur_cpu = buf
+                        .split_whitespace()
+                        .enumerate()
+                        .filter(|&(idx, _)| idx > 0 && idx < 5)
+                        .map(|(_, e)| e.parse::<u64>().unwrap())
+                        .collect::<Vec<_>>();
+
+                    let pre: u64 = pre_cpu.iter().sum();
+                    let cur: u64 = cur_cpu.iter().sum();
+                    let mut st = cur - pre;
+                    if st == 0 {
+                        st = 1;
+                    }
+
+                    let res = 100.0 - (100.0 * (cur_cpu[3] - pre_cpu[3]) as f64 / st as f64);
+
+                    // dbg!(&pre_cpu);
+                    // dbg!(&cur_cpu);
+
+                    pre_cpu = cur_cpu;
+
+                    G_CPU_PERCENT.store(res);
+                    CPU_FRESHNESS.touch();
+                });
             });
-        });
 
-        thread::sleep(Duration::from_millis(SAMPLE_PERIOD));
+            thread::sleep(Duration::from_millis(SAMPLE_PERIOD));
+        }
     });
 }
 
@@ -292,8 +606,7 @@ pub fn get_network() -> (bool, bool) {
 }
 
 pub fn sample(args: &Args, stat: &mut StatRequest) {
-    stat.version = env!("CARGO_PKG_VERSION").to_string();
-    stat.vnstat = args.vnstat;
+    stat.version = crate::report_version(args);
 
     stat.uptime = get_uptime();
 
@@ -306,30 +619,161 @@ pub fn sample(args: &Args, stat: &mut StatRequest) {
     stat.memory_total = mem_total;
     stat.memory_used = mem_used;
     stat.swap_total = swap_total;
-    stat.swap_used = swap_total - swap_free;
-
-    let (hdd_total, hdd_used) = get_hdd();
-    stat.hdd_total = hdd_total;
-    stat.hdd_used = hdd_used;
+    stat.swap_used = swap_total.saturating_sub(swap_free);
+
+    if let Some((hdd_total, hdd_used, hot_mounts)) =
+        get_hdd(&args.disk_mounts, crate::handshake::disk_warn_pct(args))
+    {
+        stat.hdd_total = hdd_total;
+        stat.hdd_used = hdd_used;
+        stat.hot_mounts = hot_mounts;
+    }
 
-    if args.vnstat {
-        let (network_in, network_out, m_network_in, m_network_out) = get_vnstat_traffic();
+    let vnstat = if args.vnstat { vnstat_traffic() } else { None };
+    stat.vnstat = vnstat.is_some();
+    if let Some((network_in, network_out, m_network_in, m_network_out)) = vnstat {
         stat.network_in = network_in;
         stat.network_out = network_out;
-        stat.last_network_in = network_in - m_network_in;
-        stat.last_network_out = network_out - m_network_out;
-    } else {
-        let (network_in, network_out) = get_sys_traffic();
+        stat.month_network_in = m_network_in;
+        stat.month_network_out = m_network_out;
+        stat.last_network_in = month_start_traffic(network_in, m_network_in);
+        stat.last_network_out = month_start_traffic(network_out, m_network_out);
+    } else if let Some((network_in, network_out)) = get_sys_traffic() {
         stat.network_in = network_in;
         stat.network_out = network_out;
     }
 
-    if let Ok(o) = G_CPU_PERCENT.lock() {
-        stat.cpu = *o;
+    if args.collect_systemd {
+        stat.failed_units = G_FAILED_UNITS.load().to_vec();
     }
 
-    if let Ok(o) = G_NET_SPEED.lock() {
+    if CPU_FRESHNESS.check(stat) {
+        stat.cpu = G_CPU_PERCENT.load();
+    }
+
+    if NET_SPEED_FRESHNESS.check(stat) {
+        let o = G_NET_SPEED.load();
         stat.network_rx = o.netrx;
         stat.network_tx = o.nettx;
+        if crate::handshake::report_ifaces(args) {
+            stat.ifaces = o.ifaces.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_not_installed() {
+        let err = probe_cmd("/nonexistent/vnstat", &["--json", "m"]).unwrap_err();
+        assert_eq!(unavailable_reason(&err), Some("not installed"));
+    }
+
+    #[test]
+    fn probe_stderr() {
+        let denied = [
+            "Error: Failed to open database \"/var/lib/vnstat/vnstat.db\" in read-only mode.\nThe vnStat daemon should have created the database when started.\nCheck that it is configured and running. See also \"man vnstatd\".\nError: Exec step failed (8: attempt to write a readonly database): Permission denied",
+            "Failed to connect to bus: Access denied",
+            "Operation not permitted",
+        ];
+        for stderr in denied {
+            let err = stderr_error("cmd", stderr);
+            assert_eq!(
+                unavailable_reason(&err),
+                Some("permission denied"),
+                "{}",
+                stderr
+            );
+        }
+        // 尚无数据等其它错误不算不可用
+        let err = stderr_error("vnstat", "No interfaces found in database.");
+        assert_eq!(unavailable_reason(&err), None);
+    }
+
+    #[test]
+    fn failed_units_output() {
+        let out = "nginx.service loaded failed failed A high performance web server\nbackup.timer  loaded failed failed Daily backup\n";
+        assert_eq!(parse_failed_units(out), ["nginx.service", "backup.timer"]);
+        assert!(parse_failed_units("").is_empty());
+    }
+
+    #[test]
+    fn net_rate() {
+        assert_eq!(rate(3000, 1000, 2.0), 1000);
+        // 网卡消失或计数器重置，总量变小
+        assert_eq!(rate(1000, 3000, 2.0), 0);
+        // 首次采样(prev.clock 为 0)的间隔很大，速率接近 0
+        assert_eq!(rate(3000, 0, 1.6e9), 0);
+        // 系统时间回拨或同一秒内采样
+        assert_eq!(rate(3000, 1000, 0.0), 0);
+        assert_eq!(rate(3000, 1000, -1.0), 0);
+    }
+
+    #[test]
+    fn df_output() {
+        let out = "Filesystem     Type 1M-blocks  Used Available Use% Mounted on
+/dev/vda1      ext4     40000 38000      2000  95% /
+/dev/vdb1      xfs     100000 10000     90000  10% /data dir
+total          -       140000 48000     92000  35% -
+";
+        let (total, used, hot) = parse_df(out, 90.0).unwrap();
+        assert_eq!((total, used), (140000, 48000));
+        assert_eq!(hot.len(), 1);
+        assert_eq!(
+            (hot[0].mount_point.as_str(), hot[0].fs_type.as_str()),
+            ("/", "ext4")
+        );
+
+        // df 失败或输出被截断时不再 panic
+        assert!(parse_df("", 90.0).is_none());
+        assert!(parse_df("df: /data: No such file or directory", 90.0).is_none());
+        assert!(parse_df("total - 140000", 90.0).is_none());
+    }
+
+    #[test]
+    fn month_start_traffic_pairs() {
+        // (总流量, 月流量) => 月初总流量
+        let cases = [
+            (0, 0, 0),
+            (10_000, 0, 10_000),
+            (10_000, 4_000, 6_000),
+            (10_000, 10_000, 0),
+            // 月流量大于总流量(数据库轮转、月中新增网卡)时为 0
+            (10_000, 10_001, 0),
+            (0, 500, 0),
+            (u64::MAX, 1, u64::MAX - 1),
+            (1, u64::MAX, 0),
+        ];
+        for (total, month, last) in cases {
+            assert_eq!(
+                month_start_traffic(total, month),
+                last,
+                "{} {}",
+                total,
+                month
+            );
+        }
+    }
+
+    #[test]
+    fn month_start_traffic_rollover() {
+        // 月末、次月第一次采样(vnstat 月流量清零)、次月继续累计
+        let samples = [
+            (1_000_000, 400_000, 600_000),
+            (1_000_500, 400_500, 600_000),
+            (1_000_600, 100, 1_000_500),
+            (1_002_000, 1_500, 1_000_500),
+        ];
+        for (total, month, last) in samples {
+            let last_in = month_start_traffic(total, month);
+            assert_eq!(last_in, last, "{} {}", total, month);
+            // 服务端按 总流量 - 月初总流量 计算本月流量
+            assert_eq!(total - last_in, month);
+        }
+        // 月流量先于总流量更新，短暂大于总流量，之后恢复
+        assert_eq!(month_start_traffic(1_002_000, 1_002_100), 0);
+        assert_eq!(month_start_traffic(1_003_000, 2_500), 1_000_500);
     }
 }
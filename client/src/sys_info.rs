@@ -1,16 +1,18 @@
 #![deny(warnings)]
 #![allow(unused)]
 use lazy_static::lazy_static;
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
 use std::thread;
 use std::time::Duration;
 use sysinfo::{DiskExt, NetworkExt, ProcessorExt, RefreshKind, System, SystemExt};
 
+use crate::sampler::{self, AtomicF64, Freshness, Snapshot};
 use crate::status;
-use crate::status::get_vnstat_traffic;
 use crate::Args;
-use stat_common::server_status::{StatRequest, SysInfo};
+use stat_common::server_status::{IfaceStat, StatRequest, SysInfo};
+use stat_common::units::{bytes_to_mib, kb_to_kib};
 
 const SAMPLE_PERIOD: u64 = 1000; //ms
 static IFACE_IGNORE_VEC: &[&str] = &["lo", "docker", "vnet", "veth", "vmbr", "kube", "br-"];
@@ -35,19 +37,41 @@ lazy_static! {
         "fuse.rclone",
     ]
     .to_vec();
-    pub static ref G_CPU_PERCENT: Arc<Mutex<f64>> = Arc::new(Default::default());
+    // Windows 没有 load average，由 CPU 使用率近似计算的 (1, 5, 15) 分钟负载
+    pub static ref G_WIN_LOAD_AVG: Snapshot<(f64, f64, f64)> = Snapshot::default();
+}
+pub static G_CPU_PERCENT: AtomicF64 = AtomicF64::zero();
+
+// 近似值: 繁忙核数 = cpu% * 核数，按 Unix 相同的指数衰减平滑为 1/5/15 分钟负载
+// 不包含等待 IO 及排队中的进程，仅供参考
+#[cfg(windows)]
+fn update_win_load_avg(cpu_usage: f64, cpu_num: usize) {
+    let busy = cpu_usage / 100.0 * cpu_num as f64;
+    let period = SAMPLE_PERIOD as f64 / 1000.0;
+    let decay = |load: f64, secs: f64| {
+        let e = (-period / secs).exp();
+        load * e + busy * (1.0 - e)
+    };
+    let o = G_WIN_LOAD_AVG.load();
+    G_WIN_LOAD_AVG.store((decay(o.0, 60.0), decay(o.1, 300.0), decay(o.2, 900.0)));
 }
-pub fn start_cpu_percent_collect_t() {
-    let mut sys = System::new_all();
-    sys.refresh_cpu();
-    thread::spawn(move || loop {
-        let global_processor = sys.global_processor_info();
-        if let Ok(mut cpu_percent) = G_CPU_PERCENT.lock() {
-            *cpu_percent = global_processor.cpu_usage().round() as f64;
-        }
 
+static CPU_FRESHNESS: Freshness = Freshness::new("cpu", SAMPLE_PERIOD);
+
+pub fn start_cpu_percent_collect_t() {
+    sampler::spawn("cpu", || {
+        let mut sys = System::new_all();
         sys.refresh_cpu();
-        thread::sleep(Duration::from_millis(SAMPLE_PERIOD));
+        loop {
+            let global_processor = sys.global_processor_info();
+            G_CPU_PERCENT.store(global_processor.cpu_usage() as f64);
+            CPU_FRESHNESS.touch();
+            #[cfg(windows)]
+            update_win_load_avg(global_processor.cpu_usage() as f64, sys.processors().len());
+
+            sys.refresh_cpu();
+            thread::sleep(Duration::from_millis(SAMPLE_PERIOD));
+        }
     });
 }
 
@@ -55,39 +79,54 @@ pub fn start_cpu_percent_collect_t() {
 pub struct NetSpeed {
     pub net_rx: u64,
     pub net_tx: u64,
+    // 各网卡实时网速及累计流量，--report-ifaces 时上报
+    pub ifaces: Vec<IfaceStat>,
 }
 
 lazy_static! {
-    pub static ref G_NET_SPEED: Arc<Mutex<NetSpeed>> = Arc::new(Default::default());
+    pub static ref G_NET_SPEED: Snapshot<NetSpeed> = Snapshot::default();
 }
+static NET_SPEED_FRESHNESS: Freshness = Freshness::new("speed", SAMPLE_PERIOD);
 
 pub fn start_net_speed_collect_t() {
-    let mut sys = System::new_all();
-    sys.refresh_all();
-    thread::spawn(move || loop {
-        let (mut net_rx, mut net_tx) = (0_u64, 0_u64);
-        for (name, data) in sys.networks() {
-            if IFACE_IGNORE_VEC.iter().any(|sk| name.contains(*sk)) {
-                continue;
+    sampler::spawn("speed", || {
+        let mut sys = System::new_all();
+        sys.refresh_all();
+        loop {
+            let (mut net_rx, mut net_tx) = (0_u64, 0_u64);
+            let mut ifaces = Vec::new();
+            for (name, data) in sys.networks() {
+                if IFACE_IGNORE_VEC.iter().any(|sk| name.contains(*sk)) {
+                    continue;
+                }
+                net_rx += data.received();
+                net_tx += data.transmitted();
+                ifaces.push(IfaceStat {
+                    name: name.to_string(),
+                    rx: data.received(),
+                    tx: data.transmitted(),
+                    total_in: data.total_received(),
+                    total_out: data.total_transmitted(),
+                    ..Default::default()
+                });
             }
-            net_rx += data.received();
-            net_tx += data.transmitted();
-        }
-        if let Ok(mut t) = G_NET_SPEED.lock() {
-            t.net_rx = net_rx;
-            t.net_tx = net_tx;
-        }
+            G_NET_SPEED.store(NetSpeed {
+                net_rx,
+                net_tx,
+                ifaces,
+            });
+            NET_SPEED_FRESHNESS.touch();
 
-        sys.refresh_networks();
-        thread::sleep(Duration::from_millis(SAMPLE_PERIOD));
+            sys.refresh_networks();
+            thread::sleep(Duration::from_millis(SAMPLE_PERIOD));
+        }
     });
 }
 
 pub fn sample(args: &Args, stat: &mut StatRequest) {
-    stat.version = env!("CARGO_PKG_VERSION").to_string();
-    stat.vnstat = args.vnstat;
+    stat.version = crate::report_version(args);
 
-    // 注意：sysinfo 统一使用 KB, 非KiB，需要转换一下
+    // 注意：sysinfo 内存使用 KB, 非KiB，硬盘为字节，需要转换一下
     let mut sys = System::new_with_specifics(RefreshKind::new().with_disks_list().with_memory());
 
     sys.refresh_system();
@@ -99,42 +138,74 @@ pub fn sample(args: &Args, stat: &mut StatRequest) {
     // uptime
     stat.uptime = sys.uptime();
     // load average
-    let load_avg = sys.load_average();
-    stat.load_1 = load_avg.one;
-    stat.load_5 = load_avg.five;
-    stat.load_15 = load_avg.fifteen;
+    #[cfg(not(windows))]
+    {
+        let load_avg = sys.load_average();
+        stat.load_1 = load_avg.one;
+        stat.load_5 = load_avg.five;
+        stat.load_15 = load_avg.fifteen;
+    }
+    #[cfg(windows)]
+    {
+        let o = G_WIN_LOAD_AVG.load();
+        stat.load_1 = o.0;
+        stat.load_5 = o.1;
+        stat.load_15 = o.2;
+    }
 
     // mem KB -> KiB
     let (mem_total, mem_used, swap_total, swap_free) = (
-        sys.total_memory() * 1000 / 1024,
-        sys.used_memory() * 1000 / 1024,
-        sys.total_swap() * 1000 / 1024,
-        sys.free_swap() * 1000 / 1024,
+        kb_to_kib(sys.total_memory()),
+        kb_to_kib(sys.used_memory()),
+        kb_to_kib(sys.total_swap()),
+        kb_to_kib(sys.free_swap()),
     );
     stat.memory_total = mem_total;
     stat.memory_used = mem_used;
     stat.swap_total = swap_total;
-    stat.swap_used = swap_total - swap_free;
+    stat.swap_used = swap_total.saturating_sub(swap_free);
 
-    // hdd  KB -> KiB
+    // hdd bytes -> MiB
     let (mut hdd_total, mut hdd_avail) = (0_u64, 0_u64);
+    stat.hot_mounts.clear();
     for disk in sys.disks() {
+        let mount_point = disk.mount_point().to_string_lossy();
         let fs = String::from_utf8_lossy(disk.file_system()).to_lowercase();
-        if G_EXPECT_FS.iter().any(|&k| fs.contains(k)) {
-            hdd_total += disk.total_space();
-            hdd_avail += disk.available_space();
+        if !args.disk_mounts.is_empty() {
+            // 指定挂载点时忽略文件系统类型过滤
+            if !args.disk_mounts.iter().any(|m| m.eq(&mount_point)) {
+                continue;
+            }
+        } else if !G_EXPECT_FS.iter().any(|&k| fs.contains(k)) {
+            continue;
         }
+        hdd_total += disk.total_space();
+        hdd_avail += disk.available_space();
+        stat.hot_mounts.extend(status::hot_mount(
+            &mount_point,
+            &fs,
+            bytes_to_mib(disk.total_space()),
+            bytes_to_mib(disk.total_space().saturating_sub(disk.available_space())),
+            crate::handshake::disk_warn_pct(args),
+        ));
     }
-    stat.hdd_total = hdd_total / 1024 / 1024;
-    stat.hdd_used = (hdd_total - hdd_avail) / 1024 / 1024;
+    stat.hdd_total = bytes_to_mib(hdd_total);
+    stat.hdd_used = bytes_to_mib(hdd_total.saturating_sub(hdd_avail));
 
     // traffic
-    if args.vnstat {
-        let (network_in, network_out, m_network_in, m_network_out) = get_vnstat_traffic();
+    let vnstat = if args.vnstat {
+        status::vnstat_traffic()
+    } else {
+        None
+    };
+    stat.vnstat = vnstat.is_some();
+    if let Some((network_in, network_out, m_network_in, m_network_out)) = vnstat {
         stat.network_in = network_in;
         stat.network_out = network_out;
-        stat.last_network_in = network_in - m_network_in;
-        stat.last_network_out = network_out - m_network_out;
+        stat.month_network_in = m_network_in;
+        stat.month_network_out = m_network_out;
+        stat.last_network_in = status::month_start_traffic(network_in, m_network_in);
+        stat.last_network_out = status::month_start_traffic(network_out, m_network_out);
     } else {
         sys.refresh_networks();
         let (mut network_in, mut network_out) = (0_u64, 0_u64);
@@ -149,12 +220,20 @@ pub fn sample(args: &Args, stat: &mut StatRequest) {
         stat.network_out = network_out;
     }
 
-    if let Ok(o) = G_CPU_PERCENT.lock() {
-        stat.cpu = *o;
+    if args.collect_systemd {
+        stat.failed_units = status::G_FAILED_UNITS.load().to_vec();
+    }
+
+    if CPU_FRESHNESS.check(stat) {
+        stat.cpu = G_CPU_PERCENT.load();
     }
-    if let Ok(o) = G_NET_SPEED.lock() {
+    if NET_SPEED_FRESHNESS.check(stat) {
+        let o = G_NET_SPEED.load();
         stat.network_rx = o.net_rx;
         stat.network_tx = o.net_tx;
+        if crate::handshake::report_ifaces(args) {
+            stat.ifaces = o.ifaces.clone();
+        }
     }
 }
 
@@ -165,7 +244,7 @@ pub fn collect_sys_info(args: &Args) -> SysInfo {
     sys.refresh_all();
 
     info_pb.name = args.user.to_owned();
-    info_pb.version = env!("CARGO_PKG_VERSION").to_string();
+    info_pb.version = crate::report_version(args);
 
     info_pb.os_name = std::env::consts::OS.to_string();
     info_pb.os_arch = std::env::consts::ARCH.to_string();
@@ -180,6 +259,61 @@ pub fn collect_sys_info(args: &Args) -> SysInfo {
     info_pb.cpu_vender_id = global_processor.vendor_id().to_string();
 
     info_pb.host_name = sys.host_name().unwrap_or_default();
+    info_pb.virt_type = detect_virt();
 
     info_pb
 }
+
+// 与 systemd-detect-virt 输出一致，none 为物理机
+fn detect_virt() -> String {
+    if let Ok(output) = Command::new("systemd-detect-virt").output() {
+        let virt = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !virt.is_empty() {
+            return virt;
+        }
+    }
+
+    // 容器
+    if Path::new("/.dockerenv").exists() {
+        return "docker".to_string();
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return "podman".to_string();
+    }
+    if let Ok(environ) = fs::read("/proc/1/environ") {
+        if let Some(container) = environ
+            .split(|&b| b == 0)
+            .find_map(|kv| kv.strip_prefix(b"container="))
+        {
+            return String::from_utf8_lossy(container).to_string();
+        }
+    }
+
+    // 虚拟机
+    let product = fs::read_to_string("/sys/class/dmi/id/product_name").unwrap_or_default();
+    for (key, virt) in [
+        ("KVM", "kvm"),
+        ("QEMU", "qemu"),
+        ("VMware", "vmware"),
+        ("VirtualBox", "oracle"),
+        ("HVM domU", "xen"),
+        ("Virtual Machine", "microsoft"),
+    ] {
+        if product.contains(key) {
+            return virt.to_string();
+        }
+    }
+    let hypervisor = fs::read_to_string("/proc/cpuinfo")
+        .map(|s| {
+            s.lines()
+                .any(|l| l.starts_with("flags") && l.split_whitespace().any(|f| f == "hypervisor"))
+        })
+        .unwrap_or(false);
+    if hypervisor {
+        return "vm".to_string();
+    }
+    if product.is_empty() {
+        return "".to_string();
+    }
+    "none".to_string()
+}
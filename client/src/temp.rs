@@ -0,0 +1,67 @@
+#![deny(warnings)]
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use sysinfo::{ComponentExt, RefreshKind, System, SystemExt};
+
+lazy_static! {
+    static ref G_SYS: Mutex<System> = Mutex::new(System::new_with_specifics(
+        RefreshKind::new().with_components_list()
+    ));
+}
+
+#[derive(Debug, Default)]
+pub struct Temps {
+    // core_temps 平均值，无核心温度时为 package_temp
+    pub cpu_temp: Option<f32>,
+    pub core_temps: Vec<f32>,
+    pub package_temp: Option<f32>,
+}
+
+// Intel coretemp: "Core 0", "Package id 0"
+// AMD k10temp: "Tctl"/"Tdie" 为整体温度(Tdie 为实际温度，Tctl 可能带偏移)，"Tccd1" 为各 CCD 温度
+pub fn get_temps() -> Temps {
+    let mut sys = G_SYS.lock().unwrap();
+    sys.refresh_components();
+
+    let mut cores = Vec::new();
+    let mut ccds = Vec::new();
+    let (mut package, mut tdie, mut tctl) = (None::<f32>, None, None);
+    for c in sys.components() {
+        let t = c.temperature();
+        if !t.is_finite() || t <= 0.0 {
+            continue;
+        }
+        let label = c.label();
+        if let Some(n) = label.strip_prefix("Core ") {
+            cores.push((n.trim().parse::<u32>().unwrap_or(u32::MAX), t));
+        } else if label.starts_with("Package id") {
+            // 多路 CPU 取最高
+            package = Some(package.map_or(t, |p| p.max(t)));
+        } else if label == "Tdie" {
+            tdie = Some(t);
+        } else if label == "Tctl" {
+            tctl = Some(t);
+        } else if let Some(n) = label.strip_prefix("Tccd") {
+            ccds.push((n.trim().parse::<u32>().unwrap_or(u32::MAX), t));
+        }
+    }
+    // sysinfo 按字符串排序，Core 10 会排在 Core 2 之前
+    cores.sort_by_key(|(n, _)| *n);
+    ccds.sort_by_key(|(n, _)| *n);
+    if cores.is_empty() {
+        cores = ccds;
+    }
+
+    let core_temps = cores.into_iter().map(|(_, t)| t).collect::<Vec<_>>();
+    let package_temp = package.or(tdie).or(tctl);
+    let cpu_temp = if core_temps.is_empty() {
+        package_temp
+    } else {
+        Some(core_temps.iter().sum::<f32>() / core_temps.len() as f32)
+    };
+    Temps {
+        cpu_temp,
+        core_temps,
+        package_temp,
+    }
+}
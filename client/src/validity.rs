@@ -0,0 +1,231 @@
+#![deny(warnings)]
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use stat_common::server_status::StatRequest;
+
+// 采集结果明显错误(如读取 /proc 失败导致内存总量为 0)时沿用上次的有效值，
+// 连续超过该次数后清零并加入 unavailable_metrics，服务端显示为 N/A
+const MAX_STALE_SAMPLES: u32 = 30;
+
+struct Metric {
+    name: &'static str,
+    valid: fn(&StatRequest) -> bool,
+    // 从 src 复制该指标的字段到 dst
+    copy: fn(&StatRequest, &mut StatRequest),
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "uptime",
+        valid: |s| s.uptime > 0,
+        copy: |src, dst| dst.uptime = src.uptime,
+    },
+    Metric {
+        name: "load",
+        valid: |s| {
+            [s.load_1, s.load_5, s.load_15]
+                .iter()
+                .all(|v| v.is_finite() && *v >= 0.0)
+        },
+        copy: |src, dst| {
+            dst.load_1 = src.load_1;
+            dst.load_5 = src.load_5;
+            dst.load_15 = src.load_15;
+        },
+    },
+    Metric {
+        name: "cpu",
+        valid: |s| s.cpu.is_finite() && (0.0..=100.0).contains(&s.cpu),
+        copy: |src, dst| dst.cpu = src.cpu,
+    },
+    Metric {
+        name: "memory",
+        valid: |s| s.memory_total > 0 && s.memory_used <= s.memory_total,
+        copy: |src, dst| {
+            dst.memory_total = src.memory_total;
+            dst.memory_used = src.memory_used;
+        },
+    },
+    // swap 与内存同样读自 /proc/meminfo，读取失败时 swap 为 0 与未启用 swap 无法区分，按 MemTotal 判断
+    Metric {
+        name: "swap",
+        valid: |s| s.memory_total > 0 && s.swap_used <= s.swap_total,
+        copy: |src, dst| {
+            dst.swap_total = src.swap_total;
+            dst.swap_used = src.swap_used;
+        },
+    },
+    Metric {
+        name: "hdd",
+        valid: |s| s.hdd_total > 0 && s.hdd_used <= s.hdd_total,
+        copy: |src, dst| {
+            dst.hdd_total = src.hdd_total;
+            dst.hdd_used = src.hdd_used;
+        },
+    },
+    // 客户端自身的上报即产生出站流量，读取 /proc/net/dev 失败时为 0; vnstat 新建数据库时可能为 0
+    Metric {
+        name: "traffic",
+        valid: |s| s.vnstat || s.network_out > 0,
+        copy: |src, dst| {
+            dst.network_in = src.network_in;
+            dst.network_out = src.network_out;
+            dst.last_network_in = src.last_network_in;
+            dst.last_network_out = src.last_network_out;
+            dst.month_network_in = src.month_network_in;
+            dst.month_network_out = src.month_network_out;
+        },
+    },
+];
+
+#[derive(Default)]
+struct State {
+    // 各指标最近一次有效的值
+    last: StatRequest,
+    has_last: HashMap<&'static str, bool>,
+    // 连续无效次数
+    stale: HashMap<&'static str, u32>,
+}
+
+lazy_static! {
+    static ref G_STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+fn clear(metric: &Metric, stat: &mut StatRequest) {
+    (metric.copy)(&StatRequest::default(), stat);
+}
+
+// 检查本次采集结果，无效的指标沿用上次有效值或标记为不可用
+pub fn check(stat: &mut StatRequest) {
+    check_state(&mut G_STATE.lock().unwrap(), stat);
+}
+
+fn check_state(state: &mut State, stat: &mut StatRequest) {
+    for metric in METRICS {
+        // 启动时已确认不可用或未上报
+        if stat.unavailable_metrics.iter().any(|m| m == metric.name) {
+            continue;
+        }
+        if (metric.valid)(stat) {
+            if let Some(n) = state.stale.remove(metric.name) {
+                info!(
+                    "metric `{}` recovered after {} invalid samples",
+                    metric.name, n
+                );
+            }
+            (metric.copy)(stat, &mut state.last);
+            state.has_last.insert(metric.name, true);
+            continue;
+        }
+
+        let n = state.stale.entry(metric.name).or_default();
+        *n += 1;
+        let has_last = state.has_last.get(metric.name).copied().unwrap_or(false);
+        if *n == 1 {
+            warn!(
+                "metric `{}` refresh returned invalid data, {}",
+                metric.name,
+                if has_last {
+                    "report last good value"
+                } else {
+                    "report as unavailable"
+                }
+            );
+        }
+        if has_last && *n <= MAX_STALE_SAMPLES {
+            (metric.copy)(&state.last, stat);
+            continue;
+        }
+        if has_last && *n == MAX_STALE_SAMPLES + 1 {
+            warn!(
+                "metric `{}` invalid for {} samples, report as unavailable",
+                metric.name, MAX_STALE_SAMPLES
+            );
+        }
+        clear(metric, stat);
+        stat.unavailable_metrics.push(metric.name.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(total: u64, used: u64) -> StatRequest {
+        StatRequest {
+            memory_total: total,
+            memory_used: used,
+            ..Default::default()
+        }
+    }
+
+    fn unavailable(stat: &StatRequest, metric: &str) -> bool {
+        stat.unavailable_metrics.iter().any(|m| m == metric)
+    }
+
+    #[test]
+    fn reuse_last_good_value() {
+        let mut state = State::default();
+        let mut stat = memory(1024, 512);
+        check_state(&mut state, &mut stat);
+        assert_eq!((stat.memory_total, stat.memory_used), (1024, 512));
+        assert!(!unavailable(&stat, "memory"));
+
+        // 读取失败，沿用上次的有效值
+        for _ in 0..MAX_STALE_SAMPLES {
+            let mut stat = memory(0, 0);
+            check_state(&mut state, &mut stat);
+            assert_eq!((stat.memory_total, stat.memory_used), (1024, 512));
+            assert!(!unavailable(&stat, "memory"));
+        }
+
+        // 连续超过 MAX_STALE_SAMPLES 次后清零并标记为不可用
+        for _ in 0..2 {
+            let mut stat = memory(2048, 4096);
+            check_state(&mut state, &mut stat);
+            assert_eq!((stat.memory_total, stat.memory_used), (0, 0));
+            assert!(unavailable(&stat, "memory"));
+        }
+
+        // 恢复后上报新值，重新计数
+        let mut stat = memory(2048, 1024);
+        check_state(&mut state, &mut stat);
+        assert_eq!((stat.memory_total, stat.memory_used), (2048, 1024));
+        assert!(!unavailable(&stat, "memory"));
+        assert!(!state.stale.contains_key("memory"));
+        let mut stat = memory(0, 0);
+        check_state(&mut state, &mut stat);
+        assert_eq!((stat.memory_total, stat.memory_used), (2048, 1024));
+    }
+
+    #[test]
+    fn no_last_good_value() {
+        let mut state = State::default();
+        let mut stat = memory(0, 0);
+        check_state(&mut state, &mut stat);
+        assert!(unavailable(&stat, "memory"));
+        // swap 随 /proc/meminfo 一起读取失败
+        assert!(unavailable(&stat, "swap"));
+        assert!(unavailable(&stat, "traffic"));
+    }
+
+    #[test]
+    fn skip_unavailable_metrics() {
+        let mut state = State::default();
+        let mut stat = memory(0, 0);
+        stat.unavailable_metrics.push("memory".to_string());
+        stat.vnstat = true;
+        check_state(&mut state, &mut stat);
+        assert_eq!(
+            stat.unavailable_metrics
+                .iter()
+                .filter(|m| *m == "memory")
+                .count(),
+            1
+        );
+        // vnstat 流量可能为 0
+        assert!(!unavailable(&stat, "traffic"));
+    }
+}
@@ -0,0 +1,121 @@
+#![deny(warnings)]
+use futures_util::{SinkExt, StreamExt};
+use stat_common::sign::USER_HEADER;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::signer::Signer;
+use crate::{Reply, Result};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// --transport ws，上报请求经 GET /report 升级的长连接发送，用于只放行 http(s)/443 的网络
+// 请求: json 头部({"content-type": ..., "x-report-type": ...}) + '\n' + 请求体
+// 响应: {"status": 200} + '\n' + 响应体，与 POST /report 的状态码及响应体一致
+pub struct WsConn {
+    url: String,
+    // 连接时发送的 basic auth，签名认证模式为空
+    auth: Option<String>,
+    // 签名认证模式连接时发送 x-user 及空请求体的签名
+    user: String,
+    signer: Signer,
+    user_agent: String,
+    // 同一连接上请求依次发送，出错后断开，下次请求时重连
+    conn: Mutex<Option<WsStream>>,
+}
+
+impl WsConn {
+    pub fn new(url: &str, user: &str, pass: &str, signer: Signer, user_agent: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            auth: (!signer.signed_auth)
+                .then(|| format!("Basic {}", base64::encode(format!("{}:{}", user, pass)))),
+            user: user.to_string(),
+            signer,
+            user_agent: user_agent.to_string(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    async fn connect(&self) -> Result<WsStream> {
+        let mut req = self.url.as_str().into_client_request()?;
+        let headers = req.headers_mut();
+        headers.insert("user-agent", HeaderValue::from_str(&self.user_agent)?);
+        if let Some(auth) = &self.auth {
+            headers.insert("authorization", HeaderValue::from_str(auth)?);
+        } else {
+            headers.insert(USER_HEADER, HeaderValue::from_str(&self.user)?);
+            for (key, value) in self.signer.headers(&[]) {
+                headers.insert(key, HeaderValue::from_str(&value)?);
+            }
+        }
+        let (ws, resp) = timeout(CONNECT_TIMEOUT, tokio_tungstenite::connect_async(req))
+            .await
+            .map_err(|_| "ws connect timeout")??;
+        info!("ws connected => {}", resp.status());
+        Ok(ws)
+    }
+
+    pub async fn request(&self, headers: Vec<(&str, String)>, body: Vec<u8>) -> Result<Reply> {
+        let mut conn = self.conn.lock().await;
+        if conn.is_none() {
+            *conn = Some(self.connect().await?);
+        }
+        let ws = conn.as_mut().unwrap();
+        let res = match timeout(REQUEST_TIMEOUT, round_trip(ws, headers, body)).await {
+            Ok(res) => res,
+            Err(_) => Err("ws request timeout".into()),
+        };
+        if res.is_err() {
+            *conn = None;
+        }
+        res
+    }
+}
+
+async fn round_trip(
+    ws: &mut WsStream,
+    headers: Vec<(&str, String)>,
+    body: Vec<u8>,
+) -> Result<Reply> {
+    let head = headers
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.into()))
+        .collect::<serde_json::Map<_, _>>();
+    let mut frame = serde_json::to_vec(&head)?;
+    frame.push(b'\n');
+    frame.extend(body);
+    ws.send(Message::Binary(frame)).await?;
+
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Binary(data))) => return parse_reply(&data),
+            Some(Ok(Message::Close(_))) | None => return Err("ws closed by server".into()),
+            Some(Err(err)) => return Err(err.into()),
+            // ping 在读取时自动回复
+            Some(Ok(_)) => {}
+        }
+    }
+}
+
+fn parse_reply(data: &[u8]) -> Result<Reply> {
+    let pos = data
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or("invalid ws reply")?;
+    let head = serde_json::from_slice::<serde_json::Value>(&data[..pos])?;
+    let status = head["status"].as_u64().ok_or("invalid ws reply status")? as u16;
+    Ok(Reply {
+        status,
+        body: data[pos + 1..].to_vec(),
+    })
+}
@@ -35,8 +35,16 @@ fn main() {
     }
     println!("cargo:rustc-env=APP_VERSION={}", app_version);
 
+    let mut config = prost_build::Config::new();
+    // 服务端据此生成 json schema
+    config.file_descriptor_set_path(
+        std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("server_status.bin"),
+    );
+
+    // 旧客户端 json 上报缺少新增字段时按缺省值处理
     tonic_build::configure()
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
-        .compile(&["proto/server_status.proto"], &["proto"])
+        .type_attribute(".", "#[serde(default)]")
+        .compile_with_config(config, &["proto/server_status.proto"], &["proto"])
         .unwrap();
 }
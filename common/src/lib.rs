@@ -1,3 +1,35 @@
+pub mod sign;
+pub mod units;
+
+// 协议版本，proto/server_status.proto 新增字段时递增
+// 1: clock_offset_ms
+// 2: Heartbeat, Response.capabilities
+// 3: ifaces
+// 4: StatRequest.delta_base, Response.delta_base
+// 5: sensors
+// 6: Hello, HelloResponse
+// 7: StatRequest.seq
+// 8: StatRequest.units
+// 9: StatRequest.month_network_in/out
+pub const PROTO_VERSION: u32 = 9;
+
+// 服务端在上报响应及握手中声明的功能
+pub const CAP_HEARTBEAT: &str = "heartbeat";
+// 支持签名认证模式(见 sign::signed_data)
+pub const CAP_SIGNED: &str = "signed";
+// 支持累计计数的增量上报(见 StatRequest.delta_base)
+pub const CAP_DELTA: &str = "delta";
+// 上报各网卡明细(StatRequest.ifaces)
+pub const CAP_IFACES: &str = "ifaces";
+// http 上报的消息类型请求头，缺省为完整上报
+pub const REPORT_TYPE_HEADER: &str = "x-report-type";
+// x-report-type 取值，心跳为 CAP_HEARTBEAT
+pub const REPORT_TYPE_HELLO: &str = "hello";
+
 pub mod server_status {
     tonic::include_proto!("server_status");
 }
+
+// 编码后的 FileDescriptorSet，用于生成 json schema
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/server_status.bin"));
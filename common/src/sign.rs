@@ -0,0 +1,88 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// 签名放在 http header 或 grpc metadata 中
+pub const SIGNATURE_HEADER: &str = "x-signature";
+// 签名认证模式(不发送密码)附带的 unix 时间戳(秒)及随机串，用于防重放
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+pub const NONCE_HEADER: &str = "x-nonce";
+// 签名认证模式下 http 上报不带 basic auth，由该请求头携带用户名
+pub const USER_HEADER: &str = "x-user";
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 奇数长度时最后一段取不到两个字符，返回 None
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// HMAC-SHA256(secret, data)，hex 编码
+pub fn sign(secret: &str, data: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac accepts any key size");
+    mac.update(data);
+    to_hex(&mac.finalize().into_bytes())
+}
+
+// 常量时间比较
+pub fn verify(secret: &str, data: &[u8], signature: &str) -> bool {
+    let tag = match from_hex(signature.trim()) {
+        Some(tag) => tag,
+        None => return false,
+    };
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac accepts any key size");
+    mac.update(data);
+    mac.verify_slice(&tag).is_ok()
+}
+
+// 签名认证模式的签名内容: payload || timestamp || nonce
+pub fn signed_data(payload: &[u8], timestamp: i64, nonce: &str) -> Vec<u8> {
+    let mut data = payload.to_vec();
+    data.extend_from_slice(timestamp.to_string().as_bytes());
+    data.extend_from_slice(nonce.as_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_data_layout() {
+        assert_eq!(
+            signed_data(b"payload", 1700000000, "n1"),
+            b"payload1700000000n1"
+        );
+        assert_eq!(signed_data(b"", -1, ""), b"-1");
+    }
+
+    // RFC 4231 test case 2
+    #[test]
+    fn hmac_sha256() {
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn verify_signature() {
+        let signature = sign("secret", b"data");
+        assert!(verify("secret", b"data", &signature));
+        assert!(verify("secret", b"data", &format!(" {}\n", signature)));
+        assert!(verify("secret", b"data", &signature.to_uppercase()));
+        assert!(!verify("other", b"data", &signature));
+        assert!(!verify("secret", b"data!", &signature));
+        // 截断、奇数长度及非 hex
+        assert!(!verify("secret", b"data", &signature[..62]));
+        assert!(!verify("secret", b"data", &signature[..63]));
+        assert!(!verify("secret", b"data", &signature.replace('a', "g")));
+        assert!(!verify("secret", b"data", ""));
+    }
+}
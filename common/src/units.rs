@@ -0,0 +1,241 @@
+// 单位换算及格式化，客户端采集与服务端模板共用
+// StatRequest 中内存/swap 默认为 KiB，硬盘为 MiB(见 StatRequest.units)，流量/网速为字节(每秒)
+use std::fmt;
+
+pub const KIB: u64 = 1024;
+pub const MIB: u64 = 1024 * KIB;
+pub const GIB: u64 = 1024 * MIB;
+
+// StatRequest.units 的默认值，服务端按此处理
+pub const MEMORY_UNIT: &str = "KiB";
+pub const DISK_UNIT: &str = "MiB";
+
+const BYTE_UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const BIT_UNITS: &[&str] = &["bps", "Kbps", "Mbps", "Gbps", "Tbps", "Pbps"];
+
+pub fn bytes_to_kib(bytes: u64) -> u64 {
+    bytes / KIB
+}
+
+pub fn bytes_to_mib(bytes: u64) -> u64 {
+    bytes / MIB
+}
+
+pub fn kib_to_mib(kib: u64) -> u64 {
+    kib / KIB
+}
+
+// sysinfo 的内存单位为 KB(1000)，非 KiB
+pub fn kb_to_kib(kb: u64) -> u64 {
+    (kb as u128 * 1000 / KIB as u128) as u64
+}
+
+// "KiB" => 1024，未知单位为 None
+pub fn unit_size(unit: &str) -> Option<u64> {
+    match unit {
+        "B" => Some(1),
+        "KiB" => Some(KIB),
+        "MiB" => Some(MIB),
+        "GiB" => Some(GIB),
+        _ => None,
+    }
+}
+
+// 按单位大小换算，如 convert(2048, KIB, MIB) => 2，溢出时为 u64::MAX
+pub fn convert(v: u64, from: u64, to: u64) -> u64 {
+    (v as u128 * from as u128 / to as u128).min(u64::MAX as u128) as u64
+}
+
+fn scale_idx(mut v: f64, step: f64, units: &[&str]) -> (f64, usize) {
+    let mut idx = 0;
+    while v.abs() >= step && idx < units.len() - 1 {
+        v /= step;
+        idx += 1;
+    }
+    (v, idx)
+}
+
+fn scale(v: f64, step: f64, units: &'static [&'static str]) -> (f64, &'static str) {
+    let (v, idx) = scale_idx(v, step, units);
+    (v, units[idx])
+}
+
+// 1536 => (1.5, "KiB")
+pub fn scale_bytes(bytes: f64) -> (f64, &'static str) {
+    scale(bytes, 1024.0, BYTE_UNITS)
+}
+
+// 网速按 bit 及 1000 进位，125000 字节/秒 => (1.0, "Mbps")
+pub fn scale_bits_per_sec(bytes_per_sec: f64) -> (f64, &'static str) {
+    scale(bytes_per_sec * 8.0, 1000.0, BIT_UNITS)
+}
+
+fn fmt_scaled(f: &mut fmt::Formatter, v: f64, step: f64, units: &[&str]) -> fmt::Result {
+    let (mut v, mut idx) = scale_idx(v, step, units);
+    // 最小单位为整数，其余默认 1 位小数，可用 {:.2} 指定
+    let precision = if idx == 0 {
+        0
+    } else {
+        f.precision().unwrap_or(1)
+    };
+    // 1048575 B 为 1023.999 KiB，按精度舍入后显示为 1024.0 KiB，改用下一单位
+    let factor = 10f64.powi(precision.min(16) as i32);
+    if idx > 0 && idx < units.len() - 1 && (v.abs() * factor).round() / factor >= step {
+        v /= step;
+        idx += 1;
+    }
+    write!(f, "{:.*} {}", precision, v, units[idx])
+}
+
+// 字节数，format!("{}", ByteSize(3_650_722_201)) => "3.4 GiB"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    pub fn from_kib(kib: u64) -> Self {
+        Self(kib.saturating_mul(KIB))
+    }
+
+    pub fn from_mib(mib: u64) -> Self {
+        Self(mib.saturating_mul(MIB))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_scaled(f, self.0 as f64, 1024.0, BYTE_UNITS)
+    }
+}
+
+// 网速(字节/秒)，显示为 bit/s，format!("{}", BitRate(125_000)) => "1.0 Mbps"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct BitRate(pub u64);
+
+impl fmt::Display for BitRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_scaled(f, self.0 as f64 * 8.0, 1000.0, BIT_UNITS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_conversions() {
+        assert_eq!(bytes_to_kib(0), 0);
+        assert_eq!(bytes_to_kib(KIB - 1), 0);
+        assert_eq!(bytes_to_kib(KIB), 1);
+        assert_eq!(bytes_to_kib(u64::MAX), u64::MAX / KIB);
+        assert_eq!(bytes_to_mib(MIB - 1), 0);
+        assert_eq!(bytes_to_mib(MIB), 1);
+        assert_eq!(bytes_to_mib(u64::MAX), u64::MAX / MIB);
+        assert_eq!(kib_to_mib(KIB - 1), 0);
+        assert_eq!(kib_to_mib(KIB), 1);
+        assert_eq!(kb_to_kib(0), 0);
+        assert_eq!(kb_to_kib(1), 0);
+        assert_eq!(kb_to_kib(1024), 1000);
+        assert_eq!(kb_to_kib(16_000_000), 15_625_000);
+        // 中间结果超过 u64 时不溢出
+        assert_eq!(kb_to_kib(u64::MAX), (u64::MAX as u128 * 1000 / 1024) as u64);
+    }
+
+    #[test]
+    fn unit_sizes() {
+        assert_eq!(unit_size("B"), Some(1));
+        assert_eq!(unit_size("KiB"), Some(KIB));
+        assert_eq!(unit_size("MiB"), Some(MIB));
+        assert_eq!(unit_size("GiB"), Some(GIB));
+        for unit in ["", "kib", "KB", "TiB", " KiB"] {
+            assert_eq!(unit_size(unit), None, "{:?}", unit);
+        }
+        assert_eq!(unit_size(MEMORY_UNIT), Some(KIB));
+        assert_eq!(unit_size(DISK_UNIT), Some(MIB));
+    }
+
+    #[test]
+    fn convert_between_units() {
+        assert_eq!(convert(2048, KIB, MIB), 2);
+        assert_eq!(convert(2047, KIB, MIB), 1);
+        assert_eq!(convert(0, GIB, 1), 0);
+        assert_eq!(convert(3, MIB, KIB), 3072);
+        assert_eq!(convert(7, KIB, KIB), 7);
+        assert_eq!(convert(u64::MAX, 1, 1), u64::MAX);
+        // 溢出时为 u64::MAX
+        assert_eq!(convert(u64::MAX / KIB + 1, KIB, 1), u64::MAX);
+        assert_eq!(convert(u64::MAX, GIB, 1), u64::MAX);
+        assert_eq!(convert(u64::MAX / KIB, KIB, 1), u64::MAX / KIB * KIB);
+    }
+
+    #[test]
+    fn scale_values() {
+        assert_eq!(scale_bytes(0.0), (0.0, "B"));
+        assert_eq!(scale_bytes(1023.0), (1023.0, "B"));
+        assert_eq!(scale_bytes(1024.0), (1.0, "KiB"));
+        assert_eq!(scale_bytes(1536.0), (1.5, "KiB"));
+        assert_eq!(scale_bytes(GIB as f64), (1.0, "GiB"));
+        // 最大单位后不再进位
+        assert_eq!(scale_bytes(1024f64.powi(7)), (1024.0, "EiB"));
+        assert_eq!(scale_bytes(-2048.0), (-2.0, "KiB"));
+        assert_eq!(scale_bits_per_sec(0.0), (0.0, "bps"));
+        assert_eq!(scale_bits_per_sec(124.0), (992.0, "bps"));
+        assert_eq!(scale_bits_per_sec(125.0), (1.0, "Kbps"));
+        assert_eq!(scale_bits_per_sec(125_000.0), (1.0, "Mbps"));
+        assert_eq!(scale_bits_per_sec(1.25e17), (1000.0, "Pbps"));
+    }
+
+    #[test]
+    fn byte_size_display() {
+        let cases = [
+            (0, "0 B"),
+            (1, "1 B"),
+            (1023, "1023 B"),
+            (1024, "1.0 KiB"),
+            (1536, "1.5 KiB"),
+            (1024 * 1024 - 1, "1.0 MiB"),
+            (MIB, "1.0 MiB"),
+            (GIB - 1, "1.0 GiB"),
+            (3_650_722_201, "3.4 GiB"),
+            (1024 * GIB, "1.0 TiB"),
+            (u64::MAX, "16.0 EiB"),
+        ];
+        for (bytes, s) in cases {
+            assert_eq!(ByteSize(bytes).to_string(), s, "{}", bytes);
+        }
+        assert_eq!(format!("{:.2}", ByteSize(1536)), "1.50 KiB");
+        assert_eq!(format!("{:.0}", ByteSize(1536)), "2 KiB");
+        assert_eq!(format!("{:.2}", ByteSize(1023)), "1023 B");
+        // 两位小数时 1023.999 KiB 舍入为 1024.00，进位
+        assert_eq!(format!("{:.2}", ByteSize(MIB - 1)), "1.00 MiB");
+        assert_eq!(format!("{:.3}", ByteSize(MIB - 1)), "1023.999 KiB");
+        assert_eq!(format!("{:.1}", ByteSize(1023 * KIB + 900)), "1023.9 KiB");
+    }
+
+    #[test]
+    fn byte_size_from_units() {
+        assert_eq!(ByteSize::from_kib(0), ByteSize(0));
+        assert_eq!(ByteSize::from_kib(1), ByteSize(1024));
+        assert_eq!(ByteSize::from_mib(1), ByteSize(MIB));
+        assert_eq!(ByteSize::from_kib(u64::MAX), ByteSize(u64::MAX));
+        assert_eq!(ByteSize::from_mib(u64::MAX / KIB), ByteSize(u64::MAX));
+        assert_eq!(ByteSize::from_kib(8_388_608).to_string(), "8.0 GiB");
+    }
+
+    #[test]
+    fn bit_rate_display() {
+        let cases = [
+            (0, "0 bps"),
+            (1, "8 bps"),
+            (124, "992 bps"),
+            (125, "1.0 Kbps"),
+            (124_999, "1.0 Mbps"),
+            (125_000, "1.0 Mbps"),
+            (12_500_000_000, "100.0 Gbps"),
+            (u64::MAX, "147574.0 Pbps"),
+        ];
+        for (bytes_per_sec, s) in cases {
+            assert_eq!(BitRate(bytes_per_sec).to_string(), s, "{}", bytes_per_sec);
+        }
+        assert_eq!(format!("{:.2}", BitRate(187_500)), "1.50 Mbps");
+    }
+}
@@ -0,0 +1,334 @@
+#![deny(warnings)]
+// 协议兼容性: 旧版(发布版)客户端的上报由新版解码，新版客户端的上报由旧版解码
+// fixtures/stat_request_v1.* 为发布版客户端序列化的上报，字段定义见 v1 模块，不可修改
+use prost::Message;
+use stat_common::server_status::StatRequest;
+
+// 发布版 proto/server_status.proto 的消息定义
+mod v1 {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IpInfo {
+        #[prost(string, tag = "1")]
+        pub query: String,
+        #[prost(string, tag = "2")]
+        pub source: String,
+        #[prost(string, tag = "3")]
+        pub continent: String,
+        #[prost(string, tag = "4")]
+        pub country: String,
+        #[prost(string, tag = "5")]
+        pub region_name: String,
+        #[prost(string, tag = "6")]
+        pub city: String,
+        #[prost(string, tag = "7")]
+        pub isp: String,
+        #[prost(string, tag = "8")]
+        pub org: String,
+        #[prost(string, tag = "9")]
+        pub r#as: String,
+        #[prost(string, tag = "10")]
+        pub asname: String,
+        #[prost(double, tag = "11")]
+        pub lat: f64,
+        #[prost(double, tag = "12")]
+        pub lon: f64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SysInfo {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub version: String,
+        #[prost(string, tag = "3")]
+        pub os_name: String,
+        #[prost(string, tag = "4")]
+        pub os_arch: String,
+        #[prost(string, tag = "5")]
+        pub os_family: String,
+        #[prost(string, tag = "6")]
+        pub os_release: String,
+        #[prost(string, tag = "7")]
+        pub kernel_version: String,
+        #[prost(uint32, tag = "8")]
+        pub cpu_num: u32,
+        #[prost(string, tag = "9")]
+        pub cpu_brand: String,
+        #[prost(string, tag = "10")]
+        pub cpu_vender_id: String,
+        #[prost(string, tag = "11")]
+        pub host_name: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatRequest {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub version: String,
+        #[prost(uint64, tag = "3")]
+        pub latest_ts: u64,
+        #[prost(string, tag = "4")]
+        pub frame: String,
+        #[prost(bool, tag = "7")]
+        pub vnstat: bool,
+        #[prost(bool, tag = "8")]
+        pub online4: bool,
+        #[prost(bool, tag = "9")]
+        pub online6: bool,
+        #[prost(uint64, tag = "10")]
+        pub uptime: u64,
+        #[prost(double, tag = "11")]
+        pub load_1: f64,
+        #[prost(double, tag = "12")]
+        pub load_5: f64,
+        #[prost(double, tag = "13")]
+        pub load_15: f64,
+        #[prost(uint64, tag = "23")]
+        pub network_rx: u64,
+        #[prost(uint64, tag = "24")]
+        pub network_tx: u64,
+        #[prost(uint64, tag = "25")]
+        pub network_in: u64,
+        #[prost(uint64, tag = "26")]
+        pub network_out: u64,
+        #[prost(uint64, tag = "27")]
+        pub last_network_in: u64,
+        #[prost(uint64, tag = "28")]
+        pub last_network_out: u64,
+        #[prost(double, tag = "29")]
+        pub cpu: f64,
+        #[prost(uint64, tag = "30")]
+        pub memory_total: u64,
+        #[prost(uint64, tag = "31")]
+        pub memory_used: u64,
+        #[prost(uint64, tag = "32")]
+        pub swap_total: u64,
+        #[prost(uint64, tag = "33")]
+        pub swap_used: u64,
+        #[prost(uint64, tag = "34")]
+        pub hdd_total: u64,
+        #[prost(uint64, tag = "35")]
+        pub hdd_used: u64,
+        #[prost(string, optional, tag = "36")]
+        pub custom: Option<String>,
+        #[prost(message, optional, tag = "37")]
+        pub sys_info: Option<SysInfo>,
+        #[prost(message, optional, tag = "38")]
+        pub ip_info: Option<IpInfo>,
+    }
+}
+
+const V1_BIN: &[u8] = include_bytes!("fixtures/stat_request_v1.bin");
+const V1_JSON: &str = include_str!("fixtures/stat_request_v1.json");
+
+fn v1_sample() -> v1::StatRequest {
+    v1::StatRequest {
+        name: "h1".to_string(),
+        version: "1.0.0".to_string(),
+        latest_ts: 1_700_000_000,
+        frame: "data".to_string(),
+        vnstat: true,
+        online4: true,
+        online6: false,
+        uptime: 86_400,
+        load_1: 0.5,
+        load_5: 0.25,
+        load_15: 0.125,
+        network_rx: 1_000,
+        network_tx: 2_000,
+        network_in: 30_000_000_000,
+        network_out: 40_000_000_000,
+        last_network_in: 10_000_000_000,
+        last_network_out: 20_000_000_000,
+        cpu: 12.5,
+        memory_total: 8_388_608,
+        memory_used: 4_194_304,
+        swap_total: 1_048_576,
+        swap_used: 0,
+        hdd_total: 102_400,
+        hdd_used: 51_200,
+        custom: Some("custom".to_string()),
+        sys_info: Some(v1::SysInfo {
+            name: "stat_client".to_string(),
+            version: "1.0.0".to_string(),
+            os_name: "linux".to_string(),
+            os_arch: "x86_64".to_string(),
+            os_family: "unix".to_string(),
+            os_release: "Debian 12".to_string(),
+            kernel_version: "6.1.0".to_string(),
+            cpu_num: 4,
+            cpu_brand: "Intel".to_string(),
+            cpu_vender_id: "GenuineIntel".to_string(),
+            host_name: "host1".to_string(),
+        }),
+        ip_info: Some(v1::IpInfo {
+            query: "203.0.113.1".to_string(),
+            source: "ip-api".to_string(),
+            continent: "Asia".to_string(),
+            country: "Japan".to_string(),
+            region_name: "Tokyo".to_string(),
+            city: "Tokyo".to_string(),
+            isp: "isp".to_string(),
+            org: "org".to_string(),
+            r#as: "AS64500".to_string(),
+            asname: "asname".to_string(),
+            lat: 35.5,
+            lon: 139.75,
+        }),
+    }
+}
+
+// 共有字段一致
+fn assert_shared(new: &StatRequest, old: &v1::StatRequest) {
+    assert_eq!(new.name, old.name);
+    assert_eq!(new.version, old.version);
+    assert_eq!(new.latest_ts, old.latest_ts);
+    assert_eq!(new.frame, old.frame);
+    assert_eq!(new.vnstat, old.vnstat);
+    assert_eq!((new.online4, new.online6), (old.online4, old.online6));
+    assert_eq!(new.uptime, old.uptime);
+    assert_eq!(
+        (new.load_1, new.load_5, new.load_15),
+        (old.load_1, old.load_5, old.load_15)
+    );
+    assert_eq!(
+        (
+            new.network_rx,
+            new.network_tx,
+            new.network_in,
+            new.network_out
+        ),
+        (
+            old.network_rx,
+            old.network_tx,
+            old.network_in,
+            old.network_out
+        )
+    );
+    assert_eq!(
+        (new.last_network_in, new.last_network_out),
+        (old.last_network_in, old.last_network_out)
+    );
+    assert_eq!(new.cpu, old.cpu);
+    assert_eq!(
+        (new.memory_total, new.memory_used),
+        (old.memory_total, old.memory_used)
+    );
+    assert_eq!(
+        (new.swap_total, new.swap_used),
+        (old.swap_total, old.swap_used)
+    );
+    assert_eq!((new.hdd_total, new.hdd_used), (old.hdd_total, old.hdd_used));
+    assert_eq!(new.custom, old.custom);
+
+    let (sys, old_sys) = (
+        new.sys_info.as_ref().unwrap(),
+        old.sys_info.as_ref().unwrap(),
+    );
+    assert_eq!(
+        (&sys.name, &sys.version, &sys.host_name),
+        (&old_sys.name, &old_sys.version, &old_sys.host_name)
+    );
+    assert_eq!(
+        (&sys.os_name, &sys.os_arch, &sys.os_family),
+        (&old_sys.os_name, &old_sys.os_arch, &old_sys.os_family)
+    );
+    assert_eq!(
+        (&sys.os_release, &sys.kernel_version),
+        (&old_sys.os_release, &old_sys.kernel_version)
+    );
+    assert_eq!(
+        (sys.cpu_num, &sys.cpu_brand, &sys.cpu_vender_id),
+        (old_sys.cpu_num, &old_sys.cpu_brand, &old_sys.cpu_vender_id)
+    );
+
+    let (ip, old_ip) = (new.ip_info.as_ref().unwrap(), old.ip_info.as_ref().unwrap());
+    assert_eq!(
+        (&ip.query, &ip.source, &ip.country, &ip.city),
+        (&old_ip.query, &old_ip.source, &old_ip.country, &old_ip.city)
+    );
+    assert_eq!(
+        (&ip.r#as, ip.lat, ip.lon),
+        (&old_ip.r#as, old_ip.lat, old_ip.lon)
+    );
+}
+
+// fixture 与发布版的定义一致
+#[test]
+fn fixture_matches_release() {
+    assert_eq!(v1_sample().encode_to_vec(), V1_BIN);
+    assert_eq!(v1::StatRequest::decode(V1_BIN).unwrap(), v1_sample());
+}
+
+// 新版服务端接收发布版客户端的 grpc 上报，新增字段为缺省值
+#[test]
+fn decode_release_protobuf() {
+    let stat = StatRequest::decode(V1_BIN).unwrap();
+    assert_shared(&stat, &v1_sample());
+    assert!(stat.labels.is_empty());
+    assert!(stat.ifaces.is_empty());
+    assert!(stat.unavailable_metrics.is_empty());
+    assert_eq!(stat.proto_version, 0);
+    assert_eq!(stat.delta_base, 0);
+    assert_eq!(stat.cpu_temp, None);
+    assert_eq!(stat.clock_offset_ms, None);
+    assert!(!stat.buffered);
+}
+
+// 新版重新编码后发布版仍能解码出相同内容
+#[test]
+fn release_fields_roundtrip() {
+    let stat = StatRequest::decode(V1_BIN).unwrap();
+    let old = v1::StatRequest::decode(&stat.encode_to_vec()[..]).unwrap();
+    assert_eq!(old, v1_sample());
+}
+
+// 发布版客户端的 http json 上报，缺少的新增字段按缺省值处理
+#[test]
+fn decode_release_json() {
+    let stat: StatRequest = serde_json::from_str(V1_JSON).unwrap();
+    assert_shared(&stat, &v1_sample());
+    assert!(stat.labels.is_empty());
+    assert_eq!(stat.proto_version, 0);
+}
+
+// 发布版服务端接收新版客户端的上报，忽略新增字段
+#[test]
+fn release_ignores_new_fields() {
+    let mut stat = StatRequest::decode(V1_BIN).unwrap();
+    stat.labels.insert("env".to_string(), "prod".to_string());
+    stat.unavailable_metrics.push("swap".to_string());
+    stat.cpu_temp = Some(55.5);
+    stat.proto_version = stat_common::PROTO_VERSION;
+    stat.delta_base = 7;
+    stat.buffered = true;
+    let old = v1::StatRequest::decode(&stat.encode_to_vec()[..]).unwrap();
+    assert_eq!(old, v1_sample());
+}
+
+// 比当前版本更新的客户端带有未知字段，解码时忽略
+#[test]
+fn ignore_unknown_fields() {
+    use prost::encoding::{encode_key, encode_varint, WireType};
+
+    let mut buf = V1_BIN.to_vec();
+    // varint / length-delimited / fixed64 / fixed32 各一个未知字段
+    encode_key(9001, WireType::Varint, &mut buf);
+    encode_varint(42, &mut buf);
+    encode_key(9002, WireType::LengthDelimited, &mut buf);
+    encode_varint(3, &mut buf);
+    buf.extend_from_slice(b"new");
+    encode_key(9003, WireType::SixtyFourBit, &mut buf);
+    buf.extend_from_slice(&1.5f64.to_le_bytes());
+    encode_key(9004, WireType::ThirtyTwoBit, &mut buf);
+    buf.extend_from_slice(&7u32.to_le_bytes());
+    let stat = StatRequest::decode(&buf[..]).unwrap();
+    assert_shared(&stat, &v1_sample());
+
+    let mut json: serde_json::Value = serde_json::from_str(V1_JSON).unwrap();
+    json["future_field"] = serde_json::json!({"a": [1, 2]});
+    json["sys_info"]["future_field"] = serde_json::json!("x");
+    let stat: StatRequest = serde_json::from_value(json).unwrap();
+    assert_shared(&stat, &v1_sample());
+}
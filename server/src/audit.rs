@@ -0,0 +1,480 @@
+#![deny(warnings)]
+use anyhow::Result;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tonic::transport::server::Connected;
+
+use crate::listener::{canonical_ip, PeerAddr};
+
+static AUDIT: OnceCell<Audit> = OnceCell::new();
+// grpc 长连接，按客户端地址记录认证结果，连接关闭时写入
+static CONNS: Lazy<Mutex<HashMap<SocketAddr, ConnState>>> = Lazy::new(Default::default);
+
+fn default_file() -> String {
+    "audit.log".to_string()
+}
+fn default_max_size_mb() -> u64 {
+    10
+}
+fn default_max_files() -> usize {
+    5
+}
+fn default_ring_size() -> usize {
+    1000
+}
+// 待写入的行数上限，写入线程跟不上时丢弃新的记录，不阻塞上报
+const QUEUE_SIZE: usize = 4096;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default = "Default::default")]
+    pub enabled: bool,
+    #[serde(default = "default_file")]
+    pub file: String,
+    // 超过后轮转为 audit.log.1 ... audit.log.N
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    // GET /admin/audit 可查询的最近记录数
+    #[serde(default = "default_ring_size")]
+    pub ring_size: usize,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: default_file(),
+            max_size_mb: default_max_size_mb(),
+            max_files: default_max_files(),
+            ring_size: default_ring_size(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub ts: u64,
+    // report(http 每次上报) / upgrade(ws 上报连接) / open / close(grpc 连接) / auth_fail(grpc) / reject(banned ip 直接断开)
+    pub event: &'static str,
+    pub transport: &'static str,
+    pub ip: Option<IpAddr>,
+    pub user: Option<String>,
+    pub auth: Option<bool>,
+    // 认证失败或上报被拒绝的原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub tls: bool,
+    pub bytes: u64,
+}
+
+// 文件读写在单独的线程中进行，上报处理及 Drop 中只写入环形缓冲并投递到队列
+struct Writer {
+    cfg: &'static Config,
+    file: Option<File>,
+    size: u64,
+}
+
+impl Writer {
+    fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.cfg.file)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        for i in (1..self.cfg.max_files).rev() {
+            let from = format!("{}.{}", self.cfg.file, i);
+            if fs::metadata(&from).is_ok() {
+                fs::rename(&from, format!("{}.{}", self.cfg.file, i + 1))?;
+            }
+        }
+        if self.cfg.max_files > 0 {
+            fs::rename(&self.cfg.file, format!("{}.1", self.cfg.file))?;
+        } else {
+            fs::remove_file(&self.cfg.file)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.size + line.len() as u64 > self.cfg.max_size_mb * 1024 * 1024 && self.size > 0 {
+            self.file = None;
+            self.rotate()?;
+            self.size = 0;
+        }
+        if self.file.is_none() {
+            self.file = Some(self.open()?);
+        }
+        self.file.as_mut().unwrap().write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn run(mut self, rx: Receiver<String>) {
+        for line in rx {
+            if let Err(err) = self.write(&line) {
+                error!("write audit log {} err => {:?}", self.cfg.file, err);
+            }
+        }
+    }
+}
+
+struct Audit {
+    cfg: &'static Config,
+    ring: Mutex<VecDeque<Entry>>,
+    tx: SyncSender<String>,
+}
+
+impl Audit {
+    fn new(cfg: &'static Config) -> io::Result<Self> {
+        let mut writer = Writer {
+            cfg,
+            file: None,
+            size: fs::metadata(&cfg.file).map_or(0, |o| o.len()),
+        };
+        writer.file = Some(writer.open()?);
+        let (tx, rx) = sync_channel(QUEUE_SIZE);
+        thread::Builder::new()
+            .name("audit".to_string())
+            .spawn(move || writer.run(rx))?;
+        Ok(Self {
+            cfg,
+            ring: Mutex::new(VecDeque::new()),
+            tx,
+        })
+    }
+
+    fn push(&self, entry: Entry) {
+        if let Ok(line) = serde_json::to_string(&entry) {
+            if let Err(TrySendError::Full(_)) = self.tx.try_send(line + "\n") {
+                if crate::ingest::should_log("audit".to_string()) {
+                    warn!("audit log queue full, drop entries");
+                }
+            }
+        }
+        let mut ring = self.ring.lock().unwrap();
+        ring.push_back(entry);
+        while ring.len() > self.cfg.ring_size {
+            ring.pop_front();
+        }
+    }
+
+    fn query(&self, since: u64, limit: usize) -> Vec<Entry> {
+        let ring = self.ring.lock().unwrap();
+        let list = ring.iter().filter(|o| o.ts >= since).collect::<Vec<_>>();
+        list[list.len().saturating_sub(limit)..]
+            .iter()
+            .map(|o| (*o).clone())
+            .collect()
+    }
+}
+
+pub fn init(cfg: &'static Config) -> Result<()> {
+    if !cfg.enabled {
+        return Ok(());
+    }
+    let _ = AUDIT.set(Audit::new(cfg)?);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    AUDIT.get().is_some()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn push(entry: Entry) {
+    if let Some(audit) = AUDIT.get() {
+        audit.push(entry);
+    }
+}
+
+// ts >= since 的最近 limit 条，按时间先后排列
+pub fn query(since: u64, limit: usize) -> Vec<Entry> {
+    AUDIT
+        .get()
+        .map_or_else(Vec::new, |audit| audit.query(since, limit))
+}
+
+// 未通过 report_allow/deny 的连接
+pub fn reject(transport: &'static str, ip: IpAddr, tls: bool) {
+    push(Entry {
+        ts: now(),
+        event: "reject",
+        transport,
+        ip: Some(ip),
+        user: None,
+        auth: Some(false),
+        reason: Some("banned ip".to_string()),
+        tls,
+        bytes: 0,
+    });
+}
+
+// http 单次上报，drop 时写入，覆盖提前返回的各分支
+pub struct Record {
+    pub entry: Entry,
+}
+
+impl Record {
+    pub fn http(ip: Option<IpAddr>, tls: bool) -> Self {
+        Self {
+            entry: Entry {
+                ts: now(),
+                event: "report",
+                transport: "http",
+                ip,
+                user: None,
+                auth: None,
+                reason: None,
+                tls,
+                bytes: 0,
+            },
+        }
+    }
+
+    pub fn fail(&mut self, reason: impl ToString) {
+        self.entry.reason = Some(reason.to_string());
+    }
+}
+
+impl Drop for Record {
+    fn drop(&mut self) {
+        if enabled() {
+            push(self.entry.clone());
+        }
+    }
+}
+
+#[derive(Default)]
+struct ConnState {
+    user: Option<String>,
+    auth: Option<bool>,
+    reason: Option<String>,
+}
+
+// grpc 认证结果，同一连接只在首次失败时单独记录
+pub fn grpc_auth(addr: Option<SocketAddr>, tls: bool, user: Option<&str>, reason: Option<&str>) {
+    let addr = match addr {
+        Some(addr) if enabled() => addr,
+        _ => return,
+    };
+    let mut conns = CONNS.lock().unwrap();
+    let st = conns.entry(addr).or_default();
+    let first_fail = reason.is_some() && st.auth != Some(false);
+    st.user = user.map(|s| s.to_string());
+    st.auth = Some(reason.is_none());
+    st.reason = reason.map(|s| s.to_string());
+    drop(conns);
+    if first_fail {
+        push(Entry {
+            ts: now(),
+            event: "auth_fail",
+            transport: "grpc",
+            ip: Some(canonical_ip(addr.ip())),
+            user: user.map(|s| s.to_string()),
+            auth: Some(false),
+            reason: reason.map(|s| s.to_string()),
+            tls,
+            bytes: 0,
+        });
+    }
+}
+
+// 记录 grpc 连接的打开、关闭及接收字节数
+pub struct Conn<S> {
+    inner: S,
+    peer: Option<SocketAddr>,
+    tls: bool,
+    bytes: u64,
+}
+
+impl<S: PeerAddr> Conn<S> {
+    pub fn new(inner: S, tls: bool) -> Self {
+        let peer = inner.peer();
+        if enabled() {
+            push(Entry {
+                ts: now(),
+                event: "open",
+                transport: "grpc",
+                ip: peer.map(|o| canonical_ip(o.ip())),
+                user: None,
+                auth: None,
+                reason: None,
+                tls,
+                bytes: 0,
+            });
+        }
+        Self {
+            inner,
+            peer,
+            tls,
+            bytes: 0,
+        }
+    }
+}
+
+impl<S> Drop for Conn<S> {
+    fn drop(&mut self) {
+        if !enabled() {
+            return;
+        }
+        let st = self
+            .peer
+            .and_then(|addr| CONNS.lock().unwrap().remove(&addr))
+            .unwrap_or_default();
+        push(Entry {
+            ts: now(),
+            event: "close",
+            transport: "grpc",
+            ip: self.peer.map(|o| canonical_ip(o.ip())),
+            user: st.user,
+            auth: st.auth,
+            reason: st.reason,
+            tls: self.tls,
+            bytes: self.bytes,
+        });
+    }
+}
+
+impl<S: Connected> Connected for Conn<S> {
+    type ConnectInfo = S::ConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.inner.connect_info()
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Conn<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.bytes += (buf.filled().len() - before) as u64;
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Conn<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(tag: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("stat_audit_{}_{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(dir: &std::path::Path, max_size_mb: u64, ring_size: usize) -> &'static Config {
+        Box::leak(Box::new(Config {
+            enabled: true,
+            file: dir.join("audit.log").to_str().unwrap().to_string(),
+            max_size_mb,
+            max_files: 2,
+            ring_size,
+        }))
+    }
+
+    fn entry(ts: u64) -> Entry {
+        Entry {
+            ts,
+            event: "report",
+            transport: "http",
+            ip: None,
+            user: None,
+            auth: None,
+            reason: None,
+            tls: false,
+            bytes: 0,
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = temp_dir("rotate");
+        // max_size_mb 为 0 时每次写入前均轮转(首次写入除外)
+        let cfg = config(&dir, 0, 10);
+        let mut writer = Writer {
+            cfg,
+            file: None,
+            size: 0,
+        };
+        for line in ["1\n", "2\n", "3\n", "4\n"] {
+            writer.write(line).unwrap();
+        }
+        let read = |suffix: &str| fs::read_to_string(format!("{}{}", cfg.file, suffix)).unwrap();
+        assert_eq!(read(""), "4\n");
+        assert_eq!(read(".1"), "3\n");
+        assert_eq!(read(".2"), "2\n");
+        // 超过 max_files 的最早文件被覆盖
+        assert!(fs::metadata(format!("{}.3", cfg.file)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn query_ring() {
+        let dir = temp_dir("query");
+        let cfg = config(&dir, 10, 3);
+        let audit = Audit::new(cfg).unwrap();
+        for ts in 1..=5 {
+            audit.push(entry(ts));
+        }
+        let ts = |list: Vec<Entry>| list.iter().map(|o| o.ts).collect::<Vec<_>>();
+        // 只保留最近 ring_size 条
+        assert_eq!(ts(audit.query(0, 10)), [3, 4, 5]);
+        assert_eq!(ts(audit.query(4, 10)), [4, 5]);
+        assert_eq!(ts(audit.query(0, 2)), [4, 5]);
+        assert!(audit.query(6, 10).is_empty());
+
+        // 文件由写入线程异步写入，包含全部记录
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while fs::read_to_string(&cfg.file).unwrap().lines().count() < 5 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "audit log not written"
+            );
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,432 @@
+#![deny(warnings)]
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::listener::canonical_ip;
+use crate::storage;
+
+// 持久化到 storage kv
+const BANS_KEY: &str = "bans";
+
+static CONFIG: OnceCell<&'static Config> = OnceCell::new();
+static TABLE: Lazy<Mutex<Table>> = Lazy::new(Default::default);
+
+fn default_max_failures() -> u32 {
+    5
+}
+fn default_window_secs() -> u64 {
+    300
+}
+fn default_ban_secs() -> u64 {
+    3600
+}
+fn default_max_entries() -> usize {
+    10000
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default = "Default::default")]
+    pub enabled: bool,
+    // window_secs 内认证失败达到 max_failures 次后封禁 ban_secs
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    #[serde(default = "default_ban_secs")]
+    pub ban_secs: u64,
+    // 记录的 ip 上限，超出淘汰最久未活动的
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    // 封禁列表写入 [storage]，重启后恢复
+    #[serde(default = "Default::default")]
+    pub persist: bool,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_failures: default_max_failures(),
+            window_secs: default_window_secs(),
+            ban_secs: default_ban_secs(),
+            max_entries: default_max_entries(),
+            persist: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub ip: IpAddr,
+    pub since: u64,
+    pub until: u64,
+    pub failures: u32,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    // 当前窗口起始时间及失败次数
+    window_start: u64,
+    failures: u32,
+    ban: Option<Ban>,
+    tick: u64,
+}
+
+// LRU: entries 按 tick 排序索引在 order 中
+#[derive(Default)]
+struct Table {
+    entries: HashMap<IpAddr, Entry>,
+    order: BTreeMap<u64, IpAddr>,
+    tick: u64,
+    // 最早到期的封禁时间，到达后查询时清理全部过期封禁
+    next_expiry: Option<u64>,
+}
+
+impl Table {
+    fn touch(&mut self, ip: IpAddr) -> &mut Entry {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.entry(ip).or_default();
+        if entry.tick > 0 {
+            self.order.remove(&entry.tick);
+        }
+        entry.tick = tick;
+        self.order.insert(tick, ip);
+        self.entries.get_mut(&ip).unwrap()
+    }
+
+    fn remove(&mut self, ip: &IpAddr) -> Option<Entry> {
+        let entry = self.entries.remove(ip)?;
+        self.order.remove(&entry.tick);
+        Some(entry)
+    }
+
+    fn evict(&mut self, max_entries: usize) {
+        while self.entries.len() > max_entries {
+            let ip = match self.order.iter().next() {
+                Some((_, ip)) => *ip,
+                None => break,
+            };
+            if let Some(Entry { ban: Some(ban), .. }) = self.remove(&ip) {
+                info!(ip = ip.to_string(); "unban {} (evicted)", ban.ip);
+            }
+        }
+    }
+
+    fn ban(&mut self, ban: Ban) {
+        self.next_expiry = Some(self.next_expiry.map_or(ban.until, |o| o.min(ban.until)));
+        let entry = self.touch(ban.ip);
+        entry.failures = ban.failures;
+        entry.ban = Some(ban);
+    }
+
+    // 移除已到期的封禁，返回是否有变化
+    fn prune(&mut self, now: u64) -> bool {
+        if !matches!(self.next_expiry, Some(t) if t <= now) {
+            return false;
+        }
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, o)| matches!(&o.ban, Some(ban) if ban.until <= now))
+            .map(|(ip, _)| *ip)
+            .collect::<Vec<_>>();
+        for ip in expired.iter() {
+            self.remove(ip);
+            info!(ip = ip.to_string(); "unban {} (expired)", ip);
+        }
+        self.next_expiry = self
+            .entries
+            .values()
+            .filter_map(|o| o.ban.as_ref().map(|ban| ban.until))
+            .min();
+        !expired.is_empty()
+    }
+
+    fn load(&mut self, saved: Vec<Ban>, now: u64, max_entries: usize) {
+        for ban in saved.into_iter().filter(|o| o.until > now) {
+            self.ban(ban);
+        }
+        self.evict(max_entries);
+    }
+
+    // 返回 (是否封禁, 封禁列表是否有变化)
+    fn is_banned(&mut self, ip: IpAddr, now: u64) -> (bool, bool) {
+        let changed = self.prune(now);
+        let banned = matches!(self.entries.get(&ip), Some(Entry { ban: Some(_), .. }));
+        (banned, changed)
+    }
+
+    // 新增封禁时返回 true
+    fn failure(&mut self, cfg: &Config, ip: IpAddr, now: u64) -> bool {
+        let entry = self.touch(ip);
+        if entry.ban.is_some() {
+            return false;
+        }
+        if now >= entry.window_start + cfg.window_secs {
+            entry.window_start = now;
+            entry.failures = 0;
+        }
+        entry.failures += 1;
+        let failures = entry.failures;
+        let banned = failures >= cfg.max_failures;
+        if banned {
+            self.ban(Ban {
+                ip,
+                since: now,
+                until: now + cfg.ban_secs,
+                failures,
+            });
+            warn!(ip = ip.to_string(); "ban {} for {}s after {} auth failures", ip, cfg.ban_secs, failures);
+        }
+        self.evict(cfg.max_entries);
+        banned
+    }
+
+    fn success(&mut self, ip: IpAddr) {
+        if matches!(self.entries.get(&ip), Some(o) if o.ban.is_none()) {
+            self.remove(&ip);
+        }
+    }
+
+    fn bans(&self) -> Vec<Ban> {
+        let mut list = self
+            .entries
+            .values()
+            .filter_map(|o| o.ban.clone())
+            .collect::<Vec<_>>();
+        list.sort_by_key(|o| o.since);
+        list
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn config() -> Option<&'static Config> {
+    CONFIG.get().copied().filter(|o| o.enabled)
+}
+
+// 需在 storage::init 之后调用
+pub fn init(cfg: &'static Config) {
+    let _ = CONFIG.set(cfg);
+    if !cfg.enabled || !cfg.persist {
+        return;
+    }
+    let saved = storage::get(BANS_KEY)
+        .and_then(|s| serde_json::from_str::<Vec<Ban>>(&s).ok())
+        .unwrap_or_default();
+    let mut table = TABLE.lock().unwrap();
+    table.load(saved, now(), cfg.max_entries);
+    if !table.entries.is_empty() {
+        info!("load {} bans", table.entries.len());
+    }
+}
+
+fn persist(list: Option<Vec<Ban>>) {
+    if let (Some(list), Some(cfg)) = (list, config()) {
+        if cfg.persist {
+            storage::set(BANS_KEY, &serde_json::to_string(&list).unwrap());
+        }
+    }
+}
+
+// 封禁期间直接断开，不逐条记录日志；到期后自动解除
+pub fn is_banned(ip: IpAddr) -> bool {
+    if config().is_none() {
+        return false;
+    }
+    let mut table = TABLE.lock().unwrap();
+    let (banned, changed) = table.is_banned(canonical_ip(ip), now());
+    let list = changed.then(|| table.bans());
+    drop(table);
+    persist(list);
+    banned
+}
+
+// 认证失败
+pub fn failure(ip: IpAddr) {
+    let cfg = match config() {
+        Some(cfg) => cfg,
+        None => return,
+    };
+    let mut table = TABLE.lock().unwrap();
+    let list = table
+        .failure(cfg, canonical_ip(ip), now())
+        .then(|| table.bans());
+    drop(table);
+    persist(list);
+}
+
+// 认证成功，清零失败计数
+pub fn success(ip: IpAddr) {
+    if config().is_none() {
+        return;
+    }
+    TABLE.lock().unwrap().success(canonical_ip(ip));
+}
+
+pub fn list() -> Vec<Ban> {
+    let now = now();
+    let table = TABLE.lock().unwrap();
+    table.bans().into_iter().filter(|o| o.until > now).collect()
+}
+
+// ip 为空时解除全部，返回解除的数量
+pub fn clear(ip: Option<IpAddr>) -> usize {
+    let mut table = TABLE.lock().unwrap();
+    let ips = match ip {
+        Some(ip) => vec![canonical_ip(ip)],
+        None => table
+            .entries
+            .iter()
+            .filter(|(_, o)| o.ban.is_some())
+            .map(|(ip, _)| *ip)
+            .collect(),
+    };
+    let mut n = 0;
+    for ip in ips {
+        if let Some(Entry { ban: Some(_), .. }) = table.remove(&ip) {
+            info!(ip = ip.to_string(); "unban {} (admin)", ip);
+            n += 1;
+        }
+    }
+    let list = table.bans();
+    drop(table);
+    if n > 0 {
+        persist(Some(list));
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_entries: usize) -> Config {
+        Config {
+            enabled: true,
+            max_failures: 3,
+            window_secs: 60,
+            ban_secs: 600,
+            max_entries,
+            persist: true,
+        }
+    }
+
+    fn ip(n: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, n])
+    }
+
+    fn banned(table: &mut Table, ip: IpAddr, now: u64) -> bool {
+        table.is_banned(ip, now).0
+    }
+
+    #[test]
+    fn ban_after_max_failures() {
+        let cfg = config(100);
+        let mut table = Table::default();
+        assert!(!table.failure(&cfg, ip(1), 1000));
+        assert!(!table.failure(&cfg, ip(1), 1001));
+        assert!(!banned(&mut table, ip(1), 1001));
+        assert!(table.failure(&cfg, ip(1), 1002));
+        assert!(banned(&mut table, ip(1), 1002));
+        // 封禁期间不再计数
+        assert!(!table.failure(&cfg, ip(1), 1003));
+        assert_eq!(table.bans().len(), 1);
+        assert_eq!(table.bans()[0].until, 1602);
+        assert!(!banned(&mut table, ip(2), 1002));
+    }
+
+    #[test]
+    fn failures_reset_after_window() {
+        let cfg = config(100);
+        let mut table = Table::default();
+        table.failure(&cfg, ip(1), 1000);
+        table.failure(&cfg, ip(1), 1059);
+        // 窗口过期，重新计数
+        assert!(!table.failure(&cfg, ip(1), 1060));
+        assert!(!table.failure(&cfg, ip(1), 1061));
+        assert!(table.failure(&cfg, ip(1), 1062));
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let cfg = config(100);
+        let mut table = Table::default();
+        table.failure(&cfg, ip(1), 1000);
+        table.failure(&cfg, ip(1), 1001);
+        table.success(ip(1));
+        assert!(!table.failure(&cfg, ip(1), 1002));
+        assert!(!table.failure(&cfg, ip(1), 1003));
+        assert!(table.failure(&cfg, ip(1), 1004));
+        // 已封禁的 ip 认证成功也不解除
+        table.success(ip(1));
+        assert!(banned(&mut table, ip(1), 1005));
+    }
+
+    #[test]
+    fn evict_least_recent() {
+        let cfg = config(2);
+        let mut table = Table::default();
+        for _ in 0..3 {
+            table.failure(&cfg, ip(1), 1000);
+        }
+        table.failure(&cfg, ip(2), 1001);
+        // ip(1) 重新活动，淘汰最久未活动的 ip(2)
+        table.failure(&cfg, ip(1), 1002);
+        table.failure(&cfg, ip(3), 1003);
+        assert_eq!(table.entries.len(), 2);
+        assert_eq!(table.order.len(), 2);
+        assert!(table.entries.contains_key(&ip(1)));
+        assert!(!table.entries.contains_key(&ip(2)));
+        assert!(banned(&mut table, ip(1), 1003));
+    }
+
+    #[test]
+    fn prune_expired_on_lookup() {
+        let cfg = config(100);
+        let mut table = Table::default();
+        for n in 1..=2 {
+            for _ in 0..3 {
+                table.failure(&cfg, ip(n), 1000 + n as u64);
+            }
+        }
+        assert_eq!(table.next_expiry, Some(1601));
+        assert_eq!(table.is_banned(ip(9), 1600), (false, false));
+        // 查询任意 ip 都会清理全部过期封禁
+        assert_eq!(table.is_banned(ip(9), 1601), (false, true));
+        assert_eq!(table.bans().len(), 1);
+        assert_eq!(table.next_expiry, Some(1602));
+        assert_eq!(table.is_banned(ip(2), 1602), (false, true));
+        assert!(table.entries.is_empty());
+        assert_eq!(table.next_expiry, None);
+    }
+
+    #[test]
+    fn persist_roundtrip() {
+        let cfg = config(100);
+        let mut table = Table::default();
+        for _ in 0..3 {
+            table.failure(&cfg, ip(1), 1000);
+            table.failure(&cfg, ip(2), 1500);
+        }
+        let saved = serde_json::to_string(&table.bans()).unwrap();
+
+        // 重启后恢复未到期的封禁
+        let mut table = Table::default();
+        table.load(serde_json::from_str(&saved).unwrap(), 1700, cfg.max_entries);
+        assert!(!banned(&mut table, ip(1), 1700));
+        assert!(banned(&mut table, ip(2), 1700));
+        assert_eq!(table.bans()[0].failures, 3);
+        assert!(!banned(&mut table, ip(2), 2100));
+    }
+}
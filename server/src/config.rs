@@ -1,21 +1,295 @@
 #![deny(warnings)]
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::env;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
 use crate::notifier;
+use crate::payload::{HostStat, Level, StatusLevel, MAX_LABELS};
 
 fn default_as_true() -> bool {
     true
 }
-fn default_grpc_addr() -> String {
-    "0.0.0.0:9394".to_string()
+// bcrypt 校验较慢，缓存校验通过的凭据，避免每次轮询 stats.json 都计算
+// 缓存键为凭据以每进程随机盐计算的 HMAC-SHA256，内存中不保留明文密码
+static WEB_AUTH_CACHE: Lazy<Mutex<HashSet<String>>> = Lazy::new(Default::default);
+static WEB_AUTH_SALT: Lazy<String> = Lazy::new(|| Uuid::new_v4().to_string());
+const WEB_AUTH_CACHE_SIZE: usize = 64;
+
+fn ct_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+fn default_grpc_addr() -> Vec<String> {
+    vec!["0.0.0.0:9394".to_string()]
+}
+fn default_http_addr() -> Vec<String> {
+    vec!["0.0.0.0:8080".to_string()]
+}
+// "addr" 或 ["addr1", "addr2"]
+fn string_or_seq<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+fn default_http_socket_mode() -> String {
+    "660".to_string()
+}
+fn default_register_group() -> String {
+    "default".to_string()
+}
+fn default_expected_hosts_check_secs() -> u64 {
+    3600
+}
+fn default_precision() -> usize {
+    1
+}
+fn default_push_interval_ms() -> u64 {
+    1000
+}
+fn default_startup_quiet_secs() -> u64 {
+    60
+}
+fn default_shutdown_grace_secs() -> u64 {
+    10
+}
+fn default_identity_conflict_window_secs() -> u64 {
+    60
+}
+fn default_hmac_max_skew_secs() -> u64 {
+    300
+}
+fn default_uptime_retention_days() -> u64 {
+    90
+}
+fn default_log_format() -> String {
+    crate::logger::LOG_FORMAT_TEXT.to_string()
+}
+fn default_report_rate_limit() -> f64 {
+    4.0
+}
+fn default_report_max_size() -> usize {
+    64 * 1024
+}
+fn default_report_max_speed() -> u64 {
+    // 100Gbps
+    12_500_000_000
+}
+
+// stats.json 主机排序方式，相同时按 name 排序
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    // 配置文件顺序
+    Pos,
+    // weight 大的在前
+    Weight,
+    Name,
+    GroupThenName,
+    OnlineFirst,
+}
+// 上报数值超出合理范围(cpu > 100%、used > total、计数回绕等)时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidValueMode {
+    // 丢弃整条上报
+    Reject,
+    // 修正到合理范围后接收
+    Clamp,
+}
+impl Default for InvalidValueMode {
+    fn default() -> Self {
+        InvalidValueMode::Reject
+    }
+}
+
+// 服务端未运行期间在可用率中的处理方式
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerDownMode {
+    // 不计入统计时长
+    Exclude,
+    // 计入统计时长，但既不算在线也不算离线
+    Unknown,
+}
+impl Default for ServerDownMode {
+    fn default() -> Self {
+        ServerDownMode::Exclude
+    }
+}
+
+impl Default for SortBy {
+    fn default() -> Self {
+        SortBy::Pos
+    }
+}
+
+// 指标百分比阈值，超过 warn/crit 时 status_level 为 warn/crit
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Threshold {
+    pub warn: f64,
+    pub crit: f64,
+}
+impl Threshold {
+    fn value_level(&self, v: f64) -> Level {
+        if v >= self.crit {
+            Level::Crit
+        } else if v >= self.warn {
+            Level::Warn
+        } else {
+            Level::Ok
+        }
+    }
+}
+
+fn default_cpu_threshold() -> Threshold {
+    Threshold {
+        warn: 70.0,
+        crit: 90.0,
+    }
 }
-fn default_http_addr() -> String {
-    "0.0.0.0:8080".to_string()
+fn default_memory_threshold() -> Threshold {
+    Threshold {
+        warn: 80.0,
+        crit: 95.0,
+    }
+}
+fn default_swap_threshold() -> Threshold {
+    Threshold {
+        warn: 50.0,
+        crit: 80.0,
+    }
+}
+fn default_hdd_threshold() -> Threshold {
+    Threshold {
+        warn: 85.0,
+        crit: 95.0,
+    }
+}
+fn default_oom_threshold() -> Threshold {
+    Threshold {
+        warn: 1.0,
+        crit: 3.0,
+    }
+}
+fn default_clock_threshold() -> Threshold {
+    Threshold {
+        warn: 500.0,
+        crit: 2000.0,
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Thresholds {
+    #[serde(default = "default_cpu_threshold")]
+    pub cpu: Threshold,
+    #[serde(default = "default_memory_threshold")]
+    pub memory: Threshold,
+    #[serde(default = "default_swap_threshold")]
+    pub swap: Threshold,
+    #[serde(default = "default_hdd_threshold")]
+    pub hdd: Threshold,
+    // 单个上报间隔内的 OOM kill 次数，非百分比
+    #[serde(default = "default_oom_threshold")]
+    pub oom: Threshold,
+    // 时钟偏差绝对值(毫秒)，非百分比
+    #[serde(default = "default_clock_threshold")]
+    pub clock: Threshold,
+}
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            cpu: default_cpu_threshold(),
+            memory: default_memory_threshold(),
+            swap: default_swap_threshold(),
+            hdd: default_hdd_threshold(),
+            oom: default_oom_threshold(),
+            clock: default_clock_threshold(),
+        }
+    }
+}
+impl Thresholds {
+    pub fn status_level(&self, stat: &HostStat) -> StatusLevel {
+        StatusLevel {
+            cpu: self.cpu.value_level(stat.cpu as f64),
+            memory: self.memory.value_level(stat.memory_pct()),
+            swap: self.swap.value_level(stat.swap_pct()),
+            hdd: self.hdd.value_level(stat.hdd_pct()),
+            oom: stat
+                .oom_kills
+                .map_or(Level::Ok, |n| self.oom.value_level(n as f64)),
+            clock: stat
+                .clock_offset_ms
+                .map_or(Level::Ok, |ms| self.clock.value_level(ms.abs())),
+        }
+    }
+}
+
+// 模板数值格式化 num/pct/bytes_human/bps
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NumberFormat {
+    #[serde(default = "default_precision")]
+    pub precision: usize,
+    // eg: de-DE 使用逗号作为小数点
+    #[serde(default = "Default::default")]
+    pub locale: String,
+}
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            precision: default_precision(),
+            locale: "".to_string(),
+        }
+    }
+}
+
+// 按分组/标签筛选主机的独立面板，/view/{slug}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct View {
+    pub slug: String,
+    // 主机 group 属于其一，空则不限
+    #[serde(default = "Default::default")]
+    pub groups: Vec<String>,
+    // label selectors, eg: ["dc=fra1"]，需全部匹配
+    #[serde(default = "Default::default")]
+    pub labels: Vec<String>,
+    // 设置后该面板需 basic auth，admin 也可访问
+    #[serde(default = "Default::default")]
+    pub user: String,
+    #[serde(default = "Default::default")]
+    pub pass: String,
+}
+
+impl View {
+    pub fn matches(&self, stat: &HostStat) -> bool {
+        (self.groups.is_empty() || self.groups.iter().any(|g| g.eq(&stat.group)))
+            && self.labels.iter().all(|s| stat.match_label(s))
+    }
+    pub fn auth_enabled(&self) -> bool {
+        !self.user.is_empty() && !self.pass.is_empty()
+    }
+    pub fn auth(&self, user: &str, pass: &str) -> bool {
+        ct_eq(user, &self.user) & ct_eq(pass, &self.pass)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -24,16 +298,46 @@ pub struct Host {
     pub password: String,
     #[serde(default = "Default::default")]
     pub alias: String,
+    #[serde(default = "Default::default")]
+    pub notes: String,
     pub location: String,
     pub region: String,
     #[serde(rename = "type")]
     pub host_type: String,
+    #[serde(default = "Default::default")]
+    pub group: String,
     #[serde(default = "u32::default")]
     pub monthstart: u32,
     #[serde(default = "default_as_true")]
     pub notify: bool,
     #[serde(default = "bool::default")]
     pub disabled: bool,
+    #[serde(default = "Default::default")]
+    pub labels: BTreeMap<String, String>,
+    pub hide_offline_after_days: Option<u64>,
+    #[serde(default = "Default::default")]
+    pub weight: i64,
+    // 默认为全局 offline_threshold
+    pub offline_timeout_secs: Option<u64>,
+    // 下发给客户端的上报间隔，默认为客户端 1s
+    pub report_interval_secs: Option<u64>,
+    // 国家代码，不设置时根据上报来源 IP 查询 geoip_db
+    pub country_code: Option<String>,
+    // 上报签名密钥，设置后拒绝未签名或签名错误的上报
+    pub hmac_secret: Option<String>,
+    // 只接受签名认证模式(不发送密码)的上报，需设置 hmac_secret
+    #[serde(default = "Default::default")]
+    pub signed_only: bool,
+    // 月可用率目标(%)，如 99.9，未设置时按 group_sla_targets
+    pub sla_target: Option<f64>,
+    // 网卡/挂载点展示名，如 {"enp3s0" = "WAN"}，客户端仍上报原始名称
+    #[serde(default = "Default::default")]
+    pub iface_aliases: BTreeMap<String, String>,
+    #[serde(default = "Default::default")]
+    pub mount_aliases: BTreeMap<String, String>,
+    // 通过握手下发给客户端的采集设置，不设置时沿用客户端参数
+    pub report_ifaces: Option<bool>,
+    pub disk_warn_pct: Option<f64>,
 
     #[serde(skip_deserializing)]
     pub last_network_in: u64,
@@ -47,20 +351,167 @@ pub struct Host {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
-    #[serde(default = "default_http_addr")]
-    pub http_addr: String,
-    #[serde(default = "default_grpc_addr")]
-    pub grpc_addr: String,
+    // host:port 或 unix:/path/to.sock
+    #[serde(
+        default = "default_http_addr",
+        alias = "http_listen",
+        deserialize_with = "string_or_seq"
+    )]
+    pub http_addr: Vec<String>,
+    #[serde(default = "default_http_socket_mode")]
+    pub http_socket_mode: String,
+    #[serde(default = "Default::default")]
+    pub http_socket_owner: String,
+    // /ws 推送时同一主机的最小间隔
+    #[serde(default = "default_push_interval_ms")]
+    pub push_interval_ms: u64,
+    // 反向代理子路径，如 "/status"，默认 "/"
+    #[serde(default = "Default::default")]
+    pub base_path: String,
+    // 不带 base_path 的请求重定向到 base_path 下，默认 404
+    #[serde(default = "Default::default")]
+    pub base_path_redirect: bool,
+    #[serde(
+        default = "default_grpc_addr",
+        alias = "grpc_listen",
+        deserialize_with = "string_or_seq"
+    )]
+    pub grpc_addr: Vec<String>,
+    // pem 证书路径，同时配置时 http 和 grpc 启用 tls
+    #[serde(default = "Default::default")]
+    pub tls_cert: String,
+    #[serde(default = "Default::default")]
+    pub tls_key: String,
+    // 上报限速(次/秒)，按用户名计算，0 为不限速
+    #[serde(default = "default_report_rate_limit")]
+    pub report_rate_limit: f64,
+    // 上报数据最大字节数
+    #[serde(default = "default_report_max_size")]
+    pub report_max_size: usize,
+    // 网速合理上限(字节/秒)，超出视为异常数据
+    #[serde(default = "default_report_max_speed")]
+    pub report_max_speed: u64,
+    #[serde(default = "Default::default")]
+    pub report_invalid_values: InvalidValueMode,
+    // 上报 ip 白名单/黑名单(CIDR)，先匹配黑名单，白名单为空时允许所有
+    #[serde(default = "Default::default")]
+    pub report_allow_ips: Vec<String>,
+    #[serde(default = "Default::default")]
+    pub report_deny_ips: Vec<String>,
+    #[serde(skip)]
+    pub report_allow_nets: Vec<IpNet>,
+    #[serde(skip)]
+    pub report_deny_nets: Vec<IpNet>,
     #[serde(default = "Default::default")]
     pub notify_interval: u64,
+    // 在线主机 online/degraded 切换时发送 degraded/recovered 通知
+    #[serde(default = "Default::default")]
+    pub notify_degraded: bool,
+    // 主机上报的 kernel_version/os_release 与上次记录不同时发送 os_changed 通知
+    #[serde(default = "Default::default")]
+    pub notify_os_change: bool,
+    // stats.json summary 是否包含长时间离线隐藏的主机
+    #[serde(default = "Default::default")]
+    pub summary_include_hidden: bool,
+    // summary 中按 os_name/os_arch 分组汇总
+    #[serde(default = "Default::default")]
+    pub summary_by_os: bool,
+    // 客户端开启 vnstat 时月流量优先使用 vnstat 数据，否则使用服务端累计值
+    #[serde(default = "default_as_true")]
+    pub traffic_prefer_vnstat: bool,
+    // 启动后不发送上下线通知的时间，避免重启时误报
+    #[serde(default = "default_startup_quiet_secs")]
+    pub startup_quiet_secs: u64,
+    // 退出时等待未完成通知的最长时间
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+    // 同一用户名在窗口期内从不同来源交替上报时标记冲突，0 为关闭
+    #[serde(default = "default_identity_conflict_window_secs")]
+    pub identity_conflict_window_secs: u64,
+    // 冲突时拒绝较新出现的来源
+    #[serde(default = "Default::default")]
+    pub identity_conflict_reject: bool,
+    // 签名认证模式允许的时间戳偏差(秒)，nonce 在 2 倍该时长内不可重复使用
+    #[serde(default = "default_hmac_max_skew_secs")]
+    pub hmac_max_skew_secs: u64,
+    // 离线记录保留天数
+    #[serde(default = "default_uptime_retention_days")]
+    pub uptime_retention_days: u64,
+    #[serde(default = "Default::default")]
+    pub uptime_server_down: ServerDownMode,
+    // 日志格式 text/json，日志级别未设置时使用 RUST_LOG
+    #[serde(default = "default_log_format")]
+    pub log_format: String,
+    #[serde(default = "Default::default")]
+    pub log_level: String,
     #[serde(default = "Default::default")]
     pub offline_threshold: u64,
+    #[serde(default = "Default::default")]
+    pub hide_offline_after_days: u64,
+    // 自动注册的主机超过 N 秒未上报时移除，0 为不移除，hosts 中配置的主机始终保留
+    #[serde(default = "Default::default")]
+    pub offline_purge_secs: u64,
+    // 应当存在的主机，启动静默期结束后及每 expected_hosts_check_secs 检查，从未上报过的发送 missing 通知
+    #[serde(default = "Default::default")]
+    pub expected_hosts: Vec<String>,
+    #[serde(default = "default_expected_hosts_check_secs")]
+    pub expected_hosts_check_secs: u64,
+    // group => 月可用率目标(%)，主机未设置 sla_target 时使用
+    #[serde(default = "Default::default")]
+    pub group_sla_targets: HashMap<String, f64>,
+    #[serde(default = "Default::default")]
+    pub sort_by: SortBy,
     // admin user&pass
     pub admin_user: Option<String>,
     pub admin_pass: Option<String>,
+    // 管理 API 的 bearer token，供自动化脚本使用
+    #[serde(default = "Default::default")]
+    pub admin_token: String,
+    // 面板及 stats.json 访问认证，不设置则公开访问, web_pass 可为 bcrypt hash
+    #[serde(default = "Default::default")]
+    pub web_user: String,
+    #[serde(default = "Default::default")]
+    pub web_pass: String,
+
+    // 未配置主机首次上报时自动注册
+    #[serde(default = "Default::default")]
+    pub auto_register: bool,
+    #[serde(default = "Default::default")]
+    pub register_password: String,
+    #[serde(default = "default_register_group")]
+    pub register_group: String,
 
+    #[serde(default = "Default::default")]
+    pub number_format: NumberFormat,
+    #[serde(default = "Default::default")]
+    pub event_labels: notifier::templates::EventLabels,
+    #[serde(default = "Default::default")]
+    pub thresholds: Thresholds,
+    // MaxMind GeoLite2-Country mmdb 路径，为空不启用
+    #[serde(default = "Default::default")]
+    pub geoip_db: String,
+    // 模板 datetime 过滤器使用的时区，如 "Asia/Shanghai"，默认 UTC
+    #[serde(default = "Default::default")]
+    pub timezone: String,
+    // 通知渠道使用的 http(s) 代理，为空时使用 HTTP(S)_PROXY 环境变量
+    #[serde(default = "Default::default")]
+    pub notify_proxy: String,
+    #[serde(default = "Default::default")]
+    pub storage: crate::storage::Config,
+    #[serde(default = "Default::default")]
+    pub audit: crate::audit::Config,
+    #[serde(default = "Default::default")]
+    pub bans: crate::bans::Config,
     #[serde(default = "Default::default")]
     pub tgbot: notifier::tgbot::Config,
+    #[serde(default = "Default::default")]
+    pub webhook: notifier::webhook::Config,
+    #[serde(default = "Default::default")]
+    pub kafka: notifier::kafka::Config,
+    #[serde(default = "Default::default")]
+    pub reports: Vec<crate::reports::Report>,
+    #[serde(default = "Default::default")]
+    pub views: Vec<View>,
     pub hosts: Vec<Host>,
 
     #[serde(skip_deserializing)]
@@ -68,20 +519,149 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn auth(&self, user: &str, pass: &str) -> bool {
-        if let Some(o) = self.hosts_map.get(user) {
-            return pass.eq(o.password.as_str());
+    // 失败时返回原因，记录到审计日志
+    pub fn auth(&self, user: &str, pass: &str) -> Result<(), &'static str> {
+        let expected = match self.hosts_map.get(user) {
+            Some(o) => o.password.as_str(),
+            None if self.auto_register => self.register_password.as_str(),
+            None => {
+                warn!(host = user; "reject unknown host `{}`", user);
+                return Err("unknown user");
+            }
+        };
+        if pass.eq(expected) {
+            Ok(())
+        } else {
+            Err("bad password")
         }
-        false
+    }
+    // 0 为客户端默认间隔
+    pub fn report_interval_ms(&self, name: &str) -> u64 {
+        self.hosts_map
+            .get(name)
+            .and_then(|o| o.report_interval_secs)
+            .map_or(0, |secs| secs * 1000)
+    }
+    pub fn sla_target(&self, name: &str, group: &str) -> Option<f64> {
+        self.hosts_map
+            .get(name)
+            .and_then(|o| o.sla_target)
+            .or_else(|| self.group_sla_targets.get(group).copied())
+    }
+    pub fn hmac_secret(&self, name: &str) -> Option<&str> {
+        self.hosts_map
+            .get(name)
+            .and_then(|o| o.hmac_secret.as_deref())
+            .filter(|s| !s.is_empty())
+    }
+    pub fn signed_only(&self, name: &str) -> bool {
+        self.hosts_map.get(name).map_or(false, |o| o.signed_only)
+    }
+    pub fn register_host(&self, name: &str, pos: usize) -> Host {
+        Host {
+            name: name.to_string(),
+            password: self.register_password.to_string(),
+            alias: name.to_string(),
+            notes: "".to_string(),
+            location: "".to_string(),
+            region: "".to_string(),
+            host_type: "".to_string(),
+            group: self.register_group.to_string(),
+            monthstart: 1,
+            notify: true,
+            disabled: false,
+            labels: BTreeMap::new(),
+            hide_offline_after_days: None,
+            weight: 0,
+            offline_timeout_secs: None,
+            report_interval_secs: None,
+            country_code: None,
+            hmac_secret: None,
+            signed_only: false,
+            sla_target: None,
+            iface_aliases: BTreeMap::new(),
+            mount_aliases: BTreeMap::new(),
+            report_ifaces: None,
+            disk_warn_pct: None,
+            last_network_in: 0,
+            last_network_out: 0,
+            pos,
+        }
+    }
+    pub fn validate_listen(&self) -> Result<()> {
+        if self.grpc_addr.is_empty() || self.http_addr.is_empty() {
+            anyhow::bail!("grpc_addr and http_addr can't be empty");
+        }
+        for addr in &self.grpc_addr {
+            addr.parse::<SocketAddr>()
+                .with_context(|| format!("invalid grpc_addr `{}`", addr))?;
+        }
+        if self.http_unix_socket().is_some() {
+            if self.http_addr.len() > 1 {
+                anyhow::bail!("unix socket http_addr can't be mixed with others");
+            }
+            return Ok(());
+        }
+        for addr in &self.http_addr {
+            addr.parse::<SocketAddr>()
+                .with_context(|| format!("invalid http_addr `{}`", addr))?;
+        }
+        Ok(())
+    }
+    pub fn http_unix_socket(&self) -> Option<&str> {
+        self.http_addr
+            .iter()
+            .find_map(|addr| addr.strip_prefix("unix:"))
+    }
+    pub fn report_ip_allowed(&self, ip: IpAddr) -> bool {
+        if self.report_deny_nets.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.report_allow_nets.is_empty()
+            || self.report_allow_nets.iter().any(|net| net.contains(&ip))
+    }
+    pub fn tls_enabled(&self) -> bool {
+        !self.tls_cert.is_empty() && !self.tls_key.is_empty()
     }
     pub fn admin_auth(&self, user: &str, pass: &str) -> bool {
         if let (Some(u), Some(p)) = (self.admin_user.as_ref(), self.admin_pass.as_ref()) {
-            return user.eq(u.as_str()) && pass.eq(p.as_str());
+            return ct_eq(user, u) & ct_eq(pass, p);
         }
         false
     }
-    pub fn get_host(&self, name: &str) -> Option<&Host> {
-        self.hosts_map.get(name)
+    pub fn admin_token_auth(&self, token: &str) -> bool {
+        !self.admin_token.is_empty() && ct_eq(token, &self.admin_token)
+    }
+    pub fn web_auth_enabled(&self) -> bool {
+        !self.web_user.is_empty() && !self.web_pass.is_empty()
+    }
+    pub fn web_auth(&self, user: &str, pass: &str) -> bool {
+        if !ct_eq(user, &self.web_user) {
+            return false;
+        }
+        if !self.web_pass.starts_with("$2") {
+            return ct_eq(pass, &self.web_pass);
+        }
+
+        let key = stat_common::sign::sign(&WEB_AUTH_SALT, format!("{}:{}", user, pass).as_bytes());
+        let mut cache = WEB_AUTH_CACHE.lock().unwrap();
+        if cache.contains(&key) {
+            return true;
+        }
+        match bcrypt::verify(pass, &self.web_pass) {
+            Ok(true) => {
+                if cache.len() >= WEB_AUTH_CACHE_SIZE {
+                    cache.clear();
+                }
+                cache.insert(key);
+                true
+            }
+            Ok(false) => false,
+            Err(err) => {
+                error!("invalid web_pass bcrypt hash => {:?}", err);
+                false
+            }
+        }
     }
 }
 
@@ -92,6 +672,18 @@ pub fn test_from_file(cfg: &str) -> Result<Config> {
         .map_err(anyhow::Error::new)
 }
 
+// 支持 CIDR 及单个 ip
+fn parse_nets(list: &[String]) -> Option<Vec<IpNet>> {
+    list.iter()
+        .map(|s| {
+            s.parse::<IpNet>()
+                .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| eprintln!("❗ invalid ip or cidr `{}`", s))
+                .ok()
+        })
+        .collect()
+}
+
 pub fn from_str(content: &str) -> Option<Config> {
     let mut o = toml::from_str::<Config>(content).unwrap();
     o.hosts_map = HashMap::new();
@@ -104,11 +696,22 @@ pub fn from_str(content: &str) -> Option<Config> {
         if host.monthstart < 1 || host.monthstart > 31 {
             host.monthstart = 1;
         }
+        if host.labels.len() > MAX_LABELS {
+            eprintln!("❗ {} labels exceed {}, truncated", host.name, MAX_LABELS);
+            host.labels = host.labels.clone().into_iter().take(MAX_LABELS).collect();
+        }
+        if host.signed_only && host.hmac_secret.as_deref().map_or(true, str::is_empty) {
+            eprintln!("❗ {} signed_only requires hmac_secret", host.name);
+            return None;
+        }
         o.hosts_map.insert(host.name.to_owned(), host.clone());
     }
     if o.notify_interval < 30 {
         o.notify_interval = 30;
     }
+    if o.expected_hosts_check_secs < 30 {
+        o.expected_hosts_check_secs = 30;
+    }
     if o.offline_threshold < 30 {
         o.offline_threshold = 30;
     }
@@ -118,6 +721,37 @@ pub fn from_str(content: &str) -> Option<Config> {
     if o.admin_pass.is_none() || o.admin_pass.as_ref()?.is_empty() {
         o.admin_pass = Some(Uuid::new_v4().to_string());
     }
+    o.report_allow_nets = parse_nets(&o.report_allow_ips)?;
+    o.report_deny_nets = parse_nets(&o.report_deny_ips)?;
+    // "/status/" => "/status", "/" => ""
+    o.base_path = o.base_path.trim_matches('/').to_string();
+    if !o.base_path.is_empty() {
+        o.base_path.insert(0, '/');
+    }
+    if o.auto_register && o.register_password.is_empty() {
+        eprintln!("❗ auto_register requires register_password, disabled");
+        o.auto_register = false;
+    }
+    let mut slugs = HashSet::new();
+    for view in &o.views {
+        if view.slug.is_empty()
+            || !view
+                .slug
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            eprintln!("❗ invalid view slug `{}`", view.slug);
+            return None;
+        }
+        if !slugs.insert(view.slug.as_str()) {
+            eprintln!("❗ duplicate view slug `{}`", view.slug);
+            return None;
+        }
+        if view.groups.is_empty() && view.labels.is_empty() {
+            eprintln!("❗ view `{}` requires groups or labels", view.slug);
+            return None;
+        }
+    }
 
     eprintln!("✨ admin_user: {}", o.admin_user.as_ref()?);
     eprintln!("✨ admin_pass: {}", o.admin_pass.as_ref()?);
@@ -0,0 +1,121 @@
+#![deny(warnings)]
+use anyhow::Result;
+use chrono::Utc;
+use hyper::{header, Body, Response, StatusCode};
+
+use crate::payload::HostStat;
+use crate::G_STATS_MGR;
+
+const CSV_HEADER: &[&str] = &[
+    "name",
+    "alias",
+    "group",
+    "location",
+    "region",
+    "type",
+    "online4",
+    "online6",
+    "uptime",
+    "load_1",
+    "load_5",
+    "load_15",
+    "cpu",
+    "memory_total",
+    "memory_used",
+    "swap_total",
+    "swap_used",
+    "hdd_total",
+    "hdd_used",
+    "network_rx",
+    "network_tx",
+    "network_in",
+    "network_out",
+    "month_in",
+    "month_out",
+    "latest_ts",
+    "age_secs",
+];
+
+// RFC 4180, 含逗号、引号、换行时加引号，引号转义为两个引号
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn csv_row(stat: &HostStat) -> String {
+    [
+        csv_field(&stat.name),
+        csv_field(&stat.alias),
+        csv_field(&stat.group),
+        csv_field(&stat.location),
+        csv_field(&stat.region),
+        csv_field(&stat.host_type),
+        stat.online4.to_string(),
+        stat.online6.to_string(),
+        stat.uptime.to_string(),
+        stat.load_1.to_string(),
+        stat.load_5.to_string(),
+        stat.load_15.to_string(),
+        stat.cpu.to_string(),
+        stat.memory_total.to_string(),
+        stat.memory_used.to_string(),
+        stat.swap_total.to_string(),
+        stat.swap_used.to_string(),
+        stat.hdd_total.to_string(),
+        stat.hdd_used.to_string(),
+        stat.network_rx.to_string(),
+        stat.network_tx.to_string(),
+        stat.network_in.to_string(),
+        stat.network_out.to_string(),
+        stat.month_in.to_string(),
+        stat.month_out.to_string(),
+        stat.latest_ts.to_string(),
+        stat.age_secs.to_string(),
+    ]
+    .join(",")
+}
+
+// GET /api/export?format=csv|json, 当前所有主机的快照
+pub fn snapshot(format: &str) -> Result<Response<Body>> {
+    let resp = G_STATS_MGR.get().unwrap().get_stats();
+    let o = resp.lock().unwrap();
+    let servers = o.servers.iter().filter(|stat| !stat.hidden);
+
+    let (content_type, ext, body) = match format {
+        "csv" => {
+            let mut body = CSV_HEADER.join(",");
+            body.push_str("\r\n");
+            for stat in servers {
+                body.push_str(&csv_row(stat));
+                body.push_str("\r\n");
+            }
+            ("text/csv; charset=utf-8", "csv", body)
+        }
+        "json" => (
+            "application/json",
+            "json",
+            serde_json::to_string(&servers.collect::<Vec<_>>())?,
+        ),
+        _ => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("format must be csv or json".into())?);
+        }
+    };
+
+    let filename = format!(
+        "serverstatus-{}.{}",
+        Utc::now().format("%Y%m%d-%H%M%S"),
+        ext
+    );
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(Body::from(body))?)
+}
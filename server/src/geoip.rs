@@ -0,0 +1,48 @@
+#![deny(warnings)]
+use anyhow::Result;
+use maxminddb::{geoip2, Reader};
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+// 缓存上限，超出后清空重建
+const CACHE_SIZE: usize = 4096;
+
+static READER: OnceCell<Reader<Vec<u8>>> = OnceCell::new();
+static CACHE: Lazy<Mutex<HashMap<IpAddr, String>>> = Lazy::new(Default::default);
+
+// 加载 GeoLite2-Country mmdb，未配置时不启用
+pub fn init(path: &str) -> Result<()> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    let reader = Reader::open_readfile(path)
+        .map_err(|err| anyhow::anyhow!("can't open geoip_db `{}` => {}", path, err))?;
+    let _ = READER.set(reader);
+    Ok(())
+}
+
+// 查询国家代码，未启用或查询失败时返回空
+pub fn country_code(ip: IpAddr) -> String {
+    let reader = match READER.get() {
+        Some(reader) => reader,
+        None => return String::new(),
+    };
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(code) = cache.get(&ip) {
+        return code.to_string();
+    }
+    let code = reader
+        .lookup::<geoip2::Country>(ip)
+        .ok()
+        .and_then(|o| o.country)
+        .and_then(|o| o.iso_code)
+        .unwrap_or_default()
+        .to_string();
+    if cache.len() >= CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(ip, code.to_string());
+    code
+}
@@ -1,26 +1,190 @@
 // #![allow(unused)]
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use hyper::Body;
+use once_cell::sync::OnceCell;
+use prost::Message;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use tonic::{transport::Server, Request, Response, Status};
+use tower::{Layer, Service};
 
 use stat_common::server_status;
 use stat_common::server_status::server_status_server::{ServerStatus, ServerStatusServer};
-use stat_common::server_status::StatRequest;
+use stat_common::server_status::{Heartbeat, Hello, HelloResponse, StatRequest};
+use stat_common::sign::{NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
 
+use crate::audit;
+use crate::bans;
+use crate::handshake::{self, capabilities};
+use crate::ingest::{self, Reject};
+use crate::listener::canonical_ip;
+use crate::stats;
 use crate::G_CONFIG;
 use crate::G_STATS_MGR;
 
 #[derive(Default)]
 pub struct ServerStatusSrv {}
 
+// 签名认证模式的 (timestamp, nonce)，均存在时启用
+fn signed_meta<T>(request: &Request<T>) -> Option<(String, String)> {
+    let get = |key: &str| {
+        request
+            .metadata()
+            .get(key)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    Some((get(TIMESTAMP_HEADER)?, get(NONCE_HEADER)?))
+}
+
+// 请求中 gRPC 消息的原始字节，由 FrameLimit 在解码前记录
+// 签名按原始字节校验，不依赖服务端重新编码(解码时会丢弃新版客户端的未知字段，map 顺序也不固定)
+// 未记录时按空消息校验，签名不通过
+fn raw_message<T>(request: &Request<T>) -> &[u8] {
+    request
+        .extensions()
+        .get::<RawMessage>()
+        .and_then(|raw| raw.0.get())
+        .map_or(&[], |bytes| &bytes[..])
+}
+
+// 签名认证模式不经过 check_auth 校验密码，在此记录认证结果
+fn signed_auth(addr: Option<SocketAddr>, user: &str, check: &Result<(), Reject>) {
+    let reason = check.as_ref().err().map(|r| r.to_string());
+    audit::grpc_auth(
+        addr,
+        G_CONFIG.get().unwrap().tls_enabled(),
+        Some(user),
+        reason.as_deref(),
+    );
+    if let Some(addr) = addr {
+        match check {
+            Ok(_) => bans::success(addr.ip()),
+            Err(_) => bans::failure(addr.ip()),
+        }
+    }
+}
+
+// 心跳及握手等小消息的限速及签名校验
+fn check_message<T>(request: &Request<T>, name: &str) -> Result<(), Reject> {
+    let remote_addr = request.remote_addr();
+    let ip = remote_addr.map(|addr| canonical_ip(addr.ip()));
+    let signature = request
+        .metadata()
+        .get(SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let signed = signed_meta(request);
+    let check = ingest::check_rate(name).and_then(|_| {
+        let check = ingest::check_signature(
+            name,
+            raw_message(request),
+            signature,
+            signed
+                .as_ref()
+                .map(|(ts, nonce)| (ts.as_str(), nonce.as_str())),
+        );
+        if signed.is_some() {
+            signed_auth(remote_addr, name, &check);
+        }
+        check
+    });
+    check.map_err(|reason| {
+        ingest::reject(reason, name, ip);
+        reason
+    })
+}
+
+fn message_status(reason: Reject) -> Status {
+    match reason {
+        Reject::RateLimit => Status::resource_exhausted("rate limited"),
+        _ => Status::unauthenticated(reason.to_string()),
+    }
+}
+
 #[tonic::async_trait]
 impl ServerStatus for ServerStatusSrv {
     async fn report(
         &self,
         request: Request<StatRequest>,
     ) -> Result<Response<server_status::Response>, Status> {
+        if stats::is_shutting_down() {
+            return Err(Status::unavailable("server shutting down"));
+        }
+        let stat = request.get_ref();
+        let remote_addr = request.remote_addr();
+        let ip = remote_addr.map(|addr| canonical_ip(addr.ip()));
+        let signature = request
+            .metadata()
+            .get(SIGNATURE_HEADER)
+            .and_then(|v| v.to_str().ok());
+        let signed = signed_meta(&request);
+        let check = ingest::check_rate(&stat.name)
+            // 解码前已由 FrameLimitLayer 按长度前缀检查，此处兜底
+            .and_then(|_| ingest::check_size(stat.encoded_len()))
+            .and_then(|_| {
+                let check = ingest::check_signature(
+                    &stat.name,
+                    raw_message(&request),
+                    signature,
+                    signed
+                        .as_ref()
+                        .map(|(ts, nonce)| (ts.as_str(), nonce.as_str())),
+                );
+                if signed.is_some() {
+                    signed_auth(remote_addr, &stat.name, &check);
+                }
+                check
+            });
+        if let Err(reason) = check {
+            ingest::reject(reason, &stat.name, ip);
+            return Err(match reason {
+                Reject::RateLimit => Status::resource_exhausted("rate limited"),
+                Reject::BadSignature | Reject::Expired | Reject::Replay => {
+                    Status::unauthenticated(reason.to_string())
+                }
+                _ => Status::invalid_argument(reason.to_string()),
+            });
+        }
+
+        let interval_ms = G_CONFIG.get().unwrap().report_interval_ms(&stat.name);
+        // 增量上报还原为绝对值，基准不一致时要求客户端改为完整上报
+        let mut stat = stat.clone();
+        ingest::mark_absent(&mut stat);
+        if let Err(reason) = ingest::normalize_units(&mut stat) {
+            ingest::reject(reason, &stat.name, ip);
+            return Err(Status::invalid_argument(reason.to_string()));
+        }
+        ingest::track_seq(&stat.name, stat.seq, stat.buffered);
+        let delta_base = match ingest::apply_delta(&mut stat) {
+            Some(id) => id,
+            None => {
+                info!(host = stat.name.as_str(); "delta base mismatch from `{}`, full report required", stat.name);
+                return Ok(Response::new(server_status::Response {
+                    code: 2,
+                    message: "full report required".to_string(),
+                    interval_ms,
+                    capabilities: capabilities(&stat.name),
+                    delta_base: 0,
+                }));
+            }
+        };
+        let stat = &stat;
         if let Some(mgr) = G_STATS_MGR.get() {
-            match serde_json::to_value(request.get_ref()) {
-                Ok(v) => {
-                    let _ = mgr.report(v);
+            match serde_json::to_value(stat) {
+                Ok(mut v) => {
+                    let host_name = stat.sys_info.as_ref().map_or("", |o| o.host_name.as_str());
+                    if let Err(reason) = ingest::validate(&stat.name, &mut v)
+                        .and_then(|_| ingest::check_identity(&stat.name, ip, host_name))
+                    {
+                        ingest::reject(reason, &stat.name, ip);
+                        return Err(match reason {
+                            Reject::Conflict => Status::already_exists(reason.to_string()),
+                            _ => Status::invalid_argument(reason.to_string()),
+                        });
+                    }
+                    let _ = mgr.report(v, ip);
                 }
                 Err(err) => {
                     error!("serde_json::to_value err => {:?}", err);
@@ -31,11 +195,67 @@ impl ServerStatus for ServerStatusSrv {
         Ok(Response::new(server_status::Response {
             code: 0,
             message: "ok".to_string(),
+            interval_ms,
+            capabilities: capabilities(&stat.name),
+            delta_base,
+        }))
+    }
+
+    // 主机尚无数据或已离线时返回 code 1，客户端随后改为完整上报
+    async fn report_heartbeat(
+        &self,
+        request: Request<Heartbeat>,
+    ) -> Result<Response<server_status::Response>, Status> {
+        if stats::is_shutting_down() {
+            return Err(Status::unavailable("server shutting down"));
+        }
+        let hb = request.get_ref();
+        check_message(&request, &hb.name).map_err(message_status)?;
+        let ip = request.remote_addr().map(|addr| canonical_ip(addr.ip()));
+        if let Err(reason) = ingest::check_heartbeat_identity(&hb.name, ip) {
+            ingest::reject(reason, &hb.name, ip);
+            return Err(Status::already_exists(reason.to_string()));
+        }
+        ingest::track_seq(&hb.name, hb.seq, false);
+
+        let ok = G_STATS_MGR
+            .get()
+            .map_or(false, |mgr| mgr.heartbeat(&hb.name));
+        Ok(Response::new(server_status::Response {
+            code: if ok { 0 } else { 1 },
+            message: if ok { "ok" } else { "full report required" }.to_string(),
+            interval_ms: G_CONFIG.get().unwrap().report_interval_ms(&hb.name),
+            capabilities: capabilities(&hb.name),
+            delta_base: 0,
         }))
     }
+
+    // 每个连接建立后客户端首先调用，旧客户端不调用
+    async fn handshake(&self, request: Request<Hello>) -> Result<Response<HelloResponse>, Status> {
+        if stats::is_shutting_down() {
+            return Err(Status::unavailable("server shutting down"));
+        }
+        let hello = request.get_ref();
+        check_message(&request, &hello.name).map_err(message_status)?;
+        let conn = request.remote_addr().map(|addr| addr.to_string());
+        Ok(Response::new(handshake::negotiate(hello, conn.as_deref())))
+    }
 }
 
 fn check_auth(req: Request<()>) -> Result<Request<()>, Status> {
+    let cfg = G_CONFIG.get().unwrap();
+    // 已建立的长连接在封禁后也拒绝
+    if req
+        .remote_addr()
+        .map_or(false, |addr| bans::is_banned(addr.ip()))
+    {
+        return Err(Status::permission_denied("banned"));
+    }
+    let tls = cfg.tls_enabled();
+    // 签名认证模式不带密码，由 report/report_heartbeat 校验签名
+    if req.metadata().get("authorization").is_none() && signed_meta(&req).is_some() {
+        return Ok(req);
+    }
     match req.metadata().get("authorization") {
         Some(token) => {
             let tuple = token
@@ -44,29 +264,302 @@ fn check_auth(req: Request<()>) -> Result<Request<()>, Status> {
                 .split("@_@")
                 .collect::<Vec<_>>();
 
+            let mut reason = "missing credentials";
             if tuple.len() == 2 {
-                if let Some(mgr) = G_CONFIG.get() {
-                    if mgr.auth(tuple[0], tuple[1]) {
+                match cfg.auth(tuple[0], tuple[1]) {
+                    Ok(_) => {
+                        audit::grpc_auth(req.remote_addr(), tls, Some(tuple[0]), None);
+                        if let Some(addr) = req.remote_addr() {
+                            bans::success(addr.ip());
+                        }
                         return Ok(req);
                     }
+                    Err(err) => reason = err,
                 }
             }
+            audit::grpc_auth(req.remote_addr(), tls, tuple.first().copied(), Some(reason));
+
+            let ip = req.remote_addr().map(|addr| canonical_ip(addr.ip()));
+            warn!(ip = ip.map(|ip| ip.to_string()); "grpc auth fail from {:?}", ip);
+            if let Some(ip) = ip {
+                bans::failure(ip);
+            }
+            Err(Status::unauthenticated("invalid user && pass"))
+        }
 
+        _ => {
+            audit::grpc_auth(req.remote_addr(), tls, None, Some("missing credentials"));
+            if let Some(addr) = req.remote_addr() {
+                bans::failure(addr.ip());
+            }
             Err(Status::unauthenticated("invalid user && pass"))
         }
+    }
+}
 
-        _ => Err(Status::unauthenticated("invalid user && pass")),
+// 未通过 report_allow/deny 的连接计入审计日志
+fn check_ip(ip: IpAddr) -> bool {
+    if ingest::check_ip(ip) {
+        return true;
     }
+    audit::reject(
+        "grpc",
+        canonical_ip(ip),
+        G_CONFIG.get().unwrap().tls_enabled(),
+    );
+    false
 }
 
-pub async fn serv_grpc(addr: &str) -> anyhow::Result<()> {
-    let sock_addr = addr.parse().unwrap();
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// gRPC 消息的 5 字节前缀: 1 字节压缩标志 + 4 字节大端长度
+const GRPC_HEADER_SIZE: usize = 5;
+
+// 上报均为 unary 调用，只记录第一个消息
+#[derive(Clone, Default)]
+struct RawMessage(Arc<OnceCell<Bytes>>);
+
+// 按请求体中 gRPC 消息的长度前缀检查大小，同时记录消息原始字节
+// tonic 读到前缀后即按声明长度分配缓冲区，超过 report_max_size 时在此之前中断请求
+struct FrameCheck {
+    max: usize,
+    header: Vec<u8>,
+    // 当前消息未读完的字节数
+    body_left: usize,
+    message: Vec<u8>,
+    raw: RawMessage,
+}
+
+impl FrameCheck {
+    fn new(max: usize, raw: RawMessage) -> Self {
+        Self {
+            max,
+            header: Vec::with_capacity(GRPC_HEADER_SIZE),
+            body_left: 0,
+            message: Vec::new(),
+            raw,
+        }
+    }
+
+    fn finish_message(&mut self) {
+        let message = std::mem::take(&mut self.message);
+        let _ = self.raw.0.set(message.into());
+    }
+
+    // 超出时返回消息声明的长度
+    fn check(&mut self, mut chunk: &[u8]) -> Result<(), usize> {
+        while !chunk.is_empty() {
+            if self.body_left > 0 {
+                let n = self.body_left.min(chunk.len());
+                if self.raw.0.get().is_none() {
+                    self.message.extend_from_slice(&chunk[..n]);
+                }
+                self.body_left -= n;
+                chunk.advance(n);
+                if self.body_left == 0 {
+                    self.finish_message();
+                }
+                continue;
+            }
+            let n = (GRPC_HEADER_SIZE - self.header.len()).min(chunk.len());
+            self.header.extend_from_slice(&chunk[..n]);
+            chunk.advance(n);
+            if self.header.len() == GRPC_HEADER_SIZE {
+                let len = (&self.header[1..]).get_u32() as usize;
+                if len > self.max {
+                    return Err(len);
+                }
+                self.body_left = len;
+                self.header.clear();
+                if len == 0 {
+                    self.finish_message();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn limit_frames(
+    body: Body,
+    max: usize,
+    raw: RawMessage,
+) -> impl Stream<Item = Result<Bytes, BoxError>> {
+    let mut frames = FrameCheck::new(max, raw);
+    body.map(move |chunk| {
+        let chunk = chunk?;
+        if let Err(len) = frames.check(&chunk) {
+            debug!("grpc message of {} bytes exceeds report_max_size", len);
+            return Err(Status::resource_exhausted(Reject::TooLarge.to_string()).into());
+        }
+        Ok(chunk)
+    })
+}
+
+#[derive(Clone)]
+struct FrameLimitLayer {
+    max: usize,
+}
+
+impl<S> Layer<S> for FrameLimitLayer {
+    type Service = FrameLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FrameLimit {
+            inner,
+            max: self.max,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct FrameLimit<S> {
+    inner: S,
+    max: usize,
+}
+
+impl<S> Service<hyper::Request<Body>> for FrameLimit<S>
+where
+    S: Service<hyper::Request<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: hyper::Request<Body>) -> Self::Future {
+        let max = self.max;
+        let raw = RawMessage::default();
+        req.extensions_mut().insert(raw.clone());
+        self.inner
+            .call(req.map(|body| Body::wrap_stream(limit_frames(body, max, raw))))
+    }
+}
+
+pub async fn serv_grpc(addrs: &[String], tls: bool) -> anyhow::Result<()> {
     let sss = ServerStatusSrv::default();
-    eprintln!("🚀 listening on grpc://{}", sock_addr);
     let svc = ServerStatusServer::with_interceptor(sss, check_auth);
+    let limit = FrameLimitLayer {
+        max: G_CONFIG.get().unwrap().report_max_size,
+    };
+    if tls {
+        let incoming = crate::tls::incoming(addrs, check_ip)?
+            .map(|r| r.map(|stream| audit::Conn::new(stream, true)));
+        for addr in addrs {
+            eprintln!("🚀 listening on grpcs://{}", addr);
+        }
+        crate::health::set_grpc_ready();
+        return Server::builder()
+            .layer(limit)
+            .add_service(svc)
+            .serve_with_incoming(incoming)
+            .await
+            .map_err(anyhow::Error::new);
+    }
+    let incoming = crate::listener::incoming(addrs, check_ip)?
+        .map(|r| r.map(|stream| audit::Conn::new(stream, false)));
+    for addr in addrs {
+        eprintln!("🚀 listening on grpc://{}", addr);
+    }
+    crate::health::set_grpc_ready();
     Server::builder()
+        .layer(limit)
         .add_service(svc)
-        .serve(sock_addr)
+        .serve_with_incoming(incoming)
         .await
         .map_err(anyhow::Error::new)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: u32) -> Vec<u8> {
+        let mut buf = vec![0];
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.resize(GRPC_HEADER_SIZE + len as usize, 1);
+        buf
+    }
+
+    #[test]
+    fn frames_within_limit() {
+        let mut frames = FrameCheck::new(16, RawMessage::default());
+        let mut body = frame(16);
+        body.extend(frame(0));
+        body.extend(frame(3));
+        assert!(frames.check(&body).is_ok());
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut frames = FrameCheck::new(16, RawMessage::default());
+        assert_eq!(frames.check(&frame(17)[..GRPC_HEADER_SIZE]), Err(17));
+    }
+
+    // 前缀及消息体跨多个 chunk
+    #[test]
+    fn split_chunks() {
+        let mut body = frame(10);
+        body.extend(frame(4096));
+        let mut frames = FrameCheck::new(10, RawMessage::default());
+        let res = body
+            .chunks(3)
+            .map(|chunk| frames.check(chunk))
+            .find(|r| r.is_err());
+        assert!(res.is_some());
+
+        let mut frames = FrameCheck::new(4096, RawMessage::default());
+        assert!(body.chunks(3).all(|chunk| frames.check(chunk).is_ok()));
+    }
+
+    fn message_frame(msg: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0];
+        buf.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+        buf.extend_from_slice(msg);
+        buf
+    }
+
+    // 新版客户端的未知字段解码后丢失，重新编码与签名的字节不同，记录的原始字节不变
+    #[test]
+    fn raw_message_keeps_unknown_fields() {
+        let stat = StatRequest {
+            name: "h1".to_string(),
+            labels: (0..8).map(|i| (i.to_string(), "v".to_string())).collect(),
+            ..Default::default()
+        };
+        let mut msg = stat.encode_to_vec();
+        // field 9999, varint 1
+        prost::encoding::encode_key(9999, prost::encoding::WireType::Varint, &mut msg);
+        prost::encoding::encode_varint(1, &mut msg);
+        let decoded = StatRequest::decode(&msg[..]).unwrap();
+        assert_ne!(decoded.encode_to_vec(), msg);
+
+        let raw = RawMessage::default();
+        let mut frames = FrameCheck::new(4096, raw.clone());
+        let body = message_frame(&msg);
+        assert!(body.chunks(7).all(|chunk| frames.check(chunk).is_ok()));
+        assert_eq!(raw.0.get().map(|b| &b[..]), Some(&msg[..]));
+    }
+
+    #[test]
+    fn raw_message_first_only() {
+        let raw = RawMessage::default();
+        let mut frames = FrameCheck::new(16, raw.clone());
+        let mut body = message_frame(b"");
+        body.extend(message_frame(b"abc"));
+        assert!(frames.check(&body).is_ok());
+        assert_eq!(raw.0.get().map(|b| &b[..]), Some(&b""[..]));
+
+        let raw = RawMessage::default();
+        let mut frames = FrameCheck::new(16, raw.clone());
+        let mut body = message_frame(b"abc");
+        body.extend(message_frame(b"defg"));
+        assert!(frames.check(&body[..4]).is_ok());
+        assert!(raw.0.get().is_none());
+        assert!(frames.check(&body[4..]).is_ok());
+        assert_eq!(raw.0.get().map(|b| &b[..]), Some(&b"abc"[..]));
+    }
+}
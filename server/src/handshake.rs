@@ -0,0 +1,105 @@
+#![deny(warnings)]
+use once_cell::sync::Lazy;
+use stat_common::server_status::{Hello, HelloResponse};
+use stat_common::{CAP_DELTA, CAP_HEARTBEAT, CAP_IFACES, CAP_SIGNED, PROTO_VERSION};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::G_CONFIG;
+
+// 服务端支持的可选功能，未握手的旧客户端从每次上报的响应中获取
+pub const CAPABILITIES: &[&str] = &[CAP_HEARTBEAT, CAP_SIGNED, CAP_DELTA, CAP_IFACES];
+
+// name => 最近一次握手协商的功能
+static NEGOTIATED: Lazy<Mutex<HashMap<String, Vec<String>>>> = Lazy::new(Default::default);
+
+// 上报响应中声明的功能: 握手过的主机只声明协商结果，未握手的旧客户端声明全部支持的功能
+pub fn capabilities(name: &str) -> Vec<String> {
+    if let Some(caps) = NEGOTIATED.lock().unwrap().get(name) {
+        return caps.clone();
+    }
+    CAPABILITIES.iter().map(|c| c.to_string()).collect()
+}
+
+// 协商结果为客户端声明与服务端支持功能的交集，hosts 中 report_ifaces = false 时不接受 ifaces
+pub fn negotiate(hello: &Hello, conn: Option<&str>) -> HelloResponse {
+    let cfg = G_CONFIG.get().unwrap();
+    let host = cfg.hosts_map.get(&hello.name);
+    let report_ifaces = host.and_then(|o| o.report_ifaces);
+    let mut caps = Vec::new();
+    for cap in &hello.capabilities {
+        if !CAPABILITIES.contains(&cap.as_str()) || caps.contains(cap) {
+            continue;
+        }
+        if cap == CAP_IFACES && report_ifaces == Some(false) {
+            continue;
+        }
+        caps.push(cap.to_string());
+    }
+    info!(
+        host = hello.name.as_str();
+        "hello from `{}` v{} (proto {}) via {}, negotiated {:?}",
+        hello.name,
+        hello.version,
+        hello.proto_version,
+        conn.unwrap_or("-"),
+        caps
+    );
+    NEGOTIATED
+        .lock()
+        .unwrap()
+        .insert(hello.name.to_string(), caps.clone());
+    HelloResponse {
+        capabilities: caps,
+        interval_ms: cfg.report_interval_ms(&hello.name),
+        proto_version: PROTO_VERSION,
+        report_ifaces,
+        disk_warn_pct: host.and_then(|o| o.disk_warn_pct),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn hello(name: &str, caps: &[&str]) -> Hello {
+        Hello {
+            name: name.to_string(),
+            capabilities: caps.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn negotiate_intersection() {
+        testing::init_config();
+        // 未握手时声明全部功能
+        assert_eq!(capabilities("h1"), CAPABILITIES);
+
+        // 未知及重复的功能忽略
+        let resp = negotiate(
+            &hello("h1", &[CAP_DELTA, "zstd", CAP_DELTA, CAP_HEARTBEAT]),
+            None,
+        );
+        assert_eq!(resp.capabilities, [CAP_DELTA, CAP_HEARTBEAT]);
+        assert_eq!(resp.proto_version, PROTO_VERSION);
+        assert_eq!((resp.report_ifaces, resp.disk_warn_pct), (None, None));
+        // 之后的上报响应只声明协商结果
+        assert_eq!(capabilities("h1"), [CAP_DELTA, CAP_HEARTBEAT]);
+
+        let resp = negotiate(&hello("h1", &[]), None);
+        assert!(resp.capabilities.is_empty());
+        assert!(capabilities("h1").is_empty());
+    }
+
+    // hosts 中 report_ifaces = false 时不接受 ifaces，并下发采集设置
+    #[test]
+    fn negotiate_host_settings() {
+        testing::init_config();
+        let resp = negotiate(&hello("no_ifaces", &[CAP_IFACES, CAP_SIGNED]), None);
+        assert_eq!(resp.capabilities, [CAP_SIGNED]);
+        assert_eq!(resp.report_ifaces, Some(false));
+        assert_eq!(resp.disk_warn_pct, Some(80.0));
+    }
+}
@@ -0,0 +1,77 @@
+#![deny(warnings)]
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{stats, G_CONFIG, G_STATS_MGR};
+
+// /healthz /readyz 供负载均衡及监控探测，不需要认证，不受 base_path 限制，不返回主机数据
+static HTTP_READY: AtomicBool = AtomicBool::new(false);
+static GRPC_READY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_http_ready() {
+    HTTP_READY.store(true, Ordering::SeqCst);
+}
+
+pub fn set_grpc_ready() {
+    GRPC_READY.store(true, Ordering::SeqCst);
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// 进程已启动即可
+fn healthz() -> Response<Body> {
+    json_response(
+        StatusCode::OK,
+        json!({"status": "ok", "version": env!("CARGO_PKG_VERSION")}),
+    )
+}
+
+// 配置已加载、状态已恢复、上报端口已监听，退出中返回 503 以便摘除流量
+fn readyz() -> Response<Body> {
+    let checks = [
+        ("config", G_CONFIG.get().is_some()),
+        ("state", G_STATS_MGR.get().is_some()),
+        ("http_listener", HTTP_READY.load(Ordering::SeqCst)),
+        ("grpc_listener", GRPC_READY.load(Ordering::SeqCst)),
+        ("not_shutting_down", !stats::is_shutting_down()),
+    ];
+    let failing = checks
+        .iter()
+        .filter(|(_, ok)| !ok)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    if failing.is_empty() {
+        json_response(StatusCode::OK, json!({"status": "ok"}))
+    } else {
+        json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            json!({"status": "unavailable", "failing": failing}),
+        )
+    }
+}
+
+// 根路径及 base_path 下均可访问
+pub fn handle(req: &Request<Body>) -> Option<Response<Body>> {
+    if req.method() != hyper::Method::GET {
+        return None;
+    }
+    let path = req.uri().path();
+    let path = G_CONFIG
+        .get()
+        .and_then(|cfg| path.strip_prefix(cfg.base_path.as_str()))
+        .filter(|route| route.starts_with('/'))
+        .unwrap_or(path);
+    match path {
+        "/healthz" => Some(healthz()),
+        "/readyz" => Some(readyz()),
+        _ => None,
+    }
+}
@@ -0,0 +1,180 @@
+#![deny(warnings)]
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use stat_common::server_status::SysInfo;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::payload::SysChange;
+use crate::storage;
+
+// 主机最新 SysInfo 及客户端版本记录，重启后保留
+pub const HOST_INFO_STATE: &str = "host_info";
+// 保留最近的不同版本数
+const MAX_VERSIONS: usize = 5;
+
+static HOST_INFO: Lazy<Mutex<HashMap<String, HostInfo>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionSeen {
+    pub version: String,
+    pub first_seen: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub name: String,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    // 旧版本客户端或 --disable-extra 时为 null
+    pub sys_info: Option<SysInfo>,
+    pub versions: Vec<VersionSeen>,
+}
+
+// 比较变化的系统信息字段，任一方为空(旧客户端或未获取到)时不比较
+fn sys_changes(old: &SysInfo, new: &SysInfo) -> Vec<SysChange> {
+    [
+        ("kernel_version", &old.kernel_version, &new.kernel_version),
+        ("os_release", &old.os_release, &new.os_release),
+    ]
+    .into_iter()
+    .filter(|(_, from, to)| !from.is_empty() && !to.is_empty() && from != to)
+    .map(|(field, from, to)| SysChange {
+        field: field.to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+    })
+    .collect()
+}
+
+// 每次上报时更新，未携带 sys_info 时保留上次的值
+// 返回与上次记录相比变化的内核/系统版本，记录重启后保留，首次上报时为空
+pub fn update(name: &str, version: &str, sys_info: Option<&SysInfo>) -> Vec<SysChange> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut host_info = HOST_INFO.lock().unwrap();
+    let o = host_info
+        .entry(name.to_string())
+        .or_insert_with(|| HostInfo {
+            name: name.to_string(),
+            first_seen: now,
+            ..Default::default()
+        });
+    o.last_seen = now;
+    let mut changes = Vec::new();
+    if let Some(sys_info) = sys_info {
+        if let Some(old) = &o.sys_info {
+            changes = sys_changes(old, sys_info);
+        }
+        o.sys_info = Some(sys_info.clone());
+    }
+    if !version.is_empty() && o.versions.last().map_or(true, |v| v.version != version) {
+        o.versions.retain(|v| v.version != version);
+        o.versions.push(VersionSeen {
+            version: version.to_string(),
+            first_seen: now,
+        });
+        if o.versions.len() > MAX_VERSIONS {
+            o.versions.remove(0);
+        }
+    }
+    changes
+}
+
+pub fn get(name: &str) -> Option<HostInfo> {
+    HOST_INFO.lock().unwrap().get(name).cloned()
+}
+
+pub fn remove(name: &str) {
+    HOST_INFO.lock().unwrap().remove(name);
+}
+
+pub fn load() {
+    let host_info = match storage::load_state(HOST_INFO_STATE) {
+        Some(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("ignore invalid {} => {:?}", HOST_INFO_STATE, err);
+            HashMap::new()
+        }),
+        None => HashMap::new(),
+    };
+    *HOST_INFO.lock().unwrap() = host_info;
+}
+
+pub fn save() {
+    let data = serde_json::to_string(&*HOST_INFO.lock().unwrap());
+    match data {
+        Ok(s) => storage::save_state(HOST_INFO_STATE, &s),
+        Err(err) => error!("save {} fail => {:?}", HOST_INFO_STATE, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sys_info(kernel_version: &str, os_release: &str) -> SysInfo {
+        SysInfo {
+            kernel_version: kernel_version.to_string(),
+            os_release: os_release.to_string(),
+            // 不参与比较
+            host_name: kernel_version.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn fields(changes: &[SysChange]) -> Vec<(&str, &str, &str)> {
+        changes
+            .iter()
+            .map(|o| (o.field.as_str(), o.from.as_str(), o.to.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn sys_changes_fields() {
+        let old = sys_info("5.10", "Debian 11");
+        // 任一方为空时不比较
+        assert!(sys_changes(&old, &sys_info("", "")).is_empty());
+        assert!(sys_changes(&sys_info("", ""), &old).is_empty());
+        assert!(sys_changes(&old, &sys_info("", "Debian 11")).is_empty());
+        // 相同
+        assert!(sys_changes(&old, &old.clone()).is_empty());
+        // 变化
+        assert_eq!(
+            fields(&sys_changes(&old, &sys_info("6.1", "Debian 11"))),
+            [("kernel_version", "5.10", "6.1")]
+        );
+        assert_eq!(
+            fields(&sys_changes(&old, &sys_info("6.1", "Debian 12"))),
+            [
+                ("kernel_version", "5.10", "6.1"),
+                ("os_release", "Debian 11", "Debian 12")
+            ]
+        );
+    }
+
+    #[test]
+    fn update_records_changes() {
+        let name = "hostinfo_update";
+        // 首次上报没有可比较的记录
+        assert!(update(name, "1.0", Some(&sys_info("5.10", "Debian 11"))).is_empty());
+        // 未携带 sys_info 时保留上次的值
+        assert!(update(name, "1.0", None).is_empty());
+        assert_eq!(
+            fields(&update(name, "1.1", Some(&sys_info("6.1", "Debian 11")))),
+            [("kernel_version", "5.10", "6.1")]
+        );
+        let info = get(name).unwrap();
+        assert_eq!(info.sys_info.unwrap().kernel_version, "6.1");
+        assert_eq!(
+            info.versions
+                .iter()
+                .map(|v| v.version.as_str())
+                .collect::<Vec<_>>(),
+            ["1.0", "1.1"]
+        );
+        remove(name);
+    }
+}
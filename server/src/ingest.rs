@@ -0,0 +1,1184 @@
+#![deny(warnings)]
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use stat_common::server_status::StatRequest;
+use stat_common::sign;
+use stat_common::units::{self, KIB, MIB};
+
+use crate::bans;
+use crate::config::InvalidValueMode;
+use crate::listener::canonical_ip;
+use crate::G_CONFIG;
+
+// 同一来源的违规日志间隔
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+// 限速表超过该数量时清理空闲项
+const MAX_BUCKETS: usize = 4096;
+// nonce 表上限，超过时先清理过期项，仍已满则拒绝新的签名上报(不淘汰未过期的 nonce，以免被重放)
+const MAX_NONCES: usize = 65536;
+const MAX_NONCE_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Reject {
+    RateLimit,
+    TooLarge,
+    Invalid(&'static str),
+    BadSignature,
+    // 签名认证模式时间戳超出 hmac_max_skew_secs
+    Expired,
+    // 签名认证模式 nonce 重复
+    Replay,
+    Conflict,
+}
+
+impl fmt::Display for Reject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reject::RateLimit => write!(f, "rate limited"),
+            Reject::TooLarge => write!(f, "payload too large"),
+            Reject::Invalid(key) => write!(f, "invalid field `{}`", key),
+            Reject::BadSignature => write!(f, "bad signature"),
+            Reject::Expired => write!(f, "timestamp out of window"),
+            Reject::Replay => write!(f, "nonce reused"),
+            Reject::Conflict => write!(f, "identity conflict"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DropCounters {
+    pub ip_denied: u64,
+    pub rate_limit: u64,
+    pub too_large: u64,
+    pub invalid: u64,
+    pub bad_signature: u64,
+    pub expired: u64,
+    pub replay: u64,
+    pub conflict: u64,
+    pub banned: u64,
+    // 未丢弃，修正后接收
+    pub clamped: u64,
+}
+
+static IP_DENIED_DROPS: AtomicU64 = AtomicU64::new(0);
+static RATE_LIMIT_DROPS: AtomicU64 = AtomicU64::new(0);
+static TOO_LARGE_DROPS: AtomicU64 = AtomicU64::new(0);
+static INVALID_DROPS: AtomicU64 = AtomicU64::new(0);
+static BAD_SIGNATURE_DROPS: AtomicU64 = AtomicU64::new(0);
+static EXPIRED_DROPS: AtomicU64 = AtomicU64::new(0);
+static REPLAY_DROPS: AtomicU64 = AtomicU64::new(0);
+static CONFLICT_DROPS: AtomicU64 = AtomicU64::new(0);
+static BANNED_DROPS: AtomicU64 = AtomicU64::new(0);
+// report_invalid_values = "clamp" 时修正后接收的上报数
+static CLAMPED: AtomicU64 = AtomicU64::new(0);
+
+// key => (tokens, last refill)
+static BUCKETS: Lazy<Mutex<HashMap<String, (f64, Instant)>>> = Lazy::new(Default::default);
+static LAST_LOG: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+static IDENTITIES: Lazy<Mutex<HashMap<String, Identity>>> = Lazy::new(Default::default);
+// name => 最近一次接收的上报，用于还原增量上报
+static DELTA_BASES: Lazy<Mutex<HashMap<String, DeltaBase>>> = Lazy::new(Default::default);
+// user => 上次接收的 (network_in, network_out)
+static LAST_TRAFFIC: Lazy<Mutex<HashMap<String, (u64, u64)>>> = Lazy::new(Default::default);
+// "user nonce" => 首次使用时间
+static NONCES: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+// name => 上报序号统计
+static SEQS: Lazy<Mutex<HashMap<String, SeqStat>>> = Lazy::new(Default::default);
+
+struct DeltaBase {
+    id: u32,
+    network_in: u64,
+    network_out: u64,
+    uptime: u64,
+}
+
+struct Source {
+    // "ip hostname"
+    addr: String,
+    first_seen: Instant,
+    last_seen: Instant,
+}
+
+// 按客户端上报序号(StatRequest.seq / Heartbeat.seq)统计丢失的上报，服务端重启后重新统计
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeqStat {
+    last_seq: u64,
+    pub reports_received: u64,
+    // 序号跳过的上报数，之后补发的缓存上报会抵扣
+    pub reports_lost: u64,
+    // 序号回退(客户端重启)次数
+    pub seq_resets: u64,
+}
+
+impl SeqStat {
+    pub fn loss_rate(&self) -> f64 {
+        let total = self.reports_received + self.reports_lost;
+        if total == 0 {
+            0.0
+        } else {
+            self.reports_lost as f64 / total as f64
+        }
+    }
+}
+
+// 同一用户名最近的上报来源
+#[derive(Default)]
+struct Identity {
+    sources: Vec<Source>,
+    conflict_at: Option<Instant>,
+}
+
+pub fn drop_counters() -> DropCounters {
+    DropCounters {
+        ip_denied: IP_DENIED_DROPS.load(Ordering::Relaxed),
+        rate_limit: RATE_LIMIT_DROPS.load(Ordering::Relaxed),
+        too_large: TOO_LARGE_DROPS.load(Ordering::Relaxed),
+        invalid: INVALID_DROPS.load(Ordering::Relaxed),
+        bad_signature: BAD_SIGNATURE_DROPS.load(Ordering::Relaxed),
+        expired: EXPIRED_DROPS.load(Ordering::Relaxed),
+        replay: REPLAY_DROPS.load(Ordering::Relaxed),
+        conflict: CONFLICT_DROPS.load(Ordering::Relaxed),
+        banned: BANNED_DROPS.load(Ordering::Relaxed),
+        clamped: CLAMPED.load(Ordering::Relaxed),
+    }
+}
+
+fn take_token(buckets: &mut HashMap<String, (f64, Instant)>, key: String, rate: f64) -> bool {
+    let now = Instant::now();
+    let burst = rate.max(1.0);
+    let (tokens, last) = buckets.entry(key).or_insert((burst, now));
+    *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(burst);
+    *last = now;
+    if *tokens >= 1.0 {
+        *tokens -= 1.0;
+        return true;
+    }
+    false
+}
+
+// 上报 ip 黑白名单及认证失败封禁，认证前检查，unix socket 等无地址时放行
+pub fn check_ip(ip: IpAddr) -> bool {
+    let ip = canonical_ip(ip);
+    if bans::is_banned(ip) {
+        BANNED_DROPS.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    if G_CONFIG.get().unwrap().report_ip_allowed(ip) {
+        return true;
+    }
+    IP_DENIED_DROPS.fetch_add(1, Ordering::Relaxed);
+    if should_log(ip.to_string()) {
+        warn!(ip = ip.to_string(); "reject report connection from {}", ip);
+    }
+    false
+}
+
+// 同一来源/主机的日志每 LOG_INTERVAL 最多一条，避免每次上报都记录
+pub fn should_log(offender: String) -> bool {
+    let mut last_log = LAST_LOG.lock().unwrap();
+    if last_log.len() > MAX_BUCKETS {
+        last_log.retain(|_, t| t.elapsed() < LOG_INTERVAL);
+    }
+    if last_log
+        .get(&offender)
+        .map_or(false, |t| t.elapsed() < LOG_INTERVAL)
+    {
+        return false;
+    }
+    last_log.insert(offender, Instant::now());
+    true
+}
+
+// 按用户名限速，report_rate_limit 为 0 时不限速
+// 不按来源地址限速: 客户端每次上报可能使用新连接(端口不同)，同一出口 ip 后也可能有多台主机
+pub fn check_rate(user: &str) -> Result<(), Reject> {
+    let rate = G_CONFIG.get().unwrap().report_rate_limit;
+    if rate <= 0.0 {
+        return Ok(());
+    }
+    let mut buckets = BUCKETS.lock().unwrap();
+    if buckets.len() > MAX_BUCKETS {
+        let idle = Duration::from_secs_f64(rate.max(1.0) / rate);
+        buckets.retain(|_, (_, last)| last.elapsed() < idle);
+    }
+    if take_token(&mut buckets, user.to_string(), rate) {
+        Ok(())
+    } else {
+        Err(Reject::RateLimit)
+    }
+}
+
+pub fn check_size(size: usize) -> Result<(), Reject> {
+    if size > G_CONFIG.get().unwrap().report_max_size {
+        return Err(Reject::TooLarge);
+    }
+    Ok(())
+}
+
+// 签名认证模式附带的 (timestamp, nonce)
+pub type Signed<'a> = (&'a str, &'a str);
+
+// 主机配置了 hmac_secret 时要求上报带有效签名
+// signed 为签名认证模式(未校验密码)，签名包含时间戳及 nonce，并检查时间偏差和重放
+pub fn check_signature(
+    user: &str,
+    data: &[u8],
+    signature: Option<&str>,
+    signed: Option<Signed>,
+) -> Result<(), Reject> {
+    let cfg = G_CONFIG.get().unwrap();
+    let secret = cfg.hmac_secret(user);
+    let signature = signature.unwrap_or_default();
+    let (secret, (timestamp, nonce)) = match (secret, signed) {
+        (None, None) => return Ok(()),
+        (None, Some(_)) => return Err(Reject::BadSignature),
+        (Some(_), None) if cfg.signed_only(user) => return Err(Reject::BadSignature),
+        (Some(secret), None) if sign::verify(secret, data, signature) => return Ok(()),
+        (Some(_), None) => return Err(Reject::BadSignature),
+        (Some(secret), Some(signed)) => (secret, signed),
+    };
+    let ts = timestamp
+        .parse::<i64>()
+        .map_err(|_| Reject::Invalid(sign::TIMESTAMP_HEADER))?;
+    if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+        return Err(Reject::Invalid(sign::NONCE_HEADER));
+    }
+    // 先校验签名，未通过认证的请求不写入 nonce 表
+    if !sign::verify(secret, &sign::signed_data(data, ts, nonce), signature) {
+        return Err(Reject::BadSignature);
+    }
+    let skew = cfg.hmac_max_skew_secs;
+    if (Utc::now().timestamp() - ts).unsigned_abs() > skew {
+        return Err(Reject::Expired);
+    }
+
+    // 超出偏差窗口的时间戳已被拒绝，nonce 只需保留 2 倍窗口
+    let ttl = Duration::from_secs(skew.saturating_mul(2).saturating_add(1));
+    let key = format!("{} {}", user, nonce);
+    insert_nonce(&mut NONCES.lock().unwrap(), key, ttl, MAX_NONCES)
+}
+
+fn insert_nonce(
+    nonces: &mut HashMap<String, Instant>,
+    key: String,
+    ttl: Duration,
+    max: usize,
+) -> Result<(), Reject> {
+    if nonces.get(&key).map_or(false, |t| t.elapsed() < ttl) {
+        return Err(Reject::Replay);
+    }
+    if nonces.len() >= max && !nonces.contains_key(&key) {
+        nonces.retain(|_, t| t.elapsed() < ttl);
+        if nonces.len() >= max {
+            if should_log("nonces".to_string()) {
+                warn!("nonce table full ({}), reject signed reports", max);
+            }
+            return Err(Reject::RateLimit);
+        }
+    }
+    nonces.insert(key, Instant::now());
+    Ok(())
+}
+
+// 同一用户名在窗口期内从不同来源(ip + 主机名)交替上报视为身份冲突，
+// 旧来源停止后新来源接替(故障切换)时不会交替，不视为冲突
+pub fn check_identity(user: &str, ip: Option<IpAddr>, host_name: &str) -> Result<(), Reject> {
+    let addr = match ip {
+        Some(ip) => format!("{} {}", ip, host_name),
+        None => host_name.to_string(),
+    };
+    check_source(user, |_| addr.trim().to_string())
+}
+
+// 心跳不带主机名，沿用同一 ip 最近一次上报的来源，本机的心跳与完整上报不视为交替上报
+pub fn check_heartbeat_identity(user: &str, ip: Option<IpAddr>) -> Result<(), Reject> {
+    let ip = match ip {
+        Some(ip) => ip.to_string(),
+        None => return Ok(()),
+    };
+    let prefix = format!("{} ", ip);
+    check_source(user, |sources| {
+        sources
+            .iter()
+            .filter(|s| s.addr == ip || s.addr.starts_with(&prefix))
+            .max_by_key(|s| s.last_seen)
+            .map_or_else(|| ip.clone(), |s| s.addr.clone())
+    })
+}
+
+fn check_source(user: &str, addr: impl FnOnce(&[Source]) -> String) -> Result<(), Reject> {
+    let cfg = G_CONFIG.get().unwrap();
+    if cfg.identity_conflict_window_secs == 0 {
+        return Ok(());
+    }
+    let window = Duration::from_secs(cfg.identity_conflict_window_secs);
+
+    let mut identities = IDENTITIES.lock().unwrap();
+    if identities.len() > MAX_BUCKETS {
+        identities.retain(|_, o| o.sources.iter().any(|s| s.last_seen.elapsed() < window));
+    }
+    let identity = identities.entry(user.to_string()).or_default();
+    identity.sources.retain(|s| s.last_seen.elapsed() < window);
+    let addr = addr(&identity.sources);
+
+    let now = Instant::now();
+    let newest = match identity.sources.iter().position(|s| s.addr == addr) {
+        Some(i) => {
+            // 本来源上次上报之后其它来源也有上报，即交替上报
+            let last_seen = identity.sources[i].last_seen;
+            if identity
+                .sources
+                .iter()
+                .any(|s| s.addr != addr && s.last_seen > last_seen)
+            {
+                identity.conflict_at = Some(now);
+            }
+            identity.sources[i].last_seen = now;
+            identity
+                .sources
+                .iter()
+                .all(|s| s.first_seen <= identity.sources[i].first_seen)
+        }
+        None => {
+            identity.sources.push(Source {
+                addr,
+                first_seen: now,
+                last_seen: now,
+            });
+            true
+        }
+    };
+
+    let conflict = identity.conflict_at.map_or(false, |t| t.elapsed() < window);
+    if conflict && newest && identity.sources.len() > 1 && cfg.identity_conflict_reject {
+        return Err(Reject::Conflict);
+    }
+    Ok(())
+}
+
+// 冲突中的来源列表，无冲突时返回 None
+pub fn conflict(user: &str) -> Option<Vec<String>> {
+    let window = Duration::from_secs(G_CONFIG.get().unwrap().identity_conflict_window_secs);
+    let identities = IDENTITIES.lock().unwrap();
+    let identity = identities.get(user)?;
+    let sources = identity
+        .sources
+        .iter()
+        .filter(|s| s.last_seen.elapsed() < window)
+        .map(|s| s.addr.to_string())
+        .collect::<Vec<_>>();
+    if sources.len() > 1 && identity.conflict_at?.elapsed() < window {
+        Some(sources)
+    } else {
+        None
+    }
+}
+
+// 校验解码后的数据: NaN/负数负载、cpu 超过 100%、used 大于 total、网速超过上限、流量计数回绕
+// report_invalid_values = "reject" 时丢弃整条上报，"clamp" 时修正后接收
+pub fn validate(user: &str, v: &mut serde_json::Value) -> Result<(), Reject> {
+    validate_mode(user, v, G_CONFIG.get().unwrap().report_invalid_values)
+}
+
+fn validate_mode(
+    user: &str,
+    v: &mut serde_json::Value,
+    mode: InvalidValueMode,
+) -> Result<(), Reject> {
+    let cfg = G_CONFIG.get().unwrap();
+    // 字段 => 修正值
+    let mut fixes: Vec<(&'static str, serde_json::Value)> = Vec::new();
+
+    for key in ["load_1", "load_5", "load_15", "cpu"] {
+        if let Some(x) = v.get(key) {
+            match x.as_f64() {
+                Some(f) if f.is_finite() && f >= 0.0 => {}
+                _ => fixes.push((key, 0.0.into())),
+            }
+        }
+    }
+    // cpu 为所有核心的总使用率
+    if v["cpu"].as_f64().map_or(false, |f| f > 100.0) {
+        fixes.push(("cpu", 100.0.into()));
+    }
+    for (used, total) in [
+        ("memory_used", "memory_total"),
+        ("swap_used", "swap_total"),
+        ("hdd_used", "hdd_total"),
+    ] {
+        if let (Some(u), Some(t)) = (v[used].as_u64(), v[total].as_u64()) {
+            if u > t {
+                fixes.push((used, t.into()));
+            }
+        }
+    }
+    for key in ["network_rx", "network_tx"] {
+        if v[key].as_u64().map_or(false, |n| n > cfg.report_max_speed) {
+            fixes.push((key, 0.into()));
+        }
+    }
+    // 超过 i64::MAX 的累计流量为客户端相减下溢，修正为该主机上次的值
+    let mut last_traffic = LAST_TRAFFIC.lock().unwrap();
+    let last = last_traffic.get(user).copied().unwrap_or_default();
+    for (key, last) in [("network_in", last.0), ("network_out", last.1)] {
+        if v[key].as_u64().map_or(false, |n| n > i64::MAX as u64) {
+            fixes.push((key, last.into()));
+        }
+    }
+
+    if let Some((key, _)) = fixes.first() {
+        if mode == InvalidValueMode::Reject {
+            return Err(Reject::Invalid(key));
+        }
+        CLAMPED.fetch_add(1, Ordering::Relaxed);
+        if should_log(format!("clamp:{}", user)) {
+            warn!(
+                host = user;
+                "clamp out-of-range values from user `{}` => {:?}",
+                user,
+                fixes.iter().map(|(k, _)| *k).collect::<Vec<_>>()
+            );
+        }
+        for (key, fix) in fixes {
+            v[key] = fix;
+        }
+    }
+    if let (Some(i), Some(o)) = (v["network_in"].as_u64(), v["network_out"].as_u64()) {
+        if last_traffic.len() > MAX_BUCKETS && !last_traffic.contains_key(user) {
+            last_traffic.clear();
+        }
+        last_traffic.insert(user.to_string(), (i, o));
+    }
+    Ok(())
+}
+
+// 计数并记录日志，同一来源每分钟最多一条
+pub fn reject(reason: Reject, user: &str, ip: Option<IpAddr>) {
+    match reason {
+        Reject::RateLimit => &RATE_LIMIT_DROPS,
+        Reject::TooLarge => &TOO_LARGE_DROPS,
+        Reject::Invalid(_) => &INVALID_DROPS,
+        Reject::BadSignature => &BAD_SIGNATURE_DROPS,
+        Reject::Expired => &EXPIRED_DROPS,
+        Reject::Replay => &REPLAY_DROPS,
+        Reject::Conflict => &CONFLICT_DROPS,
+    }
+    .fetch_add(1, Ordering::Relaxed);
+
+    if should_log(format!("{}@{:?}", user, ip)) {
+        warn!(
+            host = user,
+            ip = ip.map(|ip| ip.to_string());
+            "drop report from user `{}` ip {:?} => {}, {:?}",
+            user,
+            ip,
+            reason,
+            drop_counters()
+        );
+    }
+}
+
+// 还原增量上报并保存为下次的基准，返回本次的编号(补发的缓存上报不作为基准，为 0)
+// 增量的基准与服务端保存的不一致(上次响应丢失、服务端重启等)时返回 None，客户端改为完整上报
+pub fn apply_delta(stat: &mut StatRequest) -> Option<u32> {
+    let mut bases = DELTA_BASES.lock().unwrap();
+    if stat.delta_base != 0 {
+        let base = bases.get(&stat.name).filter(|b| b.id == stat.delta_base)?;
+        stat.network_in = base.network_in.saturating_add(stat.network_in);
+        stat.network_out = base.network_out.saturating_add(stat.network_out);
+        stat.uptime = base.uptime.saturating_add(stat.uptime);
+        stat.delta_base = 0;
+    }
+    if stat.buffered {
+        return Some(0);
+    }
+    let id = bases
+        .get(&stat.name)
+        .map_or(1, |b| b.id.wrapping_add(1).max(1));
+    if bases.len() > MAX_BUCKETS && !bases.contains_key(&stat.name) {
+        bases.clear();
+    }
+    bases.insert(
+        stat.name.to_string(),
+        DeltaBase {
+            id,
+            network_in: stat.network_in,
+            network_out: stat.network_out,
+            uptime: stat.uptime,
+        },
+    );
+    Some(id)
+}
+
+// seq 为 0(旧客户端)时不统计
+// 序号回退视为客户端重启，不计入丢失；补发的缓存上报序号小于最近的序号，抵扣之前计入的丢失
+pub fn track_seq(name: &str, seq: u64, buffered: bool) {
+    if seq == 0 {
+        return;
+    }
+    let mut seqs = SEQS.lock().unwrap();
+    if seqs.len() > MAX_BUCKETS && !seqs.contains_key(name) {
+        seqs.clear();
+    }
+    let st = seqs.entry(name.to_string()).or_default();
+    st.reports_received += 1;
+    if buffered && seq < st.last_seq {
+        st.reports_lost = st.reports_lost.saturating_sub(1);
+        return;
+    }
+    if st.last_seq > 0 && seq > st.last_seq {
+        let gap = seq - st.last_seq - 1;
+        if gap > 0 {
+            debug!(host = name; "host `{}` seq {} => {}, {} reports lost", name, st.last_seq, seq, gap);
+        }
+        st.reports_lost += gap;
+    } else if seq <= st.last_seq {
+        info!(host = name; "host `{}` seq reset {} => {}, client restarted", name, st.last_seq, seq);
+        st.seq_resets += 1;
+    }
+    st.last_seq = seq;
+}
+
+pub fn seq_stat(name: &str) -> Option<SeqStat> {
+    SEQS.lock().unwrap().get(name).copied()
+}
+
+fn unit_size(unit: &str, default: &str) -> Result<u64, Reject> {
+    let unit = if unit.is_empty() { default } else { unit };
+    units::unit_size(unit).ok_or(Reject::Invalid("units"))
+}
+
+// 按上报的 units 将容量字段换算为默认单位(内存/swap 为 KiB，硬盘为 MiB)，之后均按默认单位处理
+pub fn normalize_units(stat: &mut StatRequest) -> Result<(), Reject> {
+    let units = match stat.units.take() {
+        Some(units) => units,
+        None => return Ok(()),
+    };
+    let memory = unit_size(&units.memory, units::MEMORY_UNIT)?;
+    let disk = unit_size(&units.disk, units::DISK_UNIT)?;
+    if memory != KIB {
+        for v in [
+            &mut stat.memory_total,
+            &mut stat.memory_used,
+            &mut stat.swap_total,
+            &mut stat.swap_used,
+        ] {
+            *v = units::convert(*v, memory, KIB);
+        }
+    }
+    if disk != MIB {
+        stat.hdd_total = units::convert(stat.hdd_total, disk, MIB);
+        stat.hdd_used = units::convert(stat.hdd_used, disk, MIB);
+        for m in stat.hot_mounts.iter_mut() {
+            m.total = units::convert(m.total, disk, MIB);
+            m.used = units::convert(m.used, disk, MIB);
+        }
+    }
+    Ok(())
+}
+
+// 指标 => (引入时的 proto_version, 对应字段)，名称同客户端 --report-fields 及 unavailable_metrics
+// 新增无法区分未上报与 0/空 的字段(非 optional 的标量、repeated、map)时在此登记
+const METRIC_FIELDS: &[(&str, u32, &[&str])] = &[
+    ("load", 0, &["load_1", "load_5", "load_15"]),
+    ("swap", 0, &["swap_total", "swap_used"]),
+    ("hdd", 0, &["hdd_total", "hdd_used"]),
+    ("traffic", 0, &["network_in", "network_out"]),
+    ("speed", 0, &["network_rx", "network_tx"]),
+    ("ifaces", 3, &["ifaces"]),
+    ("ipmi", 5, &["sensors"]),
+];
+
+fn mark_unavailable(stat: &mut StatRequest, metric: &str) {
+    if !stat.unavailable_metrics.iter().any(|m| m == metric) {
+        stat.unavailable_metrics.push(metric.to_string());
+    }
+}
+
+// protobuf 无法区分未上报与 0，客户端 proto_version 低于引入版本时视为未上报，前端显示 N/A
+pub fn mark_absent(stat: &mut StatRequest) {
+    for (metric, since, _) in METRIC_FIELDS {
+        if stat.proto_version < *since {
+            mark_unavailable(stat, metric);
+        }
+    }
+}
+
+// json 上报按字段是否存在判断，指标的字段均缺少时视为未上报
+pub fn mark_absent_fields(stat: &mut StatRequest, present: impl Fn(&str) -> bool) {
+    for (metric, _, fields) in METRIC_FIELDS {
+        if !fields.iter().any(|f| present(f)) {
+            mark_unavailable(stat, metric);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use stat_common::server_status::{MountInfo, Units};
+    use stat_common::units::{ByteSize, GIB};
+    use std::thread;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    // 保证 Instant 严格递增
+    fn tick() {
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    #[test]
+    fn rate_per_user() {
+        // report_rate_limit 默认 4 次/秒，突发上限同为 4
+        testing::init_config();
+        for _ in 0..4 {
+            assert!(check_rate("rate_a").is_ok());
+        }
+        assert!(matches!(check_rate("rate_a"), Err(Reject::RateLimit)));
+        // 其它用户不受影响
+        assert!(check_rate("rate_b").is_ok());
+        // 按速率恢复
+        thread::sleep(Duration::from_millis(300));
+        assert!(check_rate("rate_a").is_ok());
+    }
+
+    #[test]
+    fn interleaved_sources_conflict() {
+        testing::init_config();
+        let user = "identity_interleaved";
+        assert!(check_identity(user, ip("10.0.0.1"), "a").is_ok());
+        tick();
+        // 新来源出现时尚未交替
+        assert!(check_identity(user, ip("10.0.0.2"), "b").is_ok());
+        assert!(conflict(user).is_none());
+        tick();
+        // 旧来源再次上报，标记冲突，较早出现的来源不拒绝
+        assert!(check_identity(user, ip("10.0.0.1"), "a").is_ok());
+        let mut sources = conflict(user).unwrap();
+        sources.sort();
+        assert_eq!(sources, ["10.0.0.1 a", "10.0.0.2 b"]);
+        tick();
+        // identity_conflict_reject 时拒绝较新的来源
+        assert!(matches!(
+            check_identity(user, ip("10.0.0.2"), "b"),
+            Err(Reject::Conflict)
+        ));
+        assert!(check_identity(user, ip("10.0.0.1"), "a").is_ok());
+    }
+
+    // 同一 ip 不同主机名也是不同来源
+    #[test]
+    fn interleaved_host_names_conflict() {
+        testing::init_config();
+        let user = "identity_host_name";
+        for host_name in ["a", "b", "a"] {
+            assert!(check_identity(user, ip("10.0.0.1"), host_name).is_ok());
+            tick();
+        }
+        assert!(conflict(user).is_some());
+    }
+
+    fn signed(
+        user: &str,
+        payload: &[u8],
+        ts: i64,
+        nonce: &str,
+        secret: &str,
+    ) -> Result<(), Reject> {
+        let signature = sign::sign(secret, &sign::signed_data(payload, ts, nonce));
+        let ts = ts.to_string();
+        check_signature(user, payload, Some(&signature), Some((&ts, nonce)))
+    }
+
+    #[test]
+    fn signed_auth_replay() {
+        testing::init_config();
+        let now = Utc::now().timestamp();
+        assert!(signed("signed", b"stat", now, "replay-1", testing::SECRET).is_ok());
+        // 同一 nonce 再次使用，内容不同也拒绝
+        assert!(matches!(
+            signed("signed", b"stat", now, "replay-1", testing::SECRET),
+            Err(Reject::Replay)
+        ));
+        assert!(matches!(
+            signed("signed", b"other", now, "replay-1", testing::SECRET),
+            Err(Reject::Replay)
+        ));
+        assert!(signed("signed", b"stat", now, "replay-2", testing::SECRET).is_ok());
+    }
+
+    #[test]
+    fn signed_auth_skew() {
+        testing::init_config();
+        let skew = testing::init_config().hmac_max_skew_secs as i64;
+        let now = Utc::now().timestamp();
+        assert!(signed("signed", b"stat", now - skew + 5, "skew-1", testing::SECRET).is_ok());
+        assert!(signed("signed", b"stat", now + skew - 5, "skew-2", testing::SECRET).is_ok());
+        for ts in [now - skew - 5, now + skew + 5] {
+            assert!(matches!(
+                signed("signed", b"stat", ts, "skew-3", testing::SECRET),
+                Err(Reject::Expired)
+            ));
+        }
+        // 超出窗口的请求不占用 nonce
+        assert!(signed("signed", b"stat", now, "skew-3", testing::SECRET).is_ok());
+    }
+
+    #[test]
+    fn signed_auth_bad_signature() {
+        testing::init_config();
+        let now = Utc::now().timestamp();
+        assert!(matches!(
+            signed("signed", b"stat", now, "bad-1", "wrong"),
+            Err(Reject::BadSignature)
+        ));
+        // 签名覆盖 payload、时间戳及 nonce
+        let signature = sign::sign(testing::SECRET, &sign::signed_data(b"stat", now, "bad-2"));
+        let ts = now.to_string();
+        let ts_1 = (now - 1).to_string();
+        for (payload, ts, nonce) in [
+            (&b"stat!"[..], ts.as_str(), "bad-2"),
+            (b"stat", ts_1.as_str(), "bad-2"),
+            (b"stat", ts.as_str(), "bad-3"),
+        ] {
+            assert!(matches!(
+                check_signature("signed", payload, Some(&signature), Some((ts, nonce))),
+                Err(Reject::BadSignature)
+            ));
+        }
+        // 失败的请求未占用 nonce
+        assert!(signed("signed", b"stat", now, "bad-2", testing::SECRET).is_ok());
+        // 未配置 hmac_secret 的主机不接受签名认证
+        assert!(matches!(
+            signed("h1", b"stat", now, "bad-4", testing::SECRET),
+            Err(Reject::BadSignature)
+        ));
+    }
+
+    // 密码认证 + 签名(不含时间戳及 nonce)
+    #[test]
+    fn password_auth_signature() {
+        testing::init_config();
+        let signature = sign::sign(testing::SECRET, b"stat");
+        assert!(check_signature("signed", b"stat", Some(&signature), None).is_ok());
+        assert!(matches!(
+            check_signature("signed", b"stat", None, None),
+            Err(Reject::BadSignature)
+        ));
+        assert!(matches!(
+            check_signature("signed", b"stat!", Some(&signature), None),
+            Err(Reject::BadSignature)
+        ));
+        assert!(check_signature("h1", b"stat", None, None).is_ok());
+    }
+
+    #[test]
+    fn signed_only_refuses_password_auth() {
+        testing::init_config();
+        let signature = sign::sign(testing::SECRET, b"stat");
+        assert!(matches!(
+            check_signature("signed_only", b"stat", Some(&signature), None),
+            Err(Reject::BadSignature)
+        ));
+        let now = Utc::now().timestamp();
+        assert!(signed("signed_only", b"stat", now, "only-1", testing::SECRET).is_ok());
+    }
+
+    #[test]
+    fn nonce_table_cap() {
+        let ttl = Duration::from_secs(60);
+        let mut nonces = HashMap::new();
+        for i in 0..4 {
+            assert!(insert_nonce(&mut nonces, i.to_string(), ttl, 4).is_ok());
+        }
+        // 未过期的 nonce 不淘汰，已满时拒绝
+        assert!(matches!(
+            insert_nonce(&mut nonces, "4".to_string(), ttl, 4),
+            Err(Reject::RateLimit)
+        ));
+        assert!(matches!(
+            insert_nonce(&mut nonces, "0".to_string(), ttl, 4),
+            Err(Reject::Replay)
+        ));
+        assert_eq!(nonces.len(), 4);
+
+        // 清理过期项后接收
+        let expired = Instant::now() - Duration::from_secs(120);
+        nonces.insert("0".to_string(), expired);
+        assert!(insert_nonce(&mut nonces, "4".to_string(), ttl, 4).is_ok());
+        assert_eq!(nonces.len(), 4);
+        assert!(!nonces.contains_key("0"));
+    }
+
+    fn report(extra: serde_json::Value) -> serde_json::Value {
+        let mut v = serde_json::json!({
+            "load_1": 0.5, "load_5": 0.5, "load_15": 0.5, "cpu": 12.5,
+            "memory_total": 1024, "memory_used": 512,
+            "swap_total": 0, "swap_used": 0,
+            "hdd_total": 1024, "hdd_used": 512,
+            "network_rx": 100, "network_tx": 100,
+            "network_in": 1000, "network_out": 2000,
+        });
+        for (k, x) in extra.as_object().unwrap() {
+            v[k] = x.clone();
+        }
+        v
+    }
+
+    fn out_of_range() -> Vec<(&'static str, serde_json::Value)> {
+        let max_speed = testing::init_config().report_max_speed;
+        vec![
+            ("load_1", serde_json::json!({"load_1": -1.0})),
+            ("load_15", serde_json::json!({"load_15": null})),
+            ("cpu", serde_json::json!({"cpu": "NaN"})),
+            ("cpu", serde_json::json!({"cpu": 100.01})),
+            ("memory_used", serde_json::json!({"memory_used": 1025})),
+            ("swap_used", serde_json::json!({"swap_used": 1})),
+            ("hdd_used", serde_json::json!({"hdd_used": u64::MAX})),
+            (
+                "network_rx",
+                serde_json::json!({"network_rx": max_speed + 1}),
+            ),
+            (
+                "network_in",
+                serde_json::json!({"network_in": i64::MAX as u64 + 1}),
+            ),
+            ("network_out", serde_json::json!({"network_out": u64::MAX})),
+        ]
+    }
+
+    #[test]
+    fn valid_values_untouched() {
+        testing::init_config();
+        let max_speed = testing::init_config().report_max_speed;
+        // 边界值均合法
+        let edge = report(serde_json::json!({
+            "load_1": 0.0, "cpu": 100.0, "memory_used": 1024, "network_rx": max_speed,
+            "network_in": i64::MAX as u64,
+        }));
+        for mode in [InvalidValueMode::Reject, InvalidValueMode::Clamp] {
+            let mut v = edge.clone();
+            assert!(validate_mode("invalid_edge", &mut v, mode).is_ok());
+            assert_eq!(v, edge);
+        }
+    }
+
+    #[test]
+    fn reject_out_of_range() {
+        testing::init_config();
+        for (key, extra) in out_of_range() {
+            let mut v = report(extra);
+            let before = v.clone();
+            match validate_mode("invalid_reject", &mut v, InvalidValueMode::Reject) {
+                Err(Reject::Invalid(k)) => assert_eq!(k, key),
+                res => panic!("{} => {:?}", key, res),
+            }
+            assert_eq!(v, before);
+        }
+    }
+
+    #[test]
+    fn clamp_out_of_range() {
+        testing::init_config();
+        let user = "invalid_clamp";
+        // 上次正常上报的累计流量
+        assert!(validate_mode(
+            user,
+            &mut report(serde_json::json!({})),
+            InvalidValueMode::Clamp
+        )
+        .is_ok());
+        let clamped = CLAMPED.load(Ordering::Relaxed);
+        let fixed = [
+            ("load_1", serde_json::json!(0.0)),
+            ("load_15", serde_json::json!(0.0)),
+            ("cpu", serde_json::json!(0.0)),
+            ("cpu", serde_json::json!(100.0)),
+            ("memory_used", serde_json::json!(1024)),
+            ("swap_used", serde_json::json!(0)),
+            ("hdd_used", serde_json::json!(1024)),
+            ("network_rx", serde_json::json!(0)),
+            ("network_in", serde_json::json!(1000)),
+            ("network_out", serde_json::json!(2000)),
+        ];
+        for ((key, extra), (fixed_key, fixed)) in out_of_range().into_iter().zip(fixed) {
+            assert_eq!(key, fixed_key);
+            let mut v = report(extra);
+            assert!(validate_mode(user, &mut v, InvalidValueMode::Clamp).is_ok());
+            assert_eq!(v[key], fixed, "{}", key);
+        }
+        assert!(CLAMPED.load(Ordering::Relaxed) >= clamped + 10);
+    }
+
+    fn delta_stat(name: &str, delta_base: u32, network_in: u64, uptime: u64) -> StatRequest {
+        StatRequest {
+            name: name.to_string(),
+            delta_base,
+            network_in,
+            network_out: network_in * 2,
+            uptime,
+            ..Default::default()
+        }
+    }
+
+    fn restored(stat: &StatRequest) -> (u64, u64, u64, u32) {
+        (
+            stat.network_in,
+            stat.network_out,
+            stat.uptime,
+            stat.delta_base,
+        )
+    }
+
+    #[test]
+    fn delta_restores_absolute() {
+        let name = "delta_restore";
+        let mut full = delta_stat(name, 0, 1000, 60);
+        assert_eq!(apply_delta(&mut full), Some(1));
+        assert_eq!(restored(&full), (1000, 2000, 60, 0));
+
+        let mut delta = delta_stat(name, 1, 500, 1);
+        assert_eq!(apply_delta(&mut delta), Some(2));
+        assert_eq!(restored(&delta), (1500, 3000, 61, 0));
+
+        // 补发的缓存上报可按当前基准还原，但不更新基准
+        let mut buffered = delta_stat(name, 2, 10, 1);
+        buffered.buffered = true;
+        assert_eq!(apply_delta(&mut buffered), Some(0));
+        assert_eq!(restored(&buffered), (1510, 3020, 62, 0));
+        let mut delta = delta_stat(name, 2, 0, 1);
+        assert_eq!(apply_delta(&mut delta), Some(3));
+        assert_eq!(restored(&delta), (1500, 3000, 62, 0));
+    }
+
+    // 上次响应丢失(客户端仍使用旧基准)或服务端重启后，要求完整上报
+    #[test]
+    fn delta_base_mismatch() {
+        let name = "delta_mismatch";
+        let mut unknown = delta_stat(name, 1, 500, 1);
+        assert_eq!(apply_delta(&mut unknown), None);
+
+        assert_eq!(apply_delta(&mut delta_stat(name, 0, 1000, 60)), Some(1));
+        assert_eq!(apply_delta(&mut delta_stat(name, 1, 100, 1)), Some(2));
+        // 客户端未收到 2 的响应，仍以 1 为基准
+        assert_eq!(apply_delta(&mut delta_stat(name, 1, 200, 2)), None);
+        // 重连后完整上报，计数已回退(客户端重启)
+        let mut full = delta_stat(name, 0, 10, 5);
+        assert_eq!(apply_delta(&mut full), Some(3));
+        assert_eq!(restored(&full), (10, 20, 5, 0));
+        let mut delta = delta_stat(name, 3, 5, 1);
+        assert_eq!(apply_delta(&mut delta), Some(4));
+        assert_eq!(restored(&delta), (15, 30, 6, 0));
+    }
+
+    // 编号回绕时跳过 0(0 表示完整上报)
+    #[test]
+    fn delta_id_wraps() {
+        let name = "delta_wrap";
+        assert_eq!(apply_delta(&mut delta_stat(name, 0, 1, 1)), Some(1));
+        DELTA_BASES.lock().unwrap().get_mut(name).unwrap().id = u32::MAX;
+        let mut delta = delta_stat(name, u32::MAX, 1, 1);
+        assert_eq!(apply_delta(&mut delta), Some(1));
+        assert_eq!(restored(&delta), (2, 4, 2, 0));
+    }
+
+    #[test]
+    fn clean_handover() {
+        testing::init_config();
+        let user = "identity_handover";
+        for _ in 0..3 {
+            assert!(check_identity(user, ip("10.0.0.1"), "a").is_ok());
+            tick();
+        }
+        // 旧来源停止后新来源接替
+        for _ in 0..3 {
+            assert!(check_identity(user, ip("10.0.0.2"), "b").is_ok());
+            tick();
+        }
+        assert!(conflict(user).is_none());
+    }
+
+    // 心跳沿用同一 ip 的完整上报来源，其它 ip 的心跳与之交替时同样视为冲突
+    #[test]
+    fn heartbeat_identity() {
+        testing::init_config();
+        let user = "identity_heartbeat";
+        for _ in 0..3 {
+            assert!(check_identity(user, ip("10.0.0.1"), "a").is_ok());
+            tick();
+            assert!(check_heartbeat_identity(user, ip("10.0.0.1")).is_ok());
+            tick();
+        }
+        assert!(conflict(user).is_none());
+
+        assert!(check_heartbeat_identity(user, ip("10.0.0.2")).is_ok());
+        tick();
+        assert!(check_heartbeat_identity(user, ip("10.0.0.1")).is_ok());
+        let mut sources = conflict(user).unwrap();
+        sources.sort();
+        assert_eq!(sources, ["10.0.0.1 a", "10.0.0.2"]);
+        tick();
+        assert!(matches!(
+            check_heartbeat_identity(user, ip("10.0.0.2")),
+            Err(Reject::Conflict)
+        ));
+    }
+
+    // 8 GiB 内存、1 GiB swap、100 GiB 硬盘，热点挂载点 50 GiB，按 (memory, disk) 单位表示
+    fn units_stat(memory: u64, disk: u64, units: Option<Units>) -> StatRequest {
+        StatRequest {
+            memory_total: 8 * GIB / memory,
+            memory_used: 2 * GIB / memory,
+            swap_total: GIB / memory,
+            hdd_total: 100 * GIB / disk,
+            hdd_used: 25 * GIB / disk,
+            hot_mounts: vec![MountInfo {
+                mount_point: "/data".to_string(),
+                total: 50 * GIB / disk,
+                used: 45 * GIB / disk,
+                ..Default::default()
+            }],
+            units,
+            ..Default::default()
+        }
+    }
+
+    fn units(memory: &str, disk: &str) -> Option<Units> {
+        Some(Units {
+            memory: memory.to_string(),
+            disk: disk.to_string(),
+        })
+    }
+
+    // 按默认单位(KiB/MiB)显示
+    fn rendered(stat: &StatRequest) -> Vec<String> {
+        vec![
+            ByteSize::from_kib(stat.memory_total).to_string(),
+            ByteSize::from_kib(stat.memory_used).to_string(),
+            ByteSize::from_kib(stat.swap_total).to_string(),
+            ByteSize::from_mib(stat.hdd_total).to_string(),
+            ByteSize::from_mib(stat.hdd_used).to_string(),
+            ByteSize::from_mib(stat.hot_mounts[0].total).to_string(),
+            ByteSize::from_mib(stat.hot_mounts[0].used).to_string(),
+        ]
+    }
+
+    #[test]
+    fn units_versions_render_same() {
+        let expected = [
+            "8.0 GiB",
+            "2.0 GiB",
+            "1.0 GiB",
+            "100.0 GiB",
+            "25.0 GiB",
+            "50.0 GiB",
+            "45.0 GiB",
+        ];
+        // 旧客户端不上报 units，新客户端按各自的单位上报
+        let cases = [
+            units_stat(KIB, MIB, None),
+            units_stat(KIB, MIB, units("", "")),
+            units_stat(KIB, MIB, units("KiB", "MiB")),
+            units_stat(1, GIB, units("B", "GiB")),
+            units_stat(MIB, 1, units("MiB", "B")),
+            units_stat(GIB, KIB, units("GiB", "KiB")),
+        ];
+        for mut stat in cases {
+            let units = stat.units.clone();
+            assert!(normalize_units(&mut stat).is_ok(), "{:?}", units);
+            assert_eq!(rendered(&stat), expected, "{:?}", units);
+            assert_eq!(stat.memory_total, 8 * MIB, "{:?}", units);
+            assert_eq!(stat.hdd_total, 100 * KIB, "{:?}", units);
+            assert!(stat.units.is_none());
+        }
+    }
+
+    #[test]
+    fn units_unknown_rejected() {
+        for units in [units("KB", ""), units("", "TiB"), units("kib", "mib")] {
+            let mut stat = units_stat(KIB, MIB, units.clone());
+            assert!(
+                matches!(normalize_units(&mut stat), Err(Reject::Invalid("units"))),
+                "{:?}",
+                units
+            );
+        }
+    }
+
+    #[test]
+    fn log_rate_limited() {
+        assert!(should_log("log_rate:a".to_string()));
+        assert!(!should_log("log_rate:a".to_string()));
+        assert!(should_log("log_rate:b".to_string()));
+        LAST_LOG
+            .lock()
+            .unwrap()
+            .insert("log_rate:a".to_string(), Instant::now() - LOG_INTERVAL);
+        assert!(should_log("log_rate:a".to_string()));
+        assert!(!should_log("log_rate:a".to_string()));
+    }
+
+    // (received, lost, resets)
+    fn seq_counts(name: &str) -> (u64, u64, u64) {
+        let st = seq_stat(name).unwrap();
+        (st.reports_received, st.reports_lost, st.seq_resets)
+    }
+
+    #[test]
+    fn seq_gaps() {
+        let name = "seq_gaps";
+        // 旧客户端不上报序号
+        track_seq(name, 0, false);
+        assert!(seq_stat(name).is_none());
+
+        for seq in [1, 2, 5] {
+            track_seq(name, seq, false);
+        }
+        assert_eq!(seq_counts(name), (3, 2, 0));
+        assert_eq!(seq_stat(name).unwrap().loss_rate(), 0.4);
+        track_seq(name, 10, false);
+        assert_eq!(seq_counts(name), (4, 6, 0));
+
+        // 恢复后补发的缓存上报抵扣丢失，不计入重启
+        for seq in [3, 4, 6] {
+            track_seq(name, seq, true);
+        }
+        assert_eq!(seq_counts(name), (7, 3, 0));
+        track_seq(name, 11, false);
+        assert_eq!(seq_counts(name), (8, 3, 0));
+    }
+
+    #[test]
+    fn seq_restart() {
+        let name = "seq_restart";
+        for seq in 1..=3 {
+            track_seq(name, seq, false);
+        }
+        assert_eq!(seq_counts(name), (3, 0, 0));
+
+        // 客户端重启后从 1 重新开始，不计入丢失
+        track_seq(name, 1, false);
+        assert_eq!(seq_counts(name), (4, 0, 1));
+        track_seq(name, 2, false);
+        track_seq(name, 4, false);
+        assert_eq!(seq_counts(name), (6, 1, 1));
+
+        // 重启前的第一次上报丢失，从 2 开始也视为重启
+        track_seq(name, 2, false);
+        assert_eq!(seq_counts(name), (7, 1, 2));
+        // 重启后补发的旧缓存序号更大，按正常序号处理
+        track_seq(name, 5, true);
+        assert_eq!(seq_counts(name), (8, 3, 2));
+    }
+}
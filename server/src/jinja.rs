@@ -1,9 +1,127 @@
 use anyhow::Result;
-use minijinja::{value::Value, Environment, Source};
-use once_cell::sync::Lazy;
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use minijinja::{value::Value, Environment, Error, ErrorKind, Source, State};
+use once_cell::sync::{Lazy, OnceCell};
+use stat_common::units;
 use std::sync::Mutex;
 
+use crate::config::{Config, NumberFormat};
+
 pub static JINJA_ENV: Lazy<Mutex<Environment>> = Lazy::new(|| Mutex::new(Environment::new()));
+static NUMBER_FORMAT: OnceCell<&'static NumberFormat> = OnceCell::new();
+static TIMEZONE: OnceCell<Tz> = OnceCell::new();
+
+// 使用逗号作为小数点的语言
+static COMMA_DECIMAL_LANGS: &[&str] = &[
+    "de", "fr", "es", "it", "pt", "nl", "ru", "pl", "tr", "sv", "da", "fi", "nb", "cs", "uk",
+];
+
+fn format_number(v: f64) -> String {
+    let (precision, comma) = NUMBER_FORMAT
+        .get()
+        .map(|o| {
+            let lang = o.locale.split(['-', '_']).next().unwrap_or_default();
+            (o.precision, COMMA_DECIMAL_LANGS.contains(&lang))
+        })
+        .unwrap_or((1, false));
+    let s = format!("{:.*}", precision, v);
+    if comma {
+        s.replace('.', ",")
+    } else {
+        s
+    }
+}
+
+// {{ 73.24 | num }} => 73.2
+fn num(_: &State, v: f64) -> Result<String, Error> {
+    Ok(format_number(v))
+}
+
+// {{ 73.24 | pct }} => 73.2%
+fn pct(_: &State, v: f64) -> Result<String, Error> {
+    Ok(format!("{}%", format_number(v)))
+}
+
+// {{ 1536 | bytes_human }} => 1.5 KiB
+fn bytes_human(_: &State, v: f64) -> Result<String, Error> {
+    let (v, unit) = units::scale_bytes(v);
+    Ok(format!("{} {}", format_number(v), unit))
+}
+
+// 网速(字节/秒)按 bit 显示，{{ host.network_rx | bps }} => 1.0 Mbps
+fn bps(_: &State, v: f64) -> Result<String, Error> {
+    let (v, unit) = units::scale_bits_per_sec(v);
+    Ok(format!("{} {}", format_number(v), unit))
+}
+
+// 配置 timezone，未设置时为 UTC
+pub fn timezone() -> Tz {
+    TIMEZONE.get().copied().unwrap_or(Tz::UTC)
+}
+
+// 按 timezone 格式化 unix 秒，默认格式 2022-07-01 08:00:00 CST
+pub fn format_time(ts: i64, fmt: Option<&str>) -> Option<String> {
+    Utc.timestamp_opt(ts, 0).single().map(|dt| {
+        dt.with_timezone(&timezone())
+            .format(fmt.unwrap_or("%Y-%m-%d %H:%M:%S %Z"))
+            .to_string()
+    })
+}
+
+// {{ now | datetime("%Y-%m-%d %H:%M %Z") }} => 2022-07-01 08:00 CST
+fn datetime(_: &State, ts: i64, fmt: Option<String>) -> Result<String, Error> {
+    format_time(ts, fmt.as_deref()).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("invalid timestamp {}", ts),
+        )
+    })
+}
+
+pub fn init_filters(cfg: &'static Config) -> Result<()> {
+    NUMBER_FORMAT.set(&cfg.number_format).unwrap();
+    if !cfg.timezone.is_empty() {
+        let tz = cfg
+            .timezone
+            .parse::<Tz>()
+            .map_err(|err| anyhow::anyhow!("invalid timezone `{}` => {}", cfg.timezone, err))?;
+        TIMEZONE.set(tz).unwrap();
+    }
+    JINJA_ENV
+        .lock()
+        .as_mut()
+        .map(|env| add_filters(env))
+        .unwrap();
+    Ok(())
+}
+
+fn add_filters(env: &mut Environment) {
+    env.add_filter("num", num);
+    env.add_filter("pct", pct);
+    env.add_filter("bytes_human", bytes_human);
+    env.add_filter("bps", bps);
+    env.add_filter("datetime", datetime);
+}
+
+// 去掉每行首尾空白及空行
+fn tidy(content: &str) -> String {
+    content
+        .split('\n')
+        .map(|t| t.trim())
+        .filter(|&t| !t.is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+// 在临时环境中渲染，不影响已注册的模板，过滤器及输出处理与正式渲染一致
+pub fn render_preview(tpl: &str, ctx: Value) -> Result<String, Error> {
+    let mut env = Environment::new();
+    add_filters(&mut env);
+    env.add_template("preview", tpl)?;
+    let content = env.get_template("preview")?.render(ctx)?;
+    Ok(tidy(&content))
+}
 
 pub fn add_template<K, T, S>(kind: K, tag: T, tpl: S)
 where
@@ -30,14 +148,7 @@ pub fn render_template(kind: &'static str, tag: &'static str, ctx: Value) -> Res
         .map(|e| {
             e.get_template(name.as_str()).map(|tmpl| {
                 tmpl.render(ctx)
-                    .map(|content| {
-                        content
-                            .split('\n')
-                            .map(|t| t.trim())
-                            .filter(|&t| !t.is_empty())
-                            .collect::<Vec<&str>>()
-                            .join("\n")
-                    })
+                    .map(|content| tidy(&content))
                     .unwrap_or_else(|err| {
                         error!("tmpl.render err => {:?}", err);
                         "".to_string()
@@ -0,0 +1,145 @@
+#![deny(warnings)]
+use anyhow::{Context, Result};
+use futures::channel::mpsc;
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+
+// 同 hyper AddrIncoming，accept 出错(如 EMFILE/ENFILE)后等待再重试，避免空转
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+const ACCEPT_ERROR_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+// 客户端地址，http 请求通过 extensions 传递
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddr(pub Option<SocketAddr>);
+
+pub trait PeerAddr {
+    fn peer(&self) -> Option<SocketAddr>;
+}
+
+impl PeerAddr for TcpStream {
+    fn peer(&self) -> Option<SocketAddr> {
+        self.peer_addr().ok()
+    }
+}
+
+impl PeerAddr for tokio_rustls::server::TlsStream<TcpStream> {
+    fn peer(&self) -> Option<SocketAddr> {
+        self.get_ref().0.peer_addr().ok()
+    }
+}
+
+#[cfg(unix)]
+impl PeerAddr for tokio::net::UnixStream {
+    fn peer(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+// 双栈监听时 ipv4 客户端地址为 ::ffff:a.b.c.d，还原为 ipv4
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    if let IpAddr::V6(v6) = ip {
+        let s = v6.segments();
+        if s[..5].iter().all(|&o| o == 0) && s[5] == 0xffff {
+            let o = v6.octets();
+            return IpAddr::V4(Ipv4Addr::new(o[12], o[13], o[14], o[15]));
+        }
+    }
+    ip
+}
+
+// 单独监听 [::] 时为双栈，同时监听多个地址时 ipv6 设置 only_v6 避免与 0.0.0.0 端口冲突
+pub fn bind(addrs: &[String]) -> Result<Vec<TcpListener>> {
+    let only_v6 = addrs.len() > 1;
+    addrs
+        .iter()
+        .map(|addr| {
+            let sock_addr: SocketAddr = addr
+                .parse()
+                .with_context(|| format!("invalid listen addr `{}`", addr))?;
+            let socket = Socket::new(Domain::for_address(sock_addr), Type::STREAM, None)?;
+            if sock_addr.is_ipv6() {
+                socket.set_only_v6(only_v6)?;
+            }
+            socket.set_reuse_address(true)?;
+            socket.set_nonblocking(true)?;
+            socket
+                .bind(&sock_addr.into())
+                .with_context(|| format!("can't bind `{}`", addr))?;
+            socket.listen(1024)?;
+            Ok(TcpListener::from_std(socket.into())?)
+        })
+        .collect()
+}
+
+// 对端在 accept 前断开等单个连接的错误，不影响后续 accept
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+// 每个 accept 循环一个，日志每分钟最多一条
+#[derive(Default)]
+pub struct AcceptBackoff {
+    last_log: Option<Instant>,
+    suppressed: u64,
+}
+
+impl AcceptBackoff {
+    pub async fn on_error(&mut self, err: io::Error) {
+        if is_connection_error(&err) {
+            return;
+        }
+        if self
+            .last_log
+            .map_or(true, |t| t.elapsed() >= ACCEPT_ERROR_LOG_INTERVAL)
+        {
+            error!(
+                "accept error => {:?}, retry in {:?} ({} suppressed)",
+                err, ACCEPT_ERROR_DELAY, self.suppressed
+            );
+            self.last_log = Some(Instant::now());
+            self.suppressed = 0;
+        } else {
+            self.suppressed += 1;
+        }
+        tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+    }
+}
+
+pub fn allow_all(_: IpAddr) -> bool {
+    true
+}
+
+// filter 返回 false 的连接直接关闭
+pub fn incoming(
+    addrs: &[String],
+    filter: fn(IpAddr) -> bool,
+) -> Result<mpsc::UnboundedReceiver<io::Result<TcpStream>>> {
+    let (tx, rx) = mpsc::unbounded();
+    for listener in bind(addrs)? {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut backoff = AcceptBackoff::default();
+            while !tx.is_closed() {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        if !filter(peer.ip()) {
+                            continue;
+                        }
+                        let _ = stream.set_nodelay(true);
+                        let _ = tx.unbounded_send(Ok(stream));
+                    }
+                    Err(err) => backoff.on_error(err).await,
+                }
+            }
+        });
+    }
+    Ok(rx)
+}
@@ -0,0 +1,95 @@
+#![deny(warnings)]
+use anyhow::{bail, Result};
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value, Visitor};
+use log::{Log, Metadata, Record};
+use once_cell::sync::Lazy;
+use serde_json::Map;
+use std::env;
+use std::io::Write;
+use std::sync::RwLock;
+
+pub const LOG_FORMAT_TEXT: &str = "text";
+pub const LOG_FORMAT_JSON: &str = "json";
+
+// 读取配置前就需要输出日志，先用 text 格式，加载配置后再替换
+struct Dispatch(RwLock<Box<dyn Log>>);
+
+impl Log for Dispatch {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.0.read().unwrap().flush()
+    }
+}
+
+static LOGGER: Lazy<Dispatch> = Lazy::new(|| Dispatch(RwLock::new(text_logger(""))));
+
+fn text_logger(level: &str) -> Box<dyn Log> {
+    let mut builder = pretty_env_logger::formatted_builder();
+    parse_filters(&mut builder, level);
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    Box::new(logger)
+}
+
+// 每行一个 json 对象，日志调用处附加的 key = value 作为独立字段
+fn json_logger(level: &str) -> Box<dyn Log> {
+    let mut builder = pretty_env_logger::env_logger::Builder::new();
+    builder.format(|f, record| {
+        let mut obj = Map::new();
+        obj.insert(
+            "timestamp".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        obj.insert("level".into(), record.level().as_str().into());
+        obj.insert("target".into(), record.target().into());
+        obj.insert("message".into(), record.args().to_string().into());
+        let _ = record.key_values().visit(&mut Fields(&mut obj));
+        writeln!(f, "{}", serde_json::Value::Object(obj))
+    });
+    parse_filters(&mut builder, level);
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    Box::new(logger)
+}
+
+// RUST_LOG 优先，未设置时使用配置的 log_level
+fn parse_filters(builder: &mut pretty_env_logger::env_logger::Builder, level: &str) {
+    match env::var("RUST_LOG") {
+        Ok(s) => builder.parse_filters(&s),
+        Err(_) => builder.parse_filters(level),
+    };
+}
+
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> Visitor<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), value.to_string().into());
+        Ok(())
+    }
+}
+
+pub fn init() {
+    log::set_logger(&*LOGGER).expect("logger already set");
+}
+
+// 按配置切换日志格式和级别
+pub fn reload(format: &str, level: &str) -> Result<()> {
+    let logger = match format {
+        LOG_FORMAT_TEXT => text_logger(level),
+        LOG_FORMAT_JSON => json_logger(level),
+        _ => bail!("invalid log_format `{}`, must be text or json", format),
+    };
+    *LOGGER.0.write().unwrap() = logger;
+    Ok(())
+}
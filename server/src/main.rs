@@ -2,32 +2,61 @@
 // #![allow(unused)]
 #[macro_use]
 extern crate log;
-extern crate pretty_env_logger;
 #[macro_use]
 extern crate prettytable;
-use bytes::Buf;
 use clap::Parser;
 use http_auth_basic::Credentials;
+use listener::{canonical_ip, ClientAddr, PeerAddr};
 use minijinja::context;
 use once_cell::sync::OnceCell;
+use payload::{HostStat, StatsResp, Summary};
 use prost::Message;
 use rust_embed::RustEmbed;
-use stat_common::server_status::StatRequest;
+use stat_common::server_status::{Heartbeat, Hello, StatRequest};
+use stat_common::sign::{NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER, USER_HEADER};
+use stat_common::{CAP_HEARTBEAT, REPORT_TYPE_HEADER, REPORT_TYPE_HELLO};
 use std::collections::HashMap;
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::runtime::Handle;
 
+mod audit;
+mod bans;
 mod config;
+mod export;
+mod geoip;
 mod grpc;
+mod handshake;
+mod health;
+mod hostinfo;
+mod ingest;
 mod jinja;
+mod listener;
+mod logger;
 mod notifier;
 mod payload;
+mod reports;
+mod schema;
+mod sla;
+mod sse;
 mod stats;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod tls;
+mod traffic;
+#[cfg(unix)]
+mod unix_socket;
+mod uptime;
+mod ws;
 
+use hyper::body::HttpBody;
+use hyper::server::accept::{self, Accept};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
 type GenericError = Box<dyn std::error::Error + Send + Sync>;
@@ -56,73 +85,502 @@ struct Args {
     notify_test: bool,
     #[clap(long = "cloud", help = "cloud mode, load cfg from env var: SRV_CONF")]
     cloud: bool,
+    #[clap(
+        long = "trigger-custom",
+        value_name = "HOST",
+        help = "render custom notify for host from saved stats"
+    )]
+    trigger_custom: Option<String>,
+    #[clap(long = "trigger-kind", help = "only this notifier, eg: tgbot")]
+    trigger_kind: Option<String>,
+    #[clap(
+        long = "trigger-send",
+        help = "also send the rendered notify, default:false"
+    )]
+    trigger_send: bool,
+    #[clap(
+        long = "dump-default-templates",
+        help = "print built-in notify templates, default:false"
+    )]
+    dump_default_templates: bool,
+    #[clap(
+        long = "render-template",
+        number_of_values = 2,
+        value_names = &["KIND", "EVENT"],
+        requires = "data",
+        help = "render notify template with host data from --data, eg: tgbot offline"
+    )]
+    render_template: Vec<String>,
+    #[clap(
+        long = "data",
+        value_name = "FILE",
+        help = "host data for --render-template, from GET /api/host/{name}/dump"
+    )]
+    data: Option<String>,
 }
 
-// stat report
-async fn stats_report(req: Request<Body>) -> Result<Response<Body>> {
+// 签名认证模式的 (user, timestamp, nonce)
+type SignedHeaders = (String, String, String);
+
+// 上报认证，返回用户及签名认证模式的请求头，失败时为 403/401
+// 签名认证模式只检查用户已配置 hmac_secret，签名由调用方在读取请求体后校验
+// POST /report 及 ws 上报的升级请求共用
+fn report_auth(
+    req: &Request<Body>,
+    audit: &mut audit::Record,
+) -> std::result::Result<(String, Option<SignedHeaders>), StatusCode> {
+    if !client_ip(req).map_or(true, ingest::check_ip) {
+        audit.fail("banned ip");
+        return Err(StatusCode::FORBIDDEN);
+    }
     let req_header = req.headers();
-    // auth
-    let mut auth_ok = false;
-    if let Some(auth) = req_header.get(hyper::header::AUTHORIZATION) {
-        let auth_header_value = auth.to_str()?.to_string();
+    let header_str = |name: &str| {
+        req_header
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    // 签名认证模式不带 basic auth，读取请求体后校验签名
+    let signed = match (
+        header_str(USER_HEADER),
+        header_str(TIMESTAMP_HEADER),
+        header_str(NONCE_HEADER),
+    ) {
+        (Some(user), Some(ts), Some(nonce))
+            if req_header.get(hyper::header::AUTHORIZATION).is_none() =>
+        {
+            Some((user, ts, nonce))
+        }
+        _ => None,
+    };
+    let mut auth_user = None;
+    audit.entry.auth = Some(false);
+    audit.fail("missing credentials");
+    if let Some((user, _, _)) = &signed {
+        audit.entry.user = Some(user.to_string());
+        if G_CONFIG.get().unwrap().hmac_secret(user).is_some() {
+            auth_user = Some(user.to_string());
+        } else {
+            warn!(host = user; "reject signed report from `{}` without hmac_secret", user);
+            audit.fail("unknown user");
+        }
+    } else if let Some(auth_header_value) = header_str(hyper::header::AUTHORIZATION.as_str()) {
         if let Ok(credentials) = Credentials::from_header(auth_header_value) {
+            audit.entry.user = Some(credentials.user_id.to_string());
             if let Some(cfg) = G_CONFIG.get() {
-                auth_ok = cfg.auth(&credentials.user_id, &credentials.password);
+                match cfg.auth(&credentials.user_id, &credentials.password) {
+                    Ok(_) => auth_user = Some(credentials.user_id),
+                    Err(reason) => audit.fail(reason),
+                }
+            }
+        }
+    }
+    match auth_user {
+        // 签名认证模式在校验签名后记录
+        Some(user) if signed.is_some() => Ok((user, signed)),
+        Some(user) => {
+            audit.entry.auth = Some(true);
+            audit.entry.reason = None;
+            if let Some(ip) = client_ip(req) {
+                bans::success(ip);
+            }
+            Ok((user, None))
+        }
+        None => {
+            let ip = client_ip(req);
+            warn!(ip = ip.map(|ip| ip.to_string()); "report auth fail from {:?}", ip);
+            if let Some(ip) = ip {
+                bans::failure(ip);
             }
+            Err(StatusCode::UNAUTHORIZED)
         }
     }
-    if !auth_ok {
+}
+
+fn auth_rejected(status: StatusCode) -> Result<Response<Body>> {
+    let body = match status {
+        StatusCode::UNAUTHORIZED => UNAUTHORIZED.into(),
+        _ => Body::empty(),
+    };
+    Ok(Response::builder().status(status).body(body)?)
+}
+
+// stat report
+async fn stats_report(req: Request<Body>) -> Result<Response<Body>> {
+    if stats::is_shutting_down() {
         return Ok(Response::builder()
-            .status(StatusCode::UNAUTHORIZED)
-            .body(UNAUTHORIZED.into())?);
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::empty())?);
+    }
+    let mut audit = audit::Record::http(client_ip(&req), G_CONFIG.get().unwrap().tls_enabled());
+    let (user, signed) = match report_auth(&req, &mut audit) {
+        Ok(auth) => auth,
+        Err(status) => return auth_rejected(status),
+    };
+    let req_header = req.headers();
+    let header_str = |name: &str| {
+        req_header
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+
+    // 限速及大小检查，在解码前进行
+    let ip = client_ip(&req);
+    let conn = req
+        .extensions()
+        .get::<ClientAddr>()
+        .and_then(|o| o.0)
+        .map(|addr| addr.to_string());
+    let signature = header_str(SIGNATURE_HEADER);
+    // 缺省为完整上报，未知的消息类型直接拒绝
+    let report_type = match req_header.get(REPORT_TYPE_HEADER).map(|v| v.to_str()) {
+        None => None,
+        Some(Ok(t @ (CAP_HEARTBEAT | REPORT_TYPE_HELLO))) => Some(t.to_string()),
+        Some(_) => {
+            let reason = ingest::Reject::Invalid(REPORT_TYPE_HEADER);
+            audit.fail(reason);
+            return reject_report(reason, &user, ip);
+        }
+    };
+    let content_length = req_header
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or_default();
+    if let Err(reason) = ingest::check_rate(&user).and_then(|_| ingest::check_size(content_length))
+    {
+        audit.fail(reason);
+        return reject_report(reason, &user, ip);
     }
-    // auth end
 
-    let mut json_data: Option<serde_json::Value> = None;
+    let mut stat_req: Option<StatRequest> = None;
     if let Ok(content_type) = req_header
         .get(hyper::header::CONTENT_TYPE)
         .unwrap()
         .clone()
         .to_str()
     {
-        let whole_body = hyper::body::aggregate(req).await?;
+        let mut body = req.into_body();
+        let mut buf = bytes::BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk?;
+            audit.entry.bytes += chunk.len() as u64;
+            if let Err(reason) = ingest::check_size(buf.len() + chunk.len()) {
+                audit.fail(reason);
+                return reject_report(reason, &user, ip);
+            }
+            buf.extend_from_slice(&chunk);
+        }
+        let check = ingest::check_signature(
+            &user,
+            &buf,
+            signature.as_deref(),
+            signed
+                .as_ref()
+                .map(|(_, ts, nonce)| (ts.as_str(), nonce.as_str())),
+        );
+        if signed.is_some() {
+            audit.entry.auth = Some(check.is_ok());
+            if let Some(ip) = ip {
+                match check {
+                    Ok(_) => bans::success(ip),
+                    Err(_) => bans::failure(ip),
+                }
+            }
+        }
+        if let Err(reason) = check {
+            audit.fail(reason);
+            return reject_report(reason, &user, ip);
+        }
+        audit.entry.reason = None;
+        let whole_body = buf.freeze();
+        match report_type.as_deref() {
+            Some(CAP_HEARTBEAT) => {
+                return heartbeat_report(&user, content_type, whole_body, ip);
+            }
+            Some(_) => {
+                return hello_report(&user, content_type, whole_body, ip, conn.as_deref());
+            }
+            None => {}
+        }
         // dbg!(content_type);
         if content_type.eq(&mime::APPLICATION_JSON.to_string()) {
-            // json
-            json_data = Some(serde_json::from_reader(whole_body.reader())?);
+            // json，字段名与 proto 一致(也接受 lowerCamelCase)，见 /api/schema/stat_request.json
+            match schema::decode_stat_request(&whole_body) {
+                Ok(stat) => stat_req = Some(stat),
+                Err(reason) => {
+                    audit.fail(reason);
+                    return reject_report(reason, &user, ip);
+                }
+            }
         } else if content_type.eq(&mime::APPLICATION_OCTET_STREAM.to_string()) {
             // protobuf
-            let stat = StatRequest::decode(whole_body)?;
-            json_data = Some(serde_json::to_value(stat)?);
+            let mut stat = StatRequest::decode(whole_body)?;
+            ingest::mark_absent(&mut stat);
+            stat_req = Some(stat);
         }
     }
 
+    let mut stat = stat_req.unwrap();
+    if let Err(reason) = ingest::normalize_units(&mut stat) {
+        audit.fail(reason);
+        return reject_report(reason, &user, ip);
+    }
+    ingest::track_seq(&stat.name, stat.seq, stat.buffered);
+    // 增量上报还原为绝对值，基准不一致时要求客户端改为完整上报
+    let delta_base = match ingest::apply_delta(&mut stat) {
+        Some(id) => id,
+        None => {
+            info!(host = stat.name.as_str(); "delta base mismatch from `{}`, full report required", stat.name);
+            let interval_ms = G_CONFIG.get().unwrap().report_interval_ms(&stat.name);
+            return report_resp(&user, 2, Some("full report required"), interval_ms, 0);
+        }
+    };
+    let mut json_data = serde_json::to_value(stat)?;
+    // 签名只证明持有该用户的密钥，上报的主机名须与之一致
+    if signed.is_some() && json_data["name"].as_str() != Some(user.as_str()) {
+        let reason = ingest::Reject::Invalid("name");
+        audit.fail(reason);
+        return reject_report(reason, &user, ip);
+    }
+    let host_name = json_data["sys_info"]["host_name"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    if let Err(reason) = ingest::validate(&user, &mut json_data)
+        .and_then(|_| ingest::check_identity(&user, ip, &host_name))
+    {
+        audit.fail(reason);
+        return reject_report(reason, &user, ip);
+    }
+    let interval_ms = json_data["name"]
+        .as_str()
+        .map_or(0, |name| G_CONFIG.get().unwrap().report_interval_ms(name));
+
     // report
     if let Some(mgr) = G_STATS_MGR.get() {
-        mgr.report(json_data.unwrap())?;
+        mgr.report(json_data, ip)?;
     }
 
-    let mut resp = HashMap::new();
-    resp.insert(&"code", serde_json::Value::from(0_i32));
-    let resp_str = serde_json::to_string(&resp)?;
+    report_resp(&user, 0, None, interval_ms, delta_base)
+}
+
+fn report_resp(
+    user: &str,
+    code: i32,
+    message: Option<&str>,
+    interval_ms: u64,
+    delta_base: u32,
+) -> Result<Response<Body>> {
+    let mut resp = serde_json::json!({
+        "code": code,
+        "interval_ms": interval_ms,
+        "capabilities": handshake::capabilities(user),
+        "delta_base": delta_base,
+    });
+    if let Some(message) = message {
+        resp["message"] = message.into();
+    }
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(resp.to_string()))?)
+}
 
+// 心跳只刷新在线状态，主机尚无数据或已离线时返回 code 1，客户端随后改为完整上报
+fn heartbeat_report(
+    user: &str,
+    content_type: &str,
+    body: bytes::Bytes,
+    ip: Option<std::net::IpAddr>,
+) -> Result<Response<Body>> {
+    let hb = if content_type.eq(mime::APPLICATION_JSON.essence_str()) {
+        serde_json::from_slice::<Heartbeat>(&body)?
+    } else {
+        Heartbeat::decode(body)?
+    };
+    if hb.name != user {
+        return reject_report(ingest::Reject::Invalid("name"), user, ip);
+    }
+    if let Err(reason) = ingest::check_heartbeat_identity(user, ip) {
+        return reject_report(reason, user, ip);
+    }
+    ingest::track_seq(user, hb.seq, false);
+    let interval_ms = G_CONFIG.get().unwrap().report_interval_ms(user);
+    if G_STATS_MGR.get().map_or(false, |mgr| mgr.heartbeat(user)) {
+        report_resp(user, 0, None, interval_ms, 0)
+    } else {
+        report_resp(user, 1, Some("full report required"), interval_ms, 0)
+    }
+}
+
+// 握手，返回协商后的功能及该主机的采集设置
+fn hello_report(
+    user: &str,
+    content_type: &str,
+    body: bytes::Bytes,
+    ip: Option<std::net::IpAddr>,
+    conn: Option<&str>,
+) -> Result<Response<Body>> {
+    let hello = if content_type.eq(mime::APPLICATION_JSON.essence_str()) {
+        serde_json::from_slice::<Hello>(&body)?
+    } else {
+        Hello::decode(body)?
+    };
+    if hello.name != user {
+        return reject_report(ingest::Reject::Invalid("name"), user, ip);
+    }
+    let resp = handshake::negotiate(&hello, conn);
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(resp_str))?)
+        .body(Body::from(serde_json::to_string(&resp)?))?)
+}
+
+fn reject_report(
+    reason: ingest::Reject,
+    user: &str,
+    ip: Option<std::net::IpAddr>,
+) -> Result<Response<Body>> {
+    ingest::reject(reason, user, ip);
+    let status = match reason {
+        ingest::Reject::RateLimit => StatusCode::TOO_MANY_REQUESTS,
+        ingest::Reject::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        ingest::Reject::Invalid(_) => StatusCode::BAD_REQUEST,
+        ingest::Reject::BadSignature | ingest::Reject::Expired | ingest::Reject::Replay => {
+            StatusCode::UNAUTHORIZED
+        }
+        ingest::Reject::Conflict => StatusCode::CONFLICT,
+    };
+    Ok(Response::builder()
+        .status(status)
+        .body(reason.to_string().into())?)
+}
+
+// GET /admin/report_drops
+async fn get_report_drops(req: Request<Body>) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&ingest::drop_counters())?))?)
 }
 
 // get json data
-async fn get_stats_json() -> Result<Response<Body>> {
+async fn get_stats_json(req: Request<Body>) -> Result<Response<Body>> {
+    let body = if query_flag(&req, "include_hidden") {
+        let resp = G_STATS_MGR.get().unwrap().get_stats();
+        let o = resp.lock().unwrap();
+        serde_json::to_string(&*o)?
+    } else {
+        G_STATS_MGR.get().unwrap().get_stats_json()
+    };
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))?)
+}
+
+// get json data, filter by `?label=k:v`
+async fn get_stats_api(req: Request<Body>) -> Result<Response<Body>> {
+    let selectors = query_params(&req)
+        .into_iter()
+        .filter(|(k, _)| k.eq("label"))
+        .map(|(_, v)| v)
+        .collect::<Vec<_>>();
+    let include_hidden = query_flag(&req, "include_hidden");
+    // 增量拉取，只返回 since(unix 秒)之后有上报或转为离线的主机
+    let since = query_params(&req)
+        .into_iter()
+        .find(|(k, _)| k.eq("since"))
+        .and_then(|(_, v)| v.parse::<u64>().ok());
+
+    let resp = G_STATS_MGR.get().unwrap().get_stats();
+    let o = resp.lock().unwrap();
+    let mut filtered = StatsResp::new();
+    filtered.updated = o.updated;
+    filtered.servers = o
+        .servers
+        .iter()
+        .filter(|stat| selectors.iter().all(|s| stat.match_label(s)))
+        .cloned()
+        .collect();
+    // 汇总是否计入隐藏主机只由 summary_include_hidden 决定，与 include_hidden 无关
+    let cfg = G_CONFIG.get().unwrap();
+    let by_os = query_flag(&req, "by_os") || cfg.summary_by_os;
+    filtered.summary = Summary::compute(filtered.servers.iter(), cfg.summary_include_hidden, by_os);
+    filtered
+        .servers
+        .retain(|stat| include_hidden || !stat.hidden);
+
+    let since = match since {
+        Some(since) => since,
+        None => {
+            return Ok(Response::builder()
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(&filtered)?))?)
+        }
+    };
+    // 全部主机名，用于前端移除已删除的主机
+    let names = filtered
+        .servers
+        .iter()
+        .map(|stat| stat.name.to_string())
+        .collect::<Vec<_>>();
+    filtered.servers.retain(|stat| {
+        stat.latest_ts >= since
+            || (!stat.online() && stat.latest_ts + stat.offline_timeout >= since)
+    });
+    let mut v = serde_json::to_value(&filtered)?;
+    v["names"] = names.into();
+    // 下次请求的 since，时间精度为秒，同一秒内的上报可能重复返回
+    v["next_since"] = filtered.updated.into();
     Ok(Response::builder()
         .header(header::CONTENT_TYPE, "application/json")
-        .body(Body::from(G_STATS_MGR.get().unwrap().get_stats_json()))?)
+        .body(Body::from(v.to_string()))?)
+}
+
+fn client_ip(req: &Request<Body>) -> Option<std::net::IpAddr> {
+    req.extensions()
+        .get::<ClientAddr>()
+        .and_then(|o| o.0)
+        .map(|addr| canonical_ip(addr.ip()))
+}
+
+fn query_params(req: &Request<Body>) -> Vec<(String, String)> {
+    req.uri()
+        .query()
+        .map(|q| {
+            url::form_urlencoded::parse(q.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 路径中的主机名，如 /api/host/{name}/dump，按 url 编码还原(主机名可含空格、中文等)
+fn path_host_name(path: &str, prefix: &str, suffix: &str) -> Option<String> {
+    let name = path.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    percent_encoding::percent_decode_str(name)
+        .decode_utf8()
+        .ok()
+        .map(|name| name.into_owned())
+}
+
+fn query_flag(req: &Request<Body>, key: &str) -> bool {
+    query_params(req)
+        .iter()
+        .any(|(k, v)| k.eq(key) && (v.eq("true") || v.eq("1")))
 }
 
 // admin auth
 fn is_admin(req: &Request<Body>) -> bool {
     if let Some(auth) = req.headers().get(hyper::header::AUTHORIZATION) {
-        let auth_header_value = auth.to_str().unwrap().to_string();
+        let auth_header_value = auth.to_str().unwrap_or_default().to_string();
+        if let Some(token) = auth_header_value.strip_prefix("Bearer ") {
+            return G_CONFIG.get().unwrap().admin_token_auth(token.trim());
+        }
         if let Ok(credentials) = Credentials::from_header(auth_header_value) {
             if let Some(cfg) = G_CONFIG.get() {
                 return cfg.admin_auth(&credentials.user_id, &credentials.password);
@@ -132,6 +590,30 @@ fn is_admin(req: &Request<Body>) -> bool {
     false
 }
 
+// web auth, admin can also view
+fn is_viewer(req: &Request<Body>) -> bool {
+    let cfg = G_CONFIG.get().unwrap();
+    if !cfg.web_auth_enabled() {
+        return true;
+    }
+    if let Some(auth) = req.headers().get(hyper::header::AUTHORIZATION) {
+        let auth_header_value = auth.to_str().unwrap_or_default().to_string();
+        if let Ok(credentials) = Credentials::from_header(auth_header_value) {
+            if cfg.web_auth(&credentials.user_id, &credentials.password) {
+                return true;
+            }
+        }
+    }
+    is_admin(req)
+}
+
+fn unauthorized() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .header(header::WWW_AUTHENTICATE, "Basic realm=\"Restricted\"")
+        .status(StatusCode::UNAUTHORIZED)
+        .body(UNAUTHORIZED.into())?)
+}
+
 fn init_jinja_tpl() -> Result<()> {
     let detail_data = Asset::get("/jinja/detail.jinja.html").expect("detail.jinja.html not found");
     let detail_html: String = String::from_utf8(detail_data.data.try_into()?).unwrap();
@@ -152,10 +634,7 @@ fn init_jinja_tpl() -> Result<()> {
 //
 async fn render_jinja_ht_tpl(tag: &'static str, req: Request<Body>) -> Result<Response<Body>> {
     if !is_admin(&req) {
-        return Ok(Response::builder()
-            .header(header::WWW_AUTHENTICATE, "Basic realm=\"Restricted\"")
-            .status(StatusCode::UNAUTHORIZED)
-            .body(UNAUTHORIZED.into())?);
+        return unauthorized();
     }
 
     // skip_serializing
@@ -163,15 +642,22 @@ async fn render_jinja_ht_tpl(tag: &'static str, req: Request<Body>) -> Result<Re
     let o = resp.lock().unwrap();
     let mut sys_info_list = Vec::new();
     let mut ip_info_list = Vec::new();
+    let mut notes_list = Vec::new();
     for stat in &*o.servers {
         ip_info_list.push(stat.ip_info.as_ref());
         sys_info_list.push(stat.sys_info.as_ref());
+        notes_list.push(stat.notes.as_str());
     }
 
     Ok(jinja::render_template(
         "main",
         tag,
-        context!(resp => &*o, ip_info_list => ip_info_list, sys_info_list => sys_info_list),
+        context!(
+            resp => &*o,
+            ip_info_list => ip_info_list,
+            sys_info_list => sys_info_list,
+            notes_list => notes_list
+        ),
     )
     .map(|contents| {
         Response::builder()
@@ -185,13 +671,572 @@ async fn render_jinja_ht_tpl(tag: &'static str, req: Request<Body>) -> Result<Re
     ))
 }
 
+// DELETE /admin/host/{name}
+async fn delete_host(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let name = path_host_name(path, "/admin/host/", "").unwrap_or_default();
+    let name = name.as_str();
+    if !G_STATS_MGR.get().unwrap().remove_host(name) {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(NOTFOUND.into())?);
+    }
+    hostinfo::remove(name);
+    uptime::remove(name);
+    info!(host = name; "remove host `{}` runtime state", name);
+
+    let mut resp = HashMap::new();
+    resp.insert(&"code", serde_json::Value::from(0_i32));
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&resp)?))?)
+}
+
+// GET /api/host/{name}/info
+async fn get_host_info(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let name = path_host_name(path, "/api/host/", "/info").unwrap_or_default();
+    match hostinfo::get(&name) {
+        Some(info) => Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&info)?))?),
+        None => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(NOTFOUND.into())?),
+    }
+}
+
+// GET /api/host/{name}/dump 导出主机当前数据，用于 --render-template --data 离线调试模板
+async fn get_host_dump(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let name = path_host_name(path, "/api/host/", "/dump").unwrap_or_default();
+    let stat = match G_STATS_MGR.get().unwrap().get_host_stats().remove(&name) {
+        Some(stat) => stat,
+        None => return json_error(StatusCode::NOT_FOUND, format!("unknown host `{}`", name)),
+    };
+    let dump = notifier::HostDump::capture(&stat)?;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_DISPOSITION, attachment(&name, "json"))
+        .body(Body::from(serde_json::to_string_pretty(&dump)?))?)
+}
+
+// 下载文件名，filename 只保留安全字符，原名(含引号、换行等)以 RFC 5987 编码放在 filename* 中
+fn attachment(name: &str, ext: &str) -> String {
+    let ascii = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    format!(
+        "attachment; filename=\"{}.{}\"; filename*=UTF-8''{}.{}",
+        ascii,
+        ext,
+        percent_encoding::utf8_percent_encode(name, percent_encoding::NON_ALPHANUMERIC),
+        ext
+    )
+}
+
+// GET /api/host/{name}/uptime?days=30
+async fn get_host_uptime(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    if !is_viewer(&req) {
+        return unauthorized();
+    }
+
+    let cfg = G_CONFIG.get().unwrap();
+    let name = path_host_name(path, "/api/host/", "/uptime").unwrap_or_default();
+    let days = query_params(&req)
+        .into_iter()
+        .find(|(k, _)| k.eq("days"))
+        .and_then(|(_, v)| v.parse::<u64>().ok())
+        .unwrap_or(30)
+        .clamp(1, cfg.uptime_retention_days.max(1));
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    match uptime::report(&name, days, now, cfg.uptime_server_down) {
+        Some(report) => Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&report)?))?),
+        None => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(NOTFOUND.into())?),
+    }
+}
+
+// GET /api/host/{name}/history?from=&to=, 默认最近 1 小时
+async fn get_host_history(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    if !is_viewer(&req) {
+        return unauthorized();
+    }
+
+    let name = path_host_name(path, "/api/host/", "/history").unwrap_or_default();
+    let params = query_params(&req);
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq(key))
+            .and_then(|(_, v)| v.parse::<u64>().ok())
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let to = param("to").unwrap_or(now);
+    let from = param("from").unwrap_or_else(|| to.saturating_sub(3600));
+    let history = match storage::query_history(&name, from, to).await {
+        Ok(history) => history,
+        Err(err) => return json_error(StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+    };
+    let history = history
+        .into_iter()
+        .filter_map(|(ts, data)| {
+            serde_json::from_str::<serde_json::Value>(&data)
+                .ok()
+                .map(|stat| serde_json::json!({"ts": ts, "stat": stat}))
+        })
+        .collect::<Vec<_>>();
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({"code": 0, "host": name, "history": history}).to_string(),
+        ))?)
+}
+
+fn json_error(status: StatusCode, error: String) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({"code": status.as_u16(), "error": error}).to_string(),
+        ))?)
+}
+
+// POST /admin/trigger-custom/{host}?kind=tgbot&send=true
+async fn trigger_custom(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let name = path_host_name(path, "/admin/trigger-custom/", "").unwrap_or_default();
+    let name = name.as_str();
+    let params = query_params(&req);
+    let kind = params
+        .iter()
+        .find(|(k, _)| k.eq("kind"))
+        .map(|(_, v)| v.as_str());
+    let send = params
+        .iter()
+        .any(|(k, v)| k.eq("send") && (v.eq("true") || v.eq("1")));
+
+    let mgr = G_STATS_MGR.get().unwrap();
+    let stat = match mgr.get_host_stats().remove(name) {
+        Some(stat) => stat,
+        None => return json_error(StatusCode::NOT_FOUND, format!("unknown host `{}`", name)),
+    };
+    let notifiers = mgr.get_notifiers();
+    let notifiers = notifiers.lock().unwrap();
+    if let Some(kind) = kind {
+        if !notifiers.iter().any(|o| o.kind() == kind) {
+            return json_error(
+                StatusCode::BAD_REQUEST,
+                format!("notifier `{}` not enabled", kind),
+            );
+        }
+    }
+    let results = notifier::trigger(
+        &notifiers,
+        kind,
+        &notifier::Event::Custom,
+        &stat,
+        send,
+        false,
+    );
+    info!(host = name; "trigger custom notify for `{}`, send => {}", name, send);
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({"code": 0, "host": name, "results": results}).to_string(),
+        ))?)
+}
+
+// 未上报过的主机，用配置中的信息构造
+fn fake_stat(host: &config::Host) -> HostStat {
+    HostStat {
+        name: host.name.to_string(),
+        alias: host.alias.to_string(),
+        notes: host.notes.to_string(),
+        host_type: host.host_type.to_string(),
+        group: host.group.to_string(),
+        location: host.location.to_string(),
+        region: host.region.to_string(),
+        country_code: host.country_code.clone().unwrap_or_default(),
+        labels: host.labels.clone(),
+        latest_ts: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        ..Default::default()
+    }
+}
+
+// POST /api/v1/test-alert?host=h1&kind=tgbot&event=down[&fake=1]
+// 按正常路由发送，kind 为空时为全部渠道；主机未上报过或 fake=1 时使用构造的数据
+async fn test_alert(req: Request<Body>) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let params = query_params(&req);
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq(key))
+            .map(|(_, v)| v.as_str())
+    };
+    let name = match param("host") {
+        Some(name) => name,
+        None => return json_error(StatusCode::BAD_REQUEST, "missing `host`".to_string()),
+    };
+    // down/up 为 offline/online 的别名
+    let event = match param("event").unwrap_or("down") {
+        "down" => "offline",
+        "up" => "online",
+        o => o,
+    };
+    let e = match notifier::Event::from_tag(event) {
+        Some(e) => e,
+        None => {
+            return json_error(
+                StatusCode::BAD_REQUEST,
+                format!("unknown event `{}`", event),
+            )
+        }
+    };
+    let kind = param("kind");
+
+    let mgr = G_STATS_MGR.get().unwrap();
+    let current = match query_flag(&req, "fake") {
+        true => None,
+        false => mgr.get_host_stats().remove(name),
+    };
+    let (stat, fake) = match (current, G_CONFIG.get().unwrap().hosts_map.get(name)) {
+        (Some(stat), _) => (stat, false),
+        (None, Some(host)) => (fake_stat(host), true),
+        (None, None) => {
+            return json_error(StatusCode::NOT_FOUND, format!("unknown host `{}`", name))
+        }
+    };
+    let notifiers = mgr.get_notifiers();
+    let notifiers = notifiers.lock().unwrap();
+    if let Some(kind) = kind {
+        if !notifiers.iter().any(|o| o.kind() == kind) {
+            return json_error(
+                StatusCode::BAD_REQUEST,
+                format!("notifier `{}` not enabled", kind),
+            );
+        }
+    }
+    let results = notifier::trigger(&notifiers, kind, &e, &stat, true, true);
+    info!(host = name; "test alert `{}` for `{}`, kind => {:?}", event, name, kind);
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({"code": 0, "host": name, "event": event, "fake": fake, "results": results})
+                .to_string(),
+        ))?)
+}
+
+// 模板预览限制，渲染线程无法中断，超时后仅放弃等待
+const PREVIEW_MAX_TPL_SIZE: usize = 64 * 1024;
+const PREVIEW_TIMEOUT: Duration = Duration::from_secs(2);
+// 超时后渲染线程无法中断，同一时间只允许一个预览渲染，避免阻塞线程堆积
+static PREVIEW_RUNNING: AtomicBool = AtomicBool::new(false);
+
+// 渲染结束(含 panic)时释放
+struct PreviewSlot;
+
+impl Drop for PreviewSlot {
+    fn drop(&mut self) {
+        PREVIEW_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct PreviewReq {
+    tpl: String,
+    host: String,
+    // online/offline/custom/register/conflict/degraded/recovered/removed/sla_budget/missing/os_changed
+    #[serde(default)]
+    event: Option<String>,
+}
+
+// POST /admin/render-preview {"tpl": "...", "host": "h1", "event": "custom"}
+async fn render_preview(req: Request<Body>) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let mut body = req.into_body();
+    let mut buf = bytes::BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        // 模板外还有 json 包装，多留 1KiB
+        if buf.len() + chunk.len() > PREVIEW_MAX_TPL_SIZE + 1024 {
+            return json_error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("template exceeds {} bytes", PREVIEW_MAX_TPL_SIZE),
+            );
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    let preview = match serde_json::from_slice::<PreviewReq>(&buf) {
+        Ok(o) if o.tpl.len() > PREVIEW_MAX_TPL_SIZE => {
+            return json_error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("template exceeds {} bytes", PREVIEW_MAX_TPL_SIZE),
+            )
+        }
+        Ok(o) => o,
+        Err(err) => return json_error(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    let event = match preview
+        .event
+        .as_deref()
+        .map_or(Some(notifier::Event::Custom), notifier::Event::from_tag)
+    {
+        Some(e) => e,
+        None => {
+            return json_error(
+                StatusCode::BAD_REQUEST,
+                format!("unknown event `{}`", preview.event.unwrap_or_default()),
+            )
+        }
+    };
+    let stat = match G_STATS_MGR
+        .get()
+        .unwrap()
+        .get_host_stats()
+        .remove(&preview.host)
+    {
+        Some(stat) => stat,
+        None => {
+            return json_error(
+                StatusCode::NOT_FOUND,
+                format!("unknown host `{}`", preview.host),
+            )
+        }
+    };
+
+    if PREVIEW_RUNNING.swap(true, Ordering::SeqCst) {
+        return json_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "previous preview still rendering".to_string(),
+        );
+    }
+    let slot = PreviewSlot;
+    let task = tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        let ctx = notifier::template_context(&event, &stat, &G_CONFIG.get().unwrap().tgbot);
+        jinja::render_preview(&preview.tpl, ctx)
+    });
+    match tokio::time::timeout(PREVIEW_TIMEOUT, task).await {
+        Ok(Ok(Ok(content))) => Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({"code": 0, "content": content}).to_string(),
+            ))?),
+        // 语法或渲染错误，minijinja 0.15 只提供行号
+        Ok(Ok(Err(err))) => Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({"code": 400, "error": err.to_string(), "line": err.line()})
+                    .to_string(),
+            ))?),
+        Ok(Err(err)) => json_error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        Err(_) => json_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("render timeout after {}s", PREVIEW_TIMEOUT.as_secs()),
+        ),
+    }
+}
+
+// GET /admin/webhook-deliveries
+async fn get_webhook_deliveries(req: Request<Body>) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(
+            &notifier::webhook::deliveries(),
+        )?))?)
+}
+
+// GET /admin/audit?since=1656000000&limit=100
+async fn get_audit(req: Request<Body>) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+    if !audit::enabled() {
+        return json_error(StatusCode::NOT_FOUND, "audit log disabled".to_string());
+    }
+
+    let params = query_params(&req);
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq(key))
+            .and_then(|(_, v)| v.parse::<u64>().ok())
+    };
+    let since = param("since").unwrap_or_default();
+    let limit = param("limit").unwrap_or(100) as usize;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({"code": 0, "entries": audit::query(since, limit)}).to_string(),
+        ))?)
+}
+
+// GET /admin/bans 查询，DELETE /admin/bans[?ip=] 解除
+async fn admin_bans(req: Request<Body>) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+    if req.method() == Method::GET {
+        return Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({"code": 0, "bans": bans::list()}).to_string(),
+            ))?);
+    }
+    let ip = match query_params(&req).into_iter().find(|(k, _)| k.eq("ip")) {
+        Some((_, v)) => match v.parse::<std::net::IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) => return json_error(StatusCode::BAD_REQUEST, format!("invalid ip `{}`", v)),
+        },
+        None => None,
+    };
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({"code": 0, "cleared": bans::clear(ip)}).to_string(),
+        ))?)
+}
+
+// GET /admin/hosts
+async fn get_admin_hosts(req: Request<Body>) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let cfg = G_CONFIG.get().unwrap();
+    let mgr = G_STATS_MGR.get().unwrap();
+    let mut stats = mgr.get_host_stats();
+    let mut names = cfg
+        .hosts
+        .iter()
+        .map(|o| o.name.to_string())
+        .collect::<Vec<_>>();
+    let mut registered = stats
+        .keys()
+        .filter(|name| !cfg.hosts_map.contains_key(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+    registered.sort();
+    names.extend(registered);
+
+    let hosts = names
+        .into_iter()
+        .map(|name| {
+            let stat = stats.remove(&name);
+            let host = cfg.hosts_map.get(&name);
+            let (alias, group, notify) = match (&stat, host) {
+                (Some(o), _) => (o.alias.as_str(), o.group.as_str(), o.notify),
+                (None, Some(o)) => (o.alias.as_str(), o.group.as_str(), o.notify),
+                _ => ("", "", true),
+            };
+            let seq = ingest::seq_stat(&name);
+            serde_json::json!({
+                "name": name,
+                "alias": alias,
+                "group": group,
+                "online": stat.as_ref().map_or(false, |o| o.online()),
+                "latest_ts": stat.as_ref().map(|o| o.latest_ts),
+                "version": stat.as_ref().map(|o| o.version.as_str()),
+                "muted": !notify,
+                "disabled": mgr.is_host_disabled(&name) || host.map_or(false, |o| o.disabled),
+                // 按上报序号统计，旧客户端不上报序号时为 null
+                "reports_received": seq.map(|o| o.reports_received),
+                "reports_lost": seq.map(|o| o.reports_lost),
+                "seq_resets": seq.map(|o| o.seq_resets),
+                "loss_rate": seq.map(|o| o.loss_rate()),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&hosts)?))?)
+}
+
+// POST /admin/host/{name}/disable, POST /admin/host/{name}/enable
+async fn set_host_disabled(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    if !is_admin(&req) {
+        return unauthorized();
+    }
+
+    let (name, disabled) = match path.rsplit_once('/') {
+        Some((name, "disable")) => (path_host_name(name, "/admin/host/", ""), true),
+        Some((name, "enable")) => (path_host_name(name, "/admin/host/", ""), false),
+        _ => (None, false),
+    };
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(NOTFOUND.into())?)
+        }
+    };
+    let name = name.as_str();
+
+    let mgr = G_STATS_MGR.get().unwrap();
+    if !G_CONFIG.get().unwrap().hosts_map.contains_key(name)
+        && !mgr.get_host_stats().contains_key(name)
+        && !mgr.is_host_disabled(name)
+    {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({"code": 404, "error": format!("unknown host `{}`", name)})
+                    .to_string(),
+            ))?);
+    }
+    mgr.set_host_disabled(name, disabled)?;
+    info!(host = name; "host `{}` disabled => {}", name, disabled);
+
+    let mut resp = HashMap::new();
+    resp.insert(&"code", serde_json::Value::from(0_i32));
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&resp)?))?)
+}
+
 use prettytable::Table;
 async fn get_detail(req: Request<Body>) -> Result<Response<Body>> {
     if !is_admin(&req) {
-        return Ok(Response::builder()
-            .header(header::WWW_AUTHENTICATE, "Basic realm=\"Restricted\"")
-            .status(StatusCode::UNAUTHORIZED)
-            .body(UNAUTHORIZED.into())?);
+        return unauthorized();
     }
 
     let resp = G_STATS_MGR.get().unwrap().get_stats();
@@ -223,7 +1268,8 @@ async fn get_detail(req: Request<Body>) -> Result<Response<Body>> {
                 s.push_str(format!("kernel_version: {}\n", o.kernel_version).as_str());
                 s.push_str(format!("cpu_num:        {}\n", o.cpu_num).as_str());
                 s.push_str(format!("cpu_brand:      {}\n", o.cpu_brand).as_str());
-                s.push_str(format!("cpu_vender_id:  {}", o.cpu_vender_id).as_str());
+                s.push_str(format!("cpu_vender_id:  {}\n", o.cpu_vender_id).as_str());
+                s.push_str(format!("virt_type:      {}", o.virt_type).as_str());
                 s
             })
             .unwrap_or_default();
@@ -296,56 +1342,333 @@ async fn get_detail(req: Request<Body>) -> Result<Response<Body>> {
     ))
 }
 
+// 去掉 base_path 前缀，不在 base_path 下返回 Err(response)
+fn strip_base_path(req: &Request<Body>) -> std::result::Result<String, Box<Response<Body>>> {
+    let cfg = G_CONFIG.get().unwrap();
+    let path = req.uri().path();
+    if cfg.base_path.is_empty() {
+        return Ok(path.to_string());
+    }
+    if let Some(route) = path.strip_prefix(cfg.base_path.as_str()) {
+        if route.starts_with('/') {
+            return Ok(route.to_string());
+        }
+        if route.is_empty() {
+            // 页面使用相对路径，需以 / 结尾
+            return Err(redirect(format!("{}/", cfg.base_path)));
+        }
+    }
+    if cfg.base_path_redirect {
+        return Err(redirect(format!(
+            "{}{}",
+            cfg.base_path,
+            req.uri().path_and_query().map_or(path, |o| o.as_str())
+        )));
+    }
+    Err(Box::new(
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(NOTFOUND.into())
+            .unwrap(),
+    ))
+}
+
+fn redirect(location: String) -> Box<Response<Body>> {
+    Box::new(
+        Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(header::LOCATION, location)
+            .body(Body::empty())
+            .unwrap(),
+    )
+}
+
+// 注入 <base> 使页面内相对路径在反向代理子路径下可用
+fn index_html(base: &str) -> Result<Response<Body>> {
+    let html = String::from_utf8_lossy(&Asset::get("/index.html").unwrap().data).replacen(
+        "<head>",
+        &format!("<head>\n    <base href=\"{}/\">", base),
+        1,
+    );
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(html))?)
+}
+
+fn static_asset(path: &str) -> Result<Option<Response<Body>>> {
+    if !(path.starts_with("/js/") || path.starts_with("/css/") || path.eq("/favicon.ico")) {
+        return Ok(None);
+    }
+    match Asset::get(path) {
+        Some(data) => {
+            let ct = mime_guess::from_path(path);
+            Ok(Some(
+                Response::builder()
+                    .header(header::CONTENT_TYPE, ct.first_raw().unwrap())
+                    .body(Body::from(data.data))?,
+            ))
+        }
+        None => {
+            error!("can't get => {:?}", path);
+            Ok(None)
+        }
+    }
+}
+
+fn not_found() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(NOTFOUND.into())?)
+}
+
+// /view/{slug}/...，只包含匹配的主机，汇总也只统计这些主机
+async fn get_view(req: Request<Body>, path: &str) -> Result<Response<Body>> {
+    let path = path.trim_start_matches("/view/");
+    let (slug, rest) = path.split_at(path.find('/').unwrap_or(path.len()));
+    let cfg = G_CONFIG.get().unwrap();
+    let view = match cfg.views.iter().find(|o| o.slug.eq(slug)) {
+        Some(view) => view,
+        None => return not_found(),
+    };
+    // 独立于 web_user/web_pass，未设置 user/pass 时公开
+    if view.auth_enabled() && !is_admin(&req) {
+        let authed = req
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|o| Credentials::from_header(o.to_str().unwrap_or_default().to_string()).ok())
+            .map_or(false, |o| view.auth(&o.user_id, &o.password));
+        if !authed {
+            return unauthorized();
+        }
+    }
+
+    match rest {
+        "" | "/" | "/index.html" => index_html(&format!("{}/view/{}", cfg.base_path, slug)),
+        "/stats.json" | "/json/stats.json" => {
+            let resp = G_STATS_MGR.get().unwrap().get_stats();
+            let o = resp.lock().unwrap();
+            let mut filtered = StatsResp::new();
+            filtered.updated = o.updated;
+            filtered.servers = o
+                .servers
+                .iter()
+                .filter(|stat| !stat.hidden && view.matches(stat))
+                .cloned()
+                .collect();
+            filtered.summary = Summary::compute(filtered.servers.iter(), true, cfg.summary_by_os);
+            Ok(Response::builder()
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(&filtered)?))?)
+        }
+        _ => match static_asset(rest)? {
+            Some(resp) => Ok(resp),
+            None => not_found(),
+        },
+    }
+}
+
 async fn main_service_func(req: Request<Body>) -> Result<Response<Body>> {
-    let req_path = req.uri().path();
+    // 探测接口不经过 base_path 及认证
+    if let Some(resp) = health::handle(&req) {
+        return Ok(resp);
+    }
+    let req_path = match strip_base_path(&req) {
+        Ok(path) => path,
+        Err(resp) => return Ok(*resp),
+    };
+    let req_path = req_path.as_str();
+    // 面板及 stats.json 认证，上报接口仍使用客户端账号
+    if matches!(
+        req_path,
+        "/" | "/index.html"
+            | "/stats.json"
+            | "/api/stats"
+            | "/api/v1/stats"
+            | "/api/export"
+            | "/ws"
+            | "/api/v1/stream"
+    ) && !is_viewer(&req)
+    {
+        return unauthorized();
+    }
     match (req.method(), req_path) {
         (&Method::POST, "/report") => stats_report(req).await,
-        (&Method::GET, "/stats.json") => get_stats_json().await,
+        (&Method::GET, "/report") => Ok(ws::report_upgrade(req).await?),
+        (&Method::GET, "/stats.json") => get_stats_json(req).await,
+        (&Method::GET, "/api/stats" | "/api/v1/stats") => get_stats_api(req).await,
+        (&Method::GET, "/api/schema/stat_request.json") => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/schema+json")
+            .body(Body::from(schema::stat_request_schema()))?),
+        (&Method::GET, "/api/export") => {
+            let format = query_params(&req)
+                .into_iter()
+                .find(|(k, _)| k.eq("format"))
+                .map_or("csv".to_string(), |(_, v)| v);
+            Ok(export::snapshot(&format)?)
+        }
+        (&Method::GET, "/ws") => Ok(ws::upgrade(req).await?),
+        (&Method::GET, "/api/v1/stream") => {
+            let group = query_params(&req)
+                .into_iter()
+                .find(|(k, _)| k.eq("group"))
+                .map(|(_, v)| v);
+            Ok(sse::stream(group).await?)
+        }
+        (&Method::GET, "/admin/report_drops") => get_report_drops(req).await,
+        (&Method::GET, "/admin/hosts") => get_admin_hosts(req).await,
+        (&Method::GET, "/admin/audit") => get_audit(req).await,
+        (&Method::GET, "/admin/bans") | (&Method::DELETE, "/admin/bans") => admin_bans(req).await,
+        (&Method::GET, "/admin/webhook-deliveries") => get_webhook_deliveries(req).await,
+        (&Method::GET, path) if path.starts_with("/api/host/") && path.ends_with("/info") => {
+            get_host_info(req, path).await
+        }
+        (&Method::GET, path) if path.starts_with("/api/host/") && path.ends_with("/dump") => {
+            get_host_dump(req, path).await
+        }
+        (&Method::GET, path) if path.starts_with("/api/host/") && path.ends_with("/uptime") => {
+            get_host_uptime(req, path).await
+        }
+        (&Method::GET, path) if path.starts_with("/api/host/") && path.ends_with("/history") => {
+            get_host_history(req, path).await
+        }
+        (&Method::POST, "/admin/render-preview") => render_preview(req).await,
+        (&Method::POST, "/api/v1/test-alert") => test_alert(req).await,
+        (&Method::POST, path) if path.starts_with("/admin/trigger-custom/") => {
+            trigger_custom(req, path).await
+        }
+        (&Method::POST, path) if path.starts_with("/admin/host/") => {
+            set_host_disabled(req, path).await
+        }
         (&Method::GET, "/detail") => get_detail(req).await,
         (&Method::GET, "/detail_ht") => render_jinja_ht_tpl("detail_ht", req).await,
         (&Method::GET, "/map") => render_jinja_ht_tpl("map", req).await,
+        (&Method::DELETE, path) if path.starts_with("/admin/host/") => delete_host(req, path).await,
         (&Method::GET, "/") | (&Method::GET, "/index.html") => {
-            let body = Body::from(Asset::get("/index.html").unwrap().data);
-            Ok(Response::builder()
-                .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-                .body(body)?)
+            index_html(&G_CONFIG.get().unwrap().base_path)
         }
+        (&Method::GET, path) if path.starts_with("/view/") => get_view(req, path).await,
         _ => {
-            if req.method() == Method::GET
-                && (req_path.starts_with("/js/")
-                    || req_path.starts_with("/css/")
-                    || req_path.eq("/favicon.ico"))
-            {
-                if let Some(data) = Asset::get(req_path) {
-                    let ct = mime_guess::from_path(req_path);
-                    return Ok(Response::builder()
-                        .header(header::CONTENT_TYPE, ct.first_raw().unwrap())
-                        .body(Body::from(data.data))?);
-                } else {
-                    error!("can't get => {:?}", req_path);
+            if req.method() == Method::GET {
+                if let Some(resp) = static_asset(req_path)? {
+                    return Ok(resp);
                 }
             }
+            not_found()
+        }
+    }
+}
 
-            // Return 404 not found response.
-            Ok(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(NOTFOUND.into())?)
+async fn serve_http<I, IO, IE>(incoming: I)
+where
+    I: Accept<Conn = IO, Error = IE>,
+    IE: Into<GenericError>,
+    IO: AsyncRead + AsyncWrite + PeerAddr + Unpin + Send + 'static,
+{
+    let http_service = make_service_fn(|conn: &IO| {
+        let client_addr = ClientAddr(conn.peer());
+        async move {
+            Ok::<_, GenericError>(service_fn(move |mut req: Request<Body>| {
+                req.extensions_mut().insert(client_addr);
+                main_service_func(req)
+            }))
         }
+    });
+    let server = Server::builder(incoming).serve(http_service);
+    let graceful = server.with_graceful_shutdown(shutdown_signal());
+    if let Err(e) = graceful.await {
+        eprintln!("server error: {}", e);
     }
 }
 
-async fn shutdown_signal() {
-    // Wait for the CTRL+C signal
+async fn wait_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        let ctrl_c = Box::pin(tokio::signal::ctrl_c());
+        let term = Box::pin(term.recv());
+        futures::future::select(ctrl_c, term).await;
+    }
+    #[cfg(not(unix))]
     tokio::signal::ctrl_c()
         .await
         .expect("failed to install CTRL+C signal handler");
 }
 
+async fn shutdown_signal() {
+    // Wait for the CTRL+C / SIGTERM signal
+    wait_signal().await;
+    eprintln!("✨ shutting down");
+
+    // 先停止接收上报，避免退出期间误报离线
+    let mgr = G_STATS_MGR.get().unwrap();
+    mgr.shutdown();
+    let grace = Duration::from_secs(G_CONFIG.get().unwrap().shutdown_grace_secs);
+    let (flushed, dropped) = notifier::flush(grace).await;
+    // 保存状态为阻塞 I/O
+    let _ = tokio::task::spawn_blocking(move || {
+        mgr.save();
+        storage::flush(grace);
+    })
+    .await;
+    if dropped > 0 {
+        warn!(
+            "shutdown: {} notifications flushed, {} dropped after {}s",
+            flushed,
+            dropped,
+            grace.as_secs()
+        );
+    } else {
+        info!("shutdown: {} notifications flushed", flushed);
+    }
+
+    // ws/sse 长连接已随 mgr.shutdown() 结束，此后等待进行中的请求完成
+    // 超过 grace 仍未结束(如客户端不读取响应)时强制退出
+    let wait = grace.max(Duration::from_secs(3));
+    tokio::spawn(async move {
+        tokio::time::sleep(wait).await;
+        warn!(
+            "shutdown: connections still open after {}s, exit",
+            wait.as_secs()
+        );
+        process::exit(0);
+    });
+}
+
+// --render-template tgbot offline --data h1.json
+fn render_template_cli(
+    cfg: &'static config::Config,
+    kind: &str,
+    event: &str,
+    data: &str,
+) -> anyhow::Result<String> {
+    use anyhow::Context;
+
+    // webhook/kafka 为固定格式，不使用模板
+    if kind != "tgbot" {
+        anyhow::bail!("notifier `{}` has no templates, only tgbot", kind);
+    }
+    let e = notifier::Event::from_tag(event)
+        .ok_or_else(|| anyhow::anyhow!("unknown event `{}`", event))?;
+    let dump: notifier::HostDump = serde_json::from_str(
+        &std::fs::read_to_string(data).with_context(|| format!("read `{}`", data))?,
+    )
+    .with_context(|| format!("parse `{}`", data))?;
+    // 创建时注册模板，不会发送
+    notifier::tgbot::TGBot::new(&cfg.tgbot).render_dump(&e, &dump)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    pretty_env_logger::init();
+    logger::init();
     let args = Args::parse();
 
+    if args.dump_default_templates {
+        print!("{}", notifier::templates::dump());
+        process::exit(0);
+    }
+
     // config test
     if args.config_test {
         config::test_from_file(&args.config).unwrap();
@@ -374,56 +1697,233 @@ async fn main() -> Result<()> {
         process::exit(1);
     }
 
+    let cfg = G_CONFIG.get().unwrap();
+    if let Err(err) = logger::reload(&cfg.log_format, &cfg.log_level) {
+        eprintln!("❗ {:?}", err);
+        process::exit(1);
+    }
+    if let Err(err) = cfg.validate_listen() {
+        eprintln!("❗ {:?}", err);
+        process::exit(1);
+    }
+
+    // init storage
+    if let Err(err) = storage::init(&cfg.storage) {
+        eprintln!("❗ storage init err => {:?}", err);
+        process::exit(1);
+    }
+
+    bans::init(&cfg.bans);
+
+    // init audit log
+    if let Err(err) = audit::init(&cfg.audit) {
+        eprintln!("❗ audit log init err => {:?}", err);
+        process::exit(1);
+    }
+
+    // init tls
+    if cfg.tls_enabled() {
+        if let Err(err) = tls::init(&cfg.tls_cert, &cfg.tls_key) {
+            eprintln!("❗ {:?}", err);
+            process::exit(1);
+        }
+        tls::reload_on_sighup(&cfg.tls_cert, &cfg.tls_key);
+    }
+
+    // init geoip, 失败时不影响启动，country_code 为空
+    if let Err(err) = geoip::init(&cfg.geoip_db) {
+        eprintln!("❗ {:?}", err);
+    }
+
     // init tpl
+    if let Err(err) = jinja::init_filters(G_CONFIG.get().unwrap()) {
+        eprintln!("❗ {:?}", err);
+        process::exit(1);
+    }
     init_jinja_tpl().unwrap();
 
     // init notifier
-    *notifier::NOTIFIER_HANDLE.lock().unwrap() = Some(Handle::current());
-    let cfg = G_CONFIG.get().unwrap();
+    notifier::init_handle(Handle::current());
+    if let Err(err) = notifier::init_http_client(&cfg.notify_proxy) {
+        eprintln!("❗ {:?}", err);
+        process::exit(1);
+    }
     let notifies: Arc<Mutex<Vec<Box<dyn notifier::Notifier + Send>>>> =
         Arc::new(Mutex::new(Vec::new()));
     if cfg.tgbot.enabled {
         let o = Box::new(notifier::tgbot::TGBot::new(&cfg.tgbot));
         notifies.lock().unwrap().push(o);
     }
+    if cfg.webhook.enabled {
+        let o = Box::new(notifier::webhook::Webhook::new(&cfg.webhook));
+        notifies.lock().unwrap().push(o);
+    }
+    if cfg.kafka.enabled {
+        #[cfg(feature = "kafka")]
+        match notifier::kafka::Kafka::new(&cfg.kafka) {
+            Ok(o) => notifies.lock().unwrap().push(Box::new(o)),
+            Err(err) => {
+                eprintln!("❗ kafka init err => {:?}", err);
+                process::exit(1);
+            }
+        }
+        #[cfg(not(feature = "kafka"))]
+        eprintln!("❗ kafka notifier requires building with --features kafka, ignored");
+    }
     // init notifier end
 
     // notify test
     if args.notify_test {
         for notifier in &*notifies.lock().unwrap() {
             eprintln!("send test message to {}", notifier.kind());
-            notifier.notify_test().unwrap();
+            if let Err(err) = notifier.notify_test() {
+                eprintln!("❗ {} send test message err => {:?}", notifier.kind(), err);
+            }
         }
         thread::sleep(Duration::from_millis(7000)); // TODO: wait
         eprintln!("Please check for notifications");
         process::exit(0);
     }
 
+    // 用导出的主机数据渲染模板，只输出不发送
+    if let [kind, event] = args.render_template.as_slice() {
+        match render_template_cli(cfg, kind, event, args.data.as_deref().unwrap_or_default()) {
+            Ok(content) => {
+                println!("{}", content);
+                process::exit(0);
+            }
+            Err(err) => {
+                eprintln!("❗ {:?}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    // 用保存的主机数据(stats 状态)预览 custom 通知
+    if let Some(name) = &args.trigger_custom {
+        let stat = match stats::load_saved_stat(cfg, name) {
+            Ok(Some(stat)) => stat,
+            Ok(None) => {
+                eprintln!("❗ unknown host `{}` in saved stats", name);
+                process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("❗ load saved stats => {:?}", err);
+                process::exit(1);
+            }
+        };
+        let kind = args.trigger_kind.as_deref();
+        let results = {
+            let notifiers = notifies.lock().unwrap();
+            if kind.map_or(false, |kind| !notifiers.iter().any(|o| o.kind() == kind)) {
+                eprintln!("❗ notifier `{}` not enabled", kind.unwrap());
+                process::exit(1);
+            }
+            notifier::trigger(
+                &notifiers,
+                kind,
+                &notifier::Event::Custom,
+                &stat,
+                args.trigger_send,
+                false,
+            )
+        };
+        let mut failed = false;
+        for o in results {
+            eprintln!("✨ {} matched: {} sent: {}", o.notifier, o.matched, o.sent);
+            if let Some(err) = o.error {
+                eprintln!("❗ {}", err);
+                failed = true;
+            }
+            println!("{}", o.content);
+        }
+        if args.trigger_send {
+            notifier::flush(Duration::from_secs(10)).await;
+        }
+        process::exit(i32::from(failed));
+    }
+
     // init mgr
     let mut mgr = crate::stats::StatsMgr::new();
-    mgr.init(G_CONFIG.get().unwrap(), notifies)?;
+    mgr.init(G_CONFIG.get().unwrap(), notifies.clone())?;
     if G_STATS_MGR.set(mgr).is_err() {
         error!("can't set G_STATS_MGR");
         process::exit(1);
     }
 
+    // scheduled reports
+    if let Err(err) = reports::start(cfg, notifies) {
+        eprintln!("❗ {:?}", err);
+        process::exit(1);
+    }
+
     // serv grpc
     tokio::spawn(async move {
-        let addr = &*G_CONFIG.get().unwrap().grpc_addr;
-        grpc::serv_grpc(addr).await
+        let cfg = G_CONFIG.get().unwrap();
+        if let Err(err) = grpc::serv_grpc(&cfg.grpc_addr, cfg.tls_enabled()).await {
+            eprintln!("❗ grpc server error: {:?}", err);
+            process::exit(1);
+        }
     });
 
     // serv http
-    let http_service =
-        make_service_fn(|_| async { Ok::<_, GenericError>(service_fn(main_service_func)) });
+    #[cfg(unix)]
+    if let Some(path) = cfg.http_unix_socket() {
+        let incoming = unix_socket::incoming(path, &cfg.http_socket_mode, &cfg.http_socket_owner)?;
+        eprintln!("🚀 listening on unix:{}", path);
+        health::set_http_ready();
+        serve_http(accept::from_stream(incoming)).await;
+        return Ok(());
+    }
 
-    let http_addr = G_CONFIG.get().unwrap().http_addr.parse()?;
-    eprintln!("🚀 listening on http://{}", http_addr);
-    let server = Server::bind(&http_addr).serve(http_service);
-    let graceful = server.with_graceful_shutdown(shutdown_signal());
-    if let Err(e) = graceful.await {
-        eprintln!("server error: {}", e);
+    if cfg.tls_enabled() {
+        let incoming = tls::incoming(&cfg.http_addr, listener::allow_all)?;
+        for addr in &cfg.http_addr {
+            eprintln!("🚀 listening on https://{}", addr);
+        }
+        health::set_http_ready();
+        serve_http(accept::from_stream(incoming)).await;
+        return Ok(());
+    }
+
+    let incoming = listener::incoming(&cfg.http_addr, listener::allow_all)?;
+    for addr in &cfg.http_addr {
+        eprintln!("🚀 listening on http://{}", addr);
     }
+    health::set_http_ready();
+    serve_http(accept::from_stream(incoming)).await;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_name_from_path() {
+        let name = |path| path_host_name(path, "/api/host/", "/dump");
+        assert_eq!(name("/api/host/h1/dump").as_deref(), Some("h1"));
+        assert_eq!(name("/api/host/web%201/dump").as_deref(), Some("web 1"));
+        assert_eq!(
+            name("/api/host/%E4%B8%9C%E4%BA%AC/dump").as_deref(),
+            Some("东京")
+        );
+        // 主机名本身以 /dump 结尾时只去掉一次
+        assert_eq!(name("/api/host/a/dump/dump").as_deref(), Some("a/dump"));
+        assert_eq!(name("/api/host/%FF/dump"), None);
+        assert_eq!(name("/api/host/h1/info"), None);
+    }
+
+    #[test]
+    fn attachment_filename() {
+        assert_eq!(
+            attachment("h1", "json"),
+            "attachment; filename=\"h1.json\"; filename*=UTF-8''h1.json"
+        );
+        assert_eq!(
+            attachment("a\"b\r\n东京", "json"),
+            "attachment; filename=\"a_b____.json\"; filename*=UTF-8''a%22b%0D%0A%E4%B8%9C%E4%BA%AC.json"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+#![deny(warnings)]
+use serde::{Deserialize, Serialize};
+
+fn default_queue_size() -> usize {
+    1000
+}
+
+// 需以 --features kafka 编译
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    pub enabled: bool,
+    // eg: "10.0.0.1:9092,10.0.0.2:9092"
+    pub brokers: String,
+    pub topic: String,
+    // broker 不可用时最多缓存的消息数，超出丢弃最早的
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    // 只发送这些事件，空则全部
+    #[serde(default = "Default::default")]
+    pub events: Vec<String>,
+    // label selectors, eg: ["dc=fra1"]
+    #[serde(default = "Default::default")]
+    pub labels: Vec<String>,
+}
+
+#[cfg(feature = "kafka")]
+pub use producer::Kafka;
+
+#[cfg(feature = "kafka")]
+mod producer {
+    use anyhow::Result;
+    use rdkafka::config::ClientConfig;
+    use rdkafka::producer::{FutureProducer, FutureRecord};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::Notify;
+    use tokio::time::Duration;
+
+    use super::Config;
+    use crate::notifier::webhook::{event_name, payload};
+    use crate::notifier::{self, Event, FailureLog, HostStat};
+
+    const KIND: &str = "kafka";
+    const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+    // 待发送消息: (key 为主机名，同一主机落在同一分区保证顺序, json)
+    struct Queue {
+        items: Mutex<VecDeque<(String, Vec<u8>)>>,
+        notify: Notify,
+        capacity: usize,
+    }
+
+    pub struct Kafka {
+        config: &'static Config,
+        queue: Arc<Queue>,
+    }
+
+    impl Kafka {
+        pub fn new(cfg: &'static Config) -> Result<Self> {
+            let producer: FutureProducer = ClientConfig::new()
+                .set("bootstrap.servers", &cfg.brokers)
+                .set("message.timeout.ms", "5000")
+                .create()?;
+            let queue = Arc::new(Queue {
+                items: Mutex::new(VecDeque::new()),
+                notify: Notify::new(),
+                capacity: cfg.queue_size.max(1),
+            });
+            notifier::handle()?.spawn(run(producer, &cfg.topic, queue.clone()));
+            Ok(Self { config: cfg, queue })
+        }
+
+        // test 不受 events 限制
+        fn accept(&self, event: &str) -> bool {
+            event == "test"
+                || self.config.events.is_empty()
+                || self.config.events.iter().any(|e| e == event)
+        }
+
+        fn enqueue(&self, event: &str, host: &str, stat: Option<&HostStat>, message: Option<&str>) {
+            if !self.accept(event) {
+                return;
+            }
+            let body = match payload(event, host, stat, message) {
+                Ok(body) => body,
+                Err(err) => {
+                    error!("kafka serialize err => {:?}", err);
+                    return;
+                }
+            };
+            let mut items = self.queue.items.lock().unwrap();
+            if items.len() >= self.queue.capacity {
+                items.pop_front();
+                warn!(notifier = KIND; "kafka queue full, drop oldest event");
+            }
+            items.push_back((host.to_string(), body));
+            drop(items);
+            self.queue.notify.notify_one();
+        }
+    }
+
+    // 按顺序发送，失败时保留在队首并退避重试
+    async fn run(producer: FutureProducer, topic: &'static str, queue: Arc<Queue>) {
+        let failure_log = FailureLog::new(KIND);
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let front = queue.items.lock().unwrap().front().cloned();
+            let (key, body) = match front {
+                Some(o) => o,
+                None => {
+                    queue.notify.notified().await;
+                    continue;
+                }
+            };
+            let record = FutureRecord::to(topic).key(&key).payload(&body);
+            match producer.send(record, SEND_TIMEOUT).await {
+                Ok(_) => {
+                    failure_log.success();
+                    backoff = Duration::from_secs(1);
+                    queue.items.lock().unwrap().pop_front();
+                }
+                Err((err, _)) => {
+                    failure_log.failure(err);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    impl crate::notifier::Notifier for Kafka {
+        fn kind(&self) -> &'static str {
+            KIND
+        }
+
+        fn match_host(&self, stat: &HostStat) -> bool {
+            self.config.labels.iter().all(|s| stat.match_label(s))
+        }
+
+        fn send_notify(&self, content: String) -> Result<()> {
+            self.enqueue("test", "", None, Some(&content));
+            Ok(())
+        }
+
+        fn send_report(&self, name: &str, content: String) -> Result<()> {
+            self.enqueue("report", name, None, Some(&content));
+            Ok(())
+        }
+
+        // 消息体与 webhook 相同，不发送时为空
+        fn render(&self, e: &Event, stat: &HostStat) -> Result<String> {
+            match event_name(e, stat) {
+                Some(event) if self.accept(event) => Ok(String::from_utf8(payload(
+                    event,
+                    &stat.name,
+                    Some(stat),
+                    None,
+                )?)?),
+                _ => Ok(String::new()),
+            }
+        }
+
+        fn notify(&self, e: &Event, stat: &HostStat) -> Result<()> {
+            if let Some(event) = event_name(e, stat) {
+                self.enqueue(event, &stat.name, Some(stat), None);
+            }
+            Ok(())
+        }
+    }
+}
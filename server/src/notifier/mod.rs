@@ -1,19 +1,143 @@
 use anyhow::Result;
-use once_cell::sync::Lazy;
+use chrono::Utc;
+use minijinja::{context, value::Value};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use stat_common::server_status::SysInfo;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tokio::runtime::Handle;
 
+use crate::hostinfo;
+use crate::jinja;
 use crate::payload::HostStat;
+use crate::G_CONFIG;
 
+pub mod kafka;
+pub mod templates;
 pub mod tgbot;
+pub mod webhook;
 
-pub static NOTIFIER_HANDLE: Lazy<Mutex<Option<Handle>>> = Lazy::new(Default::default);
+// 通知发送所在的 runtime，启动时在创建各通知渠道前设置
+static NOTIFIER_HANDLE: OnceCell<Handle> = OnceCell::new();
+static HTTP_CLIENT: OnceCell<reqwest::Client> = OnceCell::new();
+
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/zdz/ServerStatus-Rust)"
+);
+
+// 各通知渠道共用的 http client，未配置 notify_proxy 时使用 HTTP(S)_PROXY 环境变量
+pub fn init_http_client(proxy: &str) -> Result<()> {
+    let mut builder = reqwest::Client::builder().user_agent(USER_AGENT);
+    if !proxy.is_empty() {
+        let proxy = reqwest::Proxy::all(proxy)
+            .map_err(|err| anyhow::anyhow!("invalid notify_proxy `{}` => {}", proxy, err))?;
+        builder = builder.proxy(proxy);
+    }
+    let _ = HTTP_CLIENT.set(builder.build()?);
+    Ok(())
+}
+
+pub fn http_client() -> reqwest::Client {
+    HTTP_CLIENT.get_or_init(reqwest::Client::new).clone()
+}
+
+pub fn init_handle(handle: Handle) {
+    let _ = NOTIFIER_HANDLE.set(handle);
+}
+
+// 未设置时返回错误，调用方丢弃本次通知而不是 panic
+pub fn handle() -> Result<Handle> {
+    NOTIFIER_HANDLE
+        .get()
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("notifier runtime not initialized"))
+}
+// 进行中的通知任务数，退出时等待其完成
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+// 已完成的通知任务数
+static DONE: AtomicUsize = AtomicUsize::new(0);
+// stats 通知队列中尚未交给各渠道的事件数，退出时一并等待
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn enqueued() {
+    QUEUED.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn dequeued() {
+    QUEUED.fetch_sub(1, Ordering::SeqCst);
+}
+
+// 在通知 runtime 上发送，并计入进行中的任务
+pub fn spawn<F>(fut: F) -> Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let handle = handle()?;
+    PENDING.fetch_add(1, Ordering::SeqCst);
+    handle.spawn(async move {
+        fut.await;
+        DONE.fetch_add(1, Ordering::SeqCst);
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+// 最多等待 grace，让队列中的事件交给各渠道并发送完成
+// 返回 (期间完成的通知数, 未处理的事件数 + 未完成的通知数)
+pub async fn flush(grace: Duration) -> (usize, usize) {
+    let done = DONE.load(Ordering::SeqCst);
+    let deadline = Instant::now() + grace;
+    let left = || QUEUED.load(Ordering::SeqCst) + PENDING.load(Ordering::SeqCst);
+    while left() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    (DONE.load(Ordering::SeqCst) - done, left())
+}
 
 #[derive(Debug)]
 pub enum Event {
     NodeUp,
     NodeDown,
     Custom,
+    Register,
+    Conflict,
+    // 在线主机健康状态变化，需开启 notify_degraded
+    Degraded,
+    Recovered,
+    // 超过 offline_purge_secs 未上报被移除
+    Removed,
+    // 本月 SLA 错误预算消耗达到 50%/90%/100%
+    SlaBudget,
+    // expected_hosts 中从未上报过的主机，上报过后离线为 NodeDown
+    Missing,
+    // kernel_version/os_release 变化，需开启 notify_os_change
+    OsChanged,
+}
+
+impl Event {
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "online" => Some(Event::NodeUp),
+            "offline" => Some(Event::NodeDown),
+            "custom" => Some(Event::Custom),
+            "register" => Some(Event::Register),
+            "conflict" => Some(Event::Conflict),
+            "degraded" => Some(Event::Degraded),
+            "recovered" => Some(Event::Recovered),
+            "removed" => Some(Event::Removed),
+            "sla_budget" => Some(Event::SlaBudget),
+            "missing" => Some(Event::Missing),
+            "os_changed" => Some(Event::OsChanged),
+            _ => None,
+        }
+    }
 }
 
 fn get_tag(e: &Event) -> &'static str {
@@ -21,15 +145,360 @@ fn get_tag(e: &Event) -> &'static str {
         Event::NodeUp => "online",
         Event::NodeDown => "offline",
         Event::Custom => "custom",
+        Event::Register => "register",
+        Event::Conflict => "conflict",
+        Event::Degraded => "degraded",
+        Event::Recovered => "recovered",
+        Event::Removed => "removed",
+        Event::SlaBudget => "sla_budget",
+        Event::Missing => "missing",
+        Event::OsChanged => "os_changed",
+    }
+}
+
+// 所有通知渠道共用的模板上下文，同一模板可在各渠道间通用
+pub fn template_context<C: Serialize>(e: &Event, stat: &HostStat, config: &C) -> Value {
+    let sys_info = hostinfo::get(&stat.name).and_then(|o| o.sys_info);
+    build_context(e, stat, stat, sys_info, config)
+}
+
+// 导出的主机数据快照，GET /api/host/{name}/dump 生成，--render-template --data 读取
+// host 即模板中的 {{host.xxx}}(同 stats.json 中的主机)，sys_info 为最近一次上报的系统信息
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HostDump {
+    pub host: serde_json::Value,
+    // 备注不在 host 中，单独导出
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub sys_info: Option<SysInfo>,
+}
+
+impl HostDump {
+    pub fn capture(stat: &HostStat) -> Result<Self> {
+        Ok(Self {
+            host: serde_json::to_value(stat)?,
+            notes: stat.notes.to_string(),
+            sys_info: hostinfo::get(&stat.name).and_then(|o| o.sys_info),
+        })
+    }
+
+    // 只还原上报字段，用于计算 online/memory_percent 等
+    fn stat(&self) -> Result<HostStat> {
+        let mut v = self.host.clone();
+        let o = v
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("dump `host` is not an object"))?;
+        // uptime 导出的是格式化后的字符串，模板使用 host 中的原值
+        o.insert("uptime".to_string(), 0.into());
+        let mut stat: HostStat = serde_json::from_value(v)?;
+        stat.notes = self.notes.to_string();
+        Ok(stat)
+    }
+}
+
+// 使用导出数据的模板上下文，host 保持导出时的原样
+pub fn dump_context<C: Serialize>(e: &Event, dump: &HostDump, config: &C) -> Result<Value> {
+    let stat = dump.stat()?;
+    Ok(build_context(
+        e,
+        &dump.host,
+        &stat,
+        dump.sys_info.clone(),
+        config,
+    ))
+}
+
+fn build_context<C: Serialize, H: Serialize>(
+    e: &Event,
+    host: &H,
+    stat: &HostStat,
+    sys_info: Option<SysInfo>,
+    config: &C,
+) -> Value {
+    // 备注不出现在 stats.json，只在模板中以 {{ host.notes }} 提供
+    let mut host = serde_json::to_value(host).unwrap_or_default();
+    if let Some(o) = host.as_object_mut() {
+        o.insert("notes".to_string(), stat.notes.as_str().into());
+    }
+    // 事件时间，模板中用 {{ now | datetime("%Y-%m-%d %H:%M %Z") }} 格式化
+    let now = Utc::now().timestamp();
+    context!(
+        host => host,
+        config => config,
+        event => get_tag(e),
+        now => now,
+        timestamp => now,
+        // 按配置 timezone 格式化的事件时间，如 2022-07-01 08:00:00 CST
+        time => jinja::format_time(now, None),
+        timezone => jinja::timezone().name(),
+        // [event_labels]，默认模板中的事件文字
+        labels => G_CONFIG.get().map(|o| &o.event_labels),
+        // 最近一次上报的 SysInfo，如 {{ sys_info.kernel_version }}
+        sys_info => sys_info,
+        online => stat.online(),
+        memory_percent => stat.memory_pct(),
+        swap_percent => stat.swap_pct(),
+        hdd_percent => stat.hdd_pct(),
+        // 容量统一为字节，如 {{ bytes.memory_used | bytes_human }}
+        bytes => context!(
+            memory_used => stat.memory_used_bytes(),
+            memory_total => stat.memory_total_bytes(),
+            swap_used => stat.swap_used_bytes(),
+            swap_total => stat.swap_total_bytes(),
+            hdd_used => stat.hdd_used_bytes(),
+            hdd_total => stat.hdd_total_bytes()
+        ),
+        oom_kills => stat.oom_kills.unwrap_or_default()
+    )
+}
+
+const FAILURE_LOG_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct FailureState {
+    // 当前周期内的失败次数
+    count: u64,
+    // 连续失败总次数
+    total: u64,
+    since: Option<Instant>,
+}
+
+// 通知渠道故障期间合并重复的错误日志，避免刷屏
+pub struct FailureLog {
+    kind: &'static str,
+    state: Mutex<FailureState>,
+}
+
+impl FailureLog {
+    pub fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            state: Mutex::new(FailureState::default()),
+        }
+    }
+
+    pub fn failure<E: Debug>(&self, err: E) {
+        let mut st = self.state.lock().unwrap();
+        st.count += 1;
+        st.total += 1;
+        match st.since {
+            None => {
+                error!(notifier = self.kind; "{} send msg error => {:?}", self.kind, err);
+                st.count = 0;
+                st.since = Some(Instant::now());
+            }
+            Some(since) if since.elapsed() >= FAILURE_LOG_INTERVAL => {
+                error!(
+                    notifier = self.kind;
+                    "{} send failed {} times in last {}m, last error => {:?}",
+                    self.kind,
+                    st.count,
+                    FAILURE_LOG_INTERVAL.as_secs() / 60,
+                    err
+                );
+                st.count = 0;
+                st.since = Some(Instant::now());
+            }
+            _ => {}
+        }
+    }
+
+    pub fn success(&self) {
+        let mut st = self.state.lock().unwrap();
+        if st.total > 0 {
+            info!(
+                notifier = self.kind;
+                "{} send recovered after {} failures",
+                self.kind,
+                st.total
+            );
+            *st = FailureState::default();
+        }
     }
 }
 
 pub trait Notifier {
     fn kind(&self) -> &'static str;
+    // notify 将发送的内容，为空表示不发送
+    fn render(&self, e: &Event, stat: &HostStat) -> Result<String>;
     fn notify(&self, e: &Event, stat: &HostStat) -> Result<()>;
+    // label selector routing
+    fn match_host(&self, _stat: &HostStat) -> bool {
+        true
+    }
     // send notify impl
     fn send_notify(&self, content: String) -> Result<()>;
+    // [[reports]] 定时报表
+    fn send_report(&self, _name: &str, content: String) -> Result<()> {
+        self.send_notify(content)
+    }
     fn notify_test(&self) -> Result<()> {
         self.send_notify("❗ServerStatus test msg".to_string())
     }
 }
+
+#[derive(Debug, Serialize)]
+pub struct Triggered {
+    pub notifier: &'static str,
+    // 不匹配该渠道 labels 的主机，正常流程中不会通知
+    pub matched: bool,
+    // 为空表示不会发送
+    pub content: String,
+    pub sent: bool,
+    // 渲染或发送失败
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// 用主机数据走一遍事件通知流程，kind 为空时为全部渠道，用于调试模板及告警路由
+// routed 为 true 时与正常流程一致，不发送给不匹配 labels 的渠道
+pub fn trigger(
+    notifiers: &[Box<dyn Notifier + Send>],
+    kind: Option<&str>,
+    e: &Event,
+    stat: &HostStat,
+    send: bool,
+    routed: bool,
+) -> Vec<Triggered> {
+    notifiers
+        .iter()
+        .filter(|o| kind.map_or(true, |kind| o.kind() == kind))
+        .map(|o| {
+            let matched = o.match_host(stat);
+            let mut triggered = Triggered {
+                notifier: o.kind(),
+                matched,
+                content: String::new(),
+                sent: false,
+                error: None,
+            };
+            match o.render(e, stat) {
+                Ok(content) => triggered.content = content,
+                Err(err) => {
+                    triggered.error = Some(err.to_string());
+                    return triggered;
+                }
+            }
+            if send && !triggered.content.is_empty() && (matched || !routed) {
+                match o.notify(e, stat) {
+                    Ok(_) => triggered.sent = true,
+                    Err(err) => triggered.error = Some(err.to_string()),
+                }
+            }
+            triggered
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::NOTIFIER_LOCK;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // 备注只在模板的 host 中提供
+    #[test]
+    fn notes_in_host_context() {
+        let stat = HostStat {
+            name: "ctx_h1".to_string(),
+            notes: "rack 3".to_string(),
+            ..Default::default()
+        };
+        let v = serde_json::to_value(template_context(&Event::Custom, &stat, &())).unwrap();
+        assert_eq!(v["host"]["notes"], "rack 3");
+        assert!(v.get("notes").is_none());
+    }
+
+    // 队列中未交给渠道的事件也计入等待，超时后计为丢弃
+    #[tokio::test]
+    async fn flush_waits_for_queued_events() {
+        let _lock = NOTIFIER_LOCK.lock().await;
+        enqueued();
+        let (flushed, dropped) = flush(Duration::from_millis(200)).await;
+        assert_eq!((flushed, dropped), (0, 1));
+
+        tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            dequeued();
+        });
+        let start = Instant::now();
+        let (flushed, dropped) = flush(Duration::from_secs(5)).await;
+        assert_eq!((flushed, dropped), (0, 0));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    // 设置 runtime 前通知只丢弃并返回错误，不 panic；设置后正常投递
+    #[tokio::test]
+    async fn notify_before_and_after_handle_init() {
+        let _lock = NOTIFIER_LOCK.lock().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let cfg = Box::leak(Box::new(webhook::Config {
+            enabled: true,
+            urls: vec![url.clone()],
+            ..Default::default()
+        }));
+        let hook = webhook::Webhook::new(cfg);
+        let stat = HostStat {
+            name: "notify_handle".to_string(),
+            ..Default::default()
+        };
+        let delivered = || {
+            webhook::deliveries()
+                .into_iter()
+                .filter(|d| d.host == stat.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(handle().is_err());
+        assert!(spawn(async {}).is_err());
+        assert!(hook.notify(&Event::NodeUp, &stat).is_ok());
+        assert!(delivered().is_empty());
+        assert_eq!(PENDING.load(Ordering::SeqCst), 0);
+
+        init_handle(Handle::current());
+        assert!(handle().is_ok());
+        assert!(hook.notify(&Event::NodeUp, &stat).is_ok());
+        let (mut conn, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        // 读完整个请求再响应
+        let mut req = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = conn.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed");
+            req.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&req);
+            if let Some(pos) = text.find("\r\n\r\n") {
+                let len = text[..pos]
+                    .lines()
+                    .find_map(|l| {
+                        l.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap();
+                if req.len() >= pos + 4 + len {
+                    break;
+                }
+            }
+        }
+        assert!(req.starts_with(b"POST /hook "));
+        conn.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        let (flushed, left) = flush(Duration::from_secs(5)).await;
+        assert_eq!((flushed, left), (1, 0));
+        let delivered = delivered();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(
+            (delivered[0].url.as_str(), delivered[0].event.as_str()),
+            (url.as_str(), "online")
+        );
+        assert_eq!(delivered[0].status, Some(200));
+    }
+}
@@ -0,0 +1,129 @@
+#![deny(warnings)]
+use serde::{Deserialize, Serialize};
+
+// 内置默认模板，配置中未设置 *_tpl 时使用，设为空字符串则不发送该事件
+// HTML 风格(tgbot parse_mode=HTML)，按事件 tag 索引
+const HTML: &[(&str, &str)] = &[
+    ("online", include_str!("templates/html/online.jinja")),
+    ("offline", include_str!("templates/html/offline.jinja")),
+    ("custom", include_str!("templates/html/custom.jinja")),
+    ("register", include_str!("templates/html/register.jinja")),
+    ("conflict", include_str!("templates/html/conflict.jinja")),
+    ("degraded", include_str!("templates/html/degraded.jinja")),
+    ("recovered", include_str!("templates/html/recovered.jinja")),
+    ("removed", include_str!("templates/html/removed.jinja")),
+    (
+        "sla_budget",
+        include_str!("templates/html/sla_budget.jinja"),
+    ),
+    ("missing", include_str!("templates/html/missing.jinja")),
+    (
+        "os_changed",
+        include_str!("templates/html/os_changed.jinja"),
+    ),
+];
+
+pub fn html(tag: &str) -> &'static str {
+    HTML.iter()
+        .find(|(t, _)| *t == tag)
+        .map_or("", |(_, tpl)| tpl)
+}
+
+// None 使用默认模板，Some("") 为停用
+pub fn resolve<'a>(tpl: &'a Option<String>, tag: &str) -> &'a str {
+    tpl.as_deref().unwrap_or_else(|| html(tag))
+}
+
+// --dump-default-templates，输出可直接粘贴到 [tgbot] 的配置
+pub fn dump() -> String {
+    HTML.iter()
+        .map(|(tag, tpl)| format!("{}_tpl = \"\"\"\n{}\"\"\"\n", tag, tpl))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EventLabel {
+    #[serde(default = "Default::default")]
+    pub emoji: String,
+    #[serde(default = "Default::default")]
+    pub text: String,
+}
+
+fn label(emoji: &str, text: &str) -> EventLabel {
+    EventLabel {
+        emoji: emoji.to_string(),
+        text: text.to_string(),
+    }
+}
+fn default_node_up() -> EventLabel {
+    label("😆", "主机恢复上线啦")
+}
+fn default_node_down() -> EventLabel {
+    label("😱", "主机已经掉线啦")
+}
+fn default_register() -> EventLabel {
+    label("🆕", "新主机已注册")
+}
+fn default_conflict() -> EventLabel {
+    label("⚠️", "同时从多个来源上报")
+}
+fn default_degraded() -> EventLabel {
+    label("🟡", "有指标达到告警线")
+}
+fn default_recovered() -> EventLabel {
+    label("🟢", "指标已恢复正常")
+}
+fn default_removed() -> EventLabel {
+    label("🗑", "长时间未上报，已移除")
+}
+fn default_sla_budget() -> EventLabel {
+    label("📉", "本月 SLA 离线预算已消耗")
+}
+fn default_missing() -> EventLabel {
+    label("❓", "从未上报，请检查客户端是否已部署")
+}
+fn default_os_changed() -> EventLabel {
+    label("🔄", "系统版本已变化")
+}
+
+// 默认模板中的事件文字及 emoji，模板中为 {{labels.node_up.text}}，用于本地化
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EventLabels {
+    #[serde(default = "default_node_up")]
+    pub node_up: EventLabel,
+    #[serde(default = "default_node_down")]
+    pub node_down: EventLabel,
+    #[serde(default = "default_register")]
+    pub register: EventLabel,
+    #[serde(default = "default_conflict")]
+    pub conflict: EventLabel,
+    #[serde(default = "default_degraded")]
+    pub degraded: EventLabel,
+    #[serde(default = "default_recovered")]
+    pub recovered: EventLabel,
+    #[serde(default = "default_removed")]
+    pub removed: EventLabel,
+    #[serde(default = "default_sla_budget")]
+    pub sla_budget: EventLabel,
+    #[serde(default = "default_missing")]
+    pub missing: EventLabel,
+    #[serde(default = "default_os_changed")]
+    pub os_changed: EventLabel,
+}
+impl Default for EventLabels {
+    fn default() -> Self {
+        Self {
+            node_up: default_node_up(),
+            node_down: default_node_down(),
+            register: default_register(),
+            conflict: default_conflict(),
+            degraded: default_degraded(),
+            recovered: default_recovered(),
+            removed: default_removed(),
+            sla_budget: default_sla_budget(),
+            missing: default_missing(),
+            os_changed: default_os_changed(),
+        }
+    }
+}
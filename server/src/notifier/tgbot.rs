@@ -1,32 +1,51 @@
 #![deny(warnings)]
 use anyhow::Result;
-use log::{error, info};
-use minijinja::context;
+use log::info;
+use minijinja::value::Value;
 use reqwest;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::time::Duration;
 
 use crate::jinja::{add_template, render_template};
-use crate::notifier::{get_tag, Event, HostStat, NOTIFIER_HANDLE};
+use crate::notifier::{self, get_tag, templates, Event, FailureLog, HostDump, HostStat};
 
 const KIND: &str = "tgbot";
 
+fn default_title() -> String {
+    "❗<b>Server Status</b>".to_string()
+}
+
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Config {
     pub enabled: bool,
     pub bot_token: String,
     pub chat_id: String,
+    #[serde(default = "default_title")]
     pub title: String,
-    pub online_tpl: String,
-    pub offline_tpl: String,
-    pub custom_tpl: String,
+    // 不设置时使用内置默认模板，设为空字符串则不发送该事件
+    pub online_tpl: Option<String>,
+    pub offline_tpl: Option<String>,
+    pub custom_tpl: Option<String>,
+    pub register_tpl: Option<String>,
+    pub conflict_tpl: Option<String>,
+    pub degraded_tpl: Option<String>,
+    pub recovered_tpl: Option<String>,
+    pub removed_tpl: Option<String>,
+    pub sla_budget_tpl: Option<String>,
+    pub missing_tpl: Option<String>,
+    pub os_changed_tpl: Option<String>,
+    // label selectors, eg: ["dc=fra1"]
+    #[serde(default = "Default::default")]
+    pub labels: Vec<String>,
 }
 
 pub struct TGBot {
     config: &'static Config,
     tg_url: String,
     http_client: reqwest::Client,
+    failure_log: Arc<FailureLog>,
 }
 
 impl TGBot {
@@ -34,27 +53,43 @@ impl TGBot {
         let o = Self {
             config: cfg,
             tg_url: format!("https://api.telegram.org/bot{}/sendMessage", &cfg.bot_token),
-            http_client: reqwest::Client::new(),
+            http_client: notifier::http_client(),
+            failure_log: Arc::new(FailureLog::new(KIND)),
         };
 
-        add_template(
-            KIND,
-            get_tag(&Event::NodeUp),
-            o.config.online_tpl.to_string(),
-        );
-        add_template(
-            KIND,
-            get_tag(&Event::NodeDown),
-            o.config.offline_tpl.to_string(),
-        );
-        add_template(
-            KIND,
-            get_tag(&Event::Custom),
-            o.config.custom_tpl.to_string(),
-        );
+        let cfg = o.config;
+        for (e, tpl) in [
+            (Event::NodeUp, &cfg.online_tpl),
+            (Event::NodeDown, &cfg.offline_tpl),
+            (Event::Custom, &cfg.custom_tpl),
+            (Event::Register, &cfg.register_tpl),
+            (Event::Conflict, &cfg.conflict_tpl),
+            (Event::Degraded, &cfg.degraded_tpl),
+            (Event::Recovered, &cfg.recovered_tpl),
+            (Event::Removed, &cfg.removed_tpl),
+            (Event::SlaBudget, &cfg.sla_budget_tpl),
+            (Event::Missing, &cfg.missing_tpl),
+            (Event::OsChanged, &cfg.os_changed_tpl),
+        ] {
+            let tag = get_tag(&e);
+            add_template(KIND, tag, templates::resolve(tpl, tag).to_string());
+        }
 
         o
     }
+
+    fn render_context(&self, e: &Event, ctx: Value) -> Result<String> {
+        let content = render_template(KIND, get_tag(e), ctx)?;
+        Ok(match *e {
+            Event::Custom if !content.is_empty() => format!("{}\n{}", self.config.title, content),
+            _ => content,
+        })
+    }
+
+    // --render-template，使用导出的主机数据渲染，不发送
+    pub fn render_dump(&self, e: &Event, dump: &HostDump) -> Result<String> {
+        self.render_context(e, notifier::dump_context(e, dump, self.config)?)
+    }
 }
 
 impl crate::notifier::Notifier for TGBot {
@@ -62,6 +97,10 @@ impl crate::notifier::Notifier for TGBot {
         KIND
     }
 
+    fn match_host(&self, stat: &HostStat) -> bool {
+        self.config.labels.iter().all(|s| stat.match_label(s))
+    }
+
     fn send_notify(&self, html_content: String) -> Result<()> {
         let mut data = HashMap::new();
         data.insert("chat_id", self.config.chat_id.to_string());
@@ -69,9 +108,9 @@ impl crate::notifier::Notifier for TGBot {
         data.insert("text", html_content);
 
         let tg_url = self.tg_url.to_string();
-        let handle = NOTIFIER_HANDLE.lock().unwrap().as_ref().unwrap().clone();
         let http_client = self.http_client.clone();
-        handle.spawn(async move {
+        let failure_log = self.failure_log.clone();
+        notifier::spawn(async move {
             match http_client
                 .post(&tg_url)
                 .timeout(Duration::from_secs(5))
@@ -79,35 +118,71 @@ impl crate::notifier::Notifier for TGBot {
                 .send()
                 .await
             {
-                Ok(resp) => {
+                Ok(resp) if resp.status().is_success() => {
                     info!("tg send msg resp => {:?}", resp);
+                    failure_log.success();
+                }
+                Ok(resp) => {
+                    failure_log.failure(resp.status());
                 }
                 Err(err) => {
-                    error!("tg send msg error => {:?}", err);
+                    failure_log.failure(err);
                 }
             }
-        });
+        })
+    }
 
-        Ok(())
+    fn render(&self, e: &Event, stat: &HostStat) -> Result<String> {
+        self.render_context(e, notifier::template_context(e, stat, self.config))
     }
 
+    // 渲染或发送失败时返回错误，由调用方记录日志并丢弃本次通知
     fn notify(&self, e: &Event, stat: &HostStat) -> Result<()> {
-        render_template(
-            self.kind(),
-            get_tag(e),
-            context!(host => stat, config => self.config),
-        )
-        .map(|content| match *e {
-            Event::NodeUp | Event::NodeDown => self.send_notify(content).unwrap(),
-            Event::Custom => {
-                info!("render.custom.tpl => {}", content);
-                if !content.is_empty() {
-                    self.send_notify(format!("{}\n{}", self.config.title, content))
-                        .unwrap_or_else(|err| {
-                            error!("send_msg err => {:?}", err);
-                        });
-                }
-            }
-        })
+        let content = self.render(e, stat)?;
+        if matches!(e, Event::Custom) {
+            info!("render.custom.tpl => {}", content);
+        }
+        if content.is_empty() {
+            return Ok(());
+        }
+        self.send_notify(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 导出的快照经 json 往返后渲染，host 保持导出时的原样，备注单独导出
+    #[test]
+    fn render_dump_roundtrip() {
+        let cfg = Box::leak(Box::new(Config {
+            title: "title".to_string(),
+            offline_tpl: Some("{{host.name}} offline, uptime {{host.uptime}}".to_string()),
+            custom_tpl: Some("{{host.alias}} load {{host.load_1}} {{host.notes}}".to_string()),
+            ..Default::default()
+        }));
+        let bot = TGBot::new(cfg);
+        let stat = HostStat {
+            name: "dump_h1".to_string(),
+            alias: "Tokyo".to_string(),
+            notes: "rack 3".to_string(),
+            uptime: 90061,
+            uptime_str: "1 天".to_string(),
+            load_1: 0.5,
+            ..Default::default()
+        };
+        let data = serde_json::to_string(&HostDump::capture(&stat).unwrap()).unwrap();
+        let dump: HostDump = serde_json::from_str(&data).unwrap();
+        assert_eq!(dump.notes, "rack 3");
+
+        assert_eq!(
+            bot.render_dump(&Event::NodeDown, &dump).unwrap(),
+            "dump_h1 offline, uptime 1 天"
+        );
+        assert_eq!(
+            bot.render_dump(&Event::Custom, &dump).unwrap(),
+            "title\nTokyo load 0.5 rack 3"
+        );
     }
 }
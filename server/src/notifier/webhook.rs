@@ -0,0 +1,242 @@
+#![deny(warnings)]
+use anyhow::Result;
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use stat_common::sign::{self, SIGNATURE_HEADER};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+
+use crate::notifier::{self, get_tag, Event, FailureLog, HostStat};
+
+const KIND: &str = "webhook";
+// /admin/webhook-deliveries 保留的最近投递记录数
+const MAX_DELIVERIES: usize = 100;
+
+static DELIVERIES: Lazy<Mutex<VecDeque<Delivery>>> = Lazy::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn default_retries() -> u32 {
+    3
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    pub enabled: bool,
+    pub urls: Vec<String>,
+    // HMAC-SHA256 密钥，为空时不带签名头
+    #[serde(default = "Default::default")]
+    pub secret: String,
+    // 失败后的重试次数，间隔 1s/2s/4s... 递增
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    // 只投递这些事件，空则全部
+    #[serde(default = "Default::default")]
+    pub events: Vec<String>,
+    // label selectors, eg: ["dc=fra1"]
+    #[serde(default = "Default::default")]
+    pub labels: Vec<String>,
+}
+
+// 固定格式，不使用模板
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    // online/offline/alert/degraded/recovered/register/conflict/removed/sla_budget/missing/os_changed/report/test
+    event: &'a str,
+    host: &'a str,
+    timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stat: Option<&'a HostStat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub url: String,
+    pub event: String,
+    pub host: String,
+    // 第几次尝试，从 1 开始
+    pub attempt: u32,
+    pub timestamp: i64,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+fn record(delivery: Delivery) {
+    let mut deliveries = DELIVERIES.lock().unwrap();
+    if deliveries.len() >= MAX_DELIVERIES {
+        deliveries.pop_front();
+    }
+    deliveries.push_back(delivery);
+}
+
+// 最近的投递记录，按时间先后排列
+pub fn deliveries() -> Vec<Delivery> {
+    DELIVERIES.lock().unwrap().iter().cloned().collect()
+}
+
+pub(crate) fn payload(
+    event: &str,
+    host: &str,
+    stat: Option<&HostStat>,
+    message: Option<&str>,
+) -> serde_json::Result<Vec<u8>> {
+    serde_json::to_vec(&Payload {
+        event,
+        host,
+        timestamp: Utc::now().timestamp(),
+        stat,
+        message,
+    })
+}
+
+// custom 按 notify_interval 周期触发，有指标达到 [thresholds] 告警线时投递 alert
+pub(crate) fn event_name(e: &Event, stat: &HostStat) -> Option<&'static str> {
+    match *e {
+        Event::Custom => {
+            if !stat.status_level.tripped() {
+                return None;
+            }
+            Some("alert")
+        }
+        _ => Some(get_tag(e)),
+    }
+}
+
+pub struct Webhook {
+    config: &'static Config,
+    http_client: reqwest::Client,
+    failure_log: Arc<FailureLog>,
+}
+
+impl Webhook {
+    pub fn new(cfg: &'static Config) -> Self {
+        Self {
+            config: cfg,
+            http_client: notifier::http_client(),
+            failure_log: Arc::new(FailureLog::new(KIND)),
+        }
+    }
+
+    // test 不受 events 限制
+    fn accept(&self, event: &str) -> bool {
+        event == "test"
+            || self.config.events.is_empty()
+            || self.config.events.iter().any(|e| e == event)
+    }
+
+    fn deliver(&self, event: &str, host: &str, stat: Option<&HostStat>, message: Option<&str>) {
+        if !self.accept(event) {
+            return;
+        }
+        let body = match payload(event, host, stat, message) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("webhook serialize err => {:?}", err);
+                return;
+            }
+        };
+        let signature =
+            (!self.config.secret.is_empty()).then(|| sign::sign(&self.config.secret, &body));
+
+        for url in &self.config.urls {
+            let (url, event, host) = (url.to_string(), event.to_string(), host.to_string());
+            let (body, signature) = (body.clone(), signature.clone());
+            let http_client = self.http_client.clone();
+            let failure_log = self.failure_log.clone();
+            let retries = self.config.retries;
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let res = notifier::spawn(async move {
+                for attempt in 1..=retries + 1 {
+                    let mut req = http_client
+                        .post(&url)
+                        .timeout(Duration::from_secs(5))
+                        .header(reqwest::header::CONTENT_TYPE, "application/json")
+                        .header("x-event", &event)
+                        .body(body.clone());
+                    if let Some(signature) = &signature {
+                        req = req.header(SIGNATURE_HEADER, signature);
+                    }
+                    let res = req.send().await;
+                    let (status, err) = match &res {
+                        Ok(resp) => (Some(resp.status().as_u16()), None),
+                        Err(err) => (None, Some(err.to_string())),
+                    };
+                    record(Delivery {
+                        id,
+                        url: url.to_string(),
+                        event: event.to_string(),
+                        host: host.to_string(),
+                        attempt,
+                        timestamp: Utc::now().timestamp(),
+                        status,
+                        error: err,
+                    });
+                    match res {
+                        Ok(resp) if resp.status().is_success() => {
+                            failure_log.success();
+                            return;
+                        }
+                        // 4xx 为接收方拒绝，重试无意义
+                        Ok(resp) if resp.status().is_client_error() => {
+                            failure_log.failure(resp.status());
+                            return;
+                        }
+                        Ok(resp) => failure_log.failure(resp.status()),
+                        Err(err) => failure_log.failure(err),
+                    }
+                    if attempt <= retries {
+                        tokio::time::sleep(Duration::from_secs(1 << (attempt - 1).min(6))).await;
+                    }
+                }
+            });
+            if let Err(err) = res {
+                error!(notifier = KIND; "webhook delivery {} dropped => {:?}", id, err);
+            }
+        }
+    }
+}
+
+impl crate::notifier::Notifier for Webhook {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn match_host(&self, stat: &HostStat) -> bool {
+        self.config.labels.iter().all(|s| stat.match_label(s))
+    }
+
+    fn send_notify(&self, content: String) -> Result<()> {
+        self.deliver("test", "", None, Some(&content));
+        Ok(())
+    }
+
+    fn send_report(&self, name: &str, content: String) -> Result<()> {
+        self.deliver("report", name, None, Some(&content));
+        Ok(())
+    }
+
+    // 请求体，不投递时为空
+    fn render(&self, e: &Event, stat: &HostStat) -> Result<String> {
+        match event_name(e, stat) {
+            Some(event) if self.accept(event) => Ok(String::from_utf8(payload(
+                event,
+                &stat.name,
+                Some(stat),
+                None,
+            )?)?),
+            _ => Ok(String::new()),
+        }
+    }
+
+    fn notify(&self, e: &Event, stat: &HostStat) -> Result<()> {
+        if let Some(event) = event_name(e, stat) {
+            self.deliver(event, &stat.name, Some(stat), None);
+        }
+        Ok(())
+    }
+}
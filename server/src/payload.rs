@@ -1,23 +1,110 @@
 #![deny(warnings)]
 use serde::{Deserialize, Serialize};
-use stat_common::server_status::{IpInfo, SysInfo};
+use stat_common::server_status::{IfaceStat, IpInfo, MountInfo, Sensor, SysInfo};
+use stat_common::units::{KIB, MIB};
+
+use crate::config::SortBy;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub const MAX_LABELS: usize = 32;
+
 fn default_as_true() -> bool {
     true
 }
 
+fn percent(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        used as f64 * 100.0 / total as f64
+    }
+}
+
+// 指标状态等级，阈值见配置 [thresholds]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Ok,
+    Warn,
+    Crit,
+}
+impl Default for Level {
+    fn default() -> Self {
+        Level::Ok
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatusLevel {
+    pub cpu: Level,
+    pub memory: Level,
+    pub swap: Level,
+    pub hdd: Level,
+    pub oom: Level,
+    pub clock: Level,
+}
+
+impl StatusLevel {
+    // 有指标达到 warn 及以上
+    pub fn tripped(&self) -> bool {
+        [
+            self.cpu,
+            self.memory,
+            self.swap,
+            self.hdd,
+            self.oom,
+            self.clock,
+        ]
+        .iter()
+        .any(|&l| l != Level::Ok)
+    }
+}
+
+// 主机整体状态，离线优先，在线且有指标达到告警线时为 degraded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostState {
+    Online,
+    Degraded,
+    Offline,
+}
+impl Default for HostState {
+    fn default() -> Self {
+        HostState::Offline
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct HostStat {
     pub name: String,
+    // 客户端版本
+    #[serde(default)]
+    pub version: String,
+    // 客户端协议版本，旧客户端为 0
+    #[serde(default)]
+    pub proto_version: u32,
     #[serde(default = "Default::default", skip_deserializing)]
     pub alias: String,
+    // 备注可能含敏感信息，不出现在 stats.json
+    #[serde(skip_serializing, skip_deserializing)]
+    pub notes: String,
     #[serde(rename = "type", skip_deserializing)]
     pub host_type: String,
     #[serde(skip_deserializing)]
+    pub group: String,
+    #[serde(skip_deserializing)]
     pub location: String,
     #[serde(skip_deserializing)]
     pub region: String,
+    // 配置指定或根据 source_ip 查询 geoip
+    #[serde(skip_deserializing)]
+    pub country_code: String,
+    // 上报连接的来源地址
+    #[serde(skip)]
+    pub source_ip: Option<IpAddr>,
     #[serde(default = "bool::default")]
     pub vnstat: bool,
 
@@ -39,23 +126,88 @@ pub struct HostStat {
     pub network_tx: u64,
     pub network_in: u64,
     pub network_out: u64,
+    // 各网卡明细，客户端 --report-ifaces 时上报
+    #[serde(default)]
+    pub ifaces: Vec<IfaceStat>,
 
     #[serde(default)]
     pub last_network_in: u64,
     #[serde(default)]
     pub last_network_out: u64,
+    // 客户端上报的 vnstat 本月流量，proto_version 9 起
+    #[serde(default, skip_serializing)]
+    pub month_network_in: u64,
+    #[serde(default, skip_serializing)]
+    pub month_network_out: u64,
+    // 本月流量，来源见配置 traffic_prefer_vnstat
+    #[serde(skip_deserializing)]
+    pub month_in: u64,
+    #[serde(skip_deserializing)]
+    pub month_out: u64,
 
     pub cpu: f32,
     pub memory_total: u64,
     pub memory_used: u64,
     pub swap_total: u64,
     pub swap_used: u64,
+    // swap 使用率(%)，由服务端计算
+    #[serde(skip_deserializing)]
+    pub swap_percent: f64,
+    // 距上次上报的 OOM kill 次数，客户端不支持时为 null
+    #[serde(default)]
+    pub oom_kills: Option<u64>,
     pub hdd_total: u64,
     pub hdd_used: u64,
+    // 使用率超过客户端 --disk-warn-pct 的挂载点
+    #[serde(default)]
+    pub hot_mounts: Vec<MountInfo>,
 
     #[serde(skip_deserializing)]
     pub custom: String,
 
+    #[serde(default)]
+    pub failed_units: Vec<String>,
+
+    // docker/podman 容器数，客户端 --collect-docker 时上报
+    #[serde(default)]
+    pub containers_running: Option<u32>,
+    #[serde(default)]
+    pub containers_total: Option<u32>,
+
+    // CPU 温度(℃)，cpu_temp 为各核心平均值
+    #[serde(default)]
+    pub cpu_temp: Option<f32>,
+    #[serde(default)]
+    pub core_temps: Vec<f32>,
+    #[serde(default)]
+    pub package_temp: Option<f32>,
+
+    // 客户端时钟相对 NTP 的偏差(毫秒)，本机快为正，客户端 --collect-clock 时上报
+    #[serde(default)]
+    pub clock_offset_ms: Option<f64>,
+
+    // BMC 温度、风扇、电源传感器，客户端 --collect-ipmi 时上报
+    #[serde(default)]
+    pub sensors: Vec<Sensor>,
+
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    // 客户端因权限不足无法采集或通过 --report-fields 未上报的指标，前端应显示 N/A
+    #[serde(default)]
+    pub unavailable_metrics: Vec<String>,
+
+    // 客户端断线期间缓存后补发，latest_ts 为客户端原始采样时间
+    #[serde(default, skip_serializing)]
+    pub buffered: bool,
+
+    // 由服务端阈值计算，前端据此统一着色
+    #[serde(skip_deserializing)]
+    pub status_level: StatusLevel,
+    // online/degraded/offline
+    #[serde(skip_deserializing)]
+    pub state: HostState,
+
     #[serde(skip_serializing)]
     pub ip_info: Option<IpInfo>,
     #[serde(skip_serializing)]
@@ -64,16 +216,205 @@ pub struct HostStat {
     // user data
     #[serde(skip_deserializing)]
     pub latest_ts: u64,
+    // 以下均使用服务端接收时间计算，与客户端时钟无关
+    #[serde(skip_serializing, skip_deserializing)]
+    pub recv_ms: u64,
+    // 距最后一次上报的秒数
+    #[serde(skip_deserializing)]
+    pub age_secs: u64,
+    // 上报间隔的滑动平均(秒)
+    #[serde(skip_deserializing)]
+    pub avg_gap_secs: f64,
+    // 期望上报间隔(秒)，age_secs 超过其 2 倍可视为上报延迟
+    #[serde(skip_deserializing)]
+    pub expected_interval_secs: f64,
+    // 本计费周期剩余的离线时长预算(秒)，超出为负数，未设置 sla_target 时为 null
+    #[serde(skip_deserializing)]
+    pub sla_budget_remaining_secs: Option<i64>,
+    #[serde(skip_deserializing)]
+    pub sla_budget_consumed_pct: Option<f64>,
+    // 同一用户名从多个来源(ip 主机名)交替上报
+    #[serde(skip_deserializing)]
+    pub conflict: bool,
+    #[serde(skip_deserializing)]
+    pub conflict_sources: Vec<String>,
+    // os_changed 通知中变化的系统信息，仅随通知发送，stats.json 中不出现
+    #[serde(skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub sys_changes: Vec<SysChange>,
 
     #[serde(skip_serializing, skip_deserializing)]
     pub pos: usize,
     #[serde(skip_serializing, skip_deserializing)]
+    pub weight: i64,
+    #[serde(skip_serializing, skip_deserializing)]
     pub disabled: bool,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub notify: bool,
+    #[serde(skip_deserializing)]
+    pub hidden: bool,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub hide_offline_secs: u64,
+    #[serde(skip_deserializing)]
+    pub offline_timeout: u64,
+}
+
+// SysInfo 中 kernel_version/os_release 与上次记录不同
+#[derive(Serialize, Debug, Clone)]
+pub struct SysChange {
+    pub field: String,
+    pub from: String,
+    pub to: String,
+}
+
+// 原始字段沿用上报单位(内存/swap 为 KiB，硬盘为 MiB)，stats.json 输出保持不变
+// 服务端计算(告警、模板、汇总)使用以下方法，容量统一为字节，使用率为 %
+impl HostStat {
+    pub fn online(&self) -> bool {
+        self.online4 || self.online6
+    }
+
+    pub fn memory_used_bytes(&self) -> u64 {
+        self.memory_used.saturating_mul(KIB)
+    }
+
+    pub fn memory_total_bytes(&self) -> u64 {
+        self.memory_total.saturating_mul(KIB)
+    }
+
+    pub fn swap_used_bytes(&self) -> u64 {
+        self.swap_used.saturating_mul(KIB)
+    }
+
+    pub fn swap_total_bytes(&self) -> u64 {
+        self.swap_total.saturating_mul(KIB)
+    }
+
+    pub fn hdd_used_bytes(&self) -> u64 {
+        self.hdd_used.saturating_mul(MIB)
+    }
+
+    pub fn hdd_total_bytes(&self) -> u64 {
+        self.hdd_total.saturating_mul(MIB)
+    }
+
+    // 总量为 0 时为 0
+    pub fn memory_pct(&self) -> f64 {
+        percent(self.memory_used, self.memory_total)
+    }
+
+    pub fn swap_pct(&self) -> f64 {
+        percent(self.swap_used, self.swap_total)
+    }
+
+    pub fn hdd_pct(&self) -> f64 {
+        percent(self.hdd_used, self.hdd_total)
+    }
+
+    pub fn hide_expired(&self, now: u64) -> bool {
+        self.hide_offline_secs > 0
+            && !self.online()
+            && self.latest_ts + self.hide_offline_secs < now
+    }
+
+    // selector: `key:value` or `key=value`
+    pub fn match_label(&self, selector: &str) -> bool {
+        selector
+            .split_once([':', '='])
+            .map(|(k, v)| {
+                self.labels
+                    .get(k.trim())
+                    .map(|o| o.eq(v.trim()))
+                    .unwrap_or(false)
+            })
+            .unwrap_or(false)
+    }
+}
+
+// 全部主机汇总，实时网速只统计在线主机
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Summary {
+    pub hosts: usize,
+    pub online: usize,
+    // 在线但有指标达到告警线的主机数，计入 online
+    pub degraded: usize,
+    pub network_rx: u64,
+    pub network_tx: u64,
+    pub memory_total: u64,
+    pub memory_used: u64,
+    pub hdd_total: u64,
+    pub hdd_used: u64,
+    pub month_in: u64,
+    pub month_out: u64,
+    // 按 os_name 分组，开启 summary_by_os 时输出
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by_os: Option<BTreeMap<String, OsSummary>>,
+}
+impl Summary {
+    pub fn compute<'a>(
+        servers: impl Iterator<Item = &'a HostStat>,
+        include_hidden: bool,
+        by_os: bool,
+    ) -> Self {
+        let mut o = Self::default();
+        let mut os_map = BTreeMap::new();
+        for stat in servers.filter(|stat| include_hidden || !stat.hidden) {
+            o.add(stat);
+            if by_os {
+                let (os_name, os_arch) = stat.sys_info.as_ref().map_or(("", ""), |info| {
+                    (info.os_name.as_str(), info.os_arch.as_str())
+                });
+                let entry: &mut OsSummary = os_map.entry(or_unknown(os_name)).or_default();
+                entry.total.add(stat);
+                entry.arch.entry(or_unknown(os_arch)).or_default().add(stat);
+            }
+        }
+        if by_os {
+            o.by_os = Some(os_map);
+        }
+        o
+    }
+
+    fn add(&mut self, stat: &HostStat) {
+        self.hosts += 1;
+        self.memory_total += stat.memory_total;
+        self.memory_used += stat.memory_used;
+        self.hdd_total += stat.hdd_total;
+        self.hdd_used += stat.hdd_used;
+        self.month_in += stat.month_in;
+        self.month_out += stat.month_out;
+        if stat.online() {
+            self.online += 1;
+            self.network_rx += stat.network_rx;
+            self.network_tx += stat.network_tx;
+        }
+        if stat.state == HostState::Degraded {
+            self.degraded += 1;
+        }
+    }
+}
+
+// 未上报 sys_info 的主机归入 unknown
+fn or_unknown(s: &str) -> String {
+    if s.is_empty() {
+        "unknown".to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+// 某一 os_name 的汇总，arch 为其下按 os_arch 的汇总
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OsSummary {
+    #[serde(flatten)]
+    pub total: Summary,
+    pub arch: BTreeMap<String, Summary>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StatsResp {
     pub updated: u64,
+    #[serde(default)]
+    pub summary: Summary,
     pub servers: Vec<HostStat>,
 }
 impl StatsResp {
@@ -83,7 +424,305 @@ impl StatsResp {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            summary: Summary::default(),
             servers: Vec::new(),
         }
     }
+
+    pub fn sort_servers(&mut self, by: SortBy) {
+        self.servers.sort_by(|a, b| {
+            match by {
+                SortBy::Pos => a.pos.cmp(&b.pos),
+                SortBy::Weight => b.weight.cmp(&a.weight),
+                SortBy::Name => Ordering::Equal,
+                SortBy::GroupThenName => a.group.cmp(&b.group),
+                SortBy::OnlineFirst => b.online().cmp(&a.online()),
+            }
+            .then_with(|| a.name.cmp(&b.name))
+        });
+    }
+
+    pub fn without_hidden(&self) -> Self {
+        Self {
+            updated: self.updated,
+            summary: self.summary.clone(),
+            servers: self.servers.iter().filter(|o| !o.hidden).cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stat_common::server_status::StatRequest;
+
+    fn host(name: &str, pos: usize, weight: i64, group: &str, online: bool) -> HostStat {
+        HostStat {
+            name: name.to_string(),
+            pos,
+            weight,
+            group: group.to_string(),
+            online4: online,
+            online6: false,
+            ..Default::default()
+        }
+    }
+
+    // 不同输入顺序排序后序列化结果一致
+    fn sorted_names(by: SortBy) -> Vec<String> {
+        let hosts = vec![
+            host("c", 2, 1, "g2", false),
+            host("a", 2, 5, "g1", true),
+            host("d", 0, 5, "g2", true),
+            host("b", 1, 1, "g1", false),
+        ];
+        let mut last: Option<String> = None;
+        let mut names = Vec::new();
+        for i in 0..hosts.len() {
+            let mut resp = StatsResp::new();
+            resp.servers = hosts.clone();
+            resp.servers.rotate_left(i);
+            if i % 2 == 1 {
+                resp.servers.reverse();
+            }
+            resp.sort_servers(by);
+            let json = serde_json::to_string(&resp.servers).unwrap();
+            if let Some(last) = &last {
+                assert_eq!(last, &json, "{:?} not stable", by);
+            }
+            last = Some(json);
+            names = resp.servers.into_iter().map(|o| o.name).collect();
+        }
+        names
+    }
+
+    #[test]
+    fn sort_by_pos() {
+        // pos 相同的 a/c 按 name
+        assert_eq!(sorted_names(SortBy::Pos), ["d", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn sort_by_weight() {
+        assert_eq!(sorted_names(SortBy::Weight), ["a", "d", "b", "c"]);
+    }
+
+    #[test]
+    fn sort_by_name() {
+        assert_eq!(sorted_names(SortBy::Name), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn sort_by_group_then_name() {
+        assert_eq!(sorted_names(SortBy::GroupThenName), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn sort_online_first() {
+        assert_eq!(sorted_names(SortBy::OnlineFirst), ["a", "d", "b", "c"]);
+    }
+
+    // 上报经 ingest 处理后转换为 stats.json 中的主机数据
+    fn render(stat: &StatRequest) -> serde_json::Value {
+        let host: HostStat = serde_json::from_value(serde_json::to_value(stat).unwrap()).unwrap();
+        serde_json::to_value(&host).unwrap()
+    }
+
+    fn unavailable(v: &serde_json::Value) -> Vec<&str> {
+        let mut list = v["unavailable_metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m.as_str().unwrap())
+            .collect::<Vec<_>>();
+        list.sort_unstable();
+        list
+    }
+
+    // 只含必填字段的 json 上报，缺少的指标为 N/A，可选字段为 null 而不是 0
+    #[test]
+    fn minimal_json_report_renders_na() {
+        let body = serde_json::json!({
+            "name": "h1",
+            "uptime": 60,
+            "cpu": 1.5,
+            "memory_total": 1024,
+            "memory_used": 512,
+        });
+        let stat = crate::schema::decode_stat_request(body.to_string().as_bytes()).unwrap();
+        let v = render(&stat);
+        assert_eq!(
+            unavailable(&v),
+            ["hdd", "ifaces", "ipmi", "load", "speed", "swap", "traffic"]
+        );
+        for key in [
+            "cpu_temp",
+            "package_temp",
+            "containers_running",
+            "containers_total",
+            "clock_offset_ms",
+            "oom_kills",
+        ] {
+            assert!(v[key].is_null(), "{} => {}", key, v[key]);
+        }
+        for key in ["core_temps", "sensors", "hot_mounts", "failed_units"] {
+            assert_eq!(v[key], serde_json::json!([]), "{}", key);
+        }
+        assert_eq!(v["cpu"], 1.5);
+        assert_eq!(v["memory_used"], 512);
+    }
+
+    // 旧版客户端的 protobuf 上报，之后版本新增的指标为 N/A
+    #[test]
+    fn old_protobuf_report_renders_na() {
+        use prost::Message;
+
+        let mut stat = StatRequest {
+            name: "h1".to_string(),
+            proto_version: 2,
+            cpu: 1.5,
+            ..Default::default()
+        };
+        stat = StatRequest::decode(&stat.encode_to_vec()[..]).unwrap();
+        crate::ingest::mark_absent(&mut stat);
+        let v = render(&stat);
+        assert_eq!(unavailable(&v), ["ifaces", "ipmi"]);
+        assert!(v["cpu_temp"].is_null());
+        assert_eq!(v["sensors"], serde_json::json!([]));
+
+        // 当前版本不标记，客户端自行声明的保留
+        stat.proto_version = stat_common::PROTO_VERSION;
+        stat.unavailable_metrics = vec!["swap".to_string()];
+        crate::ingest::mark_absent(&mut stat);
+        assert_eq!(unavailable(&render(&stat)), ["swap"]);
+    }
+
+    const GOLDEN: &str = include_str!("../tests/fixtures/host_stat.json");
+
+    fn golden_host() -> HostStat {
+        let mut labels = BTreeMap::new();
+        labels.insert("env".to_string(), "prod".to_string());
+        HostStat {
+            name: "h1".to_string(),
+            version: "1.0.0".to_string(),
+            proto_version: 9,
+            alias: "Host 1".to_string(),
+            notes: "secret".to_string(),
+            host_type: "kvm".to_string(),
+            group: "g1".to_string(),
+            location: "Tokyo".to_string(),
+            region: "JP".to_string(),
+            country_code: "JP".to_string(),
+            source_ip: Some("203.0.113.1".parse().unwrap()),
+            vnstat: true,
+            online4: true,
+            online6: false,
+            uptime: 90061,
+            uptime_str: "1 天".to_string(),
+            load_1: 0.5,
+            load_5: 0.25,
+            load_15: 0.125,
+            network_rx: 1000,
+            network_tx: 2000,
+            network_in: 30000,
+            network_out: 40000,
+            ifaces: vec![IfaceStat {
+                name: "eth0".to_string(),
+                rx: 1000,
+                tx: 2000,
+                total_in: 30000,
+                total_out: 40000,
+                alias: "WAN".to_string(),
+            }],
+            last_network_in: 10000,
+            last_network_out: 20000,
+            month_network_in: 5000,
+            month_network_out: 6000,
+            month_in: 20000,
+            month_out: 20000,
+            cpu: 12.5,
+            memory_total: 8388608,
+            memory_used: 4194304,
+            swap_total: 1048576,
+            swap_used: 0,
+            swap_percent: 0.0,
+            oom_kills: Some(1),
+            hdd_total: 102400,
+            hdd_used: 51200,
+            hot_mounts: vec![MountInfo {
+                mount_point: "/data".to_string(),
+                fs_type: "ext4".to_string(),
+                total: 100,
+                used: 95,
+                alias: "Data".to_string(),
+            }],
+            custom: "custom".to_string(),
+            failed_units: vec!["nginx.service".to_string()],
+            containers_running: Some(3),
+            containers_total: Some(4),
+            cpu_temp: Some(55.5),
+            core_temps: vec![55.0, 56.0],
+            package_temp: None,
+            clock_offset_ms: Some(-1.5),
+            sensors: vec![Sensor {
+                name: "Fan1".to_string(),
+                value: 3000.0,
+                unit: "RPM".to_string(),
+                status: "ok".to_string(),
+            }],
+            labels,
+            unavailable_metrics: vec!["ipmi".to_string()],
+            status_level: StatusLevel {
+                hdd: Level::Warn,
+                ..Default::default()
+            },
+            state: HostState::Degraded,
+            latest_ts: 1700000000,
+            age_secs: 1,
+            avg_gap_secs: 1.0,
+            expected_interval_secs: 1.0,
+            sla_budget_remaining_secs: Some(-60),
+            sla_budget_consumed_pct: Some(102.5),
+            conflict: false,
+            offline_timeout: 30,
+            ..Default::default()
+        }
+    }
+
+    // stats.json 中主机数据的字段、顺序及取值格式，前端及第三方依赖此格式
+    // 有意修改输出时以 UPDATE_GOLDEN=1 运行测试更新 fixture
+    #[test]
+    fn host_stat_golden_json() {
+        let json = serde_json::to_string_pretty(&golden_host()).unwrap() + "\n";
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/host_stat.json");
+            std::fs::write(path, &json).unwrap();
+            return;
+        }
+        assert_eq!(json, GOLDEN);
+    }
+
+    // 只在服务端使用的字段不输出
+    #[test]
+    fn host_stat_private_fields() {
+        let v = serde_json::to_value(golden_host()).unwrap();
+        for key in [
+            "notes",
+            "source_ip",
+            "month_network_in",
+            "month_network_out",
+            "buffered",
+            "ip_info",
+            "sys_info",
+            "recv_ms",
+            "pos",
+            "weight",
+            "disabled",
+            "notify",
+            "hide_offline_secs",
+            "sys_changes",
+        ] {
+            assert!(v.get(key).is_none(), "{}", key);
+        }
+    }
 }
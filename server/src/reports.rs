@@ -0,0 +1,501 @@
+#![deny(warnings)]
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use minijinja::context;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::notifier::Notifier;
+use crate::{jinja, stats, storage, uptime};
+
+// 各报表上次运行时间及周期内累计流量，重启后不重复发送
+pub const REPORTS_STATE: &str = "reports";
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// 服务端停机期间错过的计划时间，最多回溯这么久补发一次
+const MAX_CATCH_UP_SECS: u64 = 2 * 86400;
+const TPL_KIND: &str = "report";
+
+static STATE: Lazy<Mutex<State>> = Lazy::new(Default::default);
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Report {
+    pub name: String,
+    // cron 表达式: 分 时 日 月 周，或 @hourly/@daily/@weekly/@monthly
+    pub schedule: String,
+    // 发送渠道: tgbot/webhook
+    pub notifier: String,
+    // 为空时使用 timezone，均未设置为 UTC
+    #[serde(default = "Default::default")]
+    pub timezone: String,
+    pub tpl: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+struct Traffic {
+    network_in: u64,
+    network_out: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ReportState {
+    last_run: u64,
+    // 本周期内各主机累计流量
+    traffic: HashMap<String, Traffic>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    reports: HashMap<String, ReportState>,
+    // 上次检查时各主机的 network_in/out 计数
+    counters: HashMap<String, Traffic>,
+}
+
+#[derive(Debug, Serialize)]
+struct HostTraffic<'a> {
+    name: &'a str,
+    alias: &'a str,
+    network_in: u64,
+    network_out: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Downtime {
+    name: String,
+    start: u64,
+    end: Option<u64>,
+    duration: u64,
+}
+
+// 位图表示各字段允许的取值
+#[derive(Debug)]
+struct Cron {
+    minute: u64,
+    hour: u64,
+    dom: u64,
+    month: u64,
+    dow: u64,
+    dom_any: bool,
+    dow_any: bool,
+}
+
+// 支持 * a a-b a,b */n a-b/n a/n
+fn parse_field(s: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0;
+    for part in s.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>()?)),
+            None => (part, None),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (a.parse()?, b.parse()?)
+        } else {
+            let v = range.parse()?;
+            (v, if step.is_some() { max } else { v })
+        };
+        let step = step.unwrap_or(1);
+        if step == 0 || lo < min || hi > max || lo > hi {
+            bail!("`{}` out of range {}-{}", part, min, max);
+        }
+        for v in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+impl Cron {
+    fn parse(s: &str) -> Result<Self> {
+        let s = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            s => s,
+        };
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            bail!("expect 5 fields: minute hour day month weekday");
+        }
+        let mut dow = parse_field(fields[4], 0, 7)?;
+        // 0 和 7 都表示周日
+        if dow & (1 << 7) != 0 {
+            dow |= 1;
+        }
+        Ok(Self {
+            minute: parse_field(fields[0], 0, 59)?,
+            hour: parse_field(fields[1], 0, 23)?,
+            dom: parse_field(fields[2], 1, 31)?,
+            month: parse_field(fields[3], 1, 12)?,
+            dow,
+            dom_any: fields[2] == "*",
+            dow_any: fields[4] == "*",
+        })
+    }
+
+    fn matches(&self, dt: &DateTime<Tz>) -> bool {
+        let bit = |mask: u64, v: u32| mask & (1 << v) != 0;
+        let dom = bit(self.dom, dt.day());
+        let dow = bit(self.dow, dt.weekday().num_days_from_sunday());
+        // 与 crontab 一致，日和周都有限定时满足其一即可
+        let day = match (self.dom_any, self.dow_any) {
+            (false, false) => dom || dow,
+            _ => dom && dow,
+        };
+        day && bit(self.minute, dt.minute())
+            && bit(self.hour, dt.hour())
+            && bit(self.month, dt.month())
+    }
+
+    // (from, to] 内是否有计划时间
+    fn due(&self, tz: &Tz, from: u64, to: u64) -> bool {
+        let start = from.max(to.saturating_sub(MAX_CATCH_UP_SECS)) / 60 * 60 + 60;
+        (start..=to).step_by(60).any(|ts| {
+            Utc.timestamp_opt(ts as i64, 0)
+                .single()
+                .map_or(false, |dt| self.matches(&dt.with_timezone(tz)))
+        })
+    }
+}
+
+struct Job {
+    report: &'static Report,
+    cron: Cron,
+    tz: Tz,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn load() -> State {
+    match storage::load_state(REPORTS_STATE) {
+        Some(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("ignore invalid {} => {:?}", REPORTS_STATE, err);
+            State::default()
+        }),
+        None => State::default(),
+    }
+}
+
+fn save(state: &State) {
+    match serde_json::to_string(state) {
+        Ok(s) => storage::save_state(REPORTS_STATE, &s),
+        Err(err) => error!("save {} fail => {:?}", REPORTS_STATE, err),
+    }
+}
+
+fn parse_jobs(cfg: &'static Config) -> Result<Vec<Job>> {
+    let mut names = HashSet::new();
+    cfg.reports
+        .iter()
+        .map(|report| {
+            if report.name.is_empty() || !names.insert(report.name.as_str()) {
+                bail!("report name `{}` empty or duplicated", report.name);
+            }
+            let cron = Cron::parse(&report.schedule).with_context(|| {
+                format!(
+                    "invalid report `{}` schedule `{}`",
+                    report.name, report.schedule
+                )
+            })?;
+            let tz = match (report.timezone.as_str(), cfg.timezone.as_str()) {
+                ("", "") => Tz::UTC,
+                ("", s) | (s, _) => s.parse::<Tz>().map_err(|err| {
+                    anyhow::anyhow!(
+                        "invalid report `{}` timezone `{}` => {}",
+                        report.name,
+                        s,
+                        err
+                    )
+                })?,
+            };
+            Ok(Job { report, cron, tz })
+        })
+        .collect()
+}
+
+// 累计两次检查之间的流量，计数变小视为客户端重启，从 0 开始计
+fn accumulate(state: &mut State) {
+    let resp = crate::G_STATS_MGR.get().unwrap().get_stats();
+    let resp = resp.lock().unwrap();
+    for o in &resp.servers {
+        let cur = Traffic {
+            network_in: o.network_in,
+            network_out: o.network_out,
+        };
+        let delta = match state.counters.insert(o.name.to_string(), cur) {
+            Some(last) => Traffic {
+                network_in: cur
+                    .network_in
+                    .checked_sub(last.network_in)
+                    .unwrap_or(cur.network_in),
+                network_out: cur
+                    .network_out
+                    .checked_sub(last.network_out)
+                    .unwrap_or(cur.network_out),
+            },
+            // 首次出现的主机以当前计数为基准
+            None => continue,
+        };
+        for report in state.reports.values_mut() {
+            let t = report.traffic.entry(o.name.to_string()).or_default();
+            t.network_in += delta.network_in;
+            t.network_out += delta.network_out;
+        }
+    }
+}
+
+fn render(job: &Job, state: &ReportState, from: u64, to: u64) -> Result<String> {
+    let resp = crate::G_STATS_MGR.get().unwrap().get_stats();
+    let resp = resp.lock().unwrap().without_hidden();
+
+    let mut traffic = resp
+        .servers
+        .iter()
+        .map(|o| {
+            let t = state.traffic.get(&o.name).copied().unwrap_or_default();
+            HostTraffic {
+                name: &o.name,
+                alias: &o.alias,
+                network_in: t.network_in,
+                network_out: t.network_out,
+            }
+        })
+        .collect::<Vec<_>>();
+    traffic.sort_by_key(|o| std::cmp::Reverse(o.network_in + o.network_out));
+    let traffic_in = traffic.iter().map(|o| o.network_in).sum::<u64>();
+    let traffic_out = traffic.iter().map(|o| o.network_out).sum::<u64>();
+
+    let visible = resp
+        .servers
+        .iter()
+        .map(|o| o.name.as_str())
+        .collect::<HashSet<_>>();
+    let downtimes = uptime::downtimes_between(from, to)
+        .into_iter()
+        .filter(|(name, _)| visible.contains(name.as_str()))
+        .map(|(name, o)| Downtime {
+            name,
+            start: o.start,
+            end: o.end,
+            duration: o.duration,
+        })
+        .collect::<Vec<_>>();
+    let offline = resp
+        .servers
+        .iter()
+        .filter(|o| !o.online())
+        .collect::<Vec<_>>();
+
+    let ctx = context!(
+        report => job.report.name,
+        from => from,
+        to => to,
+        now => to,
+        time => jinja::format_time(to as i64, None),
+        hosts => resp.servers,
+        summary => resp.summary,
+        offline => offline,
+        downtimes => downtimes,
+        traffic => traffic,
+        traffic_in => traffic_in,
+        traffic_out => traffic_out
+    );
+    let content = jinja::render_template(TPL_KIND, &job.report.name, ctx)?;
+    if content.is_empty() {
+        bail!("empty content, check the tpl");
+    }
+    Ok(content)
+}
+
+// 渲染失败时跳过本次，不补发
+fn run(job: &Job, state: &ReportState, now: u64) -> Option<String> {
+    let name = job.report.name.as_str();
+    match render(job, state, state.last_run, now) {
+        Ok(content) => Some(content),
+        Err(err) => {
+            error!(report = name; "report `{}` render fail, skipped => {:?}", name, err);
+            None
+        }
+    }
+}
+
+fn send(job: &Job, content: String, notifies: &Arc<Mutex<Vec<Box<dyn Notifier + Send>>>>) {
+    let name = job.report.name.as_str();
+    let notifies = notifies.lock().unwrap();
+    match notifies.iter().find(|o| o.kind() == job.report.notifier) {
+        Some(notifier) => {
+            info!(report = name, notifier = notifier.kind(); "send report `{}`", name);
+            if let Err(err) = notifier.send_report(name, content) {
+                error!(report = name; "send report `{}` fail => {:?}", name, err);
+            }
+        }
+        None => {
+            warn!(report = name; "report `{}` notifier `{}` not enabled", name, job.report.notifier)
+        }
+    }
+}
+
+// 按 [[reports]] 定时渲染并发送汇总报表
+pub fn start(
+    cfg: &'static Config,
+    notifies: Arc<Mutex<Vec<Box<dyn Notifier + Send>>>>,
+) -> Result<()> {
+    let jobs = parse_jobs(cfg)?;
+    if jobs.is_empty() {
+        return Ok(());
+    }
+    for job in &jobs {
+        jinja::add_template(TPL_KIND, &job.report.name, job.report.tpl.as_str());
+    }
+
+    let mut state = load();
+    let now = unix_now();
+    // 已删除的报表不再保留，新增的报表从现在开始计
+    state
+        .reports
+        .retain(|name, _| jobs.iter().any(|o| &o.report.name == name));
+    for job in &jobs {
+        state
+            .reports
+            .entry(job.report.name.to_string())
+            .or_insert_with(|| ReportState {
+                last_run: now,
+                traffic: HashMap::new(),
+            });
+    }
+    *STATE.lock().unwrap() = state;
+
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        if stats::is_shutting_down() {
+            break;
+        }
+        let now = unix_now();
+        // 发送前释放 STATE，持有 STATE 时不等待 notifiers 锁
+        let mut outbox = Vec::new();
+        {
+            let mut state = STATE.lock().unwrap();
+            accumulate(&mut state);
+            for job in &jobs {
+                let report = state.reports.get_mut(&job.report.name).unwrap();
+                if !job.cron.due(&job.tz, report.last_run, now) {
+                    continue;
+                }
+                outbox.extend(run(job, report, now).map(|content| (job, content)));
+                // 无论发送成功与否都记为已运行，保存后重启不会重复发送
+                report.last_run = now;
+                report.traffic.clear();
+            }
+            save(&state);
+        }
+        for (job, content) in outbox {
+            send(job, content, &notifies);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(mask: u64) -> Vec<u32> {
+        (0..64).filter(|v| mask & (1 << v) != 0).collect()
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(bits(parse_field("*", 0, 6).unwrap()), [0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(bits(parse_field("5", 0, 59).unwrap()), [5]);
+        assert_eq!(bits(parse_field("1-3,7", 0, 23).unwrap()), [1, 2, 3, 7]);
+        assert_eq!(bits(parse_field("*/15", 0, 59).unwrap()), [0, 15, 30, 45]);
+        assert_eq!(bits(parse_field("10-20/5", 0, 59).unwrap()), [10, 15, 20]);
+        // a/n 从 a 到最大值
+        assert_eq!(bits(parse_field("50/4", 0, 59).unwrap()), [50, 54, 58]);
+        assert_eq!(bits(parse_field("*/10", 1, 31).unwrap()), [1, 11, 21, 31]);
+
+        for s in ["60", "0", "5-3", "*/0", "a", "1-", ""] {
+            assert!(parse_field(s, 1, 59).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_cron() {
+        assert!(Cron::parse("0 0 * *").is_err());
+        assert!(Cron::parse("0 24 * * *").is_err());
+        assert!(Cron::parse("@yearly").is_err());
+
+        let daily = Cron::parse("@daily").unwrap();
+        assert_eq!(bits(daily.minute), [0]);
+        assert_eq!(bits(daily.hour), [0]);
+        assert!(daily.dom_any && daily.dow_any);
+        // 周: 0 和 7 都表示周日
+        assert_eq!(bits(Cron::parse("0 0 * * 7").unwrap().dow), [0, 7]);
+        assert_eq!(
+            bits(Cron::parse("0 0 * * 0").unwrap().dow),
+            bits(Cron::parse("@weekly").unwrap().dow)
+        );
+    }
+
+    fn at(s: &str) -> DateTime<Tz> {
+        let dt = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        Utc.from_utc_datetime(&dt).with_timezone(&Tz::UTC)
+    }
+
+    #[test]
+    fn matches_days() {
+        // 2022-07-03 为周日
+        let sunday = Cron::parse("30 8 * * 7").unwrap();
+        assert!(sunday.matches(&at("2022-07-03 08:30")));
+        assert!(!sunday.matches(&at("2022-07-03 08:31")));
+        assert!(!sunday.matches(&at("2022-07-04 08:30")));
+
+        // 日和周都有限定时满足其一即可: 每月 1 日及每周一
+        let either = Cron::parse("0 9 1 * 1").unwrap();
+        assert!(either.matches(&at("2022-07-01 09:00")));
+        assert!(either.matches(&at("2022-07-04 09:00")));
+        assert!(!either.matches(&at("2022-07-05 09:00")));
+
+        // 只限定其一时按该字段
+        let first = Cron::parse("0 9 1 * *").unwrap();
+        assert!(first.matches(&at("2022-07-01 09:00")));
+        assert!(!first.matches(&at("2022-07-04 09:00")));
+        let weekdays = Cron::parse("0 9 * 7-8 1-5").unwrap();
+        assert!(weekdays.matches(&at("2022-07-04 09:00")));
+        assert!(!weekdays.matches(&at("2022-07-03 09:00")));
+        assert!(!weekdays.matches(&at("2022-09-05 09:00")));
+    }
+
+    #[test]
+    fn due_between() {
+        let daily = Cron::parse("0 8 * * *").unwrap();
+        let ts = |s: &str| at(s).timestamp() as u64;
+        let tz = Tz::UTC;
+        // (from, to]
+        assert!(daily.due(&tz, ts("2022-07-01 07:59"), ts("2022-07-01 08:00")));
+        assert!(!daily.due(&tz, ts("2022-07-01 08:00"), ts("2022-07-01 08:30")));
+        assert!(!daily.due(&tz, ts("2022-07-01 07:00"), ts("2022-07-01 07:59")));
+        // 停机期间错过的计划时间补发
+        assert!(daily.due(&tz, ts("2022-07-01 09:00"), ts("2022-07-02 10:00")));
+        // 最多回溯 MAX_CATCH_UP_SECS，2022-07-01 为周五
+        let friday = Cron::parse("0 8 * * 5").unwrap();
+        assert!(friday.due(&tz, ts("2022-06-30 00:00"), ts("2022-07-02 09:00")));
+        assert!(!friday.due(&tz, ts("2022-06-30 00:00"), ts("2022-07-04 10:00")));
+        // 按报表时区计算
+        let shanghai: Tz = "Asia/Shanghai".parse().unwrap();
+        assert!(daily.due(&shanghai, ts("2022-06-30 23:59"), ts("2022-07-01 00:00")));
+        assert!(!daily.due(&shanghai, ts("2022-07-01 07:59"), ts("2022-07-01 08:00")));
+    }
+}
@@ -0,0 +1,341 @@
+#![deny(warnings)]
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorSet};
+use serde_json::{json, Map, Value};
+use stat_common::server_status::StatRequest;
+
+use crate::ingest::{self, Reject};
+
+const PACKAGE: &str = "server_status";
+// json 上报必须包含的字段，其余字段缺省为 0/空
+pub const REQUIRED: &[&str] = &["name", "uptime", "cpu", "memory_total", "memory_used"];
+
+static DESCRIPTORS: Lazy<FileDescriptorSet> = Lazy::new(|| {
+    FileDescriptorSet::decode(stat_common::FILE_DESCRIPTOR_SET)
+        .expect("invalid file descriptor set")
+});
+static STAT_REQUEST_SCHEMA: Lazy<String> = Lazy::new(|| build("StatRequest", REQUIRED).to_string());
+
+// 包内的消息，nested 为 StatRequest.LabelsEntry 等
+fn message(name: &str) -> Option<&'static DescriptorProto> {
+    let mut path = name.split('.');
+    let top = path.next()?;
+    let mut msg = DESCRIPTORS
+        .file
+        .iter()
+        .filter(|f| f.package() == PACKAGE)
+        .flat_map(|f| f.message_type.iter())
+        .find(|m| m.name() == top)?;
+    for nested in path {
+        msg = msg.nested_type.iter().find(|m| m.name() == nested)?;
+    }
+    Some(msg)
+}
+
+// ".server_status.StatRequest.LabelsEntry" => "StatRequest.LabelsEntry"
+fn type_name(field: &FieldDescriptorProto) -> &str {
+    field
+        .type_name()
+        .trim_start_matches('.')
+        .trim_start_matches(PACKAGE)
+        .trim_start_matches('.')
+}
+
+fn is_map(field: &FieldDescriptorProto) -> Option<&'static DescriptorProto> {
+    if field.r#type() != Type::Message {
+        return None;
+    }
+    message(type_name(field)).filter(|m| m.options.as_ref().map_or(false, |o| o.map_entry()))
+}
+
+// 字段名与 proto 字段名一致(snake_case)，u64 等为 json 数字
+fn scalar(field: &FieldDescriptorProto, defs: &mut Map<String, Value>) -> Value {
+    match field.r#type() {
+        Type::Double | Type::Float => json!({"type": "number"}),
+        Type::Int32
+        | Type::Int64
+        | Type::Sint32
+        | Type::Sint64
+        | Type::Sfixed32
+        | Type::Sfixed64 => {
+            json!({"type": "integer"})
+        }
+        Type::Uint32 | Type::Uint64 | Type::Fixed32 | Type::Fixed64 => {
+            json!({"type": "integer", "minimum": 0})
+        }
+        Type::Bool => json!({"type": "boolean"}),
+        Type::String => json!({"type": "string"}),
+        Type::Bytes => {
+            json!({"type": "array", "items": {"type": "integer", "minimum": 0, "maximum": 255}})
+        }
+        Type::Enum => json!({"type": "integer"}),
+        Type::Message | Type::Group => {
+            let name = type_name(field).to_string();
+            if !defs.contains_key(&name) {
+                // 先占位，避免递归引用
+                defs.insert(name.to_string(), Value::Null);
+                let schema = object(&name, &[], defs);
+                defs.insert(name.to_string(), schema);
+            }
+            json!({"$ref": format!("#/$defs/{}", name)})
+        }
+    }
+}
+
+fn field_schema(field: &FieldDescriptorProto, defs: &mut Map<String, Value>) -> Value {
+    if let Some(entry) = is_map(field) {
+        let value = entry.field.iter().find(|f| f.name() == "value");
+        return json!({
+            "type": "object",
+            "additionalProperties": value.map_or(json!({}), |v| scalar(v, defs)),
+        });
+    }
+    let schema = scalar(field, defs);
+    if field.label() == Label::Repeated {
+        return json!({"type": "array", "items": schema});
+    }
+    // optional 及消息字段可为 null
+    if field.proto3_optional() || field.r#type() == Type::Message {
+        return json!({"anyOf": [schema, {"type": "null"}]});
+    }
+    schema
+}
+
+fn object(name: &str, required: &[&str], defs: &mut Map<String, Value>) -> Value {
+    let msg = match message(name) {
+        Some(msg) => msg,
+        None => return json!({}),
+    };
+    let mut properties = Map::new();
+    for field in &msg.field {
+        properties.insert(field.name().to_string(), field_schema(field, defs));
+    }
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn build(name: &str, required: &[&str]) -> Value {
+    let mut defs = Map::new();
+    let mut schema = object(name, required, &mut defs);
+    schema["$schema"] = "https://json-schema.org/draft/2020-12/schema".into();
+    schema["title"] = name.into();
+    // 未知字段忽略，便于新旧版本互通
+    schema["additionalProperties"] = true.into();
+    if !defs.is_empty() {
+        schema["$defs"] = defs.into();
+    }
+    schema
+}
+
+// GET /api/schema/stat_request.json
+pub fn stat_request_schema() -> &'static str {
+    STAT_REQUEST_SCHEMA.as_str()
+}
+
+fn stat_fields() -> impl Iterator<Item = &'static str> {
+    message("StatRequest")
+        .into_iter()
+        .flat_map(|m| m.field.iter())
+        .map(|f| f.name())
+}
+
+// proto3 json 映射的字段名: memory_total => memoryTotal
+fn json_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn is_integer(field: &FieldDescriptorProto) -> bool {
+    matches!(
+        field.r#type(),
+        Type::Int32
+            | Type::Int64
+            | Type::Uint32
+            | Type::Uint64
+            | Type::Sint32
+            | Type::Sint64
+            | Type::Fixed32
+            | Type::Fixed64
+            | Type::Sfixed32
+            | Type::Sfixed64
+    )
+}
+
+// 整数可为字符串("123")，proto3 json 映射中 64 位整数即以字符串表示
+fn normalize_value(field: &FieldDescriptorProto, v: &mut Value) {
+    if let Value::Array(list) = v {
+        for item in list {
+            normalize_value(field, item);
+        }
+        return;
+    }
+    if is_integer(field) {
+        let n = v.as_str().and_then(|s| {
+            s.parse::<i64>()
+                .map(Value::from)
+                .or_else(|_| s.parse::<u64>().map(Value::from))
+                .ok()
+        });
+        if let Some(n) = n {
+            *v = n;
+        }
+    } else if let (Type::Message, Value::Object(obj)) = (field.r#type(), v) {
+        match is_map(field) {
+            Some(entry) => {
+                if let Some(value) = entry.field.iter().find(|f| f.name() == "value") {
+                    obj.values_mut().for_each(|o| normalize_value(value, o));
+                }
+            }
+            None => {
+                if let Some(msg) = message(type_name(field)) {
+                    normalize(msg, obj);
+                }
+            }
+        }
+    }
+}
+
+// 同时接受 proto 字段名及 lowerCamelCase 字段名，两者都有时以 proto 字段名为准
+fn normalize(msg: &DescriptorProto, obj: &mut Map<String, Value>) {
+    for field in &msg.field {
+        if !obj.contains_key(field.name()) {
+            if let Some(v) = obj.remove(&json_name(field.name())) {
+                obj.insert(field.name().to_string(), v);
+            }
+        }
+        if let Some(v) = obj.get_mut(field.name()) {
+            normalize_value(field, v);
+        }
+    }
+}
+
+// json 上报解码为 StatRequest，忽略未知字段，缺少必填字段或类型错误时返回该字段名
+// 缺少的可选指标加入 unavailable_metrics
+pub fn decode_stat_request(body: &[u8]) -> Result<StatRequest, Reject> {
+    let mut v = serde_json::from_slice::<Value>(body).map_err(|_| Reject::Invalid("body"))?;
+    let obj = v.as_object_mut().ok_or(Reject::Invalid("body"))?;
+    if let Some(msg) = message("StatRequest") {
+        normalize(msg, obj);
+    }
+    let obj = v.as_object().ok_or(Reject::Invalid("body"))?;
+    if let Some(key) = REQUIRED.iter().find(|k| !obj.contains_key(**k)) {
+        return Err(Reject::Invalid(key));
+    }
+    let mut stat = serde_json::from_value::<StatRequest>(v.clone()).map_err(|_| {
+        // 逐个字段解码，找出类型错误的字段
+        let field = stat_fields().find(|f| {
+            obj.get(*f).map_or(false, |val| {
+                let single = Map::from_iter([(f.to_string(), val.clone())]);
+                serde_json::from_value::<StatRequest>(single.into()).is_err()
+            })
+        });
+        Reject::Invalid(field.unwrap_or("body"))
+    })?;
+    ingest::mark_absent_fields(&mut stat, |f| obj.contains_key(f));
+    Ok(stat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(v: Value) -> Result<StatRequest, Reject> {
+        decode_stat_request(v.to_string().as_bytes())
+    }
+
+    fn minimal() -> Value {
+        json!({
+            "name": "h1",
+            "uptime": 60,
+            "cpu": 1.5,
+            "memory_total": 1024,
+            "memory_used": 512,
+        })
+    }
+
+    fn invalid(res: Result<StatRequest, Reject>) -> &'static str {
+        match res {
+            Err(Reject::Invalid(field)) => field,
+            other => panic!("expect invalid field, got {:?}", other.map(|o| o.name)),
+        }
+    }
+
+    #[test]
+    fn required_fields() {
+        for key in REQUIRED {
+            let mut v = minimal();
+            v.as_object_mut().unwrap().remove(*key);
+            assert_eq!(invalid(decode(v)), *key);
+        }
+        assert_eq!(invalid(decode_stat_request(b"[1]")), "body");
+        assert_eq!(invalid(decode_stat_request(b"{")), "body");
+    }
+
+    #[test]
+    fn unknown_fields_ignored() {
+        let mut v = minimal();
+        v["no_such_field"] = json!({"a": [1, 2]});
+        v["sys_info"] = json!({"os_name": "linux", "extra": true});
+        let stat = decode(v).unwrap();
+        assert_eq!((stat.name.as_str(), stat.memory_used), ("h1", 512));
+        assert_eq!(stat.sys_info.unwrap().os_name, "linux");
+    }
+
+    #[test]
+    fn type_errors_attributed() {
+        for (key, value) in [
+            ("load_1", json!("high")),
+            ("cpu", json!(true)),
+            ("memory_used", json!(-1)),
+            ("network_in", json!("12a")),
+            ("labels", json!(["a"])),
+            ("sys_info", json!(1)),
+        ] {
+            let mut v = minimal();
+            v[key] = value;
+            assert_eq!(invalid(decode(v)), key);
+        }
+    }
+
+    // proto3 json 映射: lowerCamelCase 字段名，64 位整数为字符串
+    #[test]
+    fn proto_json_mapping() {
+        let v = json!({
+            "name": "h1",
+            "uptime": "60",
+            "cpu": 1.5,
+            "memoryTotal": "18446744073709551615",
+            "memory_used": 512,
+            // 两者都有时以 proto 字段名为准
+            "memoryUsed": 1,
+            "networkIn": "-0",
+            "sysInfo": {"osName": "linux", "cpuNum": "4"},
+            "ifaces": [{"name": "eth0", "totalIn": "100"}],
+        });
+        let stat = decode(v).unwrap();
+        assert_eq!(stat.uptime, 60);
+        assert_eq!(stat.memory_total, u64::MAX);
+        assert_eq!(stat.memory_used, 512);
+        assert_eq!(stat.network_in, 0);
+        let sys_info = stat.sys_info.unwrap();
+        assert_eq!((sys_info.os_name.as_str(), sys_info.cpu_num), ("linux", 4));
+        assert_eq!(stat.ifaces[0].total_in, 100);
+        assert_eq!(json_name("last_network_in"), "lastNetworkIn");
+    }
+}
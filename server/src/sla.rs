@@ -0,0 +1,178 @@
+#![deny(warnings)]
+use chrono::{Local, NaiveDate, TimeZone};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::storage;
+use crate::traffic;
+use crate::uptime;
+
+// 已通知的档位，重启后不重复通知
+const SLA_KEY: &str = "sla_notified";
+// 错误预算消耗达到以下百分比时通知，每个计费周期每档一次
+pub const LEVELS: [u32; 3] = [50, 90, 100];
+
+// host => (计费周期, 已通知的最高档位)
+static NOTIFIED: Lazy<Mutex<HashMap<String, (u32, u32)>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    pub period: u32,
+    // 本周期允许的离线时长
+    pub total_secs: u64,
+    pub used_secs: u64,
+}
+
+impl Budget {
+    // 超出预算时为负数
+    pub fn remaining_secs(&self) -> i64 {
+        self.total_secs as i64 - self.used_secs as i64
+    }
+
+    pub fn consumed_pct(&self) -> f64 {
+        if self.total_secs == 0 {
+            return if self.used_secs > 0 { 100.0 } else { 0.0 };
+        }
+        self.used_secs as f64 * 100.0 / self.total_secs as f64
+    }
+}
+
+fn local_ts(date: NaiveDate) -> u64 {
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .map_or(0, |o| o.timestamp().max(0) as u64)
+}
+
+// 当前计费周期(与月流量相同的 monthstart)的错误预算，未记录过可用性的主机为 None
+pub fn budget(name: &str, target: f64, monthstart: u32, now: u64) -> Option<Budget> {
+    let today = Local
+        .timestamp_opt(now as i64, 0)
+        .single()?
+        .naive_local()
+        .date();
+    let (start, end) = traffic::period_range(today, monthstart);
+    let (from, to) = (local_ts(start), local_ts(end));
+    let used_secs = uptime::down_secs(name, from, now.min(to))?;
+    let total_secs = ((to - from) as f64 * (100.0 - target.clamp(0.0, 100.0)) / 100.0) as u64;
+    Some(Budget {
+        period: traffic::period(today, monthstart),
+        total_secs,
+        used_secs,
+    })
+}
+
+// 新达到的最高档位，同一周期内已通知过的档位不再返回
+pub fn crossed(name: &str, budget: &Budget) -> Option<u32> {
+    let consumed = budget.consumed_pct();
+    let level = LEVELS
+        .iter()
+        .rev()
+        .find(|&&o| consumed >= o as f64)
+        .copied()?;
+    let mut notified = NOTIFIED.lock().unwrap();
+    let prev = match notified.get(name) {
+        Some(&(period, level)) if period == budget.period => level,
+        _ => 0,
+    };
+    if level <= prev {
+        return None;
+    }
+    notified.insert(name.to_string(), (budget.period, level));
+    let data = serde_json::to_string(&*notified).unwrap();
+    drop(notified);
+    storage::set(SLA_KEY, &data);
+    Some(level)
+}
+
+pub fn load() {
+    if let Some(data) = storage::get(SLA_KEY) {
+        match serde_json::from_str(&data) {
+            Ok(o) => *NOTIFIED.lock().unwrap() = o,
+            Err(err) => warn!("ignore invalid {} => {:?}", SLA_KEY, err),
+        }
+    }
+}
+
+pub fn remove(name: &str) {
+    NOTIFIED.lock().unwrap().remove(name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 86400;
+
+    fn ts(y: i32, m: u32, d: u32) -> u64 {
+        local_ts(NaiveDate::from_ymd_opt(y, m, d).unwrap())
+    }
+
+    // 离线从上月最后 1 小时持续到本月前 2 小时
+    fn down_across(name: &str, rollover: u64) {
+        uptime::mark_up(name, rollover - 10 * DAY);
+        uptime::mark_down(name, rollover - HOUR, 30, rollover - HOUR + 60);
+        uptime::mark_up(name, rollover + 2 * HOUR);
+    }
+
+    #[test]
+    fn downtime_across_month_rollover() {
+        let name = "sla_rollover";
+        let rollover = ts(2024, 3, 1);
+        down_across(name, rollover);
+
+        // 上个周期只计入 2 月的部分，计到 now (周期结束前 1 秒)
+        let feb = budget(name, 99.0, 1, rollover - 1).unwrap();
+        assert_eq!(feb.period, 202402);
+        assert_eq!(feb.used_secs, HOUR - 1);
+        assert_eq!(feb.total_secs, (rollover - ts(2024, 2, 1)) / 100);
+
+        // 本周期只计入 3 月的 2 小时
+        let mar = budget(name, 99.0, 1, rollover + DAY).unwrap();
+        assert_eq!(mar.period, 202403);
+        assert_eq!(mar.used_secs, 2 * HOUR);
+        assert_eq!(mar.total_secs, (ts(2024, 4, 1) - rollover) / 100);
+        assert_eq!(
+            mar.remaining_secs(),
+            mar.total_secs as i64 - 2 * HOUR as i64
+        );
+    }
+
+    #[test]
+    fn downtime_across_monthstart() {
+        let name = "sla_monthstart";
+        let rollover = ts(2024, 3, 15);
+        down_across(name, rollover);
+
+        let before = budget(name, 99.9, 15, rollover - 1).unwrap();
+        assert_eq!(before.period, 202402);
+        assert_eq!(before.used_secs, HOUR - 1);
+        let after = budget(name, 99.9, 15, rollover + DAY).unwrap();
+        assert_eq!(after.period, 202403);
+        assert_eq!(after.used_secs, 2 * HOUR);
+    }
+
+    // 仍在离线的区间计到 now
+    #[test]
+    fn ongoing_downtime_over_budget() {
+        let name = "sla_ongoing";
+        let rollover = ts(2024, 4, 1);
+        uptime::mark_up(name, rollover - DAY);
+        uptime::mark_down(name, rollover - HOUR, 30, rollover);
+        let now = rollover + DAY;
+        let budget = budget(name, 99.0, 1, now).unwrap();
+        assert_eq!(budget.used_secs, DAY);
+        assert!(budget.remaining_secs() < 0);
+        assert!(budget.consumed_pct() > 100.0);
+
+        assert_eq!(crossed(name, &budget), Some(100));
+        assert_eq!(crossed(name, &budget), None);
+    }
+
+    #[test]
+    fn unknown_host() {
+        assert!(budget("sla_unknown", 99.0, 1, ts(2024, 3, 1)).is_none());
+    }
+}
@@ -0,0 +1,85 @@
+#![deny(warnings)]
+use anyhow::Result;
+use futures::{stream, FutureExt};
+use hyper::{header, Body, Response};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::stats;
+use crate::G_STATS_MGR;
+
+// 无更新时发送注释保持连接，避免被代理断开
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+// 按分组过滤推送消息，不匹配时返回 None
+fn filter_group(msg: &str, group: Option<&str>) -> Option<String> {
+    let group = match group {
+        Some(group) => group,
+        None => return Some(msg.to_string()),
+    };
+    let mut v = serde_json::from_str::<Value>(msg).ok()?;
+    match v["type"].as_str()? {
+        "snapshot" | "update" => {
+            let servers = v["data"]["servers"].as_array_mut()?;
+            servers.retain(|o| o["group"].as_str() == Some(group));
+            if servers.is_empty() && v["type"] == "update" {
+                return None;
+            }
+        }
+        _ => {
+            if v["data"]["group"].as_str() != Some(group) {
+                return None;
+            }
+        }
+    }
+    Some(v.to_string())
+}
+
+fn event(data: &str) -> Result<hyper::body::Bytes, std::io::Error> {
+    Ok(format!("data: {}\n\n", data).into())
+}
+
+// GET /api/v1/stream?group=xxx
+pub async fn stream(group: Option<String>) -> Result<Response<Body>> {
+    let mgr = G_STATS_MGR.get().unwrap();
+    // 先订阅再取全量，避免漏掉中间的更新
+    let rx = mgr.subscribe();
+    let snapshot = format!(r#"{{"type":"snapshot","data":{}}}"#, mgr.get_stats_json());
+    let snapshot = filter_group(&snapshot, group.as_deref()).unwrap_or_default();
+
+    let first = stream::once(async move { event(&snapshot) });
+    let updates = stream::unfold(
+        (rx, group),
+        |(mut rx, group): (broadcast::Receiver<Arc<String>>, Option<String>)| async move {
+            loop {
+                let msg = {
+                    let next = tokio::time::timeout(KEEPALIVE, rx.recv()).fuse();
+                    let shutdown = stats::shutdown_notified().fuse();
+                    futures::pin_mut!(next, shutdown);
+                    futures::select! {
+                        msg = next => msg,
+                        // 服务退出时结束，避免 graceful shutdown 一直等待
+                        _ = shutdown => return None,
+                    }
+                };
+                match msg {
+                    Ok(Ok(msg)) => {
+                        if let Some(data) = filter_group(&msg, group.as_deref()) {
+                            return Some((event(&data), (rx, group)));
+                        }
+                    }
+                    Err(_) => return Some((Ok(":\n\n".into()), (rx, group))),
+                    // 读取太慢或服务关闭，结束连接，浏览器会自动重连
+                    Ok(Err(RecvError::Lagged(_))) | Ok(Err(RecvError::Closed)) => return None,
+                }
+            }
+        },
+    );
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(futures::StreamExt::chain(first, updates)))?)
+}
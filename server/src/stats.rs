@@ -1,33 +1,207 @@
 #![allow(unused)]
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{Datelike, Local, Timelike};
 use lazy_static::lazy_static;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
+use stat_common::PROTO_VERSION;
 use std::borrow::Borrow;
 use std::borrow::BorrowMut;
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs;
-use std::fs::File;
-use std::io::Write;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::sync_channel;
 use std::sync::mpsc::SyncSender;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::{Mutex, PoisonError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Notify};
 
-use crate::notifier::{Event, Notifier};
-use crate::payload::{HostStat, StatsResp};
+use crate::geoip;
+use crate::hostinfo;
+use crate::ingest;
+use crate::notifier::{self, Event, Notifier};
+use crate::payload::{HostStat, HostState, StatsResp, Summary, MAX_LABELS};
+use crate::sla;
+use crate::storage;
+use crate::traffic::{self, MonthTraffic};
+use crate::uptime;
 
 const SAVE_INTERVAL: u64 = 60;
+// 清理过期历史数据的间隔
+const PRUNE_INTERVAL: u64 = 3600;
+const STATS_STATE: &str = "stats";
+// 通过管理 API 禁用的主机，重启后保留
+const HOST_STATE_KEY: &str = "host_state";
+// websocket 每个连接最多缓冲的消息数，超出后断开
+const WS_BUFFER: usize = 64;
+// 客户端默认上报间隔
+const DEFAULT_REPORT_INTERVAL_MS: u64 = 1000;
+// 上报间隔滑动平均中新样本的权重
+const GAP_EWMA_WEIGHT: f64 = 0.2;
+// 同一主机身份冲突通知的最小间隔
+const CONFLICT_NOTIFY_INTERVAL: Duration = Duration::from_secs(1800);
+
+lazy_static! {
+    // 各主机最近一次上报的 proto_version
+    static ref PROTO_VERSIONS: Mutex<HashMap<String, u32>> = Mutex::new(HashMap::new());
+}
 
 static STAT_SENDER: OnceCell<SyncSender<Cow<HostStat>>> = OnceCell::new();
+// 退出中，不再接收上报及触发离线通知
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+// 退出时唤醒 ws/sse 长连接，使其主动结束
+static SHUTDOWN_NOTIFY: Lazy<Notify> = Lazy::new(Notify::new);
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+// 开始退出时返回
+pub async fn shutdown_notified() {
+    // 先创建 Notified，避免检查标志后错过唤醒
+    let notified = SHUTDOWN_NOTIFY.notified();
+    if is_shutting_down() {
+        return;
+    }
+    notified.await;
+}
+
+// 通知事件队列，记录未处理的事件数，退出时等待处理完
+#[derive(Clone)]
+struct EventSender(SyncSender<(Event, Cow<'static, HostStat>)>);
+
+impl EventSender {
+    fn send(&self, msg: (Event, Cow<'static, HostStat>)) {
+        notifier::enqueued();
+        if self.0.send(msg).is_err() {
+            notifier::dequeued();
+        }
+    }
+}
+
+// 启动静默期结束时间，期间正常接收上报但不发送上下线通知
+static QUIET_UNTIL: OnceCell<Instant> = OnceCell::new();
+
+fn in_quiet_window() -> bool {
+    QUIET_UNTIL.get().map_or(false, |t| Instant::now() < *t)
+}
+
+fn save_stats(resp: &StatsResp) {
+    storage::save_state(STATS_STATE, &serde_json::to_string(resp).unwrap());
+}
+
+// 在线主机每个保存周期记录一条历史
+fn append_history(resp: &StatsResp) {
+    for stat in resp.servers.iter().filter(|o| o.online()) {
+        if let Ok(data) = serde_json::to_string(stat) {
+            storage::append_history(&stat.name, resp.updated, &data);
+        }
+    }
+}
+
+// 客户端协议版本与服务端不同时记录一次，版本变化后再次记录
+fn check_proto_version(name: &str, version: u32) {
+    let mut seen = PROTO_VERSIONS.lock().unwrap();
+    if seen.get(name) == Some(&version) {
+        return;
+    }
+    seen.insert(name.to_string(), version);
+    match version {
+        PROTO_VERSION => {}
+        0 => {
+            info!(host = name; "host `{}` client predates proto_version, server is {}", name, PROTO_VERSION)
+        }
+        v if v > PROTO_VERSION => {
+            warn!(host = name; "host `{}` proto_version {} is newer than server {}, new fields are ignored", name, v, PROTO_VERSION)
+        }
+        v => {
+            info!(host = name; "host `{}` proto_version {} is older than server {}", name, v, PROTO_VERSION)
+        }
+    }
+}
+
+// expected_hosts 中从未上报过的主机(host_info 中无记录)，hosts 中有配置时补齐展示信息
+fn missing_hosts(
+    cfg: &crate::config::Config,
+    reported: &HashMap<String, Cow<HostStat>>,
+) -> Vec<HostStat> {
+    cfg.expected_hosts
+        .iter()
+        .filter(|name| !reported.contains_key(*name) && hostinfo::get(name).is_none())
+        .filter_map(|name| {
+            let mut stat = HostStat {
+                name: name.to_string(),
+                notify: true,
+                ..Default::default()
+            };
+            if let Some(host) = cfg.hosts_map.get(name) {
+                if host.disabled || !host.notify {
+                    return None;
+                }
+                stat.alias = host.alias.to_string();
+                stat.location = host.location.to_string();
+                stat.region = host.region.to_string();
+                stat.host_type = host.host_type.to_string();
+                stat.group = host.group.to_string();
+                stat.labels = host.labels.clone();
+                stat.notes = host.notes.to_string();
+            }
+            Some(stat)
+        })
+        .collect()
+}
+
+// stats.json 中主机最近一次保存的数据，供命令行预览模板
+pub fn load_saved_stat(cfg: &crate::config::Config, name: &str) -> Result<Option<HostStat>> {
+    let contents = storage::load_state(STATS_STATE).context("no saved stats")?;
+    let mut v = serde_json::from_str::<serde_json::Value>(&contents)?;
+    let mut o = match v["servers"]
+        .as_array_mut()
+        .and_then(|servers| servers.iter_mut().find(|o| o["name"] == name))
+    {
+        Some(o) => o.take(),
+        None => return Ok(None),
+    };
+    // uptime 保存的是格式化后的字符串
+    if let Some(o) = o.as_object_mut() {
+        o.remove("uptime");
+    }
+    let mut stat: HostStat = serde_json::from_value(o)?;
+    if let Some(info) = cfg.hosts_map.get(name) {
+        stat.alias = info.alias.to_owned();
+        stat.notes = info.notes.to_owned();
+        stat.location = info.location.to_owned();
+        stat.region = info.region.to_owned();
+        stat.host_type = info.host_type.to_owned();
+        stat.group = info.group.to_owned();
+    }
+    stat.swap_percent = stat.swap_pct();
+    stat.status_level = cfg.thresholds.status_level(&stat);
+    Ok(Some(stat))
+}
+
+// 客户端上报的月初值大于总流量(旧客户端相减溢出)时取总流量，本月流量按 0 计
+// 取 0 会把全部累计流量算作本月流量，返回是否修正
+fn clamp_month_start(stat: &mut HostStat) -> bool {
+    let clamped =
+        stat.last_network_in > stat.network_in || stat.last_network_out > stat.network_out;
+    stat.last_network_in = stat.last_network_in.min(stat.network_in);
+    stat.last_network_out = stat.last_network_out.min(stat.network_out);
+    clamped
+}
 
 pub struct StatsMgr {
     resp_json: Arc<Mutex<String>>,
     stats_data: Arc<Mutex<StatsResp>>,
+    stat_dict: Arc<Mutex<HashMap<String, Cow<'static, HostStat>>>>,
+    ws_tx: broadcast::Sender<Arc<String>>,
+    disabled_hosts: Arc<Mutex<HashSet<String>>>,
+    traffic: Arc<Mutex<HashMap<String, MonthTraffic>>>,
+    notifies: Arc<Mutex<Vec<Box<dyn Notifier + Send>>>>,
 }
 
 impl StatsMgr {
@@ -35,6 +209,11 @@ impl StatsMgr {
         Self {
             resp_json: Arc::new(Mutex::new("{}".to_string())),
             stats_data: Arc::new(Mutex::new(StatsResp::new())),
+            stat_dict: Arc::new(Mutex::new(HashMap::new())),
+            ws_tx: broadcast::channel(WS_BUFFER).0,
+            disabled_hosts: Arc::new(Mutex::new(HashSet::new())),
+            traffic: Arc::new(Mutex::new(HashMap::new())),
+            notifies: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -43,10 +222,11 @@ impl StatsMgr {
         cfg: &'static crate::config::Config,
         notifies: Arc<Mutex<Vec<Box<dyn Notifier + Send>>>>,
     ) -> Result<()> {
+        self.notifies = notifies.clone();
         let mut hosts_map = cfg.hosts_map.clone();
 
         // load last_network_in/out
-        if let Ok(contents) = fs::read_to_string("stats.json") {
+        if let Some(contents) = storage::load_state(STATS_STATE) {
             if let Ok(stats_json) = serde_json::from_str::<serde_json::Value>(contents.as_str()) {
                 if let Some(servers) = stats_json["servers"].as_array() {
                     for v in servers {
@@ -70,26 +250,85 @@ impl StatsMgr {
                             error!("invalid json => {:?}", v);
                         }
                     }
-                    trace!("load stats.json succ!");
+                    trace!("load {} succ!", STATS_STATE);
                 }
             } else {
-                warn!("ignore invalid stats.json");
+                warn!("ignore invalid {}", STATS_STATE);
             }
         }
 
+        // load disabled hosts
+        if let Some(contents) = storage::get(HOST_STATE_KEY) {
+            match serde_json::from_str::<serde_json::Value>(&contents) {
+                Ok(v) => {
+                    let mut disabled_hosts = self.disabled_hosts.lock().unwrap();
+                    for name in v["disabled"].as_array().into_iter().flatten() {
+                        if let Some(name) = name.as_str() {
+                            disabled_hosts.insert(name.to_string());
+                        }
+                    }
+                }
+                Err(err) => {
+                    error!("invalid {} => {:?}", HOST_STATE_KEY, err);
+                }
+            }
+        }
+
+        QUIET_UNTIL.set(Instant::now() + Duration::from_secs(cfg.startup_quiet_secs));
+        if cfg.startup_quiet_secs > 0 {
+            info!(
+                "startup quiet window {}s, online/offline notify suppressed",
+                cfg.startup_quiet_secs
+            );
+        }
+
+        // load month traffic
+        *self.traffic.lock().unwrap() = traffic::load();
+        hostinfo::load();
+        uptime::load(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        );
+        sla::load();
+
         let (stat_tx, stat_rx) = sync_channel(512);
         STAT_SENDER.set(stat_tx).unwrap();
         let (notifier_tx, notifier_rx) = sync_channel(512);
+        let notifier_tx = EventSender(notifier_tx);
 
-        let stat_dict: Arc<Mutex<HashMap<String, Cow<HostStat>>>> =
-            Arc::new(Mutex::new(HashMap::new()));
+        let stat_dict = self.stat_dict.clone();
 
         // stat_rx thread
         let stat_dict_1 = stat_dict.clone();
         let notifier_tx_1 = notifier_tx.clone();
+        let disabled_hosts = self.disabled_hosts.clone();
+        let traffic_1 = self.traffic.clone();
+        let mut conflict_notified: HashMap<String, Instant> = HashMap::new();
         thread::spawn(move || loop {
             while let Ok(stat) = stat_rx.recv() {
                 trace!("recv stat `{:?}", stat);
+                if disabled_hosts.lock().unwrap().contains(&stat.name) {
+                    continue;
+                }
+                // 补发的数据早于当前状态，只写入历史，不覆盖实时数据
+                if stat.buffered {
+                    trace!(host = stat.name; "buffered stat `{}` to history", stat.name);
+                    if cfg.storage.history_retention_days > 0 {
+                        if let Ok(data) = serde_json::to_string(&*stat) {
+                            storage::append_history(&stat.name, stat.latest_ts, &data);
+                        }
+                    }
+                    continue;
+                }
+                let mut registered = false;
+                if cfg.auto_register && !hosts_map.contains_key(&stat.name) {
+                    info!(host = stat.name; "auto register host `{}`", &stat.name);
+                    let host = cfg.register_host(&stat.name, hosts_map.len());
+                    hosts_map.insert(stat.name.to_string(), host);
+                    registered = true;
+                }
                 if let Some(info) = hosts_map.get_mut(&stat.name) {
                     if info.disabled {
                         continue;
@@ -101,14 +340,78 @@ impl StatsMgr {
                     let mut stat_t = stat_c.to_mut();
                     stat_t.location = info.location.to_string();
                     stat_t.region = info.region.to_string();
+                    stat_t.country_code = match &info.country_code {
+                        Some(code) => code.to_string(),
+                        None => stat_t
+                            .source_ip
+                            .map(geoip::country_code)
+                            .unwrap_or_default(),
+                    };
                     stat_t.host_type = info.host_type.to_owned();
+                    stat_t.group = info.group.to_owned();
                     stat_t.pos = info.pos;
+                    stat_t.weight = info.weight;
                     stat_t.alias = info.alias.to_owned();
+                    stat_t.notes = info.notes.to_owned();
+                    for o in stat_t.ifaces.iter_mut() {
+                        o.alias = info.iface_aliases.get(&o.name).cloned().unwrap_or_default();
+                    }
+                    for m in stat_t.hot_mounts.iter_mut() {
+                        m.alias = info
+                            .mount_aliases
+                            .get(&m.mount_point)
+                            .cloned()
+                            .unwrap_or_default();
+                    }
+                    stat_t.swap_percent = stat_t.swap_pct();
+                    stat_t.status_level = cfg.thresholds.status_level(stat_t);
+                    if let Some(sources) = ingest::conflict(&stat_t.name) {
+                        stat_t.conflict = true;
+                        stat_t.conflict_sources = sources;
+                    }
+                    // 身份冲突时多台主机交替上报，系统信息及版本来回变化，不记录也不发送 os_changed
+                    let sys_changes = if stat_t.conflict {
+                        Vec::new()
+                    } else {
+                        hostinfo::update(&stat_t.name, &stat_t.version, stat_t.sys_info.as_ref())
+                    };
+                    for o in sys_changes.iter() {
+                        info!(
+                            host = stat_t.name;
+                            "host `{}` {} changed from `{}` to `{}`",
+                            stat_t.name, o.field, o.from, o.to
+                        );
+                    }
+                    check_proto_version(&stat_t.name, stat_t.proto_version);
+                    // labels 冲突时以服务端配置为准
+                    let mut labels = info.labels.clone();
+                    for (k, v) in std::mem::take(&mut stat_t.labels) {
+                        if labels.len() >= MAX_LABELS {
+                            break;
+                        }
+                        labels.entry(k).or_insert(v);
+                    }
+                    stat_t.labels = labels;
                     stat_t.disabled = info.disabled;
-                    stat_t.latest_ts = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
+                    stat_t.notify = info.notify;
+                    stat_t.expected_interval_secs = match cfg.report_interval_ms(&stat_t.name) {
+                        0 => DEFAULT_REPORT_INTERVAL_MS,
+                        ms => ms,
+                    } as f64
+                        / 1000.0;
+                    // 上报间隔较长的主机至少允许错过 3 次上报
+                    stat_t.offline_timeout = info
+                        .offline_timeout_secs
+                        .unwrap_or(cfg.offline_threshold)
+                        .max((stat_t.expected_interval_secs * 3.0).ceil() as u64);
+                    stat_t.hide_offline_secs = info
+                        .hide_offline_after_days
+                        .unwrap_or(cfg.hide_offline_after_days)
+                        * 86400;
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                    stat_t.latest_ts = now.as_secs();
+                    stat_t.recv_ms = now.as_millis() as u64;
+                    uptime::mark_up(&stat_t.name, stat_t.latest_ts);
                     // last_network_in/out
                     if !stat_t.vnstat {
                         if info.last_network_in == 0
@@ -123,6 +426,49 @@ impl StatsMgr {
                             stat_t.last_network_in = info.last_network_in;
                             stat_t.last_network_out = info.last_network_out;
                         }
+                    } else {
+                        let (last_in, last_out) = (stat_t.last_network_in, stat_t.last_network_out);
+                        // 旧客户端每次上报都会出现，日志限频
+                        if clamp_month_start(stat_t)
+                            && ingest::should_log(format!("last_traffic:{}", stat_t.name))
+                        {
+                            warn!(
+                                host = stat_t.name;
+                                "host `{}` last_network_in/out {}/{} exceeds network_in/out {}/{}, clamp to network_in/out",
+                                stat_t.name,
+                                last_in,
+                                last_out,
+                                stat_t.network_in,
+                                stat_t.network_out
+                            );
+                        }
+                    }
+
+                    // 服务端累计月流量，客户端开启 vnstat 时按配置选择来源
+                    let month = {
+                        let mut traffic = traffic_1.lock().unwrap();
+                        let t = traffic.entry(stat_t.name.to_string()).or_default();
+                        t.update(
+                            traffic::period(local_now.naive_local().date(), info.monthstart),
+                            stat_t.network_in,
+                            stat_t.network_out,
+                        );
+                        (t.month_in, t.month_out)
+                    };
+                    if stat_t.vnstat && cfg.traffic_prefer_vnstat {
+                        if stat_t.proto_version >= 9 {
+                            stat_t.month_in = stat_t.month_network_in;
+                            stat_t.month_out = stat_t.month_network_out;
+                        } else {
+                            // 旧客户端未上报月流量，由总流量减去月初值得到
+                            stat_t.month_in =
+                                stat_t.network_in.saturating_sub(stat_t.last_network_in);
+                            stat_t.month_out =
+                                stat_t.network_out.saturating_sub(stat_t.last_network_out);
+                        }
+                    } else {
+                        stat_t.month_in = month.0;
+                        stat_t.month_out = month.1;
                     }
 
                     // uptime str
@@ -144,14 +490,47 @@ impl StatsMgr {
                             if stat_t.ip_info.is_none() {
                                 stat_t.ip_info = pre_stat.ip_info.to_owned();
                             }
+                            let gap =
+                                stat_t.recv_ms.saturating_sub(pre_stat.recv_ms) as f64 / 1000.0;
+                            stat_t.avg_gap_secs = if pre_stat.avg_gap_secs > 0.0 {
+                                pre_stat.avg_gap_secs * (1.0 - GAP_EWMA_WEIGHT)
+                                    + gap * GAP_EWMA_WEIGHT
+                            } else {
+                                gap
+                            };
 
                             if info.notify
-                                && (pre_stat.latest_ts + cfg.offline_threshold < stat_t.latest_ts)
+                                && !in_quiet_window()
+                                && (pre_stat.latest_ts + pre_stat.offline_timeout
+                                    < stat_t.latest_ts)
                             {
                                 // node up notify
                                 notifier_tx_1.send((Event::NodeUp, stat_c.to_owned()));
                             }
                         }
+                        if registered && info.notify {
+                            notifier_tx_1.send((Event::Register, stat_c.clone()));
+                        }
+                        if !sys_changes.is_empty() && cfg.notify_os_change && info.notify {
+                            let mut stat = stat_c.clone();
+                            stat.to_mut().sys_changes = sys_changes;
+                            notifier_tx_1.send((Event::OsChanged, stat));
+                        }
+                        if stat_c.conflict
+                            && info.notify
+                            && conflict_notified
+                                .get(&info.name)
+                                .map_or(true, |t| t.elapsed() >= CONFLICT_NOTIFY_INTERVAL)
+                        {
+                            warn!(
+                                host = info.name;
+                                "host `{}` reported from multiple sources => {:?}",
+                                info.name,
+                                stat_c.conflict_sources
+                            );
+                            conflict_notified.insert(info.name.to_string(), Instant::now());
+                            notifier_tx_1.send((Event::Conflict, stat_c.clone()));
+                        }
                         host_stat_map.insert(info.name.to_string(), stat_c);
                         //trace!("{:?}", host_stat_map);
                     }
@@ -166,39 +545,115 @@ impl StatsMgr {
         let stats_data = self.stats_data.clone();
         let stat_dict_2 = stat_dict.clone();
         let notifier_tx_2 = notifier_tx.clone();
+        let ws_tx = self.ws_tx.clone();
+        let traffic_2 = self.traffic.clone();
+        // websocket 推送增量
+        let mut ws_last: HashMap<String, PushState> = HashMap::new();
+        let push_interval = Duration::from_millis(cfg.push_interval_ms);
         let mut latest_notify_ts: u64 = 0;
         let mut latest_save_ts: u64 = 0;
+        let mut latest_prune_ts: u64 = 0;
+        let mut latest_missing_ts: u64 = 0;
+        // 上次通知检查时的状态，用于发送 degraded/recovered
+        let mut notified_state: HashMap<String, HostState> = HashMap::new();
+        let mut quiet = cfg.startup_quiet_secs > 0;
         thread::spawn(move || loop {
             thread::sleep(Duration::from_millis(500));
 
+            if quiet && !in_quiet_window() {
+                quiet = false;
+                info!("startup quiet window ended, online/offline notify enabled");
+            }
+
             let mut resp = StatsResp::new();
             let mut notified = false;
             if let Ok(mut host_stat_map) = stat_dict_2.lock() {
+                if cfg.offline_purge_secs > 0 {
+                    host_stat_map.retain(|name, stat| {
+                        if cfg.hosts_map.contains_key(name)
+                            || stat.latest_ts + cfg.offline_purge_secs >= resp.updated
+                        {
+                            return true;
+                        }
+                        info!(host = name; "purge host `{}`, no report for {}s", name, cfg.offline_purge_secs);
+                        hostinfo::remove(name);
+                        uptime::remove(name);
+                        sla::remove(name);
+                        traffic_2.lock().unwrap().remove(name);
+                        if stat.notify && !is_shutting_down() {
+                            notifier_tx_2.send((Event::Removed, stat.clone()));
+                        }
+                        false
+                    });
+                }
                 for (_, stat) in host_stat_map.iter_mut() {
                     if stat.disabled {
+                        let hidden = stat.hide_expired(resp.updated);
+                        stat.to_mut().hidden = hidden;
+                        stat.to_mut().state = HostState::Offline;
                         resp.servers.push(stat.to_owned().into_owned());
                         continue;
                     }
                     let stat_c = stat.borrow_mut();
                     let o = stat_c.to_mut();
                     // 30s 下线
-                    if o.latest_ts + cfg.offline_threshold < resp.updated {
+                    if o.latest_ts + o.offline_timeout < resp.updated {
                         o.online4 = false;
                         o.online6 = false;
+                        uptime::mark_down(&o.name, o.latest_ts, o.offline_timeout, resp.updated);
+                    }
+                    o.state = if o.latest_ts + o.offline_timeout < resp.updated {
+                        HostState::Offline
+                    } else if o.status_level.tripped() {
+                        HostState::Degraded
+                    } else {
+                        HostState::Online
+                    };
+                    o.age_secs = resp.updated.saturating_sub(o.latest_ts);
+                    // 长时间离线隐藏，且不再通知
+                    o.hidden = o.hide_expired(resp.updated);
+
+                    // 本月 SLA 错误预算，消耗跨过 50%/90%/100% 时各通知一次
+                    let budget = cfg.sla_target(&o.name, &o.group).and_then(|target| {
+                        let monthstart = cfg.hosts_map.get(&o.name).map_or(1, |h| h.monthstart);
+                        sla::budget(&o.name, target, monthstart, resp.updated)
+                    });
+                    o.sla_budget_remaining_secs = budget.map(|b| b.remaining_secs());
+                    o.sla_budget_consumed_pct = budget.map(|b| b.consumed_pct());
+                    if let Some(budget) = budget {
+                        if o.notify && !o.hidden && !is_shutting_down() {
+                            if let Some(level) = sla::crossed(&o.name, &budget) {
+                                info!(host = o.name; "sla budget of `{}` {}% consumed", o.name, level);
+                                notifier_tx_2.send((Event::SlaBudget, stat_c.clone()));
+                            }
+                        }
                     }
+                    let o = stat_c.to_mut();
 
-                    if let Some(info) = cfg.get_host(o.name.as_str()) {
-                        if info.notify {
-                            // notify check /30 s
-                            if latest_notify_ts + cfg.notify_interval < resp.updated {
-                                if o.online4 || o.online6 {
-                                    notifier_tx_2.send((Event::Custom, stat_c.to_owned()));
-                                } else {
-                                    o.disabled = true;
-                                    notifier_tx_2.send((Event::NodeDown, stat_c.to_owned()));
+                    if o.notify && !o.hidden && !is_shutting_down() {
+                        // notify check /30 s
+                        if latest_notify_ts + cfg.notify_interval < resp.updated {
+                            let state = o.state;
+                            let prev = notified_state.insert(o.name.to_string(), state);
+                            if cfg.notify_degraded {
+                                match (prev, state) {
+                                    (Some(HostState::Online), HostState::Degraded) => {
+                                        notifier_tx_2.send((Event::Degraded, stat_c.clone()));
+                                    }
+                                    (Some(HostState::Degraded), HostState::Online) => {
+                                        notifier_tx_2.send((Event::Recovered, stat_c.clone()));
+                                    }
+                                    _ => {}
                                 }
-                                notified = true;
                             }
+                            let o = stat_c.to_mut();
+                            if o.online() {
+                                notifier_tx_2.send((Event::Custom, stat_c.to_owned()));
+                            } else if !quiet {
+                                o.disabled = true;
+                                notifier_tx_2.send((Event::NodeDown, stat_c.to_owned()));
+                            }
+                            notified = true;
                         }
                     }
 
@@ -207,26 +662,51 @@ impl StatsMgr {
                 if notified {
                     latest_notify_ts = resp.updated;
                 }
+
+                // 启动静默期结束后检查一次，之后每 expected_hosts_check_secs 重复提醒
+                if !quiet
+                    && !cfg.expected_hosts.is_empty()
+                    && !is_shutting_down()
+                    && latest_missing_ts + cfg.expected_hosts_check_secs <= resp.updated
+                {
+                    latest_missing_ts = resp.updated;
+                    for stat in missing_hosts(cfg, &host_stat_map) {
+                        warn!(host = stat.name; "expected host `{}` has never reported", stat.name);
+                        notifier_tx_2.send((Event::Missing, Cow::Owned(stat)));
+                    }
+                }
             }
 
-            resp.servers.sort_by(|a, b| a.pos.cmp(&b.pos));
+            resp.sort_servers(cfg.sort_by);
+            resp.summary = Summary::compute(
+                resp.servers.iter(),
+                cfg.summary_include_hidden,
+                cfg.summary_by_os,
+            );
 
             // last_network_in/out save /60s
             if latest_save_ts + SAVE_INTERVAL < resp.updated {
                 latest_save_ts = resp.updated;
                 if !resp.servers.is_empty() {
-                    if let Ok(mut file) = File::create("stats.json") {
-                        file.write(serde_json::to_string(&resp).unwrap().as_bytes());
-                        file.flush();
-                        trace!("save stats.json succ!");
-                    } else {
-                        error!("save stats.json fail!");
+                    save_stats(&resp);
+                }
+                traffic::save(&traffic_2.lock().unwrap());
+                hostinfo::save();
+                uptime::save(resp.updated, cfg.uptime_retention_days);
+                let retention_days = cfg.storage.history_retention_days;
+                if retention_days > 0 {
+                    append_history(&resp);
+                    if latest_prune_ts + PRUNE_INTERVAL < resp.updated {
+                        latest_prune_ts = resp.updated;
+                        storage::prune_history(resp.updated.saturating_sub(retention_days * 86400));
                     }
                 }
             }
             //
+            let visible = resp.without_hidden();
+            push_ws_diff(&ws_tx, &mut ws_last, &visible, push_interval);
             if let Ok(mut o) = resp_json.lock() {
-                *o = serde_json::to_string(&resp).unwrap();
+                *o = serde_json::to_string(&visible).unwrap();
             }
             if let Ok(mut o) = stats_data.lock() {
                 *o = resp;
@@ -237,34 +717,142 @@ impl StatsMgr {
         thread::spawn(move || loop {
             while let Ok(msg) = notifier_rx.recv() {
                 let (e, stat) = msg;
-                let notifiers = &*notifies.lock().unwrap();
+                let notifiers = &*notifies.lock().unwrap_or_else(PoisonError::into_inner);
                 trace!("recv notify => {:?}, {:?}", e, stat);
                 for notifier in notifiers {
+                    if !notifier.match_host(stat.borrow()) {
+                        continue;
+                    }
                     trace!("{} notify {:?} => {:?}", notifier.kind(), e, stat);
-                    notifier.notify(&e, stat.borrow());
+                    // 单条通知失败只丢弃该通知
+                    if let Err(err) = notifier.notify(&e, stat.borrow()) {
+                        error!(
+                            notifier = notifier.kind();
+                            "{} notify {:?} `{}` err => {:?}",
+                            notifier.kind(),
+                            e,
+                            stat.name,
+                            err
+                        );
+                    }
                 }
+                notifier::dequeued();
             }
         });
 
         Ok(())
     }
 
+    // 停止接收上报，之后不再触发上下线通知
+    pub fn shutdown(&self) {
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+        SHUTDOWN_NOTIFY.notify_waiters();
+    }
+
+    // 保存 last_network_in/out 及月流量，退出前调用
+    pub fn save(&self) {
+        let resp = self.stats_data.lock().unwrap();
+        if !resp.servers.is_empty() {
+            save_stats(&resp);
+        }
+        traffic::save(&self.traffic.lock().unwrap());
+        hostinfo::save();
+        uptime::save(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            crate::G_CONFIG.get().unwrap().uptime_retention_days,
+        );
+    }
+
+    pub fn get_notifiers(&self) -> Arc<Mutex<Vec<Box<dyn Notifier + Send>>>> {
+        self.notifies.clone()
+    }
+
     pub fn get_stats(&self) -> Arc<Mutex<StatsResp>> {
         self.stats_data.clone()
     }
 
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<String>> {
+        self.ws_tx.subscribe()
+    }
+
     pub fn get_stats_json(&self) -> String {
         self.resp_json.lock().unwrap().to_string()
     }
 
-    pub fn report(&self, data: serde_json::Value) -> Result<()> {
+    pub fn get_host_stats(&self) -> HashMap<String, HostStat> {
+        self.stat_dict
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone().into_owned()))
+            .collect()
+    }
+
+    pub fn is_host_disabled(&self, name: &str) -> bool {
+        self.disabled_hosts.lock().unwrap().contains(name)
+    }
+
+    // 禁用后不再接收上报并从列表隐藏
+    pub fn set_host_disabled(&self, name: &str, disabled: bool) -> Result<()> {
+        let mut disabled_hosts = self.disabled_hosts.lock().unwrap();
+        if disabled {
+            disabled_hosts.insert(name.to_string());
+            self.stat_dict.lock().unwrap().remove(name);
+        } else {
+            disabled_hosts.remove(name);
+        }
+        let mut names = disabled_hosts.iter().collect::<Vec<_>>();
+        names.sort();
+        storage::set(
+            HOST_STATE_KEY,
+            &serde_json::json!({ "disabled": names }).to_string(),
+        );
+        Ok(())
+    }
+
+    // 删除主机运行时状态，再次上报后重新出现
+    pub fn remove_host(&self, name: &str) -> bool {
+        self.stat_dict.lock().unwrap().remove(name).is_some()
+    }
+
+    // 心跳只刷新上报时间(在线状态)，不改动展示数据
+    // 尚无该主机数据或已离线时返回 false，需完整上报后才能恢复上线(触发上线通知)
+    pub fn heartbeat(&self, name: &str) -> bool {
+        let mut host_stat_map = self.stat_dict.lock().unwrap();
+        let stat = match host_stat_map.get_mut(name) {
+            Some(stat) => stat,
+            None => return false,
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        if stat.latest_ts + stat.offline_timeout < now.as_secs() {
+            return false;
+        }
+        let o = stat.to_mut();
+        let gap = (now.as_millis() as u64).saturating_sub(o.recv_ms) as f64 / 1000.0;
+        o.avg_gap_secs = o.avg_gap_secs * (1.0 - GAP_EWMA_WEIGHT) + gap * GAP_EWMA_WEIGHT;
+        o.latest_ts = now.as_secs();
+        o.recv_ms = now.as_millis() as u64;
+        uptime::mark_up(name, o.latest_ts);
+        true
+    }
+
+    pub fn report(&self, data: serde_json::Value, source_ip: Option<IpAddr>) -> Result<()> {
         lazy_static! {
             static ref SENDER: SyncSender<Cow<'static, HostStat>> =
                 STAT_SENDER.get().unwrap().clone();
         }
 
-        match serde_json::from_value(data) {
-            Ok(stat) => {
+        // latest_ts 不反序列化，补发的上报需保留客户端采样时间写入历史
+        let sampled_ts = data["latest_ts"].as_u64();
+        match serde_json::from_value::<HostStat>(data) {
+            Ok(mut stat) => {
+                stat.source_ip = source_ip;
+                if stat.buffered {
+                    stat.latest_ts = sampled_ts.unwrap_or_default();
+                }
                 trace!("send stat => {:?} ", stat);
                 SENDER.send(Cow::Owned(stat));
             }
@@ -275,3 +863,170 @@ impl StatsMgr {
         Ok(())
     }
 }
+
+struct PushState {
+    json: String,
+    online: bool,
+    sent_at: Instant,
+}
+
+// 推送变化的主机及上下线事件，同一主机在 interval 内最多推送一次最新值
+fn push_ws_diff(
+    ws_tx: &broadcast::Sender<Arc<String>>,
+    last: &mut HashMap<String, PushState>,
+    resp: &StatsResp,
+    interval: Duration,
+) {
+    let mut changed = Vec::new();
+    for stat in &resp.servers {
+        let online = stat.online();
+        let json = serde_json::to_string(stat).unwrap();
+        if let Some(st) = last.get_mut(&stat.name) {
+            if st.online != online {
+                st.online = online;
+                if ws_tx.receiver_count() > 0 {
+                    let event = if online { "online" } else { "offline" };
+                    let msg = serde_json::json!({"type": event, "data": {"name": stat.name, "group": stat.group}});
+                    ws_tx.send(Arc::new(msg.to_string()));
+                }
+            }
+            if st.json.eq(&json) || st.sent_at.elapsed() < interval {
+                continue;
+            }
+            st.json = json;
+            st.sent_at = Instant::now();
+        } else {
+            last.insert(
+                stat.name.to_string(),
+                PushState {
+                    json,
+                    online,
+                    sent_at: Instant::now(),
+                },
+            );
+        }
+        changed.push(stat);
+    }
+    last.retain(|name, _| resp.servers.iter().any(|o| o.name.eq(name)));
+
+    if !changed.is_empty() && ws_tx.receiver_count() > 0 {
+        let msg = serde_json::json!({
+            "type": "update",
+            "data": {"updated": resp.updated, "servers": changed},
+        });
+        ws_tx.send(Arc::new(msg.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_stat(latest_ts: u64) -> HostStat {
+        HostStat {
+            latest_ts,
+            offline_timeout: 30,
+            ..Default::default()
+        }
+    }
+
+    // 事件进入队列即计入待处理，退出时 flush 等到处理完；投递失败立即撤销
+    #[tokio::test]
+    async fn event_queue_counted_until_handled() {
+        let _lock = crate::testing::NOTIFIER_LOCK.lock().await;
+        let (tx, rx) = sync_channel(1);
+        let sender = EventSender(tx);
+        sender.send((Event::NodeUp, Cow::Owned(HostStat::default())));
+        assert_eq!(notifier::flush(Duration::from_millis(200)).await, (0, 1));
+
+        rx.recv().unwrap();
+        notifier::dequeued();
+        assert_eq!(notifier::flush(Duration::from_millis(200)).await, (0, 0));
+
+        drop(rx);
+        sender.send((Event::NodeUp, Cow::Owned(HostStat::default())));
+        assert_eq!(notifier::flush(Duration::from_millis(200)).await, (0, 0));
+    }
+
+    #[test]
+    fn month_start_clamped_to_total() {
+        let mut stat = HostStat {
+            network_in: 1000,
+            network_out: 2000,
+            last_network_in: 400,
+            last_network_out: 2500,
+            ..Default::default()
+        };
+        assert!(clamp_month_start(&mut stat));
+        assert_eq!((stat.last_network_in, stat.last_network_out), (400, 2000));
+        // 本月流量 = 总流量 - 月初值
+        assert_eq!(stat.network_out - stat.last_network_out, 0);
+        assert!(!clamp_month_start(&mut stat));
+        assert_eq!((stat.last_network_in, stat.last_network_out), (400, 2000));
+    }
+
+    #[test]
+    fn missing_expected_hosts() {
+        let cfg = crate::config::from_str(
+            r#"
+expected_hosts = ["mh_never", "mh_online", "mh_before", "mh_disabled", "mh_muted", "mh_extra"]
+hosts = [
+  {name = "mh_never", password = "p", location = "tokyo", region = "jp", type = "kvm", group = "g1"},
+  {name = "mh_online", password = "p", location = "x", region = "x", type = "kvm"},
+  {name = "mh_before", password = "p", location = "x", region = "x", type = "kvm"},
+  {name = "mh_disabled", password = "p", location = "x", region = "x", type = "kvm", disabled = true},
+  {name = "mh_muted", password = "p", location = "x", region = "x", type = "kvm", notify = false},
+]
+"#,
+        )
+        .unwrap();
+        let mut reported = HashMap::new();
+        reported.insert(
+            "mh_online".to_string(),
+            Cow::Owned(HostStat {
+                name: "mh_online".to_string(),
+                ..Default::default()
+            }),
+        );
+        // 重启前上报过，hostinfo 中有记录
+        hostinfo::update("mh_before", "1.0.0", None);
+
+        let missing = missing_hosts(&cfg, &reported);
+        let names = missing.iter().map(|o| o.name.as_str()).collect::<Vec<_>>();
+        // 未在 hosts 中配置的主机同样提醒，禁用或关闭通知的不提醒
+        assert_eq!(names, ["mh_never", "mh_extra"]);
+        let never = &missing[0];
+        assert_eq!(
+            (never.alias.as_str(), never.location.as_str()),
+            ("mh_never", "tokyo")
+        );
+        assert_eq!(never.group, "g1");
+        assert!(never.notify);
+        assert_eq!(missing[1].location, "");
+    }
+
+    #[test]
+    fn heartbeat_requires_online_host() {
+        let mgr = StatsMgr::new();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        {
+            let mut dict = mgr.stat_dict.lock().unwrap();
+            dict.insert("hb_online".to_string(), Cow::Owned(host_stat(now - 5)));
+            dict.insert("hb_offline".to_string(), Cow::Owned(host_stat(now - 60)));
+        }
+
+        // 在线主机刷新 latest_ts
+        assert!(mgr.heartbeat("hb_online"));
+        assert!(mgr.stat_dict.lock().unwrap()["hb_online"].latest_ts >= now);
+        // 未知及已离线的主机要求完整上报(响应 code 1)，不刷新状态
+        assert!(!mgr.heartbeat("hb_unknown"));
+        assert!(!mgr.heartbeat("hb_offline"));
+        assert_eq!(
+            mgr.stat_dict.lock().unwrap()["hb_offline"].latest_ts,
+            now - 60
+        );
+    }
+}
@@ -0,0 +1,130 @@
+#![deny(warnings)]
+use anyhow::Result;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use super::Storage;
+
+// 状态及 kv 为 <dir>/<name>.json，历史为 <dir>/history/<host>.jsonl，每行 "ts\tdata"
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: &str) -> Result<Self> {
+        let dir = PathBuf::from(match dir {
+            "" => ".",
+            dir => dir,
+        });
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn json_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    fn history_dir(&self) -> PathBuf {
+        self.dir.join("history")
+    }
+
+    // 主机名可能含 / 等字符
+    fn history_path(&self, host: &str) -> PathBuf {
+        let name = url::form_urlencoded::byte_serialize(host.as_bytes()).collect::<String>();
+        self.history_dir().join(format!("{}.jsonl", name))
+    }
+
+    fn read(&self, path: PathBuf) -> Result<Option<String>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // 先写临时文件再替换，避免写到一半退出留下残缺的文件
+    fn write(&self, path: PathBuf, data: &str) -> Result<()> {
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u64, &str)> {
+    let (ts, data) = line.split_once('\t')?;
+    Some((ts.parse().ok()?, data))
+}
+
+impl Storage for FileStorage {
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn load_state(&self, name: &str) -> Result<Option<String>> {
+        self.read(self.json_path(name))
+    }
+
+    fn save_state(&self, name: &str, data: &str) -> Result<()> {
+        self.write(self.json_path(name), data)
+    }
+
+    fn append_history(&self, host: &str, ts: u64, data: &str) -> Result<()> {
+        fs::create_dir_all(self.history_dir())?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.history_path(host))?;
+        file.write_all(format!("{}\t{}\n", ts, data).as_bytes())?;
+        Ok(())
+    }
+
+    fn query_history(&self, host: &str, from: u64, to: u64) -> Result<Vec<(u64, String)>> {
+        let contents = match self.read(self.history_path(host))? {
+            Some(contents) => contents,
+            None => return Ok(Vec::new()),
+        };
+        let mut list = contents
+            .lines()
+            .filter_map(parse_line)
+            .filter(|(ts, _)| *ts >= from && *ts <= to)
+            .map(|(ts, data)| (ts, data.to_string()))
+            .collect::<Vec<_>>();
+        // 补发的数据可能晚于实时数据写入
+        list.sort_by_key(|(ts, _)| *ts);
+        Ok(list)
+    }
+
+    fn prune_history(&self, before: u64) -> Result<()> {
+        let entries = match fs::read_dir(self.history_dir()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let contents = fs::read_to_string(&path)?;
+            let kept = contents
+                .lines()
+                .filter(|line| parse_line(line).map_or(false, |(ts, _)| ts >= before))
+                .collect::<Vec<_>>();
+            if kept.is_empty() {
+                fs::remove_file(&path)?;
+            } else if kept.len() < contents.lines().count() {
+                let tmp = path.with_extension("jsonl.tmp");
+                fs::write(&tmp, kept.join("\n") + "\n")?;
+                fs::rename(tmp, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        self.read(self.json_path(key))
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.write(self.json_path(key), value)
+    }
+}
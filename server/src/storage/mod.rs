@@ -0,0 +1,399 @@
+#![deny(warnings)]
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod file;
+pub mod sqlite;
+
+// 连续失败后暂停访问存储的最长时间，期间只使用内存数据
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+static STORE: OnceCell<Store> = OnceCell::new();
+static WRITER: OnceCell<Mutex<Sender<Write>>> = OnceCell::new();
+
+fn default_history_retention_days() -> u64 {
+    7
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    // 每项状态一个 json 文件，与旧版本的文件兼容
+    File,
+    Sqlite,
+}
+impl Default for Kind {
+    fn default() -> Self {
+        Kind::File
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default = "Default::default")]
+    pub kind: Kind,
+    // file: 状态文件目录，默认当前目录; sqlite: 数据库文件，默认 stats.db
+    #[serde(default = "Default::default")]
+    pub path: String,
+    // 主机历史数据保留天数，0 为不记录
+    #[serde(default = "default_history_retention_days")]
+    pub history_retention_days: u64,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            kind: Kind::default(),
+            path: String::new(),
+            history_retention_days: default_history_retention_days(),
+        }
+    }
+}
+
+// 持久化后端，均为阻塞 I/O
+// 保持同步接口: 后端基于 std::fs 与 rusqlite，均只有阻塞 API，异步化也只是在内部包一层 spawn_blocking;
+// 主要调用方(统计、流量、在线率等定时线程及启动、退出流程)本身是同步线程，
+// 异步上下文经 spawn_blocking (query_history) 或写线程 (set) 调用，不阻塞运行时
+pub trait Storage: Send + Sync {
+    fn kind(&self) -> &'static str;
+    // 整体快照，如 stats/traffic/uptime，启动时加载，定时覆盖保存
+    fn load_state(&self, name: &str) -> Result<Option<String>>;
+    fn save_state(&self, name: &str, data: &str) -> Result<()>;
+    // 主机历史数据，data 为单行 json
+    fn append_history(&self, host: &str, ts: u64, data: &str) -> Result<()>;
+    // [from, to] 内的记录，按时间升序
+    fn query_history(&self, host: &str, from: u64, to: u64) -> Result<Vec<(u64, String)>>;
+    // 删除早于 before 的历史
+    fn prune_history(&self, before: u64) -> Result<()>;
+    // 按需读写的小数据，如禁用主机列表
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+}
+
+enum Write {
+    Set(String, String),
+    // 之前的写入完成后回复
+    Flush(mpsc::SyncSender<()>),
+}
+
+#[derive(Default)]
+struct Health {
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+// 存储出错时按操作及键退避，期间该项读写直接跳过，服务继续使用内存中的数据，
+// 其它键(如其它状态文件、其它主机的历史)不受影响
+struct Store {
+    backend: Box<dyn Storage>,
+    // "操作 键" => 连续失败状态，成功后移除
+    health: Mutex<HashMap<String, Health>>,
+}
+
+impl Store {
+    fn call<T, F>(&self, op: &str, key: &str, f: F) -> Result<T>
+    where
+        F: FnOnce(&dyn Storage) -> Result<T>,
+    {
+        let what = format!("{} {}", op, key);
+        if let Some(retry_at) = self
+            .health
+            .lock()
+            .unwrap()
+            .get(&what)
+            .and_then(|o| o.retry_at)
+        {
+            let now = Instant::now();
+            if now < retry_at {
+                let secs = (retry_at - now).as_secs();
+                trace!(
+                    "skip storage {} {}, retry in {}s",
+                    self.backend.kind(),
+                    what,
+                    secs
+                );
+                return Err(anyhow!("storage unavailable, retry in {}s", secs));
+            }
+        }
+        let res = f(self.backend.as_ref());
+        let mut healths = self.health.lock().unwrap();
+        match &res {
+            Ok(_) => {
+                if let Some(health) = healths.remove(&what) {
+                    info!(
+                        "storage {} {} recovered after {} failures",
+                        self.backend.kind(),
+                        what,
+                        health.failures
+                    );
+                }
+            }
+            Err(err) => {
+                let health = healths.entry(what.clone()).or_default();
+                health.failures += 1;
+                let backoff =
+                    Duration::from_secs(1 << (health.failures - 1).min(9)).min(MAX_BACKOFF);
+                health.retry_at = Some(Instant::now() + backoff);
+                error!(
+                    "storage {} {} err => {:?}, retry in {}s",
+                    self.backend.kind(),
+                    what,
+                    err,
+                    backoff.as_secs()
+                );
+            }
+        }
+        res
+    }
+}
+
+pub fn init(cfg: &Config) -> Result<()> {
+    let backend: Box<dyn Storage> = match cfg.kind {
+        Kind::File => Box::new(file::FileStorage::new(&cfg.path)?),
+        Kind::Sqlite => Box::new(sqlite::SqliteStorage::new(match cfg.path.as_str() {
+            "" => "stats.db",
+            path => path,
+        })?),
+    };
+    info!("storage => {}", backend.kind());
+    STORE
+        .set(Store {
+            backend,
+            health: Mutex::new(HashMap::new()),
+        })
+        .map_err(|_| anyhow!("storage already initialized"))?;
+
+    let (tx, rx) = mpsc::channel();
+    let _ = WRITER.set(Mutex::new(tx));
+    thread::Builder::new()
+        .name("storage-writer".into())
+        .spawn(move || write_loop(rx))?;
+    Ok(())
+}
+
+fn store() -> &'static Store {
+    STORE.get().expect("storage not initialized")
+}
+
+// set 按调用顺序在写线程中执行
+fn write_loop(rx: Receiver<Write>) {
+    let store = store();
+    while let Ok(write) = rx.recv() {
+        match write {
+            Write::Set(key, value) => {
+                let _ = store.call("set", &key, |backend| backend.set(&key, &value));
+            }
+            Write::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+// 以下同步接口会阻塞当前线程，只在定时线程、启动及退出流程中调用
+
+// 失败时返回 None，调用方按无数据处理
+pub fn load_state(name: &str) -> Option<String> {
+    store()
+        .call("load", name, |backend| backend.load_state(name))
+        .ok()
+        .flatten()
+}
+
+pub fn save_state(name: &str, data: &str) {
+    if store()
+        .call("save", name, |backend| backend.save_state(name, data))
+        .is_ok()
+    {
+        trace!("save state {} succ!", name);
+    }
+}
+
+pub fn append_history(host: &str, ts: u64, data: &str) {
+    let _ = store().call("append", host, |backend| {
+        backend.append_history(host, ts, data)
+    });
+}
+
+pub fn prune_history(before: u64) {
+    let _ = store().call("prune", "history", |backend| backend.prune_history(before));
+}
+
+pub fn get(key: &str) -> Option<String> {
+    store()
+        .call("get", key, |backend| backend.get(key))
+        .ok()
+        .flatten()
+}
+
+// http 接口查询，在阻塞线程池中执行
+pub async fn query_history(host: &str, from: u64, to: u64) -> Result<Vec<(u64, String)>> {
+    let host = host.to_string();
+    tokio::task::spawn_blocking(move || {
+        store().call("query", &host, |backend| {
+            backend.query_history(&host, from, to)
+        })
+    })
+    .await?
+}
+
+// 不阻塞调用方，可在异步上下文中调用(如 http 处理、grpc 认证失败记录封禁)，由写线程依次写入
+pub fn set(key: &str, value: &str) {
+    if let Some(writer) = WRITER.get() {
+        let _ = writer
+            .lock()
+            .unwrap()
+            .send(Write::Set(key.to_string(), value.to_string()));
+    }
+}
+
+// 等待已提交的 set 写入完成，退出时调用
+pub fn flush(timeout: Duration) {
+    let (done_tx, done_rx) = mpsc::sync_channel(1);
+    let sent = WRITER.get().map_or(false, |writer| {
+        writer.lock().unwrap().send(Write::Flush(done_tx)).is_ok()
+    });
+    if sent && done_rx.recv_timeout(timeout).is_err() {
+        warn!(
+            "storage: pending writes not finished after {}s",
+            timeout.as_secs()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn roundtrip(backend: &dyn Storage) {
+        assert_eq!(backend.load_state("stats").unwrap(), None);
+        backend.save_state("stats", "{\"a\":1}").unwrap();
+        backend.save_state("stats", "{\"a\":2}").unwrap();
+        assert_eq!(
+            backend.load_state("stats").unwrap().as_deref(),
+            Some("{\"a\":2}")
+        );
+
+        backend.set("bans", "[]").unwrap();
+        assert_eq!(backend.get("bans").unwrap().as_deref(), Some("[]"));
+        assert_eq!(backend.get("none").unwrap(), None);
+
+        // 补发的数据晚于实时数据写入
+        for ts in [10, 30, 20, 40] {
+            backend
+                .append_history("a/b", ts, &format!("{{\"ts\":{}}}", ts))
+                .unwrap();
+        }
+        backend.append_history("other", 25, "{}").unwrap();
+        let ts = |list: Vec<(u64, String)>| list.into_iter().map(|(ts, _)| ts).collect::<Vec<_>>();
+        assert_eq!(
+            ts(backend.query_history("a/b", 20, 40).unwrap()),
+            [20, 30, 40]
+        );
+        backend.prune_history(30).unwrap();
+        assert_eq!(
+            ts(backend.query_history("a/b", 0, u64::MAX).unwrap()),
+            [30, 40]
+        );
+        assert!(backend
+            .query_history("other", 0, u64::MAX)
+            .unwrap()
+            .is_empty());
+        assert!(backend
+            .query_history("none", 0, u64::MAX)
+            .unwrap()
+            .is_empty());
+    }
+
+    // save_state("bad") 总是失败，记录实际调用后端的次数
+    struct Flaky {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Storage for Flaky {
+        fn kind(&self) -> &'static str {
+            "flaky"
+        }
+        fn load_state(&self, _name: &str) -> Result<Option<String>> {
+            Ok(None)
+        }
+        fn save_state(&self, name: &str, _data: &str) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match name {
+                "bad" => Err(anyhow!("disk full")),
+                _ => Ok(()),
+            }
+        }
+        fn append_history(&self, _host: &str, _ts: u64, _data: &str) -> Result<()> {
+            Ok(())
+        }
+        fn query_history(&self, _host: &str, _from: u64, _to: u64) -> Result<Vec<(u64, String)>> {
+            Ok(Vec::new())
+        }
+        fn prune_history(&self, _before: u64) -> Result<()> {
+            Ok(())
+        }
+        fn get(&self, _key: &str) -> Result<Option<String>> {
+            Ok(None)
+        }
+        fn set(&self, _key: &str, _value: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // 一项写入失败只暂停该项，其它键照常写入
+    #[test]
+    fn backoff_per_key() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let store = Store {
+            backend: Box::new(Flaky {
+                calls: counter.clone(),
+            }),
+            health: Mutex::new(HashMap::new()),
+        };
+        let calls = || counter.load(Ordering::SeqCst);
+        let save = |name: &str| store.call("save", name, |b| b.save_state(name, "{}"));
+
+        assert!(save("bad").is_err());
+        assert_eq!(calls(), 1);
+        // 退避期间不再访问后端
+        assert!(save("bad").is_err());
+        assert_eq!(calls(), 1);
+        // 其它键不受影响
+        assert!(save("good").is_ok());
+        assert!(save("good").is_ok());
+        assert_eq!(calls(), 3);
+        // 同一键的其它操作也不受影响
+        assert!(store.call("load", "bad", |b| b.load_state("bad")).is_ok());
+        assert_eq!(store.health.lock().unwrap().len(), 1);
+    }
+
+    fn temp_dir(tag: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("stat_storage_{}_{}", tag, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn file_storage() {
+        let dir = temp_dir("file");
+        roundtrip(&file::FileStorage::new(dir.to_str().unwrap()).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_storage() {
+        let dir = temp_dir("sqlite");
+        roundtrip(&sqlite::SqliteStorage::new(dir.join("stats.db").to_str().unwrap()).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
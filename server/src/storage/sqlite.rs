@@ -0,0 +1,102 @@
+#![deny(warnings)]
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::Mutex;
+
+use super::Storage;
+
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn new(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS state (name TEXT PRIMARY KEY, data TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS kv (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+            CREATE TABLE IF NOT EXISTS history (host TEXT NOT NULL, ts INTEGER NOT NULL, data TEXT NOT NULL);
+            CREATE INDEX IF NOT EXISTS history_host_ts ON history (host, ts);",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn kind(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn load_state(&self, name: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row("SELECT data FROM state WHERE name = ?", [name], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn save_state(&self, name: &str, data: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO state (name, data) VALUES (?, ?)",
+            [name, data],
+        )?;
+        Ok(())
+    }
+
+    fn append_history(&self, host: &str, ts: u64, data: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO history (host, ts, data) VALUES (?, ?, ?)",
+            params![host, ts as i64, data],
+        )?;
+        Ok(())
+    }
+
+    fn query_history(&self, host: &str, from: u64, to: u64) -> Result<Vec<(u64, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT ts, data FROM history WHERE host = ? AND ts >= ? AND ts <= ? ORDER BY ts",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                host,
+                from.min(i64::MAX as u64) as i64,
+                to.min(i64::MAX as u64) as i64
+            ],
+            |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)),
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    fn prune_history(&self, before: u64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM history WHERE ts < ?",
+            [before.min(i64::MAX as u64) as i64],
+        )?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row("SELECT value FROM kv WHERE key = ?", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO kv (key, value) VALUES (?, ?)",
+            [key, value],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+#![deny(warnings)]
+// 单元测试共用的配置，G_CONFIG 全局只能设置一次，各测试使用不同的主机名互不影响
+use crate::config::{self, Config};
+use crate::G_CONFIG;
+use once_cell::sync::Lazy;
+
+// hosts 中 signed / signed_only 的 hmac_secret
+pub const SECRET: &str = "s3cret";
+
+const CONFIG: &str = r#"
+grpc_addr = ["127.0.0.1:0"]
+http_addr = ["127.0.0.1:0"]
+identity_conflict_window_secs = 60
+identity_conflict_reject = true
+hosts = [
+  {name = "h1", password = "p1", location = "x", region = "x", type = "kvm"},
+  {name = "signed", password = "p1", location = "x", region = "x", type = "kvm", hmac_secret = "s3cret"},
+  {name = "signed_only", password = "p1", location = "x", region = "x", type = "kvm", hmac_secret = "s3cret", signed_only = true},
+  {name = "no_ifaces", password = "p1", location = "x", region = "x", type = "kvm", report_ifaces = false, disk_warn_pct = 80.0},
+]
+"#;
+
+pub fn init_config() -> &'static Config {
+    G_CONFIG.get_or_init(|| config::from_str(CONFIG).unwrap())
+}
+
+// 通知的 QUEUED/PENDING 计数为全局状态，flush 按其判断是否发送完毕
+// 凡是发送通知(notifier::spawn)、经 stats 通知队列投递事件或调用 flush 的测试都须持有此锁，依次执行
+pub static NOTIFIER_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
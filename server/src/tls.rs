@@ -0,0 +1,180 @@
+#![deny(warnings)]
+use anyhow::{Context, Result};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::listener::{self, PeerAddr};
+
+static TLS_ACCEPTOR: Lazy<RwLock<Option<TlsAcceptor>>> = Lazy::new(Default::default);
+// 建立连接后不发送数据或握手中途停止时关闭，避免空闲连接一直占用
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn load_server_config(cert: &str, key: &str) -> Result<ServerConfig> {
+    let certs = File::open(cert)
+        .and_then(|f| rustls_pemfile::certs(&mut BufReader::new(f)))
+        .with_context(|| format!("can't read tls_cert `{}`", cert))?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+
+    let key_der = File::open(key)
+        .and_then(|f| rustls_pemfile::read_all(&mut BufReader::new(f)))
+        .with_context(|| format!("can't read tls_key `{}`", key))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(k) | Item::PKCS8Key(k) | Item::ECKey(k) => Some(PrivateKey(k)),
+            _ => None,
+        })
+        .with_context(|| format!("no private key found in tls_key `{}`", key))?;
+
+    let mut cfg = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key_der)
+        .with_context(|| format!("invalid tls_cert `{}` or tls_key `{}`", cert, key))?;
+    // grpc 需要 h2
+    cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(cfg)
+}
+
+pub fn init(cert: &str, key: &str) -> Result<()> {
+    let cfg = load_server_config(cert, key)?;
+    *TLS_ACCEPTOR.write().unwrap() = Some(TlsAcceptor::from(Arc::new(cfg)));
+    Ok(())
+}
+
+// SIGHUP 重新加载证书，方便 Let's Encrypt 续期
+#[cfg(unix)]
+pub fn reload_on_sighup(cert: &'static str, key: &'static str) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to install SIGHUP handler");
+        while hangup.recv().await.is_some() {
+            match init(cert, key) {
+                Ok(_) => info!("tls cert reloaded"),
+                Err(err) => error!("tls cert reload error => {:?}", err),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_cert: &'static str, _key: &'static str) {}
+
+async fn handshake<IO>(
+    acceptor: &TlsAcceptor,
+    stream: IO,
+    timeout: Duration,
+) -> io::Result<TlsStream<IO>>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(timeout, acceptor.accept(stream))
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "tls handshake timeout",
+            ))
+        })
+}
+
+// accept tcp and finish tls handshake, for both http and grpc
+pub fn incoming(
+    addrs: &[String],
+    filter: fn(IpAddr) -> bool,
+) -> Result<mpsc::UnboundedReceiver<io::Result<TlsStream<TcpStream>>>> {
+    let mut tcp_incoming = listener::incoming(addrs, filter)?;
+    let (tx, rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        while let Some(Ok(stream)) = tcp_incoming.next().await {
+            if tx.is_closed() {
+                break;
+            }
+            let acceptor = TLS_ACCEPTOR.read().unwrap().clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                if let Some(acceptor) = acceptor {
+                    let peer = stream.peer();
+                    match handshake(&acceptor, stream, HANDSHAKE_TIMEOUT).await {
+                        Ok(tls_stream) => {
+                            let _ = tx.unbounded_send(Ok(tls_stream));
+                        }
+                        Err(err) => {
+                            debug!("tls handshake with {:?} error => {:?}", peer, err);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerName};
+    use tokio_rustls::TlsConnector;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls_cert.pem");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls_key.pem");
+
+    fn acceptor() -> TlsAcceptor {
+        TlsAcceptor::from(Arc::new(load_server_config(CERT, KEY).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn idle_client_times_out() {
+        let (_client, server) = tokio::io::duplex(4096);
+        let err = handshake(&acceptor(), server, Duration::from_millis(100))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn handshake_within_timeout() {
+        let mut roots = RootCertStore::empty();
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(CERT).unwrap())).unwrap();
+        roots.add_parsable_certificates(&certs);
+        let cfg = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let (client, server) = tokio::io::duplex(4096);
+        let client = tokio::spawn(async move {
+            let name = ServerName::try_from("localhost").unwrap();
+            let mut tls = TlsConnector::from(Arc::new(cfg))
+                .connect(name, client)
+                .await
+                .unwrap();
+            tls.write_all(b"ping").await.unwrap();
+            tls.flush().await.unwrap();
+            tls
+        });
+        let mut tls = handshake(&acceptor(), server, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let mut buf = [0u8; 4];
+        tls.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        drop(client.await.unwrap());
+    }
+}
@@ -0,0 +1,206 @@
+#![deny(warnings)]
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::storage;
+
+// 服务端统计的月流量，重启后继续累加
+pub const TRAFFIC_STATE: &str = "traffic";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MonthTraffic {
+    // 计费周期起始年月，如 202207
+    pub period: u32,
+    pub month_in: u64,
+    pub month_out: u64,
+    // 上次上报的累计值，None 表示尚无基准
+    pub last_in: Option<u64>,
+    pub last_out: Option<u64>,
+}
+
+impl MonthTraffic {
+    // 累加两次上报间的增量，进入新周期时清零
+    // 服务端停机期间的流量计入恢复后收到上报的周期
+    pub fn update(&mut self, period: u32, network_in: u64, network_out: u64) {
+        if self.period != period {
+            self.period = period;
+            self.month_in = 0;
+            self.month_out = 0;
+        }
+        self.month_in += delta(self.last_in, network_in);
+        self.month_out += delta(self.last_out, network_out);
+        self.last_in = Some(network_in);
+        self.last_out = Some(network_out);
+    }
+}
+
+// 计数器变小视为客户端重启清零，本次值即为重启后的流量
+fn delta(last: Option<u64>, cur: u64) -> u64 {
+    match last {
+        Some(last) if cur >= last => cur - last,
+        Some(_) => cur,
+        None => 0,
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (y, m) = next_month(year, month);
+    NaiveDate::from_ymd_opt(y, m, 1)
+        .and_then(|d| d.pred_opt())
+        .map_or(28, |d| d.day())
+}
+
+fn next_month(year: i32, month: u32) -> (i32, u32) {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+fn prev_month(year: i32, month: u32) -> (i32, u32) {
+    if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    }
+}
+
+// 某月的周期起始日，monthstart 超过当月天数时按月末计算
+fn start_day(year: i32, month: u32, monthstart: u32) -> NaiveDate {
+    let day = monthstart.max(1).min(days_in_month(year, month));
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+// 计费周期 [start, end)，与月流量统计一致
+pub fn period_range(today: NaiveDate, monthstart: u32) -> (NaiveDate, NaiveDate) {
+    let (year, month) = (today.year(), today.month());
+    let (y, m) = if today >= start_day(year, month, monthstart) {
+        (year, month)
+    } else {
+        prev_month(year, month)
+    };
+    let (ny, nm) = next_month(y, m);
+    (start_day(y, m, monthstart), start_day(ny, nm, monthstart))
+}
+
+// 计费周期起始年月
+pub fn period(today: NaiveDate, monthstart: u32) -> u32 {
+    let (start, _) = period_range(today, monthstart);
+    start.year() as u32 * 100 + start.month()
+}
+
+pub fn load() -> HashMap<String, MonthTraffic> {
+    match storage::load_state(TRAFFIC_STATE) {
+        Some(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("ignore invalid {} => {:?}", TRAFFIC_STATE, err);
+            HashMap::new()
+        }),
+        None => HashMap::new(),
+    }
+}
+
+pub fn save(traffic: &HashMap<String, MonthTraffic>) {
+    match serde_json::to_string(traffic) {
+        Ok(s) => storage::save_state(TRAFFIC_STATE, &s),
+        Err(err) => error!("save {} fail => {:?}", TRAFFIC_STATE, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn first_report_sets_base() {
+        let mut t = MonthTraffic::default();
+        t.update(202207, 1000, 2000);
+        assert_eq!((t.month_in, t.month_out), (0, 0));
+        t.update(202207, 1500, 2100);
+        assert_eq!((t.month_in, t.month_out), (500, 100));
+    }
+
+    #[test]
+    fn month_rollover() {
+        let mut t = MonthTraffic::default();
+        t.update(202207, 1000, 1000);
+        t.update(202207, 3000, 4000);
+        // 新周期清零，跨周期的增量计入新周期
+        t.update(202208, 3500, 4100);
+        assert_eq!(t.period, 202208);
+        assert_eq!((t.month_in, t.month_out), (500, 100));
+    }
+
+    #[test]
+    fn counter_reset_mid_month() {
+        let mut t = MonthTraffic::default();
+        t.update(202207, 10_000, 10_000);
+        t.update(202207, 12_000, 11_000);
+        // 客户端重启，计数从 0 开始
+        t.update(202207, 300, 200);
+        assert_eq!((t.month_in, t.month_out), (2300, 1200));
+        t.update(202207, 400, 250);
+        assert_eq!((t.month_in, t.month_out), (2400, 1250));
+    }
+
+    #[test]
+    fn server_downtime_gap() {
+        // 停机期间的流量计入恢复后的周期
+        let mut t = MonthTraffic::default();
+        t.update(202207, 1000, 1000);
+        t.update(202207, 2000, 2000);
+        // 停机跨过 8 月，9 月恢复
+        t.update(202209, 9000, 5000);
+        assert_eq!(t.period, 202209);
+        assert_eq!((t.month_in, t.month_out), (7000, 3000));
+
+        // 从持久化状态恢复后继续累加
+        let mut t: MonthTraffic =
+            serde_json::from_str(&serde_json::to_string(&t).unwrap()).unwrap();
+        t.update(202209, 9500, 5100);
+        assert_eq!((t.month_in, t.month_out), (7500, 3100));
+    }
+
+    #[test]
+    fn period_by_monthstart() {
+        assert_eq!(period(date(2022, 7, 15), 1), 202207);
+        assert_eq!(period(date(2022, 7, 14), 15), 202206);
+        assert_eq!(period(date(2022, 7, 15), 15), 202207);
+        // 跨年
+        assert_eq!(period(date(2023, 1, 5), 10), 202212);
+        // monthstart 0 按 1 处理
+        assert_eq!(period(date(2022, 7, 1), 0), 202207);
+    }
+
+    #[test]
+    fn monthstart_clamped_to_month_end() {
+        // 2 月没有 31 日，按月末计算
+        assert_eq!(
+            period_range(date(2023, 2, 28), 31),
+            (date(2023, 2, 28), date(2023, 3, 31))
+        );
+        assert_eq!(period(date(2023, 2, 27), 31), 202301);
+        // 闰年
+        assert_eq!(
+            period_range(date(2024, 2, 29), 31),
+            (date(2024, 2, 29), date(2024, 3, 31))
+        );
+        assert_eq!(period(date(2024, 2, 28), 31), 202401);
+        assert_eq!(period(date(2024, 2, 28), 30), 202401);
+        assert_eq!(period(date(2024, 2, 29), 30), 202402);
+        // 30 天的月份
+        assert_eq!(
+            period_range(date(2022, 4, 30), 31),
+            (date(2022, 4, 30), date(2022, 5, 31))
+        );
+        assert_eq!(
+            period_range(date(2022, 5, 1), 31),
+            (date(2022, 4, 30), date(2022, 5, 31))
+        );
+    }
+}
@@ -0,0 +1,77 @@
+#![deny(warnings)]
+use anyhow::{anyhow, Context, Result};
+use futures::channel::mpsc;
+use nix::unistd::{self, Gid, Group, Uid, User};
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::listener::AcceptBackoff;
+
+// owner 格式同 chown: user、user:group、:group，也可以是数字 id
+fn parse_owner(owner: &str) -> Result<(Option<Uid>, Option<Gid>)> {
+    let (user, group) = owner.split_once(':').unwrap_or((owner, ""));
+    let uid = match user {
+        "" => None,
+        _ => Some(match user.parse() {
+            Ok(id) => Uid::from_raw(id),
+            Err(_) => {
+                User::from_name(user)?
+                    .ok_or_else(|| anyhow!("unknown user `{}`", user))?
+                    .uid
+            }
+        }),
+    };
+    let gid = match group {
+        "" => None,
+        _ => Some(match group.parse() {
+            Ok(id) => Gid::from_raw(id),
+            Err(_) => {
+                Group::from_name(group)?
+                    .ok_or_else(|| anyhow!("unknown group `{}`", group))?
+                    .gid
+            }
+        }),
+    };
+    Ok((uid, gid))
+}
+
+// http over unix domain socket, eg: nginx proxy_pass http://unix:/run/stat_server.sock;
+pub fn incoming(
+    path: &str,
+    mode: &str,
+    owner: &str,
+) -> Result<mpsc::UnboundedReceiver<io::Result<UnixStream>>> {
+    // 删除残留的 socket 文件
+    if let Ok(meta) = fs::metadata(path) {
+        if meta.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(
+        path,
+        fs::Permissions::from_mode(u32::from_str_radix(mode, 8)?),
+    )?;
+    if !owner.is_empty() {
+        let (uid, gid) = parse_owner(owner)?;
+        unistd::chown(path, uid, gid)
+            .with_context(|| format!("can't chown `{}` to `{}`", path, owner))?;
+    }
+
+    let (tx, rx) = mpsc::unbounded();
+    tokio::spawn(async move {
+        let mut backoff = AcceptBackoff::default();
+        while !tx.is_closed() {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let _ = tx.unbounded_send(Ok(stream));
+                }
+                Err(err) => backoff.on_error(err).await,
+            }
+        }
+    });
+    Ok(rx)
+}
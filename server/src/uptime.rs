@@ -0,0 +1,417 @@
+#![deny(warnings)]
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::ServerDownMode;
+use crate::storage;
+
+// 主机离线区间及服务端运行区间，用于计算可用率
+pub const UPTIME_STATE: &str = "uptime";
+
+static UPTIME_LOG: Lazy<Mutex<UptimeLog>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Run {
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Downtime {
+    start: u64,
+    // None 表示仍处于离线
+    end: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HostUptime {
+    // 开始记录的时间
+    since: u64,
+    downtimes: Vec<Downtime>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UptimeLog {
+    runs: Vec<Run>,
+    hosts: HashMap<String, HostUptime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Interval {
+    pub start: u64,
+    pub end: Option<u64>,
+    pub duration: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UptimeReport {
+    pub name: String,
+    pub from: u64,
+    pub to: u64,
+    // 可用率(%)
+    pub availability: f64,
+    pub down_secs: u64,
+    // 服务端未运行，主机状态未知的时长
+    pub unknown_secs: u64,
+    pub downtimes: Vec<Interval>,
+    pub unknown: Vec<Interval>,
+}
+
+fn clip(start: u64, end: u64, from: u64, to: u64) -> Option<(u64, u64)> {
+    let (start, end) = (start.max(from), end.min(to));
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+// 收到上报，结束进行中的离线区间
+pub fn mark_up(name: &str, now: u64) {
+    UPTIME_LOG.lock().unwrap().mark_up(name, now);
+}
+
+// 超过 offline_timeout 未上报(已去抖)，离线从最后一次上报时间算起
+// 服务端重启后从本次启动时间算起，避免把重启前的上报时间当作离线开始
+pub fn mark_down(name: &str, latest_ts: u64, offline_timeout: u64, now: u64) {
+    UPTIME_LOG
+        .lock()
+        .unwrap()
+        .mark_down(name, latest_ts, offline_timeout, now);
+}
+
+impl UptimeLog {
+    fn mark_up(&mut self, name: &str, now: u64) {
+        let host = self
+            .hosts
+            .entry(name.to_string())
+            .or_insert_with(|| HostUptime {
+                since: now,
+                downtimes: Vec::new(),
+            });
+        if let Some(o) = host.downtimes.last_mut() {
+            if o.end.is_none() {
+                o.end = Some(now.max(o.start));
+            }
+        }
+    }
+
+    fn mark_down(&mut self, name: &str, latest_ts: u64, offline_timeout: u64, now: u64) {
+        let run_start = self.runs.last().map_or(0, |o| o.start);
+        // 未收到过上报的主机不记录
+        let host = match self.hosts.get_mut(name) {
+            Some(host) => host,
+            None => return,
+        };
+        let start = match host.downtimes.last() {
+            Some(o) if o.end.is_none() => return,
+            Some(o) => latest_ts.max(o.end.unwrap_or_default()),
+            None => latest_ts.max(host.since),
+        }
+        .max(run_start);
+        if start + offline_timeout >= now {
+            return;
+        }
+        host.downtimes.push(Downtime { start, end: None });
+    }
+}
+
+// 服务端两次运行之间的间隔
+fn gaps(log: &UptimeLog, from: u64, to: u64) -> Vec<(u64, u64)> {
+    log.runs
+        .windows(2)
+        .filter_map(|w| clip(w[0].end, w[1].start, from, to))
+        .collect()
+}
+
+// [start, end) 中不与服务端停机间隔重叠的部分
+fn known_secs(gaps: &[(u64, u64)], start: u64, end: u64) -> u64 {
+    let overlap: u64 = gaps
+        .iter()
+        .filter_map(|&(a, b)| clip(start, end, a, b))
+        .map(|(a, b)| b - a)
+        .sum();
+    (end - start) - overlap
+}
+
+// [from, to) 内的离线时长，跨越区间边界的离线只计入区间内的部分
+pub fn down_secs(name: &str, from: u64, to: u64) -> Option<u64> {
+    let log = UPTIME_LOG.lock().unwrap();
+    let host = log.hosts.get(name)?;
+    let gaps = gaps(&log, from, to);
+    Some(
+        host.downtimes
+            .iter()
+            .filter_map(|o| clip(o.start, o.end.unwrap_or(to), from, to))
+            .map(|(start, end)| known_secs(&gaps, start, end))
+            .sum(),
+    )
+}
+
+impl UptimeLog {
+    fn report(
+        &self,
+        name: &str,
+        days: u64,
+        now: u64,
+        mode: ServerDownMode,
+    ) -> Option<UptimeReport> {
+        let host = self.hosts.get(name)?;
+        let from = now.saturating_sub(days * 86400).max(host.since);
+        let to = now;
+
+        let gaps = gaps(self, from, to);
+        let mut down_secs = 0;
+        let mut downtimes = Vec::new();
+        for o in &host.downtimes {
+            if let Some((start, end)) = clip(o.start, o.end.unwrap_or(now), from, to) {
+                down_secs += known_secs(&gaps, start, end);
+                downtimes.push(Interval {
+                    start: o.start,
+                    end: o.end,
+                    duration: o.end.unwrap_or(now).saturating_sub(o.start),
+                });
+            }
+        }
+        let unknown_secs = gaps.iter().map(|(a, b)| b - a).sum::<u64>();
+        let unknown = gaps
+            .iter()
+            .map(|&(start, end)| Interval {
+                start,
+                end: Some(end),
+                duration: end - start,
+            })
+            .collect();
+
+        let total = to - from;
+        let known = total - unknown_secs;
+        let denominator = match mode {
+            ServerDownMode::Exclude => known,
+            ServerDownMode::Unknown => total,
+        };
+        let availability = if denominator == 0 {
+            100.0
+        } else {
+            known.saturating_sub(down_secs) as f64 * 100.0 / denominator as f64
+        };
+
+        Some(UptimeReport {
+            name: name.to_string(),
+            from,
+            to,
+            availability,
+            down_secs,
+            unknown_secs,
+            downtimes,
+            unknown,
+        })
+    }
+
+    // 清理超出保留期的记录，当前运行区间始终保留
+    fn prune(&mut self, now: u64, retention_days: u64) {
+        let cutoff = now.saturating_sub(retention_days * 86400);
+        if let Some(run) = self.runs.last_mut() {
+            run.end = now;
+        }
+        let current = self.runs.pop();
+        self.runs.retain(|o| o.end >= cutoff);
+        self.runs.extend(current);
+        for host in self.hosts.values_mut() {
+            host.downtimes
+                .retain(|o| o.end.map_or(true, |end| end >= cutoff));
+            host.since = host.since.max(cutoff);
+        }
+    }
+}
+
+pub fn report(name: &str, days: u64, now: u64, mode: ServerDownMode) -> Option<UptimeReport> {
+    UPTIME_LOG.lock().unwrap().report(name, days, now, mode)
+}
+
+// [from, to] 内有重叠的离线区间，按开始时间排序
+pub fn downtimes_between(from: u64, to: u64) -> Vec<(String, Interval)> {
+    let log = UPTIME_LOG.lock().unwrap();
+    let mut list = log
+        .hosts
+        .iter()
+        .flat_map(|(name, host)| {
+            host.downtimes
+                .iter()
+                .filter(move |o| o.start < to && o.end.map_or(true, |end| end > from))
+                .map(move |o| {
+                    (
+                        name.to_string(),
+                        Interval {
+                            start: o.start,
+                            end: o.end,
+                            duration: o.end.unwrap_or(to).saturating_sub(o.start),
+                        },
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+    list.sort_by_key(|(_, o)| o.start);
+    list
+}
+
+pub fn remove(name: &str) {
+    UPTIME_LOG.lock().unwrap().hosts.remove(name);
+}
+
+// 启动时加载，并开始新的运行区间
+pub fn load(now: u64) {
+    let mut log: UptimeLog = match storage::load_state(UPTIME_STATE) {
+        Some(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("ignore invalid {} => {:?}", UPTIME_STATE, err);
+            UptimeLog::default()
+        }),
+        None => UptimeLog::default(),
+    };
+    log.runs.push(Run {
+        start: now,
+        end: now,
+    });
+    *UPTIME_LOG.lock().unwrap() = log;
+}
+
+// 更新当前运行区间并清理超出保留期的记录
+pub fn save(now: u64, retention_days: u64) {
+    let mut log = UPTIME_LOG.lock().unwrap();
+    log.prune(now, retention_days);
+    let data = serde_json::to_string(&*log);
+    drop(log);
+    match data {
+        Ok(s) => storage::save_state(UPTIME_STATE, &s),
+        Err(err) => error!("save {} fail => {:?}", UPTIME_STATE, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 服务端运行区间 [1000, 2000) 及 [3000, now)
+    fn log() -> UptimeLog {
+        let mut log = UptimeLog {
+            runs: vec![
+                Run {
+                    start: 1000,
+                    end: 2000,
+                },
+                Run {
+                    start: 3000,
+                    end: 3000,
+                },
+            ],
+            ..Default::default()
+        };
+        log.mark_up("h", 1000);
+        log
+    }
+
+    #[test]
+    fn mark_down_and_up() {
+        let mut log = log();
+        // 未收到过上报的主机不记录
+        log.mark_down("other", 1000, 60, 2000);
+        assert!(!log.hosts.contains_key("other"));
+
+        // 未超过 offline_timeout
+        log.mark_down("h", 3100, 60, 3150);
+        assert!(log.hosts["h"].downtimes.is_empty());
+        // 离线从最后一次上报算起，重复检测不新增区间
+        log.mark_down("h", 3100, 60, 3200);
+        log.mark_down("h", 3100, 60, 3300);
+        let downtimes = &log.hosts["h"].downtimes;
+        assert_eq!(downtimes.len(), 1);
+        assert_eq!((downtimes[0].start, downtimes[0].end), (3100, None));
+
+        log.mark_up("h", 3400);
+        assert_eq!(log.hosts["h"].downtimes[0].end, Some(3400));
+        // 再次离线不早于上次恢复
+        log.mark_down("h", 3100, 60, 3500);
+        assert_eq!(log.hosts["h"].downtimes[1].start, 3400);
+    }
+
+    #[test]
+    fn mark_down_after_restart() {
+        let mut log = log();
+        // 重启前的最后上报时间早于本次启动，离线从启动时间算起
+        log.mark_down("h", 1500, 60, 3100);
+        assert_eq!(log.hosts["h"].downtimes[0].start, 3000);
+    }
+
+    #[test]
+    fn report_modes() {
+        let mut log = log();
+        let host = log.hosts.get_mut("h").unwrap();
+        host.downtimes = vec![
+            Downtime {
+                start: 1500,
+                end: Some(1700),
+            },
+            // 跨越服务端停机间隔，只计入已知部分
+            Downtime {
+                start: 1900,
+                end: Some(3100),
+            },
+        ];
+
+        let report = log.report("h", 1, 4000, ServerDownMode::Exclude).unwrap();
+        assert_eq!((report.from, report.to), (1000, 4000));
+        assert_eq!(report.down_secs, 400);
+        assert_eq!(report.unknown_secs, 1000);
+        assert_eq!(report.downtimes.len(), 2);
+        assert_eq!(report.downtimes[1].duration, 1200);
+        assert_eq!(report.unknown.len(), 1);
+        assert_eq!(report.unknown[0].end, Some(3000));
+        // 不计入未知时长: (2000 - 400) / 2000
+        assert!((report.availability - 80.0).abs() < 1e-9);
+
+        // 未知时长按不可用计: (2000 - 400) / 3000
+        let report = log.report("h", 1, 4000, ServerDownMode::Unknown).unwrap();
+        assert!((report.availability - 1600.0 * 100.0 / 3000.0).abs() < 1e-9);
+
+        // 统计区间不早于开始记录的时间，区间外的离线不计入
+        let report = log.report("h", 0, 4000, ServerDownMode::Exclude).unwrap();
+        assert_eq!((report.from, report.down_secs), (4000, 0));
+        assert_eq!(report.availability, 100.0);
+        assert!(log
+            .report("none", 1, 4000, ServerDownMode::Exclude)
+            .is_none());
+    }
+
+    #[test]
+    fn prune_retention() {
+        let mut log = log();
+        log.hosts.get_mut("h").unwrap().downtimes = vec![
+            Downtime {
+                start: 1100,
+                end: Some(1200),
+            },
+            Downtime {
+                start: 1900,
+                end: Some(3100),
+            },
+            Downtime {
+                start: 3200,
+                end: None,
+            },
+        ];
+        // 保留 1 天，cutoff = 2500
+        log.prune(86400 + 2500, 1);
+        let runs = log
+            .runs
+            .iter()
+            .map(|o| (o.start, o.end))
+            .collect::<Vec<_>>();
+        // 当前运行区间始终保留，并更新结束时间
+        assert_eq!(runs, [(3000, 86400 + 2500)]);
+        let host = &log.hosts["h"];
+        assert_eq!(host.since, 2500);
+        let starts = host.downtimes.iter().map(|o| o.start).collect::<Vec<_>>();
+        assert_eq!(starts, [1900, 3200]);
+    }
+}
@@ -0,0 +1,325 @@
+#![deny(warnings)]
+use anyhow::Result;
+use futures::{FutureExt, SinkExt, StreamExt};
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::{Role, WebSocketConfig};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use stat_common::sign::{NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+use crate::listener::ClientAddr;
+use crate::{audit, bans, ingest, stats};
+use crate::{G_CONFIG, G_STATS_MGR};
+
+// 单条消息发送超时，超时视为客户端不再读取
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+// 上报连接空闲超时，须大于客户端上报间隔
+const REPORT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+// 上报消息的 json 头部上限，加上 report_max_size 为单条消息上限
+const MAX_HEAD_SIZE: usize = 4096;
+
+// 校验 websocket 升级请求，返回 Sec-WebSocket-Accept
+fn accept_key(req: &Request<Body>) -> Option<String> {
+    let is_ws = req
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.eq_ignore_ascii_case("websocket"));
+    let accept_key = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_KEY)
+        .map(|key| derive_accept_key(key.as_bytes()));
+    accept_key.filter(|_| is_ws)
+}
+
+fn bad_upgrade() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body("expect websocket upgrade".into())?)
+}
+
+fn switching_protocols(accept_key: String) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept_key)
+        .body(Body::empty())?)
+}
+
+// GET /ws, 连接后先推送全量，之后推送变化的主机和上下线事件
+pub async fn upgrade(mut req: Request<Body>) -> Result<Response<Body>> {
+    let accept_key = match accept_key(&req) {
+        Some(key) => key,
+        None => return bad_upgrade(),
+    };
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(&mut req).await {
+            Ok(upgraded) => {
+                // 页面只发送 close/ping，不需要大的接收缓冲
+                let config = WebSocketConfig {
+                    max_message_size: Some(MAX_HEAD_SIZE),
+                    max_frame_size: Some(MAX_HEAD_SIZE),
+                    ..Default::default()
+                };
+                let ws =
+                    WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(config)).await;
+                serve(ws).await;
+            }
+            Err(err) => {
+                error!("ws upgrade error => {:?}", err);
+            }
+        }
+    });
+
+    switching_protocols(accept_key)
+}
+
+// GET /report, 客户端 --transport ws，用于只放行 http(s) 的网络
+// 每条二进制消息为一次上报请求，与 POST /report 相同处理(认证、签名、限速、审计)
+// 请求: json 头部({"content-type": ..., "x-report-type": ...}) + '\n' + 请求体，升级请求的头部(如 basic auth)对每条消息有效
+// 响应: {"status": 200} + '\n' + 响应体
+// 升级前认证: basic auth，或签名认证模式的 x-user + 空请求体的签名，失败时返回 401/403 不升级
+pub async fn report_upgrade(mut req: Request<Body>) -> Result<Response<Body>> {
+    let accept_key = match accept_key(&req) {
+        Some(key) => key,
+        None => return bad_upgrade(),
+    };
+    if stats::is_shutting_down() {
+        return Ok(Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::empty())?);
+    }
+    if let Err(status) = upgrade_auth(&req) {
+        return Ok(Response::builder().status(status).body(Body::empty())?);
+    }
+    let mut headers = req.headers().clone();
+    // 升级请求的签名只用于建立连接，每条消息单独签名
+    for name in [
+        header::CONNECTION,
+        header::UPGRADE,
+        header::SEC_WEBSOCKET_KEY,
+        header::SEC_WEBSOCKET_VERSION,
+        header::SEC_WEBSOCKET_EXTENSIONS,
+    ] {
+        headers.remove(name);
+    }
+    for name in [TIMESTAMP_HEADER, NONCE_HEADER, SIGNATURE_HEADER] {
+        headers.remove(name);
+    }
+    let addr = req.extensions().get::<ClientAddr>().copied();
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(&mut req).await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(
+                    upgraded,
+                    Role::Server,
+                    Some(report_ws_config()),
+                )
+                .await;
+                serve_report(ws, headers, addr).await;
+            }
+            Err(err) => {
+                error!("ws upgrade error => {:?}", err);
+            }
+        }
+    });
+
+    switching_protocols(accept_key)
+}
+
+// 单条消息为 json 头部 + 上报请求体，超过后断开连接，不按 tungstenite 默认的 64 MiB 缓冲
+fn report_ws_config() -> WebSocketConfig {
+    let max_size = G_CONFIG.get().unwrap().report_max_size + MAX_HEAD_SIZE;
+    WebSocketConfig {
+        max_message_size: Some(max_size),
+        max_frame_size: Some(max_size),
+        ..Default::default()
+    }
+}
+
+fn upgrade_auth(req: &Request<Body>) -> std::result::Result<(), StatusCode> {
+    let tls = G_CONFIG.get().unwrap().tls_enabled();
+    let ip = crate::client_ip(req);
+    let mut audit = audit::Record::http(ip, tls);
+    audit.entry.event = "upgrade";
+    audit.entry.transport = "ws";
+    let (user, signed) = crate::report_auth(req, &mut audit)?;
+    let (ts, nonce) = match &signed {
+        Some((_, ts, nonce)) => (ts.as_str(), nonce.as_str()),
+        None => return Ok(()),
+    };
+    let signature = req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let check = ingest::check_signature(&user, &[], signature, Some((ts, nonce)));
+    audit.entry.auth = Some(check.is_ok());
+    if let Some(ip) = ip {
+        match check {
+            Ok(_) => bans::success(ip),
+            Err(_) => bans::failure(ip),
+        }
+    }
+    if let Err(reason) = check {
+        warn!(host = user.as_str(); "ws upgrade from `{}` rejected => {}", user, reason);
+        audit.fail(reason);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    audit.entry.reason = None;
+    Ok(())
+}
+
+async fn serve_report<S>(
+    mut ws: WebSocketStream<S>,
+    headers: header::HeaderMap,
+    addr: Option<ClientAddr>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let shutdown = stats::shutdown_notified().fuse();
+    futures::pin_mut!(shutdown);
+    loop {
+        let next = futures::select! {
+            next = tokio::time::timeout(REPORT_IDLE_TIMEOUT, ws.next()).fuse() => Some(next),
+            _ = shutdown => None,
+        };
+        // 服务退出，客户端重连后改为上报到新进程
+        let next = match next {
+            Some(next) => next,
+            None => {
+                let _ = ws.send(Message::Close(None)).await;
+                break;
+            }
+        };
+        let data = match next {
+            Ok(Some(Ok(Message::Binary(data)))) => data,
+            Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => break,
+            Ok(Some(Ok(_))) => continue,
+            Err(_) => {
+                let _ = ws.send(Message::Close(None)).await;
+                break;
+            }
+        };
+        let (status, reply) = match report_request(&data, &headers, addr).await {
+            Ok(resp) => resp,
+            Err(err) => {
+                warn!("ws report error => {:?}", err);
+                (StatusCode::BAD_REQUEST, bytes::Bytes::new())
+            }
+        };
+        let mut frame = format!(r#"{{"status":{}}}"#, status.as_u16()).into_bytes();
+        frame.push(b'\n');
+        frame.extend_from_slice(&reply);
+        if !send_msg(&mut ws, Message::Binary(frame)).await {
+            break;
+        }
+        // 认证失败不保留连接，由客户端重连后重试
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            let _ = ws.send(Message::Close(None)).await;
+            break;
+        }
+    }
+}
+
+// 还原为 POST /report 请求处理
+async fn report_request(
+    data: &[u8],
+    headers: &header::HeaderMap,
+    addr: Option<ClientAddr>,
+) -> Result<(StatusCode, bytes::Bytes)> {
+    let pos = data
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| anyhow::anyhow!("missing ws request header"))?;
+    let head = serde_json::from_slice::<HashMap<String, String>>(&data[..pos])?;
+    let body = data[pos + 1..].to_vec();
+
+    let mut builder = Request::builder().method(Method::POST).uri("/report");
+    let req_headers = builder.headers_mut().unwrap();
+    req_headers.extend(headers.clone());
+    for (k, v) in head {
+        req_headers.insert(
+            header::HeaderName::from_bytes(k.as_bytes())?,
+            header::HeaderValue::from_str(&v)?,
+        );
+    }
+    req_headers.insert(header::CONTENT_LENGTH, body.len().into());
+    let mut req = builder.body(Body::from(body))?;
+    if let Some(addr) = addr {
+        req.extensions_mut().insert(addr);
+    }
+    let resp = crate::stats_report(req)
+        .await
+        .map_err(|err| anyhow::anyhow!(err))?;
+    let status = resp.status();
+    Ok((status, hyper::body::to_bytes(resp.into_body()).await?))
+}
+
+async fn serve<S>(ws: WebSocketStream<S>)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mgr = G_STATS_MGR.get().unwrap();
+    // 先订阅再取全量，避免漏掉中间的更新
+    let mut rx = mgr.subscribe();
+    let snapshot = format!(r#"{{"type":"snapshot","data":{}}}"#, mgr.get_stats_json());
+
+    let (mut sink, stream) = ws.split();
+    let mut stream = stream.fuse();
+    if !send(&mut sink, snapshot).await {
+        return;
+    }
+    let shutdown = stats::shutdown_notified().fuse();
+    futures::pin_mut!(shutdown);
+    loop {
+        futures::select! {
+            _ = shutdown => {
+                let _ = sink.send(Message::Close(None)).await;
+                break;
+            },
+            msg = rx.recv().fuse() => match msg {
+                Ok(msg) => {
+                    if !send(&mut sink, msg.to_string()).await {
+                        break;
+                    }
+                }
+                // 缓冲区满说明客户端读取太慢，断开连接
+                Err(RecvError::Lagged(n)) => {
+                    warn!("ws client lagged {} msgs, disconnect", n);
+                    let _ = sink.send(Message::Close(None)).await;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            msg = stream.next() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+}
+
+async fn send<S>(sink: &mut S, msg: String) -> bool
+where
+    S: SinkExt<Message> + Unpin,
+{
+    send_msg(sink, Message::Text(msg)).await
+}
+
+async fn send_msg<S>(sink: &mut S, msg: Message) -> bool
+where
+    S: SinkExt<Message> + Unpin,
+{
+    matches!(
+        tokio::time::timeout(SEND_TIMEOUT, sink.send(msg)).await,
+        Ok(Ok(_))
+    )
+}
@@ -0,0 +1,109 @@
+#![deny(warnings)]
+// /admin/host/{name} 管理接口: 路径中的主机名按 url 编码还原，禁用状态重启后保持
+mod common;
+
+use std::time::{Duration, Instant};
+
+use reqwest::{Method, StatusCode};
+
+const ADMIN: &str = "admin_user = \"admin\"\nadmin_pass = \"pp\"\nreport_rate_limit = 0";
+
+async fn admin(method: Method, port: u16, path: &str) -> StatusCode {
+    reqwest::Client::new()
+        .request(method, format!("http://127.0.0.1:{}{}", port, path))
+        .basic_auth("admin", Some(common::PASSWORD))
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+// /admin/hosts 中该主机的 (online, disabled)
+async fn host_state(port: u16, name: &str) -> (bool, bool) {
+    let hosts: serde_json::Value = reqwest::Client::new()
+        .get(format!("http://127.0.0.1:{}/admin/hosts", port))
+        .basic_auth("admin", Some(common::PASSWORD))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let host = hosts
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|o| o["name"] == name)
+        .unwrap();
+    (
+        host["online"].as_bool().unwrap(),
+        host["disabled"].as_bool().unwrap(),
+    )
+}
+
+async fn wait_online(port: u16, name: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !host_state(port, name).await.0 {
+        assert!(Instant::now() < deadline, "{} not online", name);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn disable_enable_persist() {
+    let mut server = common::start("admin_disable", &["web 1"], ADMIN);
+    let port = server.http_port;
+
+    assert_eq!(
+        admin(Method::POST, port, "/admin/host/nobody/disable").await,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        admin(Method::POST, port, "/admin/host/web%201/pause").await,
+        StatusCode::NOT_FOUND
+    );
+
+    common::http_report("127.0.0.1", port, "web 1").await;
+    wait_online(port, "web 1").await;
+    assert_eq!(
+        admin(Method::POST, port, "/admin/host/web%201/disable").await,
+        StatusCode::OK
+    );
+    // 禁用后移除运行时数据，不再接收上报
+    assert_eq!(host_state(port, "web 1").await, (false, true));
+    common::http_report("127.0.0.1", port, "web 1").await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(host_state(port, "web 1").await, (false, true));
+
+    server.restart();
+    assert_eq!(host_state(port, "web 1").await, (false, true));
+
+    assert_eq!(
+        admin(Method::POST, port, "/admin/host/web%201/enable").await,
+        StatusCode::OK
+    );
+    common::http_report("127.0.0.1", port, "web 1").await;
+    wait_online(port, "web 1").await;
+
+    server.restart();
+    assert!(!host_state(port, "web 1").await.1);
+}
+
+#[tokio::test]
+async fn delete_host() {
+    let server = common::start("admin_delete", &["web 2"], ADMIN);
+    let port = server.http_port;
+
+    common::http_report("127.0.0.1", port, "web 2").await;
+    wait_online(port, "web 2").await;
+    assert_eq!(
+        admin(Method::DELETE, port, "/admin/host/web%202").await,
+        StatusCode::OK
+    );
+    assert!(!host_state(port, "web 2").await.0);
+    // 运行时数据已删除
+    assert_eq!(
+        admin(Method::DELETE, port, "/admin/host/web%202").await,
+        StatusCode::NOT_FOUND
+    );
+}
@@ -0,0 +1,225 @@
+// 集成测试共用: 启动 stat_server 并通过 http/grpc 上报
+// 各测试文件只用到其中一部分
+#![allow(dead_code)]
+use std::fs;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stat_common::server_status::server_status_client::ServerStatusClient;
+use stat_common::server_status::{Response, StatRequest};
+use tonic::metadata::MetadataValue;
+
+pub const PASSWORD: &str = "pp";
+
+pub struct Server {
+    child: Child,
+    dir: PathBuf,
+    pub grpc_port: u16,
+    pub http_port: u16,
+}
+
+impl Server {
+    // SIGTERM 正常退出(保存状态)后在同一目录、同一端口重新启动
+    pub fn restart(&mut self) {
+        let status = Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        self.child.wait().unwrap();
+        self.child = spawn(&self.dir);
+        self.wait_listening();
+    }
+
+    fn wait_listening(&self) {
+        wait_listening(&[
+            SocketAddr::from(([127, 0, 0, 1], self.grpc_port)),
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], self.grpc_port)),
+            SocketAddr::from(([127, 0, 0, 1], self.http_port)),
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], self.http_port)),
+        ]);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// 127.0.0.1 与 ::1 上都空闲的端口
+fn free_port() -> u16 {
+    loop {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        if TcpListener::bind(("::1", port)).is_ok() {
+            return port;
+        }
+    }
+}
+
+fn wait_listening(addrs: &[SocketAddr]) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    for addr in addrs {
+        while TcpStream::connect(addr).is_err() {
+            assert!(
+                Instant::now() < deadline,
+                "server not listening on {}",
+                addr
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+// hosts 中的一项，密码为 PASSWORD，fields 为附加的字段，如 `hmac_secret = "s"`
+pub fn host(name: &str, fields: &str) -> String {
+    let fields = if fields.is_empty() {
+        String::new()
+    } else {
+        format!(", {}", fields)
+    };
+    format!(
+        r#"  {{name = "{}", password = "{}", location = "x", region = "x", type = "kvm"{}}},"#,
+        name, PASSWORD, fields
+    )
+}
+
+fn spawn(dir: &Path) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_stat_server"))
+        .args(["-c", "config.toml"])
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+// 同时监听 127.0.0.1 与 [::1]，hosts 的密码均为 PASSWORD，extra 为附加的配置
+pub fn start(tag: &str, hosts: &[&str], extra: &str) -> Server {
+    let hosts = hosts.iter().map(|name| host(name, "")).collect::<Vec<_>>();
+    start_hosts(tag, &hosts, extra)
+}
+
+// hosts 各项由 host() 生成
+pub fn start_hosts(tag: &str, hosts: &[String], extra: &str) -> Server {
+    let (grpc_port, http_port) = (free_port(), free_port());
+    let dir = std::env::temp_dir().join(format!("stat_server_{}_{}", tag, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let hosts = hosts.join("\n");
+    fs::write(
+        dir.join("config.toml"),
+        format!(
+            r#"grpc_addr = ["127.0.0.1:{g}", "[::1]:{g}"]
+http_addr = ["127.0.0.1:{h}", "[::1]:{h}"]
+{extra}
+hosts = [
+{hosts}
+]
+"#,
+            g = grpc_port,
+            h = http_port,
+            extra = extra,
+            hosts = hosts
+        ),
+    )
+    .unwrap();
+    let server = Server {
+        child: spawn(&dir),
+        dir,
+        grpc_port,
+        http_port,
+    };
+    server.wait_listening();
+    server
+}
+
+pub fn stat(name: &str) -> StatRequest {
+    StatRequest {
+        name: name.to_string(),
+        online4: true,
+        uptime: 60,
+        cpu: 1.0,
+        memory_total: 1024,
+        memory_used: 512,
+        hdd_total: 1024,
+        hdd_used: 512,
+        ..Default::default()
+    }
+}
+
+pub async fn http_report(host: &str, port: u16, name: &str) {
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}:{}/report", host, port))
+        .basic_auth(name, Some(PASSWORD))
+        .json(&serde_json::json!({
+            "name": name,
+            "online4": true,
+            "uptime": 60,
+            "cpu": 1.0,
+            "memory_total": 1024,
+            "memory_used": 512,
+            "hdd_total": 1024,
+            "hdd_used": 512,
+            "network_rx": 0,
+            "network_tx": 0,
+            "network_in": 0,
+            "network_out": 0,
+            "swap_total": 0,
+            "swap_used": 0,
+            "load_1": 0.0,
+            "load_5": 0.0,
+            "load_15": 0.0,
+        }))
+        .send()
+        .await
+        .unwrap();
+    let status = resp.status();
+    assert!(
+        status.is_success(),
+        "http report via {} => {} {}",
+        host,
+        status,
+        resp.text().await.unwrap_or_default()
+    );
+}
+
+pub async fn grpc_send(
+    host: &str,
+    port: u16,
+    stat: StatRequest,
+) -> Result<Response, tonic::Status> {
+    let token = format!("{}@_@{}", stat.name, PASSWORD);
+    grpc_send_with(host, port, stat, &[("authorization", token)]).await
+}
+
+// metadata 由调用方给出，用于签名等
+pub async fn grpc_send_with(
+    host: &str,
+    port: u16,
+    stat: StatRequest,
+    metadata: &[(&'static str, String)],
+) -> Result<Response, tonic::Status> {
+    let mut client = ServerStatusClient::connect(format!("http://{}:{}", host, port))
+        .await
+        .unwrap();
+    let mut req = tonic::Request::new(stat);
+    for (key, value) in metadata {
+        req.metadata_mut()
+            .insert(*key, MetadataValue::try_from(value.as_str()).unwrap());
+    }
+    client.report(req).await.map(|resp| resp.into_inner())
+}
+
+pub async fn grpc_report(host: &str, port: u16, name: &str) {
+    let resp = grpc_send(host, port, stat(name)).await.unwrap();
+    assert_eq!(resp.code, 0, "grpc report via {} => {:?}", host, resp);
+}
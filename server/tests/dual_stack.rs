@@ -0,0 +1,42 @@
+#![deny(warnings)]
+// 同时监听 127.0.0.1 与 [::1]，两种地址分别通过 http 及 grpc 上报
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{grpc_report, http_report};
+
+const HOSTS: [&str; 4] = ["http4", "http6", "grpc4", "grpc6"];
+
+#[tokio::test]
+async fn report_over_ipv4_and_ipv6() {
+    let server = common::start("dual_stack", &HOSTS, "");
+    http_report("127.0.0.1", server.http_port, "http4").await;
+    http_report("[::1]", server.http_port, "http6").await;
+    grpc_report("127.0.0.1", server.grpc_port, "grpc4").await;
+    grpc_report("[::1]", server.grpc_port, "grpc6").await;
+
+    // 上报均已记录，stats.json 中各主机在线
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let stats: serde_json::Value =
+            reqwest::get(format!("http://127.0.0.1:{}/stats.json", server.http_port))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        // 启动后首次汇总前 servers 为空
+        let online = stats["servers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|o| o["online4"].as_bool().unwrap_or(false))
+            .count();
+        if online == HOSTS.len() {
+            break;
+        }
+        assert!(Instant::now() < deadline, "hosts not online: {}", stats);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
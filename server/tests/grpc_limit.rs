@@ -0,0 +1,19 @@
+#![deny(warnings)]
+// grpc 上报超过 report_max_size 时在解码前拒绝
+mod common;
+
+use tonic::Code;
+
+#[tokio::test]
+async fn oversized_grpc_report() {
+    let server = common::start("grpc_limit", &["h1"], "report_max_size = 4096");
+
+    common::grpc_report("127.0.0.1", server.grpc_port, "h1").await;
+
+    let mut stat = common::stat("h1");
+    stat.custom = Some("x".repeat(1 << 20));
+    let err = common::grpc_send("127.0.0.1", server.grpc_port, stat)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted, "{:?}", err);
+}
@@ -0,0 +1,87 @@
+#![deny(warnings)]
+// grpc 上报签名按请求中的原始字节校验
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+use stat_common::sign::{self, NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use tonic::Code;
+
+const SECRET: &str = "s3cret";
+
+fn signed_stat(name: &str) -> stat_common::server_status::StatRequest {
+    let mut stat = common::stat(name);
+    // 多个 label，HashMap 编码顺序不固定
+    stat.labels = (0..16)
+        .map(|i| (format!("k{}", i), format!("v{}", i)))
+        .collect();
+    stat
+}
+
+#[tokio::test]
+async fn signed_grpc_report() {
+    let hosts = [common::host(
+        "h1",
+        &format!(r#"hmac_secret = "{}""#, SECRET),
+    )];
+    let server = common::start_hosts("grpc_sign", &hosts, "");
+    let port = server.grpc_port;
+    let token = format!("h1@_@{}", common::PASSWORD);
+
+    // 密码认证 + 签名
+    let stat = signed_stat("h1");
+    let signature = sign::sign(SECRET, &stat.encode_to_vec());
+    let resp = common::grpc_send_with(
+        "127.0.0.1",
+        port,
+        stat,
+        &[
+            ("authorization", token.clone()),
+            (SIGNATURE_HEADER, signature),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(resp.code, 0, "{:?}", resp);
+
+    // 签名认证模式，不带密码
+    let stat = signed_stat("h1");
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let nonce = "grpc-sign-1".to_string();
+    let signature = sign::sign(
+        SECRET,
+        &sign::signed_data(&stat.encode_to_vec(), ts, &nonce),
+    );
+    let resp = common::grpc_send_with(
+        "127.0.0.1",
+        port,
+        stat,
+        &[
+            (TIMESTAMP_HEADER, ts.to_string()),
+            (NONCE_HEADER, nonce),
+            (SIGNATURE_HEADER, signature),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(resp.code, 0, "{:?}", resp);
+
+    // 签名与内容不符
+    let stat = signed_stat("h1");
+    let mut other = stat.clone();
+    other.cpu = 99.0;
+    let signature = sign::sign(SECRET, &other.encode_to_vec());
+    let err = common::grpc_send_with(
+        "127.0.0.1",
+        port,
+        stat,
+        &[("authorization", token), (SIGNATURE_HEADER, signature)],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated, "{:?}", err);
+}
@@ -0,0 +1,122 @@
+#![deny(warnings)]
+// 心跳上报: 主机尚无数据时要求完整上报，与完整上报同样校验用户名及身份冲突
+mod common;
+
+use std::time::{Duration, Instant};
+
+use reqwest::StatusCode;
+use stat_common::server_status::server_status_client::ServerStatusClient;
+use stat_common::server_status::Heartbeat;
+use stat_common::{CAP_HEARTBEAT, REPORT_TYPE_HEADER};
+use tonic::metadata::MetadataValue;
+
+// http 心跳，返回状态码及响应中的 code
+async fn http_heartbeat(host: &str, port: u16, user: &str, name: &str) -> (StatusCode, i64) {
+    let resp = reqwest::Client::new()
+        .post(format!("http://{}:{}/report", host, port))
+        .basic_auth(user, Some(common::PASSWORD))
+        .header(REPORT_TYPE_HEADER, CAP_HEARTBEAT)
+        .json(&serde_json::json!({"name": name, "seq": 1}))
+        .send()
+        .await
+        .unwrap();
+    let status = resp.status();
+    if !status.is_success() {
+        return (status, -1);
+    }
+    let body: serde_json::Value = resp.json().await.unwrap();
+    (status, body["code"].as_i64().unwrap())
+}
+
+async fn grpc_heartbeat(host: &str, port: u16, name: &str) -> Result<i32, tonic::Status> {
+    let mut client = ServerStatusClient::connect(format!("http://{}:{}", host, port))
+        .await
+        .unwrap();
+    let mut req = tonic::Request::new(Heartbeat {
+        name: name.to_string(),
+        seq: 1,
+    });
+    let token = format!("{}@_@{}", name, common::PASSWORD);
+    req.metadata_mut().insert(
+        "authorization",
+        MetadataValue::try_from(token.as_str()).unwrap(),
+    );
+    client
+        .report_heartbeat(req)
+        .await
+        .map(|resp| resp.into_inner().code)
+}
+
+// 完整上报经由统计线程写入，等待心跳被接受
+async fn wait_http_accepted(port: u16, name: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while http_heartbeat("127.0.0.1", port, name, name).await != (StatusCode::OK, 0) {
+        assert!(
+            Instant::now() < deadline,
+            "heartbeat of {} not accepted",
+            name
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test]
+async fn http_heartbeat_dispatch() {
+    let server = common::start(
+        "hb_http",
+        &["hb1", "hb2"],
+        "identity_conflict_reject = true\nreport_rate_limit = 0",
+    );
+    let port = server.http_port;
+
+    // 尚无数据，要求完整上报
+    assert_eq!(
+        http_heartbeat("127.0.0.1", port, "hb1", "hb1").await,
+        (StatusCode::OK, 1)
+    );
+    // 心跳中的主机名与认证用户不一致
+    assert_eq!(
+        http_heartbeat("127.0.0.1", port, "hb1", "hb2").await.0,
+        StatusCode::BAD_REQUEST
+    );
+
+    common::http_report("127.0.0.1", port, "hb1").await;
+    wait_http_accepted(port, "hb1").await;
+
+    // 另一来源的心跳与原来源交替，拒绝较新的来源
+    assert_eq!(
+        http_heartbeat("[::1]", port, "hb1", "hb1").await.0,
+        StatusCode::OK
+    );
+    assert_eq!(
+        http_heartbeat("127.0.0.1", port, "hb1", "hb1").await,
+        (StatusCode::OK, 0)
+    );
+    assert_eq!(
+        http_heartbeat("[::1]", port, "hb1", "hb1").await.0,
+        StatusCode::CONFLICT
+    );
+}
+
+#[tokio::test]
+async fn grpc_heartbeat_dispatch() {
+    let server = common::start(
+        "hb_grpc",
+        &["hb3"],
+        "identity_conflict_reject = true\nreport_rate_limit = 0",
+    );
+    let port = server.grpc_port;
+
+    assert_eq!(grpc_heartbeat("127.0.0.1", port, "hb3").await.unwrap(), 1);
+    common::grpc_report("127.0.0.1", port, "hb3").await;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while grpc_heartbeat("127.0.0.1", port, "hb3").await.unwrap() != 0 {
+        assert!(Instant::now() < deadline, "heartbeat not accepted");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    grpc_heartbeat("[::1]", port, "hb3").await.unwrap();
+    grpc_heartbeat("127.0.0.1", port, "hb3").await.unwrap();
+    let status = grpc_heartbeat("[::1]", port, "hb3").await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::AlreadyExists);
+}
@@ -0,0 +1,51 @@
+#![deny(warnings)]
+// json 上报: 缺少必填字段或类型错误时返回 400 及字段名，lowerCamelCase 字段名及字符串整数可接受
+mod common;
+
+use reqwest::StatusCode;
+
+async fn post(port: u16, body: serde_json::Value) -> (StatusCode, String) {
+    let resp = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{}/report", port))
+        .basic_auth("j1", Some(common::PASSWORD))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    (resp.status(), resp.text().await.unwrap())
+}
+
+#[tokio::test]
+async fn json_report_fields() {
+    let server = common::start("json_report", &["j1"], "report_rate_limit = 0");
+    let port = server.http_port;
+
+    let (status, body) = post(
+        port,
+        serde_json::json!({"name": "j1", "uptime": 60, "cpu": 1.0, "memory_total": 1024}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("memory_used"), "{}", body);
+
+    let (status, body) = post(
+        port,
+        serde_json::json!({
+            "name": "j1", "uptime": 60, "cpu": 1.0, "memory_total": 1024, "memory_used": 512,
+            "load_1": "high",
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("load_1"), "{}", body);
+
+    let (status, body) = post(
+        port,
+        serde_json::json!({
+            "name": "j1", "uptime": "60", "cpu": 1.0, "memoryTotal": "1024", "memoryUsed": 512,
+            "unknown": [1],
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
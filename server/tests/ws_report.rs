@@ -0,0 +1,173 @@
+#![deny(warnings)]
+// ws 上报在升级前认证，单条消息大小按 report_max_size 限制
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::{SinkExt, StreamExt};
+use http_auth_basic::Credentials;
+use stat_common::sign::{self, NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER, USER_HEADER};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+
+const SECRET: &str = "s3cret";
+
+async fn connect(
+    port: u16,
+    headers: &[(&'static str, String)],
+) -> Result<WebSocketStream<TcpStream>, Error> {
+    let mut req = format!("ws://127.0.0.1:{}/report", port)
+        .into_client_request()
+        .unwrap();
+    for (key, value) in headers {
+        req.headers_mut()
+            .insert(*key, HeaderValue::from_str(value).unwrap());
+    }
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    tokio_tungstenite::client_async(req, stream)
+        .await
+        .map(|(ws, _)| ws)
+}
+
+// 升级被拒绝时的状态码
+async fn rejected(port: u16, headers: &[(&'static str, String)]) -> StatusCode {
+    match connect(port, headers).await {
+        Err(Error::Http(resp)) => resp.status(),
+        Err(err) => panic!("unexpected error => {:?}", err),
+        Ok(_) => panic!("upgrade accepted with {:?}", headers),
+    }
+}
+
+fn basic(user: &str, pass: &str) -> (&'static str, String) {
+    (
+        "authorization",
+        Credentials::new(user, pass).as_http_header(),
+    )
+}
+
+// 签名认证模式的升级请求，空请求体签名
+fn signed(user: &str, secret: &str, nonce: &str) -> Vec<(&'static str, String)> {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    vec![
+        (USER_HEADER, user.to_string()),
+        (TIMESTAMP_HEADER, ts.to_string()),
+        (NONCE_HEADER, nonce.to_string()),
+        (
+            SIGNATURE_HEADER,
+            sign::sign(secret, &sign::signed_data(&[], ts, nonce)),
+        ),
+    ]
+}
+
+// 发送一条 json 上报，返回响应中的状态码
+async fn report(ws: &mut WebSocketStream<TcpStream>, name: &str) -> u64 {
+    let body = serde_json::to_vec(&common::stat(name)).unwrap();
+    let mut frame = br#"{"content-type": "application/json"}"#.to_vec();
+    frame.push(b'\n');
+    frame.extend(body);
+    ws.send(Message::Binary(frame)).await.unwrap();
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Binary(data))) => {
+                let pos = data.iter().position(|&b| b == b'\n').unwrap();
+                let head = serde_json::from_slice::<serde_json::Value>(&data[..pos]).unwrap();
+                return head["status"].as_u64().unwrap();
+            }
+            Some(Ok(_)) => {}
+            other => panic!("ws closed => {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn upgrade_requires_auth() {
+    let hosts = [
+        common::host("h1", ""),
+        common::host("signed", &format!(r#"hmac_secret = "{}""#, SECRET)),
+    ];
+    let server = common::start_hosts("ws_auth", &hosts, "");
+    let port = server.http_port;
+
+    assert_eq!(rejected(port, &[]).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        rejected(port, &[basic("h1", "wrong")]).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        rejected(port, &[basic("nobody", common::PASSWORD)]).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let mut ws = connect(port, &[basic("h1", common::PASSWORD)])
+        .await
+        .unwrap();
+    assert_eq!(report(&mut ws, "h1").await, 200);
+
+    // 签名认证模式: 只有 x-user、签名错误、重放及未配置 hmac_secret 的用户均拒绝
+    assert_eq!(
+        rejected(port, &[(USER_HEADER, "signed".to_string())]).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        rejected(port, &signed("signed", "wrong", "ws-1")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        rejected(port, &signed("h1", SECRET, "ws-2")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    let headers = signed("signed", SECRET, "ws-3");
+    connect(port, &headers).await.unwrap();
+    assert_eq!(rejected(port, &headers).await, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn upgrade_rejects_banned_ip() {
+    let server = common::start(
+        "ws_ban",
+        &["h1"],
+        "bans = {enabled = true, max_failures = 2}",
+    );
+    let port = server.http_port;
+
+    for _ in 0..2 {
+        assert_eq!(
+            rejected(port, &[basic("h1", "wrong")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    // 已封禁，正确的密码也不升级
+    assert_eq!(
+        rejected(port, &[basic("h1", common::PASSWORD)]).await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn message_size_limit() {
+    let server = common::start("ws_size", &["h1"], "report_max_size = 2048");
+    let port = server.http_port;
+
+    let mut ws = connect(port, &[basic("h1", common::PASSWORD)])
+        .await
+        .unwrap();
+    assert_eq!(report(&mut ws, "h1").await, 200);
+
+    // 超过 report_max_size + 头部上限，服务端不缓冲整条消息，直接断开
+    let mut frame = br#"{"content-type": "application/json"}"#.to_vec();
+    frame.push(b'\n');
+    frame.resize(64 * 1024, b' ');
+    let _ = ws.send(Message::Binary(frame)).await;
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Binary(data))) => panic!("oversized message accepted => {:?}", data),
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            Some(Ok(_)) => {}
+        }
+    }
+}
@@ -1,5 +1,5 @@
 (async () => {
-    let stats = await (await fetch("/stats.json")).json()
+    let stats = await (await fetch("stats.json")).json()
     for (let i = 0; i < stats.servers.length; i++) {
         let node = document.createElement("div")
         node.id += "table-item-" + i
@@ -8,45 +8,45 @@
         if (stats.servers[i].online4 || stats.servers[i].online6) {
             getDetails(`#table-item-${i}`, stats.servers[i])
             document.querySelector(`#table-item-${i}`).innerHTML = `<div class="node">
-    <img class="flag" src="https://npm.elemecdn.com/z-flags/square/${stats.servers[i].region.toLowerCase()}.svg" alt>
+    <img class="flag" src="https://npm.elemecdn.com/z-flags/square/${(stats.servers[i].region || stats.servers[i].country_code).toLowerCase()}.svg" alt>
     <div>
         <div class="name">${stats.servers[i].alias}</div>
         <div class="location">${stats.servers[i].location}</div>
     </div>
 </div>
 <div class="type">${stats.servers[i].type}</div>
-<div class="uptime">${stats.servers[i].uptime == "1 天" ? "1 Day" : stats.servers[i].uptime.replace(/天/, "Days")}</div>
-<div class="network">${byteConvert(stats.servers[i].network_tx)}↑ ${byteConvert(stats.servers[i].network_rx)}↓</div>
-<div class="traffic">${byteConvert(stats.servers[i].network_out)}↑ ${byteConvert(stats.servers[i].network_in)}↓</div>
+<div class="uptime">${metricText(stats.servers[i], "uptime", uptimeText(stats.servers[i].uptime))}</div>
+<div class="network">${metricText(stats.servers[i], "speed", `${byteConvert(stats.servers[i].network_tx)}↑ ${byteConvert(stats.servers[i].network_rx)}↓`)}</div>
+<div class="traffic">${metricText(stats.servers[i], "traffic", `${byteConvert(stats.servers[i].network_out)}↑ ${byteConvert(stats.servers[i].network_in)}↓`)}</div>
 <div class="cpu">
     <div class="progress">
-        <div style="width: ${Math.round(stats.servers[i].cpu)}%; background-color: ${progressConvert(Math.round(stats.servers[i].cpu))};" class="progress-bar">
-            <div>${Math.round(stats.servers[i].cpu)}%</div>
+        <div style="width: ${Math.round(stats.servers[i].cpu)}%; background-color: ${progressConvert(stats.servers[i].status_level.cpu)};" class="progress-bar">
+            <div>${metricText(stats.servers[i], "cpu", `${Math.round(stats.servers[i].cpu)}%`)}</div>
         </div>
     </div>
 </div>
 <div class="mem">
     <div class="progress">
-        <div style="width: ${Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100)}%; background-color: ${progressConvert(Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100))}" class="progress-bar">
-            <div>${Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100)}%</div>
+        <div style="width: ${Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100)}%; background-color: ${progressConvert(stats.servers[i].status_level.memory)}" class="progress-bar">
+            <div>${metricText(stats.servers[i], "memory", `${Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100)}%`)}</div>
         </div>
     </div>
 </div>
 <div class="hdd">
     <div class="progress">
-        <div style="width: ${Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100)}%; background-color: ${progressConvert(Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100))}" class="progress-bar">
-            <div>${Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100)}%</div>
+        <div style="width: ${Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100)}%; background-color: ${progressConvert(stats.servers[i].status_level.hdd)}" class="progress-bar">
+            <div>${metricText(stats.servers[i], "hdd", `${Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100)}%`)}</div>
         </div>
     </div>
 </div>
 <div class="status">
-    <div class="status-dot" style="background-color: ${Math.round(stats.servers[i].cpu) <= 70 ? "" : "#faae42"};"></div>
-    <div class="status-info">${Math.round(stats.servers[i].cpu) <= 70 ? "Available" : "Busy"}</div>
+    <div class="status-dot" style="background-color: ${hostStatus(stats.servers[i])[1]};"></div>
+    <div class="status-info">${hostStatus(stats.servers[i])[0]}</div>
 </div>`
-            document.querySelector(`#table-item-${i}`).style.borderColor = Math.round(stats.servers[i].cpu) <= 70 ? "" : "#faae42"
+            document.querySelector(`#table-item-${i}`).style.borderColor = hostStatus(stats.servers[i])[1]
         } else {
             document.querySelector(`#table-item-${i}`).innerHTML = `<div class="node">
-    <img class="flag" src="https://npm.elemecdn.com/z-flags/square/${stats.servers[i].region.toLowerCase()}.svg" alt>
+    <img class="flag" src="https://npm.elemecdn.com/z-flags/square/${(stats.servers[i].region || stats.servers[i].country_code).toLowerCase()}.svg" alt>
     <div>
         <div class="name">${stats.servers[i].alias}</div>
         <div class="location">${stats.servers[i].location}</div>
@@ -93,28 +93,28 @@ setInterval(() => {
             try {
                 if (stats.servers[i].online4 || stats.servers[i].online6) {
                     getDetails(`#table-item-${i}`, stats.servers[i])
-                    document.querySelector(`#table-item-${i}`).style.borderColor = Math.round(stats.servers[i].cpu) <= 70 ? "" : "#faae42"
-                    document.querySelector(`#table-item-${i} .flag`).src = `https://npm.elemecdn.com/z-flags/square/${stats.servers[i].region.toLowerCase()}.svg`
+                    document.querySelector(`#table-item-${i}`).style.borderColor = hostStatus(stats.servers[i])[1]
+                    document.querySelector(`#table-item-${i} .flag`).src = `https://npm.elemecdn.com/z-flags/square/${(stats.servers[i].region || stats.servers[i].country_code).toLowerCase()}.svg`
                     document.querySelector(`#table-item-${i} .location`).textContent = stats.servers[i].location
                     document.querySelector(`#table-item-${i} .type`).textContent = stats.servers[i].type
-                    document.querySelector(`#table-item-${i} .uptime`).textContent = stats.servers[i].uptime == "1 天" ? "1 Day" : stats.servers[i].uptime.replace(/天/, "Days")
-                    document.querySelector(`#table-item-${i} .network`).textContent = `${byteConvert(stats.servers[i].network_tx)}↑ ${byteConvert(stats.servers[i].network_rx)}↓`
-                    document.querySelector(`#table-item-${i} .traffic`).textContent = `${byteConvert(stats.servers[i].network_out)}↑ ${byteConvert(stats.servers[i].network_in)}↓`
+                    document.querySelector(`#table-item-${i} .uptime`).textContent = metricText(stats.servers[i], "uptime", uptimeText(stats.servers[i].uptime))
+                    document.querySelector(`#table-item-${i} .network`).textContent = metricText(stats.servers[i], "speed", `${byteConvert(stats.servers[i].network_tx)}↑ ${byteConvert(stats.servers[i].network_rx)}↓`)
+                    document.querySelector(`#table-item-${i} .traffic`).textContent = metricText(stats.servers[i], "traffic", `${byteConvert(stats.servers[i].network_out)}↑ ${byteConvert(stats.servers[i].network_in)}↓`)
                     document.querySelector(`#table-item-${i} .cpu .progress-bar`).style.width = `${Math.round(stats.servers[i].cpu)}%`
-                    document.querySelector(`#table-item-${i} .cpu .progress-bar`).style.backgroundColor = progressConvert(Math.round(stats.servers[i].cpu))
-                    document.querySelector(`#table-item-${i} .cpu .progress-bar div`).textContent = `${Math.round(stats.servers[i].cpu)}%`
+                    document.querySelector(`#table-item-${i} .cpu .progress-bar`).style.backgroundColor = progressConvert(stats.servers[i].status_level.cpu)
+                    document.querySelector(`#table-item-${i} .cpu .progress-bar div`).textContent = metricText(stats.servers[i], "cpu", `${Math.round(stats.servers[i].cpu)}%`)
                     document.querySelector(`#table-item-${i} .mem .progress-bar`).style.width = `${Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100)}%`
-                    document.querySelector(`#table-item-${i} .mem .progress-bar`).style.backgroundColor = progressConvert(Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100))
-                    document.querySelector(`#table-item-${i} .mem .progress-bar div`).textContent = `${Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100)}%`
+                    document.querySelector(`#table-item-${i} .mem .progress-bar`).style.backgroundColor = progressConvert(stats.servers[i].status_level.memory)
+                    document.querySelector(`#table-item-${i} .mem .progress-bar div`).textContent = metricText(stats.servers[i], "memory", `${Math.round(stats.servers[i].memory_used / stats.servers[i].memory_total * 100)}%`)
                     document.querySelector(`#table-item-${i} .hdd .progress-bar`).style.width = `${Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100)}%`
-                    document.querySelector(`#table-item-${i} .hdd .progress-bar`).style.backgroundColor = progressConvert(Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100))
-                    document.querySelector(`#table-item-${i} .hdd .progress-bar div`).textContent = `${Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100)}%`
-                    document.querySelector(`#table-item-${i} .status-dot`).style.backgroundColor = Math.round(stats.servers[i].cpu) <= 70 ? "" : "#faae42"
-                    document.querySelector(`#table-item-${i} .status-info`).textContent = Math.round(stats.servers[i].cpu) <= 70 ? "Available" : "Busy"
+                    document.querySelector(`#table-item-${i} .hdd .progress-bar`).style.backgroundColor = progressConvert(stats.servers[i].status_level.hdd)
+                    document.querySelector(`#table-item-${i} .hdd .progress-bar div`).textContent = metricText(stats.servers[i], "hdd", `${Math.round(stats.servers[i].hdd_used / stats.servers[i].hdd_total * 100)}%`)
+                    document.querySelector(`#table-item-${i} .status-dot`).style.backgroundColor = hostStatus(stats.servers[i])[1]
+                    document.querySelector(`#table-item-${i} .status-info`).textContent = hostStatus(stats.servers[i])[0]
                 } else {
                     document.querySelector(`#table-item-${i}`).onclick = null
                     document.querySelector(`#table-item-${i}`).style.borderColor = "#e62965"
-                    document.querySelector(`#table-item-${i} .flag`).src = `https://npm.elemecdn.com/z-flags/square/${stats.servers[i].region.toLowerCase()}.svg`
+                    document.querySelector(`#table-item-${i} .flag`).src = `https://npm.elemecdn.com/z-flags/square/${(stats.servers[i].region || stats.servers[i].country_code).toLowerCase()}.svg`
                     document.querySelector(`#table-item-${i} .location`).textContent = stats.servers[i].location
                     document.querySelector(`#table-item-${i} .type`).textContent = stats.servers[i].type
                     document.querySelector(`#table-item-${i} .uptime`).textContent = "Offline"
@@ -158,17 +158,18 @@ let getDetails = (item, data) => {
     document.querySelector(item).onclick = () => {
         Swal.fire({
             html: `<div style="margin-bottom: 20px; display: flex; align-items: center; justify-content: center;">
-                <img style="margin-right: 10px; height: 50px;" src="https://npm.elemecdn.com/z-flags/rounded-rectangle/${data.region.toLowerCase()}.svg" alt>
+                <img style="margin-right: 10px; height: 50px;" src="https://npm.elemecdn.com/z-flags/rounded-rectangle/${(data.region || data.country_code).toLowerCase()}.svg" alt>
                 <h2>${data.name}</h2>
             </div>
             <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Type:</p><p style="width: 65%;">${data.type}</p></div>
-            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Uptime:</p><p style="width: 65%;">${data.uptime == "1 天" ? "1 Day" : data.uptime.replace(/天/, "Days")}</p></div>
-            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">CPU:</p><p style="width: 65%;">${data.cpu}%</p></div>
-            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Memory:</p><p style="width: 65%;">${Math.round(data.memory_used / data.memory_total * 100)}% (${byteConvert2(data.memory_used)} / ${byteConvert2(data.memory_total)})</p></div>
-            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Swap:</p><p style="width: 65%;">${data.swap_used == 0 ? "None" : `${Math.round(data.swap_used / data.swap_total * 100)}% (${byteConvert2(data.swap_used)} / ${byteConvert2(data.swap_total)})</p></div>`}</p></div>
-            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">HDD:</p><p style="width: 65%;">${Math.round(data.hdd_used / data.hdd_total * 100)}% (${byteConvert2(data.hdd_used * 1024)} / ${byteConvert2(data.hdd_total * 1024)})</p></div>
-            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Network:</p><p style="width: 65%;">${byteConvert(data.network_tx)}↑ ${byteConvert(data.network_rx)}↓</p></div>
-            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Traffic:</p><p style="width: 65%;">${byteConvert(data.network_out)}↑ ${byteConvert(data.network_in)}↓</p></div>`,
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Uptime:</p><p style="width: 65%;">${metricText(data, "uptime", uptimeText(data.uptime))}</p></div>
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Load:</p><p style="width: 65%;">${metricText(data, "load", `${data.load_1.toFixed(2)} / ${data.load_5.toFixed(2)} / ${data.load_15.toFixed(2)}`)}</p></div>
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">CPU:</p><p style="width: 65%;">${metricText(data, "cpu", `${data.cpu}%`)}</p></div>
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Memory:</p><p style="width: 65%;">${metricText(data, "memory", `${Math.round(data.memory_used / data.memory_total * 100)}% (${byteConvert2(data.memory_used)} / ${byteConvert2(data.memory_total)})`)}</p></div>
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Swap:</p><p style="width: 65%;">${metricText(data, "swap", data.swap_used == 0 ? "None" : `${Math.round(data.swap_used / data.swap_total * 100)}% (${byteConvert2(data.swap_used)} / ${byteConvert2(data.swap_total)})`)}</p></div>
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">HDD:</p><p style="width: 65%;">${metricText(data, "hdd", `${Math.round(data.hdd_used / data.hdd_total * 100)}% (${byteConvert2(data.hdd_used * 1024)} / ${byteConvert2(data.hdd_total * 1024)})`)}</p></div>
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Network:</p><p style="width: 65%;">${metricText(data, "speed", `${byteConvert(data.network_tx)}↑ ${byteConvert(data.network_rx)}↓`)}</p></div>
+            <div style="margin: 0 auto 10px; width: 350px; text-align: left; display: flex;"><p style="width: 35%;">Traffic:</p><p style="width: 65%;">${metricText(data, "traffic", `${byteConvert(data.network_out)}↑ ${byteConvert(data.network_in)}↓`)}</p></div>`,
             showConfirmButton: false
         })
     }
@@ -200,13 +201,37 @@ let byteConvert2 = (data) => {
     }
 }
 
-let progressConvert = (data) => {
-    if (data <= 70) {
-        return ""
-    } else if (data <= 90) {
-        return "#faae42"
+let uptimeText = (uptime) => {
+    return uptime == "1 天" ? "1 Day" : uptime.replace(/天/, "Days")
+}
+
+// 客户端无法采集或未上报的指标显示 N/A
+let metricText = (server, metric, text) => {
+    return (server.unavailable_metrics || []).includes(metric) ? "N/A" : text
+}
+
+// 上报延迟超过期望间隔 2 倍时显示 Late，通常预示即将离线
+// 同一用户名从多个来源交替上报时显示 Conflict
+let hostStatus = (server) => {
+    if (server.conflict) {
+        return ["Conflict", "#e62965"]
+    } else if (server.age_secs > 2 * server.expected_interval_secs) {
+        return ["Late", "#faae42"]
+    } else if (server.status_level.cpu === "ok") {
+        return ["Available", ""]
     } else {
+        return ["Busy", "#faae42"]
+    }
+}
+
+// 颜色由服务端 [thresholds] 计算的 status_level 决定
+let progressConvert = (level) => {
+    if (level === "crit") {
         return "#e62965"
+    } else if (level === "warn") {
+        return "#faae42"
+    } else {
+        return ""
     }
 }
 